@@ -0,0 +1,20 @@
+// Color conversions shared by the shaders, prepended to each shader's source
+
+// Set when the surface encodes to sRGB on write, in which case colors are blended in linear
+// space. Otherwise colors are blended as authored, in sRGB space, the way MapLibre does.
+override SRGB_TARGET: bool = false;
+
+fn srgb_to_linear(c: vec3<f32>) -> vec3<f32> {
+    let low = c / 12.92;
+    let high = pow((c + 0.055) / 1.055, vec3(2.4));
+    return select(high, low, c <= vec3(0.04045));
+}
+
+// Converts a straight alpha style color to the premultiplied output for the render target
+fn output_color(color: vec4<f32>) -> vec4<f32> {
+    var rgb = color.rgb;
+    if SRGB_TARGET {
+        rgb = srgb_to_linear(rgb);
+    }
+    return vec4(rgb * color.a, color.a);
+}
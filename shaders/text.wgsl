@@ -1,5 +1,5 @@
 struct TextConstants {
-    scale: f32,
+    text_scale: f32,
     halo_width: f32,
     offset: vec2<f32>,
     tile_dims: vec2<f32>,
@@ -15,6 +15,7 @@ struct VertexInput {
     @location(1) uv: vec2<f32>,
     @location(2) label_offset: vec2<f32>,
     @location(3) halo: u32,
+    @location(4) color: vec4<f32>,
 }
 
 struct VertexOutput {
@@ -30,8 +31,10 @@ fn vs_main(text: VertexInput) -> VertexOutput {
     var label_offset = text.label_offset * text_constants.tile_dims;
     var tile_offset = text_constants.offset;
 
-    var offset_y = tile_offset.y + label_offset.y - text.position.y;
-    var offset_x = tile_offset.x + label_offset.x + text.position.x;
+    let position = text.position * text_constants.text_scale;
+
+    var offset_y = tile_offset.y + label_offset.y - position.y;
+    var offset_x = tile_offset.x + label_offset.x + position.x;
     var offset = vec2(offset_x, offset_y);
 
     let width = text_constants.halo_width;
@@ -47,8 +50,10 @@ fn vs_main(text: VertexInput) -> VertexOutput {
     } else if text.halo == 4u {
         out.color = text_constants.halo_color;
         offset = offset - width;
-    } else {
+    } else if text.color.w < 0.0 {
         out.color = text_constants.text_color;
+    } else {
+        out.color = text.color;
     }
 
     var a_position = vec2(offset / text_constants.window_dims);
@@ -67,5 +72,5 @@ fn vs_main(text: VertexInput) -> VertexOutput {
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     var coverage = textureSample(t_text_atlas, s_text_atlas, in.uv).x;
 
-    return vec4(pow(in.color.xyz, vec3(2.2)), in.color.w * coverage);
+    return output_color(vec4(in.color.rgb, in.color.a * coverage));
 }
@@ -1,57 +1,100 @@
 struct TileConstants {
-    fill_translate: vec2<f32>,
-    line_translate: vec2<f32>,
+    transform: mat3x3<f32>, // padded to mat3x4
+    rescale_offset: vec2<f32>,
+    rescale_scale: f32,
+    feature_offset: u32, // index of the tile's first feature in the bound paint
+}
+
+var<push_constant> tile_constants: TileConstants;
+
+// Evaluated paint of a feature, vertices index into the tile's features
+struct FeaturePaint {
     fill_color: vec4<f32>,
     line_color: vec4<f32>,
-    transform: mat3x3<f32>, // padded to mat3x4
+    pattern_from: vec4<f32>, // x, y, width, height in sprite texels
+    pattern_to: vec4<f32>,
+    fill_translate: vec2<f32>,
+    line_translate: vec2<f32>,
+    pattern_from_scale: vec2<f32>,
+    pattern_to_scale: vec2<f32>,
+    pattern_from_offset: vec2<f32>,
+    pattern_to_offset: vec2<f32>,
     line_width: f32,
-    line_dasharray: array<f32, 8>,
+    line_dasharray_row: u32,
     line_dasharray_len: u32,
     line_dasharray_total: f32,
-    rescale_scale: f32,
-    rescale_offset: vec2<f32>
+    line_gap_width: f32,
+    line_offset: f32,
+    line_blur: f32,
+    line_gradient_row: i32,
+    pattern_fade: f32,
 }
 
-var<push_constant> tile_constants: TileConstants;
+@group(1) @binding(0) var<storage, read> features: array<FeaturePaint>;
+
+// Each row holds a dash pattern's lengths, in multiples of the line width
+@group(0) @binding(0) var t_dasharray: texture_2d<f32>;
+
+// Each row holds a line gradient's colors from the start to the end of the line
+@group(0) @binding(1) var t_line_gradient: texture_2d<f32>;
+
+// The style's sprite sheet, holding the images drawn by fill-pattern and line-pattern
+@group(0) @binding(2) var t_sprite: texture_2d<f32>;
 
 struct VertexInput {
     @location(0) position: vec2<f32>,
     @location(1) normal: vec2<f32>,
-    @location(2) advancement: f32,
-    @location(3) fill: u32,
+    @location(2) offset: vec2<f32>,
+    @location(3) side: f32,
+    @location(4) advancement: f32,
+    @location(5) line_progress: f32,
+    @location(6) fill: u32,
+    @location(7) feature: u32,
 }
 
 struct VertexOutput {
     @builtin(position) position: vec4<f32>,
     @location(0) color: vec4<f32>,
     @location(1) @interpolate(linear) advancement: f32,
+    @location(2) side: f32,
+    @location(3) line_progress: f32,
+    @location(4) pattern_from_uv: vec2<f32>,
+    @location(5) pattern_to_uv: vec2<f32>,
+    @location(6) @interpolate(flat) feature: u32,
 }
 
 const FILL_LINE: u32 = 0;
 const FILL_POLYGON: u32 = 1;
 const FILL_BACKGROUND: u32 = 2;
+const FILL_OUTLINE: u32 = 3; // a fill's outline, stroked like a line and patterned like a fill
 
 @vertex
 fn vs_main(input: VertexInput) -> VertexOutput {
     var tile = input;
     tile.position = (tile.position - tile_constants.rescale_offset) * tile_constants.rescale_scale;
 
+    let feature = tile_constants.feature_offset + tile.feature;
+    let paint = features[feature];
+
     var out: VertexOutput;
+    out.feature = feature;
     var position: vec2<f32>;
 
     switch tile.fill {
-        case FILL_LINE {
-          out.color = tile_constants.line_color;
-          let line_width = tile.normal * (tile_constants.line_width / 2.0);
-          position = tile.position + line_width + tile_constants.line_translate;
+        case FILL_LINE, FILL_OUTLINE {
+          out.color = paint.line_color;
+          out.side = tile.side;
+          let extrude = tile.normal * line_outset(paint);
+          let offset = tile.offset * paint.line_offset;
+          position = tile.position + extrude + offset + paint.line_translate;
         }
         case FILL_POLYGON {
-          out.color = tile_constants.fill_color;
-          position = tile.position + tile_constants.fill_translate;
+          out.color = paint.fill_color;
+          position = tile.position + paint.fill_translate;
         }
         case FILL_BACKGROUND {
-          out.color = tile_constants.fill_color;
-          position = tile.position + tile_constants.fill_translate;
+          out.color = paint.fill_color;
+          position = tile.position + paint.fill_translate;
         }
         default: {
           out.color = vec4(1.0, 0.0, 1.0, 1.0);
@@ -61,16 +104,84 @@ fn vs_main(input: VertexInput) -> VertexOutput {
 
     out.position = vec4(tile_constants.transform * vec3(position, 1.0), 1.0);
     out.advancement = tile.advancement;
+    out.line_progress = tile.line_progress;
+
+    if paint.pattern_from.z > 0.0 {
+        let p = paint;
+        out.pattern_from_uv = pattern_uv(tile, p, p.pattern_from, p.pattern_from_scale, p.pattern_from_offset);
+        out.pattern_to_uv = pattern_uv(tile, p, p.pattern_to, p.pattern_to_scale, p.pattern_to_offset);
+    }
 
     return out;
 }
 
+// Where a vertex falls in a pattern, the image repeats every whole uv. Fills repeat the image
+// across the tile, lines stretch its height across the line and repeat it along the line.
+fn pattern_uv(tile: VertexInput, paint: FeaturePaint, rect: vec4<f32>, scale: vec2<f32>, offset: vec2<f32>) -> vec2<f32> {
+    if tile.fill == FILL_LINE {
+        let length = paint.line_width * rect.z / rect.w;
+        return vec2(tile.advancement / length, (tile.side + 1.0) / 2.0);
+    }
+    return offset + tile.position * scale;
+}
+
+fn pattern_texel(rect: vec4<f32>, uv: vec2<f32>) -> vec4<f32> {
+    let texel = rect.xy + min(floor(fract(uv) * rect.zw), rect.zw - 1.0);
+    return textureLoad(t_sprite, vec2<u32>(texel), 0);
+}
+
+// Distance from the center of the line to its outer edge. A gap splits the line into two
+// strokes of the full line width, one on either side of the gap.
+fn line_outset(paint: FeaturePaint) -> f32 {
+    let half_width = paint.line_width / 2.0;
+    let half_gap = paint.line_gap_width / 2.0;
+    if half_gap > 0.0 {
+        return half_gap + half_width * 2.0;
+    }
+    return half_width;
+}
+
+// Linearly interpolates the gradient's texels, the ramp is too short to need a sampler
+fn line_gradient(paint: FeaturePaint, progress: f32) -> vec4<f32> {
+    let row = u32(paint.line_gradient_row);
+    let last = textureDimensions(t_line_gradient).x - 1;
+    let x = clamp(progress, 0.0, 1.0) * f32(last);
+    let left = u32(floor(x));
+    let right = min(left + 1, last);
+    let a = textureLoad(t_line_gradient, vec2(left, row), 0);
+    let b = textureLoad(t_line_gradient, vec2(right, row), 0);
+    return mix(a, b, fract(x));
+}
+
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    if tile_constants.line_dasharray_len > 0 {
-        var dash_len = floor(in.advancement / tile_constants.line_dasharray_total) * tile_constants.line_dasharray_total;
-        for (var i = 0u; i < tile_constants.line_dasharray_len; i++) {
-            dash_len += tile_constants.line_dasharray[i];
+    let paint = features[in.feature];
+
+    // The side interpolates from -1 to 1 across the stroke, and is zero for fills. Unlike the
+    // normal, which is longer than 1 at miter joins, it stays a unit distance across the line.
+    let outset = line_outset(paint);
+    let dist = abs(in.side) * outset;
+    let pixel = fwidth(dist);
+
+    var alpha = 1.0;
+    let blur = paint.line_blur;
+    if blur > 0.0 {
+        alpha = clamp((outset - dist) / blur, 0.0, 1.0);
+    }
+
+    let half_gap = paint.line_gap_width / 2.0;
+    if half_gap > 0.0 {
+        let feather = max(blur, pixel);
+        alpha = min(alpha, clamp((dist - half_gap) / feather + 1.0, 0.0, 1.0));
+    }
+
+    if paint.line_dasharray_len > 0 && paint.line_width > 0.0 {
+        let width = paint.line_width;
+        let total = paint.line_dasharray_total * width;
+        var dash_len = floor(in.advancement / total) * total;
+        for (var i = 0u; i < paint.line_dasharray_len; i++) {
+            let texel = vec2(i, paint.line_dasharray_row);
+            dash_len += textureLoad(t_dasharray, texel, 0).x * width;
             if dash_len >= in.advancement {
                 if i % 2 == 1 {
                     discard;
@@ -80,5 +191,17 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
         }
     }
 
-    return vec4(pow(in.color.xyz, vec3(2.2)), in.color.w);
+    // Gradient and pattern colors are scaled by the feature color, white with its opacity
+    var color = in.color;
+    if paint.line_gradient_row >= 0 {
+        color *= line_gradient(paint, in.line_progress);
+    }
+
+    if paint.pattern_from.z > 0.0 {
+        let from_texel = pattern_texel(paint.pattern_from, in.pattern_from_uv);
+        let to_texel = pattern_texel(paint.pattern_to, in.pattern_to_uv);
+        color *= mix(from_texel, to_texel, paint.pattern_fade);
+    }
+
+    return output_color(vec4(color.rgb, color.a * alpha));
 }
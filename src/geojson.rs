@@ -0,0 +1,770 @@
+use crate::{
+    TileId,
+    proto::{Tile, tile},
+};
+
+use ahash::AHashMap as HashMap;
+use anyhow::{Result, anyhow, bail};
+use serde_json::Value;
+
+use std::{io::BufReader, path::PathBuf, sync::Arc};
+
+/// The single layer GeoJSON tiles hold, named as MapLibre names it
+pub const LAYER_NAME: &str = "_geojson";
+
+const EXTENT: f64 = 4096.0;
+/// How far features are kept past the tile's edges, in tile units, so lines and fills don't
+/// stop short of the edge
+const BUFFER: f64 = 64.0;
+
+/// Cells along each side of the grid that features are indexed in, a feature spanning the
+/// world touches every cell so this is kept small
+const GRID_SIZE: usize = 64;
+
+const MOVE_TO: u32 = 1;
+const LINE_TO: u32 = 2;
+const CLOSE_PATH: u32 = 7;
+
+/// A GeoJSON document, cut into vector tiles as they are queried the way geojson-vt cuts them
+/// for MapLibre
+pub struct GeoJsonSource {
+    features: Arc<FeatureIndex>,
+    /// Whether each part of a line in a tile is its own feature, with the fractions of the
+    /// whole line it spans in the `mapbox_clip_start` and `mapbox_clip_end` properties
+    line_metrics: bool,
+}
+
+/// Web mercator world coordinates, from 0.0 to 1.0 with y pointing south
+type Point = [f64; 2];
+
+struct Feature {
+    id: Option<u64>,
+    properties: Vec<(String, tile::Value)>,
+    geometry: Geometry,
+    /// The smallest and largest coordinates of the geometry
+    bounds: [Point; 2],
+}
+
+/// The document's features, with the features whose bounds touch each cell of a grid over
+/// the world so a tile only looks at the features near it
+struct FeatureIndex {
+    features: Vec<Feature>,
+    /// Indices into `features` in document order, rows of cells from the north
+    cells: Vec<Vec<u32>>,
+}
+
+enum Geometry {
+    Points(Vec<Point>),
+    Lines(Vec<Vec<Point>>),
+    /// Every ring of the polygons, holes included, since fills use the even-odd rule
+    Polygons(Vec<Vec<Point>>),
+}
+
+impl GeoJsonSource {
+    /// Loads a source's `data`, either inline GeoJSON or the url of a file in the data
+    /// directory, found the same way tile sources are
+    pub fn new<P: Into<PathBuf>>(data_dir: P, data: &Value, line_metrics: bool) -> Result<Self> {
+        let document = match data {
+            Value::String(uri) => {
+                let uri = url::Url::parse(uri)?;
+                let mut path = data_dir.into();
+                for seg in uri.path_segments().into_iter().flatten() {
+                    path.push(seg);
+                }
+
+                let file = BufReader::new(std::fs::File::open(path)?);
+                serde_json::from_reader(file)?
+            }
+            Value::Object(_) => data.clone(),
+            _ => bail!("expected geojson data to be a url or an object"),
+        };
+
+        let mut features = Vec::new();
+        read_object(&document, &mut features)?;
+
+        Ok(Self {
+            features: Arc::new(FeatureIndex::new(features)),
+            line_metrics,
+        })
+    }
+
+    pub fn try_clone(&self) -> Result<Self> {
+        Ok(Self {
+            features: self.features.clone(),
+            line_metrics: self.line_metrics,
+        })
+    }
+
+    pub fn query_tile(&mut self, tile_id: TileId) -> Option<Tile> {
+        // Tile rows count up from the south, tile coordinates count down from the north
+        let scale = 2f64.powi(tile_id.zoom as i32);
+        let origin = [
+            tile_id.column as f64,
+            (tile_id.limit() - tile_id.row - 1) as f64,
+        ];
+        let to_tile = |p: &Point| {
+            [
+                (p[0] * scale - origin[0]) * EXTENT,
+                (p[1] * scale - origin[1]) * EXTENT,
+            ]
+        };
+
+        let (min, max) = (-BUFFER, EXTENT + BUFFER);
+        let bounds = [min, max].map(|edge| {
+            [
+                (origin[0] + edge / EXTENT) / scale,
+                (origin[1] + edge / EXTENT) / scale,
+            ]
+        });
+
+        let mut layer = LayerBuilder::default();
+        for feature in self.features.query(bounds) {
+            let [low, high] = feature.bounds;
+            if high[0] < bounds[0][0]
+                || high[1] < bounds[0][1]
+                || low[0] > bounds[1][0]
+                || low[1] > bounds[1][1]
+            {
+                continue;
+            }
+
+            let mut geometry = GeometryEncoder::default();
+            let kind = match &feature.geometry {
+                Geometry::Points(points) => {
+                    let points: Vec<_> = points
+                        .iter()
+                        .map(to_tile)
+                        .filter(|p| p.iter().all(|&c| c >= min && c <= max))
+                        .collect();
+                    geometry.points(&round(&points));
+                    tile::GeomType::Point
+                }
+                Geometry::Lines(lines) => {
+                    for line in lines {
+                        let line: Vec<_> = line.iter().map(to_tile).collect();
+                        for part in clip_line(&line, min, max) {
+                            if !self.line_metrics {
+                                geometry.line(&round(&part.points));
+                                continue;
+                            }
+
+                            let mut part_geometry = GeometryEncoder::default();
+                            part_geometry.line(&round(&part.points));
+                            let clip = Some((part.start, part.end));
+                            layer.push(feature, clip, tile::GeomType::Linestring, part_geometry);
+                        }
+                    }
+                    tile::GeomType::Linestring
+                }
+                Geometry::Polygons(rings) => {
+                    for ring in rings {
+                        let ring: Vec<_> = ring.iter().map(to_tile).collect();
+                        geometry.ring(&round(&clip_ring(&ring, min, max)));
+                    }
+                    tile::GeomType::Polygon
+                }
+            };
+
+            layer.push(feature, None, kind, geometry);
+        }
+
+        Some(layer.into_tile())
+    }
+}
+
+impl FeatureIndex {
+    fn new(features: Vec<Feature>) -> Self {
+        let mut cells = vec![Vec::new(); GRID_SIZE * GRID_SIZE];
+        for (idx, feature) in features.iter().enumerate() {
+            let ([x0, y0], [x1, y1]) = Self::cells(feature.bounds);
+            for y in y0..=y1 {
+                for x in x0..=x1 {
+                    cells[y * GRID_SIZE + x].push(idx as u32);
+                }
+            }
+        }
+
+        Self { features, cells }
+    }
+
+    /// The features whose bounds might overlap `bounds`, in document order
+    fn query(&self, bounds: [Point; 2]) -> impl Iterator<Item = &Feature> {
+        let ([x0, y0], [x1, y1]) = Self::cells(bounds);
+        let mut found = Vec::new();
+        for y in y0..=y1 {
+            for cell in &self.cells[y * GRID_SIZE + x0..=y * GRID_SIZE + x1] {
+                found.extend_from_slice(cell);
+            }
+        }
+        found.sort_unstable();
+        found.dedup();
+
+        found.into_iter().map(|idx| &self.features[idx as usize])
+    }
+
+    /// The first and last cells on each axis that `bounds` touches
+    fn cells([min, max]: [Point; 2]) -> ([usize; 2], [usize; 2]) {
+        let cell = |c: f64| ((c * GRID_SIZE as f64).floor().max(0.0) as usize).min(GRID_SIZE - 1);
+        (min.map(cell), max.map(cell))
+    }
+}
+
+fn read_object(object: &Value, features: &mut Vec<Feature>) -> Result<()> {
+    match object.get("type").and_then(Value::as_str) {
+        Some("FeatureCollection") => {
+            let collection = object
+                .get("features")
+                .and_then(Value::as_array)
+                .ok_or(anyhow!("expected features for feature collection"))?;
+            for feature in collection {
+                read_object(feature, features)?;
+            }
+        }
+        Some("Feature") => {
+            let id = object.get("id").and_then(Value::as_u64);
+            let properties: Vec<_> = object
+                .get("properties")
+                .and_then(Value::as_object)
+                .into_iter()
+                .flatten()
+                .filter_map(|(key, value)| Some((key.clone(), property_value(value)?)))
+                .collect();
+
+            if let Some(geometry) = object.get("geometry").filter(|g| !g.is_null()) {
+                read_geometry(geometry, id, &properties, features)?;
+            }
+        }
+        Some(_) => read_geometry(object, None, &[], features)?,
+        None => bail!("missing geojson type"),
+    }
+
+    Ok(())
+}
+
+fn read_geometry(
+    geometry: &Value,
+    id: Option<u64>,
+    properties: &[(String, tile::Value)],
+    features: &mut Vec<Feature>,
+) -> Result<()> {
+    let coordinates = || {
+        geometry
+            .get("coordinates")
+            .ok_or(anyhow!("missing geometry coordinates"))
+    };
+
+    let geometry = match geometry.get("type").and_then(Value::as_str) {
+        Some("Point") => Geometry::Points(vec![read_point(coordinates()?)?]),
+        Some("MultiPoint") => Geometry::Points(read_points(coordinates()?)?),
+        Some("LineString") => Geometry::Lines(vec![read_points(coordinates()?)?]),
+        Some("MultiLineString") => Geometry::Lines(read_rings(coordinates()?)?),
+        Some("Polygon") => Geometry::Polygons(read_rings(coordinates()?)?),
+        Some("MultiPolygon") => {
+            let mut rings = Vec::new();
+            for polygon in read_array(coordinates()?)? {
+                rings.extend(read_rings(polygon)?);
+            }
+            Geometry::Polygons(rings)
+        }
+        Some("GeometryCollection") => {
+            let geometries = geometry
+                .get("geometries")
+                .ok_or(anyhow!("missing geometries for geometry collection"))?;
+            for geometry in read_array(geometries)? {
+                read_geometry(geometry, id, properties, features)?;
+            }
+            return Ok(());
+        }
+        Some(kind) => bail!("unknown geojson geometry type '{kind}'"),
+        None => bail!("missing geojson geometry type"),
+    };
+
+    let mut bounds = [[f64::INFINITY; 2], [f64::NEG_INFINITY; 2]];
+    let points = match &geometry {
+        Geometry::Points(points) => std::slice::from_ref(points),
+        Geometry::Lines(lines) | Geometry::Polygons(lines) => lines.as_slice(),
+    };
+    for point in points.iter().flatten() {
+        for axis in 0..2 {
+            bounds[0][axis] = bounds[0][axis].min(point[axis]);
+            bounds[1][axis] = bounds[1][axis].max(point[axis]);
+        }
+    }
+
+    features.push(Feature {
+        id,
+        properties: properties.to_vec(),
+        geometry,
+        bounds,
+    });
+
+    Ok(())
+}
+
+fn read_array(value: &Value) -> Result<&Vec<Value>> {
+    value
+        .as_array()
+        .ok_or(anyhow!("expected coordinates to be an array"))
+}
+
+fn read_point(value: &Value) -> Result<Point> {
+    match read_array(value)?.as_slice() {
+        [lon, lat, ..] => {
+            let lon = lon.as_f64().ok_or(anyhow!("expected longitude number"))?;
+            let lat = lat.as_f64().ok_or(anyhow!("expected latitude number"))?;
+            Ok(project(lon, lat))
+        }
+        _ => bail!("expected longitude and latitude"),
+    }
+}
+
+fn read_points(value: &Value) -> Result<Vec<Point>> {
+    read_array(value)?.iter().map(read_point).collect()
+}
+
+fn read_rings(value: &Value) -> Result<Vec<Vec<Point>>> {
+    read_array(value)?.iter().map(read_points).collect()
+}
+
+/// Projects a longitude and latitude to web mercator world coordinates
+fn project(lon: f64, lat: f64) -> Point {
+    let sin = lat.to_radians().sin();
+    let y = 0.5 - 0.25 * ((1.0 + sin) / (1.0 - sin)).ln() / std::f64::consts::PI;
+    [lon / 360.0 + 0.5, y.clamp(0.0, 1.0)]
+}
+
+/// Properties that tiles can't hold as scalars are kept as JSON text, as MapLibre keeps them
+fn property_value(value: &Value) -> Option<tile::Value> {
+    let mut property = tile::Value::default();
+    match value {
+        Value::Null => return None,
+        Value::Bool(b) => property.bool_value = Some(*b),
+        Value::Number(n) => property.double_value = n.as_f64(),
+        Value::String(s) => property.string_value = Some(s.clone().into_bytes()),
+        value => property.string_value = Some(value.to_string().into_bytes()),
+    }
+
+    Some(property)
+}
+
+/// A run of a line inside the tile, with the fractions of the whole line's length at its ends
+#[derive(Debug)]
+struct LinePart {
+    points: Vec<Point>,
+    start: f64,
+    end: f64,
+}
+
+/// Cuts the parts of a line inside the square from `min` to `max`
+fn clip_line(line: &[Point], min: f64, max: f64) -> Vec<LinePart> {
+    let distance = |a: Point, b: Point| (b[0] - a[0]).hypot(b[1] - a[1]);
+    let length: f64 = line.windows(2).map(|s| distance(s[0], s[1])).sum();
+
+    let mut parts = Vec::new();
+    let mut part: Option<LinePart> = None;
+    let mut travelled = 0.0;
+    for segment in line.windows(2) {
+        let (a, b) = (segment[0], segment[1]);
+        let segment_length = distance(a, b);
+        let at = |t: f64| {
+            let point = [a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t];
+            (point, travelled + segment_length * t)
+        };
+
+        match clip_segment(a, b, min, max) {
+            Some((t0, t1)) => {
+                let (start, start_distance) = at(t0);
+                let (end, end_distance) = at(t1);
+                let current = part.get_or_insert_with(|| LinePart {
+                    points: vec![start],
+                    start: start_distance,
+                    end: start_distance,
+                });
+                current.points.push(end);
+                current.end = end_distance;
+
+                // The line leaves the square before the segment ends
+                if t1 < 1.0 {
+                    parts.extend(part.take());
+                }
+            }
+            None => parts.extend(part.take()),
+        }
+
+        travelled += segment_length;
+    }
+    parts.extend(part);
+
+    if length > 0.0 {
+        for part in parts.iter_mut() {
+            part.start /= length;
+            part.end /= length;
+        }
+    }
+
+    parts
+}
+
+/// The span of the segment from `a` to `b` inside the square from `min` to `max`, following
+/// Liang-Barsky
+fn clip_segment(a: Point, b: Point, min: f64, max: f64) -> Option<(f64, f64)> {
+    let (mut t0, mut t1) = (0.0f64, 1.0f64);
+    for axis in 0..2 {
+        let delta = b[axis] - a[axis];
+        for (p, q) in [(-delta, a[axis] - min), (delta, max - a[axis])] {
+            if p == 0.0 {
+                if q < 0.0 {
+                    return None;
+                }
+            } else if p < 0.0 {
+                t0 = t0.max(q / p);
+            } else {
+                t1 = t1.min(q / p);
+            }
+        }
+    }
+
+    (t0 <= t1).then_some((t0, t1))
+}
+
+/// Cuts a ring to the square from `min` to `max`, one edge at a time following
+/// Sutherland-Hodgman
+fn clip_ring(ring: &[Point], min: f64, max: f64) -> Vec<Point> {
+    let mut ring = ring.to_vec();
+    if ring.len() > 1 && ring.first() == ring.last() {
+        ring.pop();
+    }
+
+    for axis in 0..2 {
+        for (edge, below) in [(min, false), (max, true)] {
+            let inside = |p: Point| match below {
+                true => p[axis] <= edge,
+                false => p[axis] >= edge,
+            };
+            let intersect = |a: Point, b: Point| {
+                let t = (edge - a[axis]) / (b[axis] - a[axis]);
+                [a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t]
+            };
+
+            let mut clipped = Vec::with_capacity(ring.len());
+            for (i, &b) in ring.iter().enumerate() {
+                let a = ring[(i + ring.len() - 1) % ring.len()];
+                match (inside(a), inside(b)) {
+                    (true, true) => clipped.push(b),
+                    (true, false) => clipped.push(intersect(a, b)),
+                    (false, true) => clipped.extend([intersect(a, b), b]),
+                    (false, false) => (),
+                }
+            }
+            ring = clipped;
+        }
+    }
+
+    ring
+}
+
+/// Rounds points to whole tile units, dropping the repeats that leaves
+fn round(points: &[Point]) -> Vec<[i64; 2]> {
+    let mut rounded: Vec<[i64; 2]> = points
+        .iter()
+        .map(|p| [p[0].round() as i64, p[1].round() as i64])
+        .collect();
+    rounded.dedup();
+    rounded
+}
+
+/// Encodes geometry as vector tile commands, each point relative to the one before
+#[derive(Default)]
+struct GeometryEncoder {
+    commands: Vec<u32>,
+    cursor: [i64; 2],
+}
+
+impl GeometryEncoder {
+    fn points(&mut self, points: &[[i64; 2]]) {
+        if points.is_empty() {
+            return;
+        }
+
+        self.command(MOVE_TO, points.len());
+        for point in points {
+            self.point(*point);
+        }
+    }
+
+    fn line(&mut self, points: &[[i64; 2]]) {
+        if points.len() < 2 {
+            return;
+        }
+
+        self.command(MOVE_TO, 1);
+        self.point(points[0]);
+        self.command(LINE_TO, points.len() - 1);
+        for point in &points[1..] {
+            self.point(*point);
+        }
+    }
+
+    fn ring(&mut self, points: &[[i64; 2]]) {
+        let points = match points {
+            [first, rest @ .., last] if first == last => &points[..rest.len() + 1],
+            points => points,
+        };
+        if points.len() < 3 {
+            return;
+        }
+
+        self.line(points);
+        self.command(CLOSE_PATH, 1);
+    }
+
+    fn command(&mut self, id: u32, count: usize) {
+        self.commands.push(id | (count as u32) << 3);
+    }
+
+    fn point(&mut self, point: [i64; 2]) {
+        for (coord, cursor) in point.into_iter().zip(self.cursor) {
+            let delta = coord - cursor;
+            self.commands.push(((delta << 1) ^ (delta >> 63)) as u32);
+        }
+        self.cursor = point;
+    }
+}
+
+/// Builds a tile's layer, interning the keys of the features' properties
+#[derive(Default)]
+struct LayerBuilder {
+    features: Vec<tile::Feature>,
+    keys: Vec<Vec<u8>>,
+    key_ids: HashMap<String, u32>,
+    values: Vec<tile::Value>,
+}
+
+impl LayerBuilder {
+    fn push(
+        &mut self,
+        feature: &Feature,
+        clip: Option<(f64, f64)>,
+        kind: tile::GeomType,
+        geometry: GeometryEncoder,
+    ) {
+        if geometry.commands.is_empty() {
+            return;
+        }
+
+        let clip: Vec<_> = clip
+            .into_iter()
+            .flat_map(|(start, end)| {
+                [("mapbox_clip_start", start), ("mapbox_clip_end", end)].map(|(key, fraction)| {
+                    let value = tile::Value {
+                        double_value: Some(fraction),
+                        ..Default::default()
+                    };
+                    (key, value)
+                })
+            })
+            .collect();
+
+        let properties = feature
+            .properties
+            .iter()
+            .map(|(key, value)| (key.as_str(), value));
+        let mut tags = Vec::new();
+        for (key, value) in properties.chain(clip.iter().map(|(key, value)| (*key, value))) {
+            let key_id = match self.key_ids.get(key) {
+                Some(id) => *id,
+                None => {
+                    let id = self.keys.len() as u32;
+                    self.keys.push(key.as_bytes().to_vec());
+                    self.key_ids.insert(key.to_string(), id);
+                    id
+                }
+            };
+
+            tags.extend([key_id, self.values.len() as u32]);
+            self.values.push(value.clone());
+        }
+
+        self.features.push(tile::Feature {
+            id: feature.id,
+            tags,
+            r#type: Some(kind as i32),
+            geometry: geometry.commands,
+        });
+    }
+
+    fn into_tile(self) -> Tile {
+        let layer = tile::Layer {
+            version: 2,
+            name: LAYER_NAME.to_string(),
+            features: self.features,
+            keys: self.keys,
+            values: self.values,
+            extent: Some(EXTENT as u32),
+        };
+
+        Tile {
+            layers: vec![layer],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_parts_span_their_share_of_the_line() {
+        // A line crossing the square twice, leaving it for the middle quarter of its length
+        let line = [[0.0, 10.0], [20.0, 10.0], [20.0, 30.0], [0.0, 30.0]];
+        let parts = clip_line(&line, 0.0, 15.0);
+
+        assert_eq!(parts.len(), 1);
+        assert_eq!(parts[0].points, vec![[0.0, 10.0], [15.0, 10.0]]);
+        assert_eq!((parts[0].start, parts[0].end), (0.0, 0.25));
+
+        let parts = clip_line(&line, 5.0, 40.0);
+        let spans: Vec<_> = parts.iter().map(|part| (part.start, part.end)).collect();
+        assert_eq!(spans, vec![(5.0 / 60.0, 55.0 / 60.0)]);
+
+        let line = [[-10.0, 5.0], [20.0, 5.0], [20.0, 8.0], [-10.0, 8.0]];
+        let parts = clip_line(&line, 0.0, 10.0);
+        let spans: Vec<_> = parts.iter().map(|part| (part.start, part.end)).collect();
+        assert_eq!(
+            spans,
+            vec![(10.0 / 63.0, 20.0 / 63.0), (43.0 / 63.0, 53.0 / 63.0)]
+        );
+    }
+
+    #[test]
+    fn rings_are_cut_to_the_square() {
+        let ring = [
+            [-5.0, -5.0],
+            [5.0, -5.0],
+            [5.0, 5.0],
+            [-5.0, 5.0],
+            [-5.0, -5.0],
+        ];
+        let mut clipped = clip_ring(&ring, 0.0, 10.0);
+        clipped.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        clipped.dedup();
+
+        assert_eq!(
+            clipped,
+            vec![[0.0, 0.0], [0.0, 5.0], [5.0, 0.0], [5.0, 5.0]]
+        );
+        assert!(clip_ring(&ring, 10.0, 20.0).is_empty());
+    }
+
+    #[test]
+    fn tiles_only_look_at_nearby_features() {
+        let point = |id: u64, lon: f64, lat: f64| {
+            serde_json::json!({
+                "type": "Feature",
+                "id": id,
+                "properties": {},
+                "geometry": { "type": "Point", "coordinates": [lon, lat] },
+            })
+        };
+        let data = serde_json::json!({
+            "type": "FeatureCollection",
+            "features": [
+                point(1, -170.0, 80.0),
+                point(2, 10.0, 10.0),
+                {
+                    "type": "Feature",
+                    "id": 3,
+                    "properties": {},
+                    "geometry": {
+                        "type": "LineString",
+                        "coordinates": [[-180.0, -80.0], [180.0, 80.0]],
+                    },
+                },
+                point(4, 10.1, 10.1),
+            ],
+        });
+
+        let source = GeoJsonSource::new("", &data, false).unwrap();
+        let index = &source.features;
+        assert_eq!(index.cells.len(), GRID_SIZE * GRID_SIZE);
+
+        // The line spans the world, so it is found once wherever the tile is and in document
+        // order with the features around it
+        let bounds = [project(9.0, 11.0), project(11.0, 9.0)];
+        let ids: Vec<_> = index.query(bounds).map(|f| f.id.unwrap()).collect();
+        assert_eq!(ids, vec![2, 3, 4]);
+
+        let ids: Vec<_> = index
+            .query([[0.0; 2], [1.0; 2]])
+            .map(|f| f.id.unwrap())
+            .collect();
+        assert_eq!(ids, vec![1, 2, 3, 4]);
+
+        let bounds = [project(-171.0, 81.0), project(-169.0, 79.0)];
+        let ids: Vec<_> = index.query(bounds).map(|f| f.id.unwrap()).collect();
+        assert_eq!(ids, vec![1, 3]);
+
+        // Bounds past the edges of the world are kept to its cells
+        let ids: Vec<_> = index
+            .query([[-1.0; 2], [0.03, 0.12]])
+            .map(|f| f.id.unwrap())
+            .collect();
+        assert_eq!(ids, vec![1, 3]);
+    }
+
+    #[test]
+    fn lines_are_split_into_features_with_line_metrics() {
+        let data = serde_json::json!({
+            "type": "Feature",
+            "id": 7,
+            "properties": { "name": "route", "lanes": 2, "tags": ["a"], "note": null },
+            "geometry": {
+                "type": "LineString",
+                "coordinates": [[-90.0, 0.0], [90.0, 0.0]],
+            },
+        });
+
+        let mut source = GeoJsonSource::new("", &data, true).unwrap();
+        let tile = source
+            .query_tile(TileId {
+                zoom: 1,
+                column: 0,
+                row: 1,
+            })
+            .unwrap();
+
+        let layer = &tile.layers[0];
+        assert_eq!(layer.name, LAYER_NAME);
+        assert_eq!(layer.features.len(), 1);
+
+        let feature = &layer.features[0];
+        assert_eq!(feature.id, Some(7));
+
+        let property = |name: &str| {
+            let tag = feature
+                .tags
+                .chunks(2)
+                .find(|tag| layer.keys[tag[0] as usize] == name.as_bytes())?;
+            Some(&layer.values[tag[1] as usize])
+        };
+
+        assert_eq!(feature.tags.len(), 10);
+        assert_eq!(
+            property("name").unwrap().string_value.as_deref(),
+            Some(&b"route"[..])
+        );
+        assert_eq!(property("lanes").unwrap().double_value, Some(2.0));
+        assert_eq!(
+            property("tags").unwrap().string_value.as_deref(),
+            Some(&b"[\"a\"]"[..])
+        );
+        assert!(property("note").is_none());
+
+        assert_eq!(
+            property("mapbox_clip_start").unwrap().double_value,
+            Some(0.0)
+        );
+        let end = property("mapbox_clip_end").unwrap().double_value.unwrap();
+        assert!((end - (0.5 + BUFFER / EXTENT)).abs() < 1e-9);
+    }
+}
@@ -1,12 +1,22 @@
+use bstr::{BString, ByteSlice};
 use wgpu::util::DeviceExt;
 use winit::window::Window;
 
-use std::sync::{Arc, Mutex, RwLock};
+use std::{
+    borrow::Cow,
+    sync::{Arc, Mutex, RwLock},
+};
 
 use ahash::AHashMap as HashMap;
 use math::{M3, Rect, V2, V4};
+use smallvec::SmallVec;
 
-use crate::{FeatureDraw, LayerLabelDraw, RectExt, TileId, tile_source::TileRectBuilder};
+use crate::{
+    FeatureDraw, LabelSize, LayerLabelDraw, RectExt, TILE_SCALE, TileId,
+    sprite::{Sprite, SpriteImage},
+    style::{COLOR_RAMP_WIDTH, ColorRamp, CrossFaded},
+    tile_source::TileRectBuilder,
+};
 use crate::{
     text::{
         AtlasEntry, GlyphKey, GlyphRender, GlyphRenderState, GlyphUploadEntry, TEXT_ATLAS_SIZE,
@@ -16,7 +26,27 @@ use crate::{
 
 pub const TILE_WGSL: &'static str = include_str!("../shaders/tile.wgsl");
 pub const TEXT_WGSL: &'static str = include_str!("../shaders/text.wgsl");
-pub const PUSH_CONSTANT_LIMIT: usize = 256;
+/// Color conversions shared by the shaders
+pub const COLOR_WGSL: &str = include_str!("../shaders/color.wgsl");
+/// Push constants hold a draw's `TileUniforms` or `TextUniforms`
+pub const PUSH_CONSTANT_SIZE: usize = {
+    let tile = std::mem::size_of::<TileUniforms>();
+    let text = std::mem::size_of::<TextUniforms>();
+    if tile > text { tile } else { text }
+};
+/// The longest dash pattern the dash atlas holds, longer patterns are truncated
+pub const DASH_ATLAS_WIDTH: u32 = 256;
+/// How many distinct dash patterns the dash atlas holds
+pub const DASH_ATLAS_ROWS: u32 = 256;
+/// How many distinct line gradients the gradient atlas holds
+pub const GRADIENT_ATLAS_ROWS: u32 = 256;
+/// How many features the feature buffer holds before it has to grow
+pub const FEATURE_BUFFER_CAPACITY: usize = 4096;
+/// How many features a draw can read when features are bound as uniforms, 10 KiB of paint
+/// within the 16 KiB uniform binding every adapter allows
+pub const FEATURE_WINDOW: usize = 64;
+/// How many draws' constants the uniform buffers hold before they have to grow
+pub const CONSTANT_BUFFER_DRAWS: usize = 1024;
 
 pub struct Gfx {
     window: &'static Window,
@@ -31,11 +61,17 @@ pub struct Gfx {
     tile_cache: TileGeometryCache,
     samples: u32,
     glyph_pipeline: GlyphPipeline,
+    dash_atlas: DashAtlas,
+    gradient_atlas: GradientAtlas,
+    sprite_atlas: SpriteAtlas,
+    tile_textures: TileTextures,
+    feature_buffer: FeatureBuffer,
+    tile_constants: DrawConstants,
     tile_size: V2<f32>,
 }
 
 impl Gfx {
-    pub fn new(window: &'static Window, tile_size: f32) -> Self {
+    pub fn new(window: &'static Window, tile_size: f32, sprite: Option<&Sprite>) -> Self {
         let size = window.inner_size();
         let size = V2::new(size.width, size.height);
         let tile_size = V2::fill(tile_size);
@@ -53,11 +89,34 @@ impl Gfx {
         });
         let adapter = pollster::block_on(adapter).unwrap();
 
+        // Downlevel and GL adapters often lack push constants, or hold too few of them for a
+        // draw's constants, draws then read their constants from uniform buffers instead
+        let push_constants = adapter.features().contains(wgpu::Features::PUSH_CONSTANTS)
+            && adapter.limits().max_push_constant_size >= PUSH_CONSTANT_SIZE as u32;
+        let (required_features, max_push_constant_size) = if push_constants {
+            (wgpu::Features::PUSH_CONSTANTS, PUSH_CONSTANT_SIZE as u32)
+        } else {
+            (wgpu::Features::empty(), 0)
+        };
+
+        // GL ES and WebGL adapters can't read storage buffers in vertex shaders, the feature
+        // paint is then bound as uniforms
+        let storage_features = adapter
+            .get_downlevel_capabilities()
+            .flags
+            .contains(wgpu::DownlevelFlags::VERTEX_STORAGE)
+            && adapter.limits().max_storage_buffers_per_shader_stage > 0;
+        let limits = if storage_features {
+            wgpu::Limits::downlevel_defaults()
+        } else {
+            wgpu::Limits::downlevel_webgl2_defaults()
+        };
+
         let device_request = adapter.request_device(&wgpu::DeviceDescriptor {
-            required_features: wgpu::Features::PUSH_CONSTANTS,
+            required_features,
             required_limits: wgpu::Limits {
-                max_push_constant_size: PUSH_CONSTANT_LIMIT as u32,
-                ..Default::default()
+                max_push_constant_size,
+                ..limits.using_resolution(adapter.limits())
             },
             label: Some("device"),
             memory_hints: wgpu::MemoryHints::Performance,
@@ -71,23 +130,41 @@ impl Gfx {
             .unwrap();
         config.present_mode = wgpu::PresentMode::AutoVsync;
 
+        // Style colors are sRGB encoded and MapLibre blends them as is, so a linear target
+        // matches its output exactly. sRGB targets still work, the shaders decode for them.
+        let capabilities = surface.get_capabilities(&adapter);
+        if let Some(format) = capabilities.formats.iter().find(|f| !f.is_srgb()) {
+            config.format = *format;
+        }
+
         surface.configure(&device, &config);
 
-        let glyph_renderer = GlyphPipeline::new(&device, &config, samples);
+        let glyph_renderer = GlyphPipeline::new(&device, &config, samples, push_constants);
+        let dash_atlas = DashAtlas::new(&device);
+        let gradient_atlas = GradientAtlas::new(&device);
+        let sprite_atlas = SpriteAtlas::new(&device, &queue, sprite);
+        let tile_textures = TileTextures::new(&device, &dash_atlas, &gradient_atlas, &sprite_atlas);
+        let feature_buffer = FeatureBuffer::new(&device, storage_features);
+        let tile_constants =
+            DrawConstants::new::<TileUniforms>(&device, "tile-constants", 2, push_constants);
 
+        let tile_source = feature_buffer.shader_source(TILE_WGSL);
         let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("tile-shader"),
-            source: wgpu::ShaderSource::Wgsl(TILE_WGSL.into()),
+            source: wgpu::ShaderSource::Wgsl(tile_constants.shader_source(&tile_source)),
         });
 
+        let mut bind_group_layouts = vec![
+            &tile_textures.bind_group_layout,
+            &feature_buffer.bind_group_layout,
+        ];
+        bind_group_layouts.extend(tile_constants.bind_group_layout());
+
         let render_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("tile-pipeline-layout"),
-                bind_group_layouts: &[],
-                push_constant_ranges: &[wgpu::PushConstantRange {
-                    stages: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-                    range: 0..PUSH_CONSTANT_LIMIT as u32,
-                }],
+                bind_group_layouts: &bind_group_layouts,
+                push_constant_ranges: tile_constants.push_constant_ranges(),
             });
 
         let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
@@ -104,10 +181,13 @@ impl Gfx {
                 entry_point: Some("fs_main"),
                 targets: &[Some(wgpu::ColorTargetState {
                     format: config.format,
-                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    blend: Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
                     write_mask: wgpu::ColorWrites::ALL,
                 })],
-                compilation_options: Default::default(),
+                compilation_options: wgpu::PipelineCompilationOptions {
+                    constants: &color_constants(config.format),
+                    ..Default::default()
+                },
             }),
             primitive: wgpu::PrimitiveState {
                 topology: wgpu::PrimitiveTopology::TriangleList,
@@ -144,6 +224,12 @@ impl Gfx {
             tile_cache: TileGeometryCache::new(),
             samples,
             glyph_pipeline: glyph_renderer,
+            dash_atlas,
+            gradient_atlas,
+            sprite_atlas,
+            tile_textures,
+            feature_buffer,
+            tile_constants,
             tile_size,
         }
     }
@@ -217,7 +303,7 @@ impl Gfx {
     }
 
     pub fn render<I: IntoIterator<Item = (TileId, Rect<i32>)> + Clone>(
-        &self,
+        &mut self,
         tiles: I,
         zoom: f32,
         scale: f32,
@@ -232,23 +318,106 @@ impl Gfx {
                 label: Some("encoder"),
             });
 
+        self.glyph_pipeline.upload(&self.queue);
+        self.dash_atlas.next_frame();
+
+        let geometry_tiles: Vec<_> = tiles
+            .clone()
+            .into_iter()
+            .filter_map(|(tile_id, rect)| {
+                let (tile, rescale_rect) = self.tile_cache.get_with_rescale(tile_id)?;
+                let scissor = rect.to_scissor(self.size)?;
+                Some((tile_id, tile, rect, rescale_rect, scissor))
+            })
+            .collect();
+
+        // A tile's geometry can stand in for several tiles of a different zoom, so its
+        // features are evaluated for each place it is drawn
+        let mut features = Vec::new();
+        let feature_offsets: Vec<u32> = geometry_tiles
+            .iter()
+            .map(|(tile_id, tile, rect, ..)| {
+                let offset = features.len() as u32;
+                let scale = rect.dimensions().x as f32;
+                for feature in tile.features.iter() {
+                    features.push(self.feature_uniforms(feature, *tile_id, scale, zoom));
+                }
+                offset
+            })
+            .collect();
+
+        self.feature_buffer
+            .write(&self.device, &self.queue, &features);
+
+        let text_tiles: Vec<_> = tiles
+            .into_iter()
+            .filter_map(
+                |(tile_id, rect)| match self.tile_cache.get(tile_id)?.text.as_ref() {
+                    Some(TileText::TextBuffers(text)) => Some((text, rect)),
+                    _ => None,
+                },
+            )
+            .collect();
+
+        let text_layers: Vec<_> = text_tiles
+            .iter()
+            .map(|(text, rect)| (text.layers.as_slice(), *rect))
+            .collect();
+        let label_draws = place_labels(self.size, self.tile_size, &text_layers, zoom, scale);
+
+        // Each window of a tile's layer is drawn at most twice, once for fills and once for
+        // outlines
+        let tile_draws = geometry_tiles
+            .iter()
+            .zip(feature_offsets.iter())
+            .map(|((_, tile, ..), feature_offset)| {
+                let mut start = *feature_offset;
+                tile.features
+                    .chunk_by(|a, b| a.layer == b.layer)
+                    .map(|features| {
+                        let features = start..start + features.len() as u32;
+                        start = features.end;
+                        self.feature_buffer.windows(features).count() * 2
+                    })
+                    .sum::<usize>()
+            })
+            .sum();
+        self.tile_constants.reserve(&self.device, tile_draws);
+        self.glyph_pipeline
+            .constants
+            .reserve(&self.device, label_draws.len());
+
+        let layer_count = geometry_tiles
+            .iter()
+            .flat_map(|(_, tile, ..)| tile.features.last())
+            .map(|feature| feature.layer + 1)
+            .chain(label_draws.last().map(|draw| draw.layer + 1))
+            .max()
+            .unwrap_or(0);
+
         {
             const DEBUG_TILES: bool = false;
             let clear_color = if DEBUG_TILES {
-                wgpu::Color {
-                    r: 1.0,
-                    g: 0.0,
-                    b: 1.0,
-                    a: 1.0,
-                }
+                super::Color::from_rgb(255, 0, 255)
             } else {
-                wgpu::Color {
+                super::Color {
                     r: 0.79,
                     g: 0.79,
                     b: 0.79,
                     a: 1.0,
                 }
             };
+            let clear_color = if self.config.format.is_srgb() {
+                clear_color.to_linear()
+            } else {
+                clear_color
+            };
+            let clear_color = wgpu::Color {
+                r: clear_color.r as f64,
+                g: clear_color.g as f64,
+                b: clear_color.b as f64,
+                a: clear_color.a as f64,
+            };
 
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("tile-render-pass"),
@@ -265,57 +434,135 @@ impl Gfx {
                 timestamp_writes: None,
                 occlusion_query_set: None,
             });
-            render_pass.set_pipeline(&self.render_pipeline);
 
-            for (tile_id, rect) in tiles.clone() {
-                let Some((tile, rescale_rect)) = self.tile_cache.get_with_rescale(tile_id) else {
-                    continue;
-                };
-
-                if let Some(scissor) = rect.to_scissor(self.size) {
-                    if DEBUG_TILES {
-                        render_pass.set_scissor_rect(
-                            scissor.min.x + 1,
-                            scissor.min.y + 1,
-                            scissor.width() - 2,
-                            scissor.height() - 2,
-                        );
-                    } else {
-                        render_pass.set_scissor_rect(
-                            scissor.min.x,
-                            scissor.min.y,
-                            scissor.width(),
-                            scissor.height(),
-                        );
-                    }
-
-                    let transform = TileTransform::new(self.size, rect);
+            // Tile features are stored in style layer order, so each tile only needs a cursor
+            let mut feature_cursors = vec![0; geometry_tiles.len()];
+            let mut label_draws = label_draws.iter().peekable();
+
+            for layer in 0..layer_count {
+                render_pass.set_pipeline(&self.render_pipeline);
+                render_pass.set_bind_group(0, &self.tile_textures.bind_group, &[]);
+
+                let layer_tiles: Vec<_> = geometry_tiles
+                    .iter()
+                    .zip(feature_offsets.iter())
+                    .zip(feature_cursors.iter_mut())
+                    .filter_map(|((geometry_tile, feature_offset), cursor)| {
+                        let features = &geometry_tile.1.features[*cursor..];
+                        let layer_features = features
+                            .iter()
+                            .position(|feature| feature.layer != layer)
+                            .unwrap_or(features.len());
+                        let first_feature = *feature_offset + *cursor as u32;
+                        *cursor += layer_features;
+
+                        let features = &features[..layer_features];
+                        (!features.is_empty()).then_some((
+                            geometry_tile,
+                            *feature_offset,
+                            first_feature,
+                            features,
+                        ))
+                    })
+                    .collect();
+
+                // Fill outlines are drawn over the fills of every tile in the layer, so the
+                // antialiased edges of one tile aren't covered by the fills of the next
+                for outlines in [false, true] {
+                    for (
+                        (_, tile, rect, rescale_rect, scissor),
+                        feature_offset,
+                        first_feature,
+                        features,
+                    ) in &layer_tiles
+                    {
+                        let layer_features = *first_feature..*first_feature + features.len() as u32;
+                        for (window, window_features) in self.feature_buffer.windows(layer_features)
+                        {
+                            let skip = (window_features.start - first_feature) as usize;
+                            let features = &features[skip..skip + window_features.len()];
+
+                            // A window's features are consecutive, so they draw as one range
+                            let elements = features
+                                .iter()
+                                .map(|feature| match outlines {
+                                    false => feature.elements.clone(),
+                                    true => feature.outline_elements.clone(),
+                                })
+                                .filter(|elements| !elements.is_empty())
+                                .reduce(|a, b| a.start.min(b.start)..a.end.max(b.end));
+
+                            let Some(elements) = elements else {
+                                continue;
+                            };
 
-                    render_pass.set_vertex_buffer(0, tile.vertex_buffer.slice(..));
-                    render_pass
-                        .set_index_buffer(tile.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                            if DEBUG_TILES {
+                                render_pass.set_scissor_rect(
+                                    scissor.min.x + 1,
+                                    scissor.min.y + 1,
+                                    scissor.width() - 2,
+                                    scissor.height() - 2,
+                                );
+                            } else {
+                                render_pass.set_scissor_rect(
+                                    scissor.min.x,
+                                    scissor.min.y,
+                                    scissor.width(),
+                                    scissor.height(),
+                                );
+                            }
 
-                    for feature in tile.features.iter() {
-                        let style = feature.paint.style(zoom);
-                        let uniforms =
-                            transform.to_uniforms(rect.dimensions().x as f32, style, rescale_rect);
-                        render_pass.set_push_constants(
-                            wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-                            0,
-                            bytemuck::bytes_of(&uniforms),
-                        );
+                            // Feature indices are relative to the start of the bound window,
+                            // wrapping around when the window starts after the tile's features
+                            let transform = TileTransform::new(self.size, *rect);
+                            let uniforms = transform
+                                .to_uniforms(*rescale_rect, feature_offset.wrapping_sub(window));
+
+                            render_pass.set_vertex_buffer(0, tile.vertex_buffer.slice(..));
+                            render_pass.set_index_buffer(
+                                tile.index_buffer.slice(..),
+                                wgpu::IndexFormat::Uint32,
+                            );
+                            self.feature_buffer.bind(&mut render_pass, window);
+                            self.tile_constants.set(&mut render_pass, &uniforms);
+
+                            render_pass.draw_indexed(
+                                elements.start as u32..elements.end as u32,
+                                0,
+                                0..1,
+                            );
+                        }
+                    }
+                }
 
-                        let start = feature.elements.start as u32;
-                        let end = feature.elements.end as u32;
+                if label_draws.peek().is_some_and(|draw| draw.layer == layer) {
+                    render_pass.set_pipeline(&self.glyph_pipeline.render_pipeline);
+                    render_pass.set_bind_group(0, &self.glyph_pipeline.atlas_bind_group, &[]);
+                    render_pass.set_scissor_rect(0, 0, self.size.x, self.size.y);
+
+                    let mut current_tile = None;
+                    while let Some(draw) = label_draws.next_if(|draw| draw.layer == layer) {
+                        if current_tile != Some(draw.tile) {
+                            let (text, _) = text_tiles[draw.tile];
+                            render_pass.set_vertex_buffer(0, text.vertex_buffer.slice(..));
+                            render_pass.set_index_buffer(
+                                text.index_buffer.slice(..),
+                                wgpu::IndexFormat::Uint32,
+                            );
+                            current_tile = Some(draw.tile);
+                        }
 
-                        render_pass.draw_indexed(start..end, 0, 0..1);
+                        self.glyph_pipeline
+                            .constants
+                            .set(&mut render_pass, &draw.uniforms);
+                        render_pass.draw_indexed(draw.elements.clone(), 0, 0..1);
                     }
                 }
             }
         }
 
-        self.glyph_pipeline.upload(&self.queue);
-        self.render_text(&mut encoder, &view, tiles, zoom, scale);
+        self.tile_constants.upload(&self.queue);
+        self.glyph_pipeline.constants.upload(&self.queue);
 
         self.queue.submit(Some(encoder.finish()));
 
@@ -324,90 +571,110 @@ impl Gfx {
         Ok(())
     }
 
-    fn render_text<I: IntoIterator<Item = (TileId, Rect<i32>)>>(
+    /// Evaluates a feature's paint for the zoom and the scale its tile is drawn at
+    fn feature_uniforms(
         &self,
-        encoder: &mut wgpu::CommandEncoder,
-        view: &wgpu::TextureView,
-        tiles: I,
-        zoom: f32,
+        feature: &FeatureDraw,
+        tile_id: TileId,
         scale: f32,
-    ) {
-        let mut text_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-            label: Some("text-render-pass"),
-            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                view: &self.multisampled_framebuffer,
-                resolve_target: Some(&view),
-                ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Load,
-                    store: wgpu::StoreOp::Store,
-                },
-                depth_slice: None,
-            })],
-            depth_stencil_attachment: None,
-            timestamp_writes: None,
-            occlusion_query_set: None,
-        });
-
-        text_pass.set_pipeline(&self.glyph_pipeline.render_pipeline);
-        text_pass.set_bind_group(0, &self.glyph_pipeline.atlas_bind_group, &[]);
-
-        let scaled_dims = self.tile_size * scale;
-        let mut label_bounds: Vec<Rect<f32>> = Vec::new();
-
-        for (tile_id, rect) in tiles {
-            let Some(tile) = self.tile_cache.get(tile_id) else {
-                continue;
-            };
+        zoom: f32,
+    ) -> FeatureUniforms {
+        let style = feature.paint.style(zoom);
+        let dash_row = self.dash_atlas.row(&self.queue, style.line_dasharray());
+        let gradient_row = style
+            .line_gradient()
+            .and_then(|ramp| self.gradient_atlas.row(&self.queue, ramp));
+
+        // Features whose pattern image is missing aren't drawn
+        let pattern = match style.pattern() {
+            Some(pattern) => {
+                let tile_size = self.tile_size.x;
+                match self.sprite_atlas.pattern(pattern, tile_id, tile_size) {
+                    Some(pattern) => Some(pattern),
+                    None => {
+                        let uniforms =
+                            FeatureUniforms::new(scale, style, dash_row, gradient_row, None);
+                        return uniforms.hidden();
+                    }
+                }
+            }
+            None => None,
+        };
 
-            let Some(TileText::TextBuffers(text)) = tile.text.as_ref() else {
-                continue;
-            };
+        FeatureUniforms::new(scale, style, dash_row, gradient_row, pattern)
+    }
+}
 
-            text_pass.insert_debug_marker("new tile");
-            text_pass.set_vertex_buffer(0, text.vertex_buffer.slice(..));
-            text_pass.set_index_buffer(text.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+/// Runs label collision for each tile, giving priority to the top-most style layers, and
+/// returns the labels that should be drawn ordered by style layer
+fn place_labels(
+    window_size: V2<u32>,
+    tile_size: V2<f32>,
+    tiles: &[(&[LabelLayerGeometry], Rect<i32>)],
+    zoom: f32,
+    scale: f32,
+) -> Vec<PlacedLabel> {
+    let scaled_dims = tile_size * scale;
+    let mut label_bounds: Vec<Rect<f32>> = Vec::new();
+    let mut draws = Vec::new();
 
-            label_bounds.clear();
+    for (tile_idx, (layers, rect)) in tiles.iter().enumerate() {
+        label_bounds.clear();
 
-            for layer in text.layers.iter().rev() {
-                let style = layer.paint.style(zoom);
+        for layer in layers.iter().rev() {
+            let style = layer.paint.style(zoom);
 
-                let uniforms = TextUniforms::new(self.size, self.tile_size, scale, rect, &style);
-                text_pass.set_push_constants(
-                    wgpu::ShaderStages::VERTEX_FRAGMENT,
-                    0,
-                    bytemuck::bytes_of(&uniforms),
-                );
+            let mut uniforms = TextUniforms::new(window_size, tile_size, scale, *rect, &style);
 
-                'outer: for label in layer.labels.iter() {
-                    let scaled_point = scaled_dims * label.point * V2::new(1.0, -1.0);
+            for label in layer.labels.iter() {
+                let scaled_point = scaled_dims * label.point * V2::new(1.0, -1.0);
+                uniforms.text_scale = label.size.scale(zoom);
 
+                'placement: for placement in label.placements.iter() {
                     let scaled_bounds = Rect::new(
-                        label.bounds.min + scaled_point,
-                        label.bounds.max + scaled_point,
+                        placement.bounds.min * uniforms.text_scale + scaled_point,
+                        placement.bounds.max * uniforms.text_scale + scaled_point,
                     );
 
                     for &placed_label in label_bounds.iter() {
                         if scaled_bounds.overlaps(placed_label) {
-                            continue 'outer;
+                            continue 'placement;
                         }
                     }
 
                     let start = if style.text_halo_width > 0.0 {
-                        label.halo_elements.start as u32
+                        placement.halo_elements.start as u32
                     } else {
-                        label.elements.start as u32
+                        placement.elements.start as u32
                     };
 
-                    let end = label.elements.end as u32;
+                    let end = placement.elements.end as u32;
 
-                    text_pass.draw_indexed(start..end, 0, 0..1);
+                    draws.push(PlacedLabel {
+                        layer: layer.layer,
+                        tile: tile_idx,
+                        elements: start..end,
+                        uniforms,
+                    });
 
                     label_bounds.push(scaled_bounds);
+                    break;
                 }
             }
         }
     }
+
+    // Collision ran top layer first, drawing must go bottom layer first
+    draws.sort_by_key(|draw| (draw.layer, draw.tile));
+
+    draws
+}
+
+struct PlacedLabel {
+    layer: usize,
+    tile: usize,
+    elements: std::ops::Range<u32>,
+    uniforms: TextUniforms,
 }
 
 pub struct GfxHandle {
@@ -422,7 +689,7 @@ impl GfxHandle {
         for layer in labels.iter() {
             for label in layer.labels.iter() {
                 for glyph in label.glyphs() {
-                    glyphs_available &= self.glyph_render.prepare(label.text_size, glyph.glyph);
+                    glyphs_available &= self.glyph_render.prepare(glyph.size, glyph.glyph);
                 }
             }
         }
@@ -479,130 +746,47 @@ impl GfxHandle {
             let cache = self.glyph_render.atlas_contents.read().unwrap();
             for layer in tile_layers {
                 for label in layer.labels.iter() {
-                    let halo_start = indices.len();
-                    let line_height = label.bounds.height() / label.lines.len() as f32;
-                    let anchor = V2::new(
-                        label.bounds.width() / 2.0,
-                        (label.bounds.height() / -2.0) + line_height,
-                    );
                     let label_offset = label.offset;
+                    let mut placements = SmallVec::new();
 
-                    for glyph in label.glyphs() {
-                        if let Some(raster) = cache.get(&glyph.glyph.with_size(label.text_size)) {
-                            let [p0, p1, p2, p3] = glyph.bounds.corners();
-                            let [uv0, uv1, uv2, uv3] = raster.uv();
-
-                            for n in 0..4 {
-                                let halo = n + 1;
-                                let h0 = TextVertex {
-                                    position: p0 - anchor,
-                                    uv: uv0,
-                                    label_offset,
-                                    halo,
-                                };
-
-                                let h1 = TextVertex {
-                                    position: p1 - anchor,
-                                    uv: uv1,
-                                    label_offset,
-                                    halo,
-                                };
-
-                                let h2 = TextVertex {
-                                    position: p2 - anchor,
-                                    uv: uv2,
-                                    label_offset,
-                                    halo,
-                                };
-
-                                let h3 = TextVertex {
-                                    position: p3 - anchor,
-                                    uv: uv3,
-                                    label_offset,
-                                    halo,
-                                };
-
-                                let idx = vertices.len() as u32;
-
-                                vertices.push(h0);
-                                vertices.push(h1);
-                                vertices.push(h2);
-                                vertices.push(h3);
-
-                                indices.push(idx + 2);
-                                indices.push(idx + 1);
-                                indices.push(idx);
-
-                                indices.push(idx + 1);
-                                indices.push(idx + 2);
-                                indices.push(idx + 3);
+                    for anchor in label.anchors.iter() {
+                        let halo_start = indices.len();
+
+                        for halo in 1..5 {
+                            for (shift, glyph) in label.positioned_glyphs(anchor) {
+                                let raster = cache.get(&glyph.glyph.with_size(glyph.size))?;
+                                let quad = TextQuad::new(glyph.bounds, shift, raster);
+                                quad.push(&mut vertices, &mut indices, label_offset, halo, None);
                             }
-                        } else {
-                            return None;
                         }
-                    }
-
-                    let element_start = indices.len();
 
-                    for glyph in label.glyphs() {
-                        if let Some(raster) = cache.get(&glyph.glyph.with_size(label.text_size)) {
-                            let [p0, p1, p2, p3] = glyph.bounds.corners();
-                            let [uv0, uv1, uv2, uv3] = raster.uv();
+                        let element_start = indices.len();
 
-                            let v0 = TextVertex {
-                                position: p0 - anchor,
-                                uv: uv0,
-                                label_offset,
-                                halo: 0,
-                            };
-
-                            let v1 = TextVertex {
-                                position: p1 - anchor,
-                                uv: uv1,
-                                label_offset,
-                                halo: 0,
-                            };
-
-                            let v2 = TextVertex {
-                                position: p2 - anchor,
-                                uv: uv2,
-                                label_offset,
-                                halo: 0,
-                            };
-
-                            let v3 = TextVertex {
-                                position: p3 - anchor,
-                                uv: uv3,
-                                label_offset,
-                                halo: 0,
-                            };
-
-                            let idx = vertices.len() as u32;
-
-                            vertices.push(v0);
-                            vertices.push(v1);
-                            vertices.push(v2);
-                            vertices.push(v3);
-
-                            indices.push(idx + 2);
-                            indices.push(idx + 1);
-                            indices.push(idx);
-
-                            indices.push(idx + 1);
-                            indices.push(idx + 2);
-                            indices.push(idx + 3);
+                        for (shift, glyph) in label.positioned_glyphs(anchor) {
+                            let raster = cache.get(&glyph.glyph.with_size(glyph.size))?;
+                            let quad = TextQuad::new(glyph.bounds, shift, raster);
+                            quad.push(&mut vertices, &mut indices, label_offset, 0, glyph.color);
                         }
+
+                        placements.push(LabelPlacement {
+                            elements: element_start..indices.len(),
+                            halo_elements: halo_start..element_start,
+                            bounds: Rect::new(
+                                label.bounds.min + anchor.shift,
+                                label.bounds.max + anchor.shift,
+                            ),
+                        });
                     }
 
                     labels.push(LabelGeometry {
-                        elements: element_start..indices.len(),
-                        halo_elements: halo_start..element_start,
-                        bounds: Rect::new(label.bounds.min - anchor, label.bounds.max - anchor),
+                        placements,
                         point: label.offset,
+                        size: label.size.clone(),
                     });
                 }
 
                 layers.push(LabelLayerGeometry {
+                    layer: layer.layer,
                     paint: layer.paint.clone(),
                     labels: labels.clone(),
                 });
@@ -656,10 +840,16 @@ struct GlyphPipeline {
     atlas_contents: Arc<RwLock<HashMap<GlyphKey, AtlasEntry>>>,
     state: Arc<Mutex<GlyphRenderState>>,
     glyph_upload: Arc<RwLock<HashMap<GlyphKey, GlyphUploadEntry>>>,
+    constants: DrawConstants,
 }
 
 impl GlyphPipeline {
-    fn new(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, samples: u32) -> Self {
+    fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        samples: u32,
+        push_constants: bool,
+    ) -> Self {
         let atlas_size = V2::new(TEXT_ATLAS_SIZE, TEXT_ATLAS_SIZE);
         let atlas_texture = device.create_texture(&wgpu::TextureDescriptor {
             label: Some("glyph-atlas-texture"),
@@ -726,19 +916,22 @@ impl GlyphPipeline {
             label: Some("glyph-atlas-bind-group"),
         });
 
+        let constants =
+            DrawConstants::new::<TextUniforms>(device, "text-constants", 1, push_constants);
+
         let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("glyph-atlas-shader"),
-            source: wgpu::ShaderSource::Wgsl(TEXT_WGSL.into()),
+            source: wgpu::ShaderSource::Wgsl(constants.shader_source(TEXT_WGSL)),
         });
 
+        let mut bind_group_layouts = vec![&atlas_bind_group_layout];
+        bind_group_layouts.extend(constants.bind_group_layout());
+
         let render_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("glyph-atlas-pipeline-layout"),
-                bind_group_layouts: &[&atlas_bind_group_layout],
-                push_constant_ranges: &[wgpu::PushConstantRange {
-                    stages: wgpu::ShaderStages::VERTEX_FRAGMENT,
-                    range: 0..PUSH_CONSTANT_LIMIT as u32,
-                }],
+                bind_group_layouts: &bind_group_layouts,
+                push_constant_ranges: constants.push_constant_ranges(),
             });
 
         let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
@@ -755,10 +948,13 @@ impl GlyphPipeline {
                 entry_point: Some("fs_main"),
                 targets: &[Some(wgpu::ColorTargetState {
                     format: config.format,
-                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    blend: Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
                     write_mask: wgpu::ColorWrites::ALL,
                 })],
-                compilation_options: Default::default(),
+                compilation_options: wgpu::PipelineCompilationOptions {
+                    constants: &color_constants(config.format),
+                    ..Default::default()
+                },
             }),
             primitive: wgpu::PrimitiveState {
                 topology: wgpu::PrimitiveTopology::TriangleList,
@@ -787,6 +983,7 @@ impl GlyphPipeline {
             atlas_contents: Arc::new(RwLock::new(HashMap::new())),
             state: Arc::new(Mutex::new(GlyphRenderState::default())),
             glyph_upload: Arc::new(RwLock::new(HashMap::new())),
+            constants,
         }
     }
 
@@ -873,6 +1070,430 @@ impl GlyphPipeline {
     }
 }
 
+/// The atlases the tile shader reads from, bound together as one group
+struct TileTextures {
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+}
+
+impl TileTextures {
+    fn new(
+        device: &wgpu::Device,
+        dash_atlas: &DashAtlas,
+        gradient_atlas: &GradientAtlas,
+        sprite_atlas: &SpriteAtlas,
+    ) -> Self {
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            },
+            count: None,
+        };
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[texture_entry(0), texture_entry(1), texture_entry(2)],
+            label: Some("tile-textures-bind-group-layout"),
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&dash_atlas.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&gradient_atlas.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&sprite_atlas.view),
+                },
+            ],
+            label: Some("tile-textures-bind-group"),
+        });
+
+        Self {
+            bind_group_layout,
+            bind_group,
+        }
+    }
+}
+
+/// Dash patterns, one per row of a float texture, so patterns of any length can be drawn
+/// without growing the push constants
+struct DashAtlas {
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    rows: Mutex<DashRows>,
+}
+
+/// Rows keyed by the bits of their dash array, with the frame each was last drawn in
+#[derive(Default)]
+struct DashRows {
+    frame: u64,
+    rows: HashMap<SmallVec<[u32; 8]>, (DashRow, u64)>,
+    /// The first row never handed out, rows past it are only reused by replacing a pattern
+    next_row: u32,
+}
+
+/// A dash pattern's place in the dash atlas, with its length in multiples of the line width
+#[derive(Debug, Copy, Clone, Default)]
+struct DashRow {
+    row: u32,
+    len: u32,
+    total: f32,
+}
+
+impl DashAtlas {
+    fn new(device: &wgpu::Device) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("dash-atlas-texture"),
+            size: wgpu::Extent3d {
+                width: DASH_ATLAS_WIDTH,
+                height: DASH_ATLAS_ROWS,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R32Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        Self {
+            texture,
+            view,
+            rows: Mutex::default(),
+        }
+    }
+
+    /// Starts a frame, freeing the rows of patterns that aren't drawn in it for reuse
+    fn next_frame(&mut self) {
+        self.rows.get_mut().unwrap().frame += 1;
+    }
+
+    /// Finds or uploads the row holding `dasharray`, `None` for solid lines
+    fn row(&self, queue: &wgpu::Queue, dasharray: &[f32]) -> Option<DashRow> {
+        let (row, pattern) = self.rows.lock().unwrap().row(dasharray)?;
+        let Some(pattern) = pattern else {
+            return Some(row);
+        };
+
+        queue.write_texture(
+            wgpu::TexelCopyTextureInfo {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d {
+                    x: 0,
+                    y: row.row,
+                    z: 0,
+                },
+                aspect: wgpu::TextureAspect::All,
+            },
+            bytemuck::cast_slice(&pattern),
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(pattern.len() as u32 * 4),
+                rows_per_image: None,
+            },
+            wgpu::Extent3d {
+                width: pattern.len() as u32,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+        );
+
+        Some(row)
+    }
+}
+
+impl DashRows {
+    /// Finds the row holding `dasharray`, or claims one for it along with the pattern to
+    /// upload to it
+    fn row(&mut self, dasharray: &[f32]) -> Option<(DashRow, Option<Vec<f32>>)> {
+        if dasharray.is_empty() || dasharray.iter().any(|n| !n.is_finite() || *n < 0.0) {
+            return None;
+        }
+
+        let key = dasharray.iter().map(|n| n.to_bits()).collect();
+        if let Some((row, last_used)) = self.rows.get_mut(&key) {
+            *last_used = self.frame;
+            return Some((*row, None));
+        }
+
+        // An odd number of lengths is repeated so dashes and gaps alternate on each cycle
+        let mut pattern: Vec<f32> = dasharray.to_vec();
+        if pattern.len() % 2 == 1 {
+            pattern.extend_from_slice(dasharray);
+        }
+        pattern.truncate(DASH_ATLAS_WIDTH as usize & !1);
+
+        let total = pattern.iter().sum::<f32>();
+        if total <= 0.0 {
+            return None;
+        }
+
+        // A full atlas replaces the least recently drawn pattern. Rows already used this
+        // frame can't be replaced, so when every row is in use lines are drawn solid
+        let index = if self.next_row < DASH_ATLAS_ROWS {
+            self.next_row += 1;
+            self.next_row - 1
+        } else {
+            let (evicted, (row, _)) = self
+                .rows
+                .iter()
+                .filter(|(_, (_, last_used))| *last_used < self.frame)
+                .min_by_key(|(_, (_, last_used))| *last_used)?;
+            let (evicted, index) = (evicted.clone(), row.row);
+            self.rows.remove(&evicted);
+            index
+        };
+
+        let row = DashRow {
+            row: index,
+            len: pattern.len() as u32,
+            total,
+        };
+        self.rows.insert(key, (row, self.frame));
+
+        Some((row, Some(pattern)))
+    }
+}
+
+/// Line gradient color ramps, one per row of a texture, sampled by the line's progress
+struct GradientAtlas {
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    /// Rows keyed by the ramp's address, holding the ramp so the address isn't reused
+    rows: Mutex<HashMap<usize, (Arc<ColorRamp>, u32)>>,
+}
+
+impl GradientAtlas {
+    fn new(device: &wgpu::Device) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("gradient-atlas-texture"),
+            size: wgpu::Extent3d {
+                width: COLOR_RAMP_WIDTH as u32,
+                height: GRADIENT_ATLAS_ROWS,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        Self {
+            texture,
+            view,
+            rows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Finds or uploads the row holding `ramp`, `None` once the atlas is full
+    fn row(&self, queue: &wgpu::Queue, ramp: &Arc<ColorRamp>) -> Option<u32> {
+        let key = Arc::as_ptr(ramp) as usize;
+        let mut rows = self.rows.lock().unwrap();
+        if let Some((_, row)) = rows.get(&key) {
+            return Some(*row);
+        }
+
+        if rows.len() as u32 >= GRADIENT_ATLAS_ROWS {
+            return None;
+        }
+
+        let row = rows.len() as u32;
+        queue.write_texture(
+            wgpu::TexelCopyTextureInfo {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x: 0, y: row, z: 0 },
+                aspect: wgpu::TextureAspect::All,
+            },
+            ramp.texels().as_flattened(),
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(COLOR_RAMP_WIDTH as u32 * 4),
+                rows_per_image: None,
+            },
+            wgpu::Extent3d {
+                width: COLOR_RAMP_WIDTH as u32,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+        );
+
+        rows.insert(key, (ramp.clone(), row));
+
+        Some(row)
+    }
+}
+
+/// The style's sprite sheet, holding the images drawn by `fill-pattern` and `line-pattern`
+struct SpriteAtlas {
+    view: wgpu::TextureView,
+    images: HashMap<String, SpriteImage>,
+}
+
+/// A pattern image's texels in the sprite sheet and how it repeats across a tile
+#[derive(Debug, Copy, Clone)]
+struct PatternImage {
+    rect: V4<f32>,
+    /// Repeats of the image per tile
+    scale: V2<f32>,
+    /// Where the tile's origin falls within the image, so patterns line up across tiles
+    offset: V2<f32>,
+}
+
+impl Default for PatternImage {
+    fn default() -> Self {
+        Self {
+            rect: V4::new(0.0, 0.0, 0.0, 0.0),
+            scale: V2::zero(),
+            offset: V2::zero(),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+struct Pattern {
+    from: PatternImage,
+    to: PatternImage,
+    fade: f32,
+}
+
+impl SpriteAtlas {
+    fn new(device: &wgpu::Device, queue: &wgpu::Queue, sprite: Option<&Sprite>) -> Self {
+        // Sheets the device can't hold are dropped, leaving patterns undrawn as if the sprite
+        // was missing
+        let max_dimension = device.limits().max_texture_dimension_2d;
+        let sprite = sprite.filter(|sprite| {
+            let fits = sprite.width <= max_dimension && sprite.height <= max_dimension;
+            if !fits {
+                eprintln!(
+                    "sprite sheet of {}x{} exceeds the maximum texture size of {max_dimension}",
+                    sprite.width, sprite.height
+                );
+            }
+            fits
+        });
+
+        let (width, height) = sprite.map_or((1, 1), |sprite| (sprite.width, sprite.height));
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("sprite-atlas-texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        if let Some(sprite) = sprite {
+            queue.write_texture(
+                wgpu::TexelCopyTextureInfo {
+                    texture: &texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                &sprite.pixels,
+                wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(width * 4),
+                    rows_per_image: None,
+                },
+                wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        Self {
+            view,
+            images: sprite
+                .map(|sprite| sprite.images.iter().map(|(k, v)| (k.clone(), *v)).collect())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Places both images of a cross faded pattern on the tile, `None` if either is missing
+    fn pattern(
+        &self,
+        pattern: &CrossFaded<BString>,
+        tile_id: TileId,
+        tile_size: f32,
+    ) -> Option<Pattern> {
+        Some(Pattern {
+            from: self.pattern_image(&pattern.from, tile_id, tile_size)?,
+            to: self.pattern_image(&pattern.to, tile_id, tile_size)?,
+            fade: pattern.fade,
+        })
+    }
+
+    fn pattern_image(
+        &self,
+        name: &BString,
+        tile_id: TileId,
+        tile_size: f32,
+    ) -> Option<PatternImage> {
+        let image = self.images.get(name.to_str().ok()?)?;
+        Some(PatternImage::new(image, tile_id, tile_size))
+    }
+}
+
+impl PatternImage {
+    /// Patterns are drawn at their pixel size on tiles drawn at `tile_size`, and so repeat
+    /// in world space, scaling with the map between zoom levels
+    fn new(image: &SpriteImage, tile_id: TileId, tile_size: f32) -> Self {
+        let (width, height) = image.size();
+
+        let repeats = V2::new(
+            (tile_size / (width * TILE_SCALE)) as f64,
+            (tile_size / (height * TILE_SCALE)) as f64,
+        );
+
+        // Rows count up from the south, tile coordinates point down
+        let column = tile_id.column as f64;
+        let row = (tile_id.limit() - 1 - tile_id.row) as f64;
+        let offset = V2::new((column * repeats.x).fract(), (row * repeats.y).fract());
+
+        PatternImage {
+            rect: V4::new(
+                image.x as f32,
+                image.y as f32,
+                image.width as f32,
+                image.height as f32,
+            ),
+            scale: repeats.as_f32(),
+            offset: offset.as_f32(),
+        }
+    }
+}
+
 pub struct TextBuffers {
     vertex_buffer: wgpu::Buffer,
     index_buffer: wgpu::Buffer,
@@ -970,11 +1591,56 @@ struct TextVertex {
     uv: V2<f32>,
     label_offset: V2<f32>,
     halo: u32,
+    /// Negative alpha uses the layer's text color
+    color: V4<f32>,
+}
+
+struct TextQuad {
+    corners: [V2<f32>; 4],
+    uv: [V2<f32>; 4],
+}
+
+impl TextQuad {
+    fn new(bounds: Rect<f32>, shift: V2<f32>, raster: &AtlasEntry) -> Self {
+        let corners = bounds.corners().map(|p| p + shift);
+        let uv = raster.uv();
+
+        Self { corners, uv }
+    }
+
+    fn push(
+        &self,
+        vertices: &mut Vec<TextVertex>,
+        indices: &mut Vec<u32>,
+        label_offset: V2<f32>,
+        halo: u32,
+        color: Option<super::Color>,
+    ) {
+        let idx = vertices.len() as u32;
+        let color = color.map_or(V4::new(0.0, 0.0, 0.0, -1.0), |c| c.as_v4());
+
+        for (position, uv) in self.corners.into_iter().zip(self.uv) {
+            vertices.push(TextVertex {
+                position,
+                uv,
+                label_offset,
+                halo,
+                color,
+            });
+        }
+
+        indices.push(idx + 2);
+        indices.push(idx + 1);
+        indices.push(idx);
+
+        indices.push(idx + 1);
+        indices.push(idx + 2);
+        indices.push(idx + 3);
+    }
 }
 
 impl TextVertex {
-    const ATTRIBS: [wgpu::VertexAttribute; 4] =
-        wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x2, 2 => Float32x2, 3 => Uint32];
+    const ATTRIBS: [wgpu::VertexAttribute; 5] = wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x2, 2 => Float32x2, 3 => Uint32, 4 => Float32x4];
 
     fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
         use std::mem;
@@ -987,28 +1653,438 @@ impl TextVertex {
     }
 }
 
+/// Pipeline-overridable constants the shaders use to encode colors for the render target
+fn color_constants(format: wgpu::TextureFormat) -> [(&'static str, f64); 1] {
+    let srgb_target = if format.is_srgb() { 1.0 } else { 0.0 };
+    [("SRGB_TARGET", srgb_target)]
+}
+
+/// The paint of every feature drawn in a frame, so each layer of a tile is drawn with a single
+/// call and the vertices look up their paint by feature. Adapters without storage buffers in
+/// vertex shaders bind the features as a uniform array instead, a window of them at each draw.
+struct FeatureBuffer {
+    buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    storage: bool,
+    /// Features between the starts of windows, so windows begin at an aligned offset
+    window_alignment: u32,
+}
+
+impl FeatureBuffer {
+    fn new(device: &wgpu::Device, storage: bool) -> Self {
+        let size = std::mem::size_of::<FeatureUniforms>();
+        let (ty, has_dynamic_offset, binding_size) = if storage {
+            let ty = wgpu::BufferBindingType::Storage { read_only: true };
+            (ty, false, size)
+        } else {
+            (
+                wgpu::BufferBindingType::Uniform,
+                true,
+                size * FEATURE_WINDOW,
+            )
+        };
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty,
+                    has_dynamic_offset,
+                    min_binding_size: wgpu::BufferSize::new(binding_size as u64),
+                },
+                count: None,
+            }],
+            label: Some("feature-buffer-bind-group-layout"),
+        });
+
+        let alignment = device.limits().min_uniform_buffer_offset_alignment as usize;
+        let window_alignment = (1..=alignment)
+            .find(|features| (features * size).is_multiple_of(alignment))
+            .unwrap_or(alignment) as u32;
+
+        let (buffer, bind_group) =
+            Self::create_buffer(device, &bind_group_layout, FEATURE_BUFFER_CAPACITY, storage);
+
+        Self {
+            buffer,
+            bind_group_layout,
+            bind_group,
+            storage,
+            window_alignment,
+        }
+    }
+
+    fn create_buffer(
+        device: &wgpu::Device,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        capacity: usize,
+        storage: bool,
+    ) -> (wgpu::Buffer, wgpu::BindGroup) {
+        let size = std::mem::size_of::<FeatureUniforms>();
+        // The last window can start just before the final feature, so it is padded to fit
+        let (usage, capacity, binding_size) = if storage {
+            (wgpu::BufferUsages::STORAGE, capacity, None)
+        } else {
+            let binding_size = wgpu::BufferSize::new((size * FEATURE_WINDOW) as u64);
+            (
+                wgpu::BufferUsages::UNIFORM,
+                capacity + FEATURE_WINDOW,
+                binding_size,
+            )
+        };
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("feature-buffer"),
+            size: (capacity * size) as u64,
+            usage: usage | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &buffer,
+                    offset: 0,
+                    size: binding_size,
+                }),
+            }],
+            label: Some("feature-buffer-bind-group"),
+        });
+
+        (buffer, bind_group)
+    }
+
+    /// Uploads the frame's features, growing the buffer when they don't fit
+    fn write(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, features: &[FeatureUniforms]) {
+        let contents: &[u8] = bytemuck::cast_slice(features);
+        let padding = match self.storage {
+            true => 0,
+            false => FEATURE_WINDOW * std::mem::size_of::<FeatureUniforms>(),
+        };
+
+        if (contents.len() + padding) as u64 > self.buffer.size() {
+            let capacity = features.len().next_power_of_two();
+            (self.buffer, self.bind_group) =
+                Self::create_buffer(device, &self.bind_group_layout, capacity, self.storage);
+        }
+
+        queue.write_buffer(&self.buffer, 0, contents);
+    }
+
+    /// Splits a draw of consecutive features, by their index in the frame, into the ranges
+    /// that fit a window, paired with the index the window starts at. Storage buffers hold
+    /// every feature, so their draws aren't split.
+    fn windows(
+        &self,
+        features: std::ops::Range<u32>,
+    ) -> impl Iterator<Item = (u32, std::ops::Range<u32>)> {
+        let (storage, alignment) = (self.storage, self.window_alignment);
+        let mut start = features.start;
+        std::iter::from_fn(move || {
+            if start >= features.end {
+                return None;
+            }
+
+            let window = if storage {
+                0
+            } else {
+                start / alignment * alignment
+            };
+            let end = match storage {
+                true => features.end,
+                false => features.end.min(window + FEATURE_WINDOW as u32),
+            };
+
+            let range = start..end;
+            start = end;
+            Some((window, range))
+        })
+    }
+
+    /// Binds the features for the draws that follow, from the window starting at `window`
+    fn bind(&self, render_pass: &mut wgpu::RenderPass, window: u32) {
+        let offset = window * std::mem::size_of::<FeatureUniforms>() as u32;
+        let offsets: &[wgpu::DynamicOffset] = if self.storage { &[] } else { &[offset] };
+        render_pass.set_bind_group(1, &self.bind_group, offsets);
+    }
+
+    fn shader_source<'a>(&self, source: &'a str) -> Cow<'a, str> {
+        Self::declare_features(source, self.storage)
+    }
+
+    /// Declares the tile shader's features as a uniform array when they can't be in storage
+    fn declare_features(source: &str, storage: bool) -> Cow<'_, str> {
+        if storage {
+            return source.into();
+        }
+
+        let uniform = format!("var<uniform> features: array<FeaturePaint, {FEATURE_WINDOW}>");
+        let storage = "var<storage, read> features: array<FeaturePaint>";
+        replace_declaration(source, storage, &uniform).into()
+    }
+}
+
+/// Rewrites a declaration the fallbacks change, which the shader must spell exactly as `from`
+/// and only once, so reformatting it fails here rather than in pipeline validation
+fn replace_declaration(source: &str, from: &str, to: &str) -> String {
+    assert_eq!(
+        source.matches(from).count(),
+        1,
+        "expected the shader to declare `{from}` once"
+    );
+    source.replace(from, to)
+}
+
+const PUSH_CONSTANT_RANGES: &[wgpu::PushConstantRange] = &[wgpu::PushConstantRange {
+    stages: wgpu::ShaderStages::VERTEX_FRAGMENT,
+    range: 0..PUSH_CONSTANT_SIZE as u32,
+}];
+
+/// How a pipeline's draws get their constants. Push constants when the adapter has them,
+/// otherwise a uniform buffer holding every draw of the frame, bound at each draw's offset.
+enum DrawConstants {
+    Push,
+    Uniform(ConstantBuffer),
+}
+
+struct ConstantBuffer {
+    label: &'static str,
+    group: u32,
+    buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    /// Size of a draw's constants
+    size: u64,
+    /// Distance between draws' constants, padded to the device's offset alignment
+    stride: u64,
+    /// The frame's constants, uploaded once its draws are recorded
+    contents: Vec<u8>,
+}
+
+impl DrawConstants {
+    fn new<T: bytemuck::NoUninit>(
+        device: &wgpu::Device,
+        label: &'static str,
+        group: u32,
+        push_constants: bool,
+    ) -> Self {
+        if push_constants {
+            return DrawConstants::Push;
+        }
+
+        let size = std::mem::size_of::<T>() as u64;
+        let alignment = device.limits().min_uniform_buffer_offset_alignment as u64;
+        let stride = size.next_multiple_of(alignment);
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: wgpu::BufferSize::new(size),
+                },
+                count: None,
+            }],
+            label: Some(label),
+        });
+
+        let (buffer, bind_group) = ConstantBuffer::create_buffer(
+            device,
+            label,
+            &bind_group_layout,
+            size,
+            stride * CONSTANT_BUFFER_DRAWS as u64,
+        );
+
+        DrawConstants::Uniform(ConstantBuffer {
+            label,
+            group,
+            buffer,
+            bind_group_layout,
+            bind_group,
+            size,
+            stride,
+            contents: Vec::new(),
+        })
+    }
+
+    fn bind_group_layout(&self) -> Option<&wgpu::BindGroupLayout> {
+        match self {
+            DrawConstants::Push => None,
+            DrawConstants::Uniform(constants) => Some(&constants.bind_group_layout),
+        }
+    }
+
+    fn push_constant_ranges(&self) -> &'static [wgpu::PushConstantRange] {
+        match self {
+            DrawConstants::Push => PUSH_CONSTANT_RANGES,
+            DrawConstants::Uniform(_) => &[],
+        }
+    }
+
+    fn shader_source(&self, source: &str) -> Cow<'static, str> {
+        let group = match self {
+            DrawConstants::Push => None,
+            DrawConstants::Uniform(constants) => Some(constants.group),
+        };
+        Self::declare_constants(source, group).into()
+    }
+
+    /// Prepends the shared color functions to a shader. The shaders declare their constants as
+    /// push constants, without them the constants are moved to the uniform buffer's `group`
+    fn declare_constants(source: &str, group: Option<u32>) -> String {
+        let source = [COLOR_WGSL, source].join("\n");
+        match group {
+            None => source,
+            Some(group) => {
+                let binding = format!("@group({group}) @binding(0) var<uniform>");
+                replace_declaration(&source, "var<push_constant>", &binding)
+            }
+        }
+    }
+
+    /// Makes room for a frame of `draws`, dropping the constants of the last frame
+    fn reserve(&mut self, device: &wgpu::Device, draws: usize) {
+        let DrawConstants::Uniform(constants) = self else {
+            return;
+        };
+
+        constants.contents.clear();
+
+        let size = draws as u64 * constants.stride;
+        if size > constants.buffer.size() {
+            let size = draws.next_power_of_two() as u64 * constants.stride;
+            (constants.buffer, constants.bind_group) = ConstantBuffer::create_buffer(
+                device,
+                constants.label,
+                &constants.bind_group_layout,
+                constants.size,
+                size,
+            );
+        }
+    }
+
+    /// Sets the constants of the draws that follow
+    fn set<T: bytemuck::NoUninit>(&mut self, render_pass: &mut wgpu::RenderPass, value: &T) {
+        match self {
+            DrawConstants::Push => {
+                render_pass.set_push_constants(
+                    wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    0,
+                    bytemuck::bytes_of(value),
+                );
+            }
+            DrawConstants::Uniform(constants) => {
+                let offset = constants.contents.len();
+                constants
+                    .contents
+                    .extend_from_slice(bytemuck::bytes_of(value));
+                constants
+                    .contents
+                    .resize(offset + constants.stride as usize, 0);
+
+                render_pass.set_bind_group(
+                    constants.group,
+                    &constants.bind_group,
+                    &[offset as wgpu::DynamicOffset],
+                );
+            }
+        }
+    }
+
+    /// Writes the frame's constants, before the frame is submitted
+    fn upload(&self, queue: &wgpu::Queue) {
+        if let DrawConstants::Uniform(constants) = self
+            && !constants.contents.is_empty()
+        {
+            queue.write_buffer(&constants.buffer, 0, &constants.contents);
+        }
+    }
+}
+
+impl ConstantBuffer {
+    fn create_buffer(
+        device: &wgpu::Device,
+        label: &'static str,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        binding_size: u64,
+        size: u64,
+    ) -> (wgpu::Buffer, wgpu::BindGroup) {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &buffer,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(binding_size),
+                }),
+            }],
+            label: Some(label),
+        });
+
+        (buffer, bind_group)
+    }
+}
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::NoUninit)]
-// Sorted fields in a weird order for padding/alignment
 struct TileUniforms {
-    fill_translate: V2<f32>,
-    line_translate: V2<f32>,
-    fill_color: V4<f32>,
-    line_color: V4<f32>,
     transform_c0: V4<f32>,
     transform_c1: V4<f32>,
     transform_c2: V4<f32>,
+    rescale_offset: V2<f32>,
+    rescale_scale: f32,
+    /// Index of the tile's first feature in the features bound for the draw
+    feature_offset: u32,
+}
+
+/// A feature's paint, evaluated each frame for the zoom and the scale of the tile it is in
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::NoUninit)]
+// Sorted fields in a weird order for padding/alignment
+struct FeatureUniforms {
+    fill_color: V4<f32>,
+    line_color: V4<f32>,
+    /// Texels of the pattern image in the sprite atlas, zero sized when there is no pattern
+    pattern_from: V4<f32>,
+    pattern_to: V4<f32>,
+    fill_translate: V2<f32>,
+    line_translate: V2<f32>,
+    pattern_from_scale: V2<f32>,
+    pattern_to_scale: V2<f32>,
+    pattern_from_offset: V2<f32>,
+    pattern_to_offset: V2<f32>,
     line_width: f32,
-    line_dasharray: [f32; 8],
+    line_dasharray_row: u32,
     line_dasharray_len: u32,
     line_dasharray_total: f32,
-    rescale_scale: f32,
-    rescale_offset: V2<f32>,
+    line_gap_width: f32,
+    line_offset: f32,
+    line_blur: f32,
+    /// Row of the line's gradient in the gradient atlas, -1 when the line has none
+    line_gradient_row: i32,
+    pattern_fade: f32,
+    /// Pads the struct to the 16 byte multiple that storage buffer arrays are strided by
+    _padding: [u32; 3],
 }
 
 const _: () = assert!(
-    std::mem::size_of::<TileUniforms>() <= PUSH_CONSTANT_LIMIT,
-    "TileUniforms must fit within push constant limit"
+    std::mem::size_of::<FeatureUniforms>() % 16 == 0,
+    "FeatureUniforms must match the array stride of the feature buffer"
 );
 
 struct TileTransform {
@@ -1037,54 +2113,71 @@ impl TileTransform {
         Self { transform }
     }
 
-    fn to_uniforms(
-        &self,
-        scale: f32,
-        style: super::FeatureStyle,
-        rescale_rect: TileRect,
-    ) -> TileUniforms {
-        TileUniforms::new(self.transform, scale, style, rescale_rect)
+    fn to_uniforms(&self, rescale_rect: TileRect, feature_offset: u32) -> TileUniforms {
+        TileUniforms {
+            transform_c0: self.transform.c0.expand(0.0),
+            transform_c1: self.transform.c1.expand(0.0),
+            transform_c2: self.transform.c2.expand(0.0),
+            rescale_offset: rescale_rect.offset,
+            rescale_scale: rescale_rect.scale,
+            feature_offset,
+        }
     }
 }
 
-impl TileUniforms {
+impl FeatureUniforms {
     fn new(
-        transform: M3<f32>,
         scale: f32,
         style: super::FeatureStyle,
-        rescale_rect: TileRect,
+        dash_row: Option<DashRow>,
+        gradient_row: Option<u32>,
+        pattern: Option<Pattern>,
     ) -> Self {
         let line_width = style.line_width() / scale;
-        let line_dasharray_vec = style.line_dasharray();
-
-        let mut line_dasharray = [0.0; 8];
-        let mut line_dasharray_len = 0;
-        let mut line_dasharray_total = 0.0;
-
-        if line_dasharray_vec.len() <= 8 {
-            for n in line_dasharray_vec {
-                let n = n * line_width;
-                line_dasharray[line_dasharray_len as usize] = n;
-                line_dasharray_total += n;
-
-                line_dasharray_len += 1;
-            }
-        }
+        let dash_row = dash_row.unwrap_or_default();
+        let pattern = pattern.unwrap_or_default();
 
         Self {
-            fill_translate: style.fill_translate() / scale,
-            line_translate: style.line_translate() / scale,
             fill_color: style.fill_color().as_v4(),
             line_color: style.line_color().as_v4(),
-            transform_c0: transform.c0.expand(0.0),
-            transform_c1: transform.c1.expand(0.0),
-            transform_c2: transform.c2.expand(0.0),
+            pattern_from: pattern.from.rect,
+            pattern_to: pattern.to.rect,
+            fill_translate: style.fill_translate() / scale,
+            line_translate: style.line_translate() / scale,
+            pattern_from_scale: pattern.from.scale,
+            pattern_to_scale: pattern.to.scale,
+            pattern_from_offset: pattern.from.offset,
+            pattern_to_offset: pattern.to.offset,
             line_width,
-            line_dasharray,
-            line_dasharray_len,
-            line_dasharray_total,
-            rescale_offset: rescale_rect.offset,
-            rescale_scale: rescale_rect.scale,
+            line_dasharray_row: dash_row.row,
+            line_dasharray_len: dash_row.len,
+            line_dasharray_total: dash_row.total,
+            line_gap_width: style.line_gap_width() / scale,
+            line_offset: style.line_offset() / scale,
+            line_blur: style.line_blur() / scale,
+            line_gradient_row: gradient_row.map_or(-1, |row| row as i32),
+            pattern_fade: pattern.fade,
+            _padding: [0; 3],
+        }
+    }
+
+    /// Clears the paint so the feature draws nothing, its draw is shared with the rest of the
+    /// layer so it can't be skipped. Lines lose their width, and the dash, gradient and pattern
+    /// are dropped along with the colors so nothing is drawn in place of them.
+    fn hidden(self) -> Self {
+        let transparent = V4::new(0.0, 0.0, 0.0, 0.0);
+        Self {
+            fill_color: transparent,
+            line_color: transparent,
+            pattern_from: transparent,
+            pattern_to: transparent,
+            line_width: 0.0,
+            line_gap_width: 0.0,
+            line_blur: 0.0,
+            line_dasharray_len: 0,
+            line_gradient_row: -1,
+            pattern_fade: 0.0,
+            ..self
         }
     }
 }
@@ -1093,7 +2186,7 @@ impl TileUniforms {
 #[derive(Debug, Copy, Clone, bytemuck::NoUninit)]
 // Sorted fields in a weird order for padding/alignment
 struct TextUniforms {
-    scale: f32,
+    text_scale: f32,
     halo_width: f32,
     offset: V2<f32>,
     tile_dims: V2<f32>,
@@ -1116,7 +2209,7 @@ impl TextUniforms {
             offset: rect.max.as_f32() - tile_dims,
             tile_dims,
             window_dims: window_size.as_f32(),
-            scale,
+            text_scale: 1.0,
             text_color: style.text_color().as_v4(),
             halo_color: style.text_halo_color().as_v4(),
         }
@@ -1125,13 +2218,20 @@ impl TextUniforms {
 
 #[derive(Debug, Clone)]
 pub struct LabelGeometry {
+    pub placements: SmallVec<[LabelPlacement; 1]>,
+    point: V2<f32>,
+    size: LabelSize,
+}
+
+#[derive(Debug, Clone)]
+pub struct LabelPlacement {
     pub elements: std::ops::Range<usize>,
     pub halo_elements: std::ops::Range<usize>,
     bounds: Rect<f32>,
-    point: V2<f32>,
 }
 #[derive(Debug, Clone)]
 pub struct LabelLayerGeometry {
+    pub layer: usize,
     pub paint: super::FeaturePaint,
     pub labels: Vec<LabelGeometry>,
 }
@@ -1141,8 +2241,17 @@ pub struct LabelLayerGeometry {
 pub struct GeoVertex {
     pub position: V2<f32>,
     pub normal: V2<f32>,
+    /// Direction the vertex moves in for each unit of `line-offset`, the same for every vertex
+    /// stroked around a point of the line so caps and joins follow the offset line
+    pub offset: V2<f32>,
+    /// 1.0 on the right side of a line and -1.0 on its left, 0.0 for fills
+    pub side: f32,
     pub advancement: f32,
+    /// Advancement from 0.0 to 1.0 along the line, read by `line-gradient`
+    pub line_progress: f32,
     pub fill: FillMode,
+    /// Index of the vertex's draw in the tile's features, which holds its paint
+    pub feature: u32,
 }
 
 #[repr(u32)]
@@ -1151,11 +2260,20 @@ pub enum FillMode {
     Line = 0,
     Polygon = 1,
     Background = 2,
+    Outline = 3,
 }
 
 impl GeoVertex {
-    const ATTRIBS: [wgpu::VertexAttribute; 4] =
-        wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x2, 2 => Float32, 3 => Uint32];
+    const ATTRIBS: [wgpu::VertexAttribute; 8] = wgpu::vertex_attr_array![
+        0 => Float32x2,
+        1 => Float32x2,
+        2 => Float32x2,
+        3 => Float32,
+        4 => Float32,
+        5 => Float32,
+        6 => Uint32,
+        7 => Uint32
+    ];
 
     fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
         use std::mem;
@@ -1171,28 +2289,274 @@ impl GeoVertex {
         GeoVertex {
             position: V2::new(-0.1, -0.1),
             normal: V2::zero(),
+            offset: V2::zero(),
+            side: 0.0,
             advancement: 0.0,
+            line_progress: 0.0,
             fill: FillMode::Background,
+            feature: 0,
         },
         GeoVertex {
             position: V2::new(1.1, -0.1),
             normal: V2::zero(),
+            offset: V2::zero(),
+            side: 0.0,
             advancement: 0.0,
+            line_progress: 0.0,
             fill: FillMode::Background,
+            feature: 0,
         },
         GeoVertex {
             position: V2::new(1.1, 1.1),
             normal: V2::zero(),
+            offset: V2::zero(),
+            side: 0.0,
             advancement: 0.0,
+            line_progress: 0.0,
             fill: FillMode::Background,
+            feature: 0,
         },
         GeoVertex {
             position: V2::new(-0.1, 1.1),
             normal: V2::zero(),
+            offset: V2::zero(),
+            side: 0.0,
             advancement: 0.0,
+            line_progress: 0.0,
             fill: FillMode::Background,
+            feature: 0,
         },
     ];
 
     pub const BACKGROUND_INDICES: &'static [u32] = &[0, 3, 1, 1, 3, 2];
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{proto::tile::GeomType, tests::TestLayer};
+
+    #[test]
+    fn dash_rows_are_never_shared() {
+        let mut rows = DashRows::default();
+        for n in 0..DASH_ATLAS_ROWS {
+            let (row, pattern) = rows.row(&[n as f32 + 1.0, 1.0]).unwrap();
+            assert_eq!(row.row, n);
+            assert!(pattern.is_some());
+        }
+
+        // Patterns with nothing to draw are solid lines and leave the full atlas alone
+        rows.frame += 1;
+        assert!(rows.row(&[0.0, 0.0]).is_none());
+        assert_eq!(rows.rows.len(), DASH_ATLAS_ROWS as usize);
+
+        let (row, _) = rows.row(&[1000.0, 1.0]).unwrap();
+        assert!(row.row < DASH_ATLAS_ROWS);
+        assert_eq!(rows.rows.len(), DASH_ATLAS_ROWS as usize);
+
+        let mut used: Vec<_> = rows.rows.values().map(|(row, _)| row.row).collect();
+        used.sort();
+        used.dedup();
+        assert_eq!(used.len(), rows.rows.len());
+
+        // Every row drawn this frame is kept, so further patterns are drawn solid
+        for n in 0..DASH_ATLAS_ROWS {
+            rows.row(&[n as f32 + 1.0, 1.0]);
+        }
+        assert!(rows.row(&[2000.0, 1.0]).is_none());
+    }
+
+    #[test]
+    fn patterns_line_up_across_tiles() {
+        let image = SpriteImage {
+            x: 16,
+            y: 8,
+            width: 24,
+            height: 12,
+            pixel_ratio: 1.0,
+        };
+        let tile = |column, row| TileId {
+            zoom: 2,
+            column,
+            row,
+        };
+
+        let pattern = PatternImage::new(&image, tile(1, 3), 512.0);
+        assert_eq!(pattern.rect, V4::new(16.0, 8.0, 24.0, 12.0));
+        // 24x12 style pixels drawn at twice their size on a 512 pixel tile
+        assert!((pattern.scale.x - 512.0 / 48.0).abs() < 1e-5);
+        assert!((pattern.scale.y - 512.0 / 24.0).abs() < 1e-5);
+        // The southmost row starts on a whole image
+        assert!((pattern.offset.x - 2.0 / 3.0).abs() < 1e-5);
+        assert_eq!(pattern.offset.y, 0.0);
+
+        // Each tile starts where its western and southern neighbours end
+        for column in 0..3 {
+            for row in 1..4 {
+                let pattern = PatternImage::new(&image, tile(column, row), 512.0);
+                let east = PatternImage::new(&image, tile(column + 1, row), 512.0);
+                let north = PatternImage::new(&image, tile(column, row - 1), 512.0);
+
+                let x = (pattern.offset.x + pattern.scale.x).fract();
+                let y = (pattern.offset.y + pattern.scale.y).fract();
+                assert!((east.offset.x - x).abs() < 1e-4, "{column} {row}");
+                assert!((north.offset.y - y).abs() < 1e-4, "{column} {row}");
+            }
+        }
+
+        // Images at a higher pixel ratio cover the same area with more texels
+        let hidpi = SpriteImage {
+            width: 48,
+            height: 24,
+            pixel_ratio: 2.0,
+            ..image
+        };
+        let hidpi = PatternImage::new(&hidpi, tile(1, 3), 512.0);
+        assert_eq!(hidpi.scale, pattern.scale);
+        assert_eq!(hidpi.offset, pattern.offset);
+    }
+
+    #[test]
+    fn hidden_features_draw_nothing() {
+        let color = V4::new(1.0, 0.5, 0.25, 1.0);
+        let rect = V4::new(16.0, 8.0, 24.0, 12.0);
+        let features = FeatureUniforms {
+            fill_color: color,
+            line_color: color,
+            pattern_from: rect,
+            pattern_to: rect,
+            fill_translate: V2::new(1.0, 2.0),
+            line_translate: V2::new(1.0, 2.0),
+            pattern_from_scale: V2::new(4.0, 4.0),
+            pattern_to_scale: V2::new(4.0, 4.0),
+            pattern_from_offset: V2::new(0.5, 0.5),
+            pattern_to_offset: V2::new(0.5, 0.5),
+            line_width: 0.01,
+            line_dasharray_row: 3,
+            line_dasharray_len: 2,
+            line_dasharray_total: 4.0,
+            line_gap_width: 0.02,
+            line_offset: 0.01,
+            line_blur: 0.01,
+            line_gradient_row: 5,
+            pattern_fade: 0.5,
+            _padding: [0; 3],
+        }
+        .hidden();
+
+        let transparent = V4::new(0.0, 0.0, 0.0, 0.0);
+        assert_eq!(features.fill_color, transparent);
+        assert_eq!(features.line_color, transparent);
+        // The shader only draws patterns with a sized image, and gradients with a row
+        assert_eq!(features.pattern_from.z, 0.0);
+        assert_eq!(features.pattern_to.z, 0.0);
+        assert_eq!(features.line_gradient_row, -1);
+        assert_eq!(features.line_dasharray_len, 0);
+        assert_eq!(features.line_width, 0.0);
+        assert_eq!(features.line_gap_width, 0.0);
+    }
+
+    /// Parses and validates each variant of a shader the adapter fallbacks can build
+    fn validate(source: &str) {
+        use naga::valid::{Capabilities, ValidationFlags, Validator};
+
+        let module = naga::front::wgsl::parse_str(source)
+            .unwrap_or_else(|e| panic!("{}", e.emit_to_string(source)));
+        Validator::new(ValidationFlags::all(), Capabilities::PUSH_CONSTANT)
+            .validate(&module)
+            .unwrap_or_else(|e| panic!("{}", e.emit_to_string(source)));
+    }
+
+    #[test]
+    fn shader_fallbacks_are_valid() {
+        for storage in [true, false] {
+            let tile = FeatureBuffer::declare_features(TILE_WGSL, storage);
+            for group in [None, Some(2)] {
+                validate(&DrawConstants::declare_constants(&tile, group));
+            }
+        }
+
+        for group in [None, Some(1)] {
+            validate(&DrawConstants::declare_constants(TEXT_WGSL, group));
+        }
+
+        let uniform = DrawConstants::declare_constants(TILE_WGSL, Some(2));
+        assert!(!uniform.contains("push_constant"));
+        assert!(uniform.contains("@group(2) @binding(0) var<uniform> tile_constants"));
+        let window = FeatureBuffer::declare_features(TILE_WGSL, false);
+        let features = format!("var<uniform> features: array<FeaturePaint, {FEATURE_WINDOW}>");
+        assert!(window.contains(&features));
+    }
+
+    #[test]
+    #[should_panic(expected = "expected the shader to declare `var<push_constant>` once")]
+    fn missing_declarations_panic() {
+        DrawConstants::declare_constants("var<push_constant > c: u32;", Some(1));
+    }
+
+    /// A label at the middle of the tile with a candidate placement for each of `bounds`,
+    /// numbering their elements from `first`
+    fn label(first: usize, bounds: &[Rect<f32>]) -> LabelGeometry {
+        let placements = bounds
+            .iter()
+            .enumerate()
+            .map(|(n, &bounds)| LabelPlacement {
+                elements: first + n..first + n + 1,
+                halo_elements: first + n..first + n + 1,
+                bounds,
+            })
+            .collect();
+
+        LabelGeometry {
+            placements,
+            point: V2::new(0.5, 0.5),
+            size: LabelSize {
+                layout: 32.0,
+                size: crate::style::Parameter::Constant(Some(16.0)),
+            },
+        }
+    }
+
+    #[test]
+    fn top_layers_win_collisions_and_labels_draw_bottom_layer_first() {
+        let style = crate::tests::load_style(serde_json::json!([{
+            "id": "labels",
+            "type": "symbol",
+            "source": "tiles",
+            "source-layer": "places"
+        }]));
+        let layer = TestLayer::new(None, GeomType::Point, serde_json::json!({}));
+        let layer = layer.resolve(&style.keys);
+        let view = layer.view(10.0);
+        let paint = crate::FeaturePaint::new(&style.layers[0], &view);
+        let layer = |layer, labels| LabelLayerGeometry {
+            layer,
+            paint: paint.clone(),
+            labels,
+        };
+
+        let rect = |x: f32| Rect::new(V2::new(x, 0.0), V2::new(x + 10.0, 10.0));
+        let tile = [
+            layer(
+                1,
+                vec![label(0, &[rect(0.0), rect(20.0)]), label(2, &[rect(100.0)])],
+            ),
+            layer(3, vec![label(4, &[rect(5.0)])]),
+        ];
+        // Collisions are only between labels of the same tile
+        let other_tile = [layer(1, vec![label(0, &[rect(5.0)])])];
+
+        let rect = Rect::new(V2::new(0, 0), V2::new(512, 512));
+        let tiles = [(tile.as_slice(), rect), (other_tile.as_slice(), rect)];
+        let draws: Vec<_> =
+            place_labels(V2::new(512, 512), V2::new(512.0, 512.0), &tiles, 10.0, 1.0)
+                .into_iter()
+                .map(|draw| (draw.layer, draw.tile, draw.elements))
+                .collect();
+
+        assert_eq!(
+            draws,
+            [(1, 0, 1..2), (1, 0, 2..3), (1, 1, 0..1), (3, 0, 4..5)]
+        );
+    }
+}
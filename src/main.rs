@@ -1,5 +1,5 @@
-use bstr::{BStr, ByteSlice};
-use clap::Parser;
+use bstr::{BStr, BString, ByteSlice};
+use clap::{Parser, Subcommand};
 use lyon::{
     math::point,
     tessellation::{
@@ -30,14 +30,16 @@ use crate::{
 };
 use crate::{
     style::SourceId,
-    text::{FontCollection, GlyphId},
+    text::{FontCollection, FontId, GlyphId},
 };
 
+mod geojson;
 mod gfx;
 mod mbtiles;
 mod proto {
     include!(concat!(env!("OUT_DIR"), "/vector_tile.rs"));
 }
+mod sprite;
 mod style;
 mod text;
 mod tile_source;
@@ -45,33 +47,97 @@ mod versatiles;
 
 const TILE_SCALE: f32 = 2.0;
 const TILE_SIZE: f32 = 256.0 * TILE_SCALE;
+/// Width of fill outlines in screen pixels. Unlike the style's widths, which are in style
+/// pixels drawn `TILE_SCALE` screen pixels wide, outlines stay a pixel wide at every scale.
+const FILL_OUTLINE_WIDTH: f32 = 1.0;
+/// Distance in screen pixels over which antialiased edges fade out. An edge's coverage falls
+/// from full half a pixel inside it to nothing half a pixel outside, so lines are drawn this
+/// much wider than their width.
+const ANTIALIAS_WIDTH: f32 = 1.0;
 
 /// Navigate OSM Vector tilesets
 #[derive(Parser, Debug)]
-#[command(author, version, about)]
+#[command(
+    author,
+    version,
+    about,
+    subcommand_negates_reqs = true,
+    args_conflicts_with_subcommands = true
+)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
     /// Path to a MapLibre style document
-    style: std::path::PathBuf,
+    #[arg(required = true)]
+    style: Option<std::path::PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Check a style document for problems, exiting with an error if any are found
+    Validate {
+        /// Path to a MapLibre style document
+        style: std::path::PathBuf,
+    },
 }
 
 fn main() {
     env_logger::init();
+    let args = Args::parse();
+
+    let style = match (args.command, args.style) {
+        (Some(Command::Validate { style }), _) => std::process::exit(validate_style(&style)),
+        (None, Some(style)) => style,
+        (None, None) => unreachable!("style path is required"),
+    };
+
     let event_loop: EventLoop<UserEvent> = EventLoop::with_user_event().build().unwrap();
     let proxy = event_loop.create_proxy();
-    let mut application = Application::new(proxy);
+    let mut application = Application::new(proxy, style);
 
     event_loop.run_app(&mut application).unwrap();
 }
 
+/// Prints every problem found in the style, returning the process exit code
+fn validate_style(path: &std::path::Path) -> i32 {
+    let document = std::fs::File::open(path)
+        .map_err(anyhow::Error::from)
+        .and_then(|file| Ok(serde_json::from_reader::<_, serde_json::Value>(file)?));
+
+    let document = match document {
+        Ok(document) => document,
+        Err(e) => {
+            eprintln!("error: {}: {e}", path.display());
+            return 1;
+        }
+    };
+
+    let diagnostics = style::validate(&document);
+    let errors = diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.severity == style::Severity::Error)
+        .count();
+    let warnings = diagnostics.len() - errors;
+
+    for diagnostic in diagnostics.iter() {
+        eprintln!("{diagnostic}");
+    }
+    eprintln!("{}: {errors} errors, {warnings} warnings", path.display());
+
+    if errors > 0 { 1 } else { 0 }
+}
+
 struct Application {
     event_loop_proxy: EventLoopProxy<UserEvent>,
+    style: std::path::PathBuf,
     state: Option<ApplicationState>,
 }
 
 impl Application {
-    fn new(event_loop_proxy: EventLoopProxy<UserEvent>) -> Self {
+    fn new(event_loop_proxy: EventLoopProxy<UserEvent>, style: std::path::PathBuf) -> Self {
         Self {
             event_loop_proxy,
+            style,
             state: None,
         }
     }
@@ -80,7 +146,11 @@ impl Application {
 impl winit::application::ApplicationHandler<UserEvent> for Application {
     fn resumed(&mut self, active_event_loop: &winit::event_loop::ActiveEventLoop) {
         if self.state.is_none() {
-            let state = ApplicationState::new(self.event_loop_proxy.clone(), active_event_loop);
+            let state = ApplicationState::new(
+                self.event_loop_proxy.clone(),
+                active_event_loop,
+                &self.style,
+            );
 
             self.state = Some(state);
         }
@@ -111,10 +181,10 @@ struct GfxWindow {
 }
 
 impl GfxWindow {
-    fn new(window: Window) -> Self {
+    fn new(window: Window, sprite: Option<&sprite::Sprite>) -> Self {
         let window = Box::new(window);
         let window_ref = unsafe { std::mem::transmute(window.as_ref()) };
-        let gfx = gfx::Gfx::new(window_ref, TILE_SIZE);
+        let gfx = gfx::Gfx::new(window_ref, TILE_SIZE, sprite);
 
         Self { gfx, window }
     }
@@ -147,12 +217,29 @@ impl ApplicationState {
     fn new(
         proxy: EventLoopProxy<UserEvent>,
         active_event_loop: &winit::event_loop::ActiveEventLoop,
+        style_path: &std::path::Path,
     ) -> Self {
-        let args = Args::parse();
-        let style_json = std::fs::File::open(&args.style).unwrap();
-        let style = style::Style::load(style_json).unwrap();
-        let data_dir = args.style.parent().unwrap();
+        let style_json = std::fs::File::open(style_path).unwrap();
+        let style = match style::Style::load(style_json) {
+            Ok(style) => style,
+            Err(e) => {
+                eprintln!("error: {}: {e}", style_path.display());
+                std::process::exit(1);
+            }
+        };
+        let data_dir = style_path.parent().unwrap();
         let tile_source = TileSourceCollection::load(data_dir, &style).unwrap();
+        let sprite =
+            style
+                .sprite
+                .as_ref()
+                .and_then(|uri| match sprite::Sprite::load(data_dir, uri) {
+                    Ok(sprite) => Some(sprite),
+                    Err(e) => {
+                        eprintln!("unable to load sprite: {e}");
+                        None
+                    }
+                });
 
         let window = active_event_loop
             .create_window(
@@ -165,7 +252,7 @@ impl ApplicationState {
             )
             .unwrap();
 
-        let mut window = GfxWindow::new(window);
+        let mut window = GfxWindow::new(window, sprite.as_ref());
 
         let input_state = InputState::new();
 
@@ -857,8 +944,11 @@ impl TileWorker {
 
 #[derive(Debug, Clone)]
 pub struct FeatureDraw {
+    pub layer: usize,
     pub paint: FeaturePaint,
     pub elements: std::ops::Range<usize>,
+    /// Fill outlines, drawn after the fills of every tile in the layer
+    pub outline_elements: std::ops::Range<usize>,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -868,16 +958,6 @@ enum Value<'a> {
     Bool(bool),
 }
 
-impl<'a> Value<'a> {
-    fn as_str(&self) -> Option<&'a bstr::BStr> {
-        match self {
-            Value::String(s) => Some(s),
-            Value::Number(_) => None,
-            Value::Bool(_) => None,
-        }
-    }
-}
-
 impl std::fmt::Display for Value<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -919,6 +999,10 @@ impl<'a> From<&'a proto::tile::Value> for Value<'a> {
 pub struct FeatureView<'a> {
     layer: &'a proto::tile::Layer,
     feature: &'a proto::tile::Feature,
+    /// The style's interned id for each of the layer's keys
+    key_ids: &'a [Option<style::KeyId>],
+    zoom: f32,
+    line_progress: f32,
 }
 
 static EMPTY_LAYER: proto::tile::Layer = proto::tile::Layer {
@@ -938,10 +1022,21 @@ static EMPTY_FEATURE: proto::tile::Feature = proto::tile::Feature {
 };
 
 impl FeatureView<'static> {
-    fn empty() -> Self {
+    fn empty(zoom: f32) -> Self {
         FeatureView {
             layer: &EMPTY_LAYER,
             feature: &EMPTY_FEATURE,
+            key_ids: &[],
+            zoom,
+            line_progress: 0.0,
+        }
+    }
+
+    /// A view for sampling `line-gradient` at `progress` along a line
+    fn at_line_progress(progress: f32) -> Self {
+        FeatureView {
+            line_progress: progress,
+            ..Self::empty(0.0)
         }
     }
 }
@@ -950,36 +1045,81 @@ impl<'a> FeatureView<'a> {
     fn key<B: AsRef<BStr>>(&self, key: B) -> Option<Value<'_>> {
         let key = key.as_ref();
         if key == "$type" {
-            return Some(match self.shape() {
-                GeomType::Polygon => "Polygon".into(),
-                GeomType::Linestring => "LineString".into(),
-                GeomType::Point => "Point".into(),
-                GeomType::Unknown => "Unknown".into(),
-            });
+            return Some(self.geometry_type().into());
+        } else if key == "$id" {
+            return self.id().map(|id| Value::Number(id as f64));
         }
 
-        for tag in self.feature.tags.chunks(2) {
+        self.properties()
+            .find(|(k, _)| *k == key)
+            .map(|(_, value)| value)
+    }
+
+    fn properties(&self) -> impl Iterator<Item = (&'a BStr, Value<'a>)> {
+        let layer = self.layer;
+        self.feature.tags.chunks(2).filter_map(move |tag| {
             let t_key = tag[0] as usize;
-            let t_value = tag[1] as usize;
+            let t_value = *tag.get(1)? as usize;
 
-            let key: Option<Value> = self
-                .layer
-                .keys
-                .get(t_key)
-                .filter(|k| k.as_slice() == key)
-                .and_then(|_| self.layer.values.get(t_value).map(Value::from));
+            let key = layer.keys.get(t_key)?;
+            let value = layer.values.get(t_value)?;
+
+            Some((key.as_bstr(), Value::from(value)))
+        })
+    }
+
+    /// Looks up a property by the id its key was interned as, avoiding key comparisons
+    fn property(&self, id: style::KeyId) -> Option<Value<'a>> {
+        let layer = self.layer;
+        self.feature.tags.chunks(2).find_map(|tag| {
+            let t_key = tag[0] as usize;
+            let t_value = *tag.get(1)? as usize;
 
-            if key.is_some() {
-                return key;
+            if *self.key_ids.get(t_key)? != Some(id) {
+                return None;
             }
-        }
 
-        None
+            layer.values.get(t_value).map(Value::from)
+        })
+    }
+
+    fn id(&self) -> Option<u64> {
+        self.feature.id
     }
 
     fn shape(&self) -> GeomType {
         self.feature.r#type()
     }
+
+    /// Name of the feature's geometry type used by `$type` filters and `geometry-type` expressions
+    fn geometry_type(&self) -> &'static str {
+        match self.shape() {
+            GeomType::Polygon => "Polygon",
+            GeomType::Linestring => "LineString",
+            GeomType::Point => "Point",
+            GeomType::Unknown => "Unknown",
+        }
+    }
+
+    /// Zoom level of the tile the feature is being evaluated for
+    fn zoom(&self) -> f32 {
+        self.zoom
+    }
+
+    fn line_progress(&self) -> f32 {
+        self.line_progress
+    }
+
+    /// The fractions of the whole line at the start and end of the part of it in the tile,
+    /// carried as properties by tiles cut from sources with `lineMetrics`
+    fn line_clip(&self) -> Option<(f32, f32)> {
+        let fraction = |key: &str| match self.key(key)? {
+            Value::Number(n) => Some(n as f32),
+            _ => None,
+        };
+
+        Some((fraction("mapbox_clip_start")?, fraction("mapbox_clip_end")?))
+    }
 }
 
 struct FeatureLayout<'a> {
@@ -1018,7 +1158,6 @@ impl<'a> FeatureLayout<'a> {
     }
 
     fn visible(&self) -> bool {
-        let supported = !self.style.paint.unsupported();
         let visible = self.style.layout.visibility == style::Visibility::Visible;
         let in_zoom = self.style.minzoom.map(|z| self.zoom >= z).unwrap_or(true)
             && self.style.maxzoom.map(|z| self.zoom <= z).unwrap_or(true);
@@ -1028,13 +1167,12 @@ impl<'a> FeatureLayout<'a> {
             _ => true,
         };
 
-        let visible =
-            valid_type && supported && visible && in_zoom && self.style.filter(&self.view);
+        let visible = valid_type && visible && in_zoom && self.style.filter(&self.view);
 
         visible
     }
 
-    fn text(&self) -> Option<smartstring::alias::String> {
+    fn text(&self) -> Option<SmallVec<[style::TextSection; 1]>> {
         self.style.layout.text(self.view)
     }
 
@@ -1042,12 +1180,76 @@ impl<'a> FeatureLayout<'a> {
         self.style.layout.text_font.iter().map(String::as_ref)
     }
 
+    /// Size glyphs are rasterized and laid out at, labels are scaled down from this to the
+    /// size at the camera's zoom so they never need to be magnified before the next zoom level
     fn text_size(&self) -> f32 {
-        self.style.layout.text_size(self.view, self.zoom) * TILE_SCALE
+        let layout = &self.style.layout;
+        let size = layout
+            .text_size(self.view, self.zoom)
+            .max(layout.text_size(self.view, self.zoom + 1.0));
+
+        size * TILE_SCALE
+    }
+
+    fn label_size(&self) -> LabelSize {
+        LabelSize {
+            layout: self.text_size(),
+            size: self.style.layout.text_size_parameter(self.view),
+        }
     }
 
     fn text_max_width(&self) -> f32 {
-        self.style.layout.text_max_width() * TILE_SCALE
+        self.style.layout.text_max_width(self.view, self.zoom)
+    }
+
+    fn text_letter_spacing(&self) -> f32 {
+        self.style.layout.text_letter_spacing(self.view, self.zoom)
+    }
+
+    fn text_line_height(&self) -> f32 {
+        self.style.layout.text_line_height(self.view, self.zoom)
+    }
+
+    /// Candidate placements for a text block, in the order they should be tried by the
+    /// collision pass, `bounds` is the unjustified block with the first baseline at zero
+    fn text_anchors(&self, bounds: Rect<f32>) -> SmallVec<[LabelAnchor; 1]> {
+        let layout = &self.style.layout;
+        let font_size = self.text_size();
+        let offset = V2::from(layout.text_offset(self.view, self.zoom));
+        let justify = layout.text_justify(self.view, self.zoom);
+
+        let place = |anchor: style::TextAnchor, offset: V2<f32>| {
+            let (h_align, v_align) = anchor.alignment();
+            let anchor_point = V2::new(
+                bounds.min.x + bounds.width() * h_align,
+                bounds.max.y - bounds.height() * v_align,
+            );
+
+            // text-offset is y-down, label space is y-up
+            let offset = V2::new(offset.x, -offset.y) * font_size;
+
+            LabelAnchor {
+                shift: offset - anchor_point,
+                justify: justify.alignment(anchor),
+            }
+        };
+
+        if let Some(anchors) = layout.text_variable_anchor() {
+            anchors
+                .iter()
+                .map(|&anchor| {
+                    // Variable anchors push the text away from the point in the anchor's direction
+                    let (h_align, v_align) = anchor.alignment();
+                    let direction = V2::new(1.0 - h_align * 2.0, 1.0 - v_align * 2.0);
+                    let offset = V2::new(offset.x.abs(), offset.y.abs()) * direction;
+
+                    place(anchor, offset)
+                })
+                .collect()
+        } else {
+            let anchor = layout.text_anchor(self.view, self.zoom);
+            smallvec::smallvec![place(anchor, offset)]
+        }
     }
 }
 
@@ -1067,11 +1269,40 @@ impl FeaturePaint {
 
     pub fn style(&self, zoom: f32) -> FeatureStyle {
         let line_width = self.paint.line_width(zoom);
-        let line_color = self.paint.line_color(zoom).into();
+        // Patterns and gradients replace the color, their texels are scaled by the opacity
+        let white = |a| Color {
+            r: 1.0,
+            g: 1.0,
+            b: 1.0,
+            a,
+        };
+
+        let line_pattern = self.paint.line_pattern(zoom);
+        let line_gradient = self
+            .paint
+            .line_gradient()
+            .filter(|_| line_pattern.is_none())
+            .cloned();
+        let line_color = if line_pattern.is_some() || line_gradient.is_some() {
+            white(self.paint.line_opacity(zoom))
+        } else {
+            self.paint.line_color(zoom).into()
+        };
+        let line_gap_width = self.paint.line_gap_width(zoom);
+        let line_offset = self.paint.line_offset(zoom);
+        let line_blur = self.paint.line_blur(zoom);
 
         let fill_translate = self.paint.fill_translate(zoom).into();
-        let fill_color = self.paint.fill_color(zoom).into();
-        let fill_outline_color = self.paint.fill_outline_color(zoom).map(Color::from);
+        let fill_antialias = self.paint.fill_antialias(zoom);
+        let fill_pattern = self.paint.fill_pattern(zoom);
+        let (fill_color, fill_outline_color) = if fill_pattern.is_some() {
+            (white(self.paint.fill_opacity(zoom)), None)
+        } else {
+            (
+                self.paint.fill_color(zoom).into(),
+                self.paint.fill_outline_color(zoom).map(Color::from),
+            )
+        };
 
         let background_color = self.paint.background_color(zoom).into();
 
@@ -1079,19 +1310,30 @@ impl FeaturePaint {
         let text_halo_width = self.paint.text_halo_width(zoom).into();
         let text_halo_color = self.paint.text_halo_color(zoom).into();
 
-        let line_dasharray = self.paint.line_dasharray();
+        let line_dasharray = if line_pattern.is_some() {
+            SmallVec::new()
+        } else {
+            self.paint.line_dasharray(zoom)
+        };
 
         FeatureStyle {
             background_color,
             line_color,
             fill_color,
             line_width,
+            line_gap_width,
+            line_offset,
+            line_blur,
             fill_translate,
+            fill_antialias,
             fill_outline_color,
             text_color,
             text_halo_width,
             text_halo_color,
             line_dasharray,
+            line_gradient,
+            fill_pattern,
+            line_pattern,
             kind: self.kind,
         }
     }
@@ -1104,12 +1346,19 @@ pub struct FeatureStyle {
     fill_color: Color,
     fill_outline_color: Option<Color>,
     line_width: f32,
+    line_gap_width: f32,
+    line_offset: f32,
+    line_blur: f32,
     kind: style::LayerType,
     fill_translate: V2<f32>,
+    fill_antialias: bool,
     text_color: Color,
     text_halo_width: f32,
     text_halo_color: Color,
     line_dasharray: SmallVec<[f32; 8]>,
+    line_gradient: Option<Arc<style::ColorRamp>>,
+    fill_pattern: Option<style::CrossFaded<BString>>,
+    line_pattern: Option<style::CrossFaded<BString>>,
 }
 
 impl FeatureStyle {
@@ -1124,9 +1373,14 @@ impl FeatureStyle {
         self.fill_outline_color
     }
 
+    /// Fills are antialiased by outlining them, pattern fills with the pattern like MapLibre
+    pub fn fill_outline(&self) -> bool {
+        self.fill_antialias
+    }
+
     pub fn line_color(&self) -> Color {
         match self.kind {
-            style::LayerType::Fill => self.fill_outline_color().unwrap_or(self.line_color),
+            style::LayerType::Fill => self.fill_outline_color().unwrap_or(self.fill_color),
             _ => self.line_color,
         }
     }
@@ -1135,8 +1389,37 @@ impl FeatureStyle {
         self.kind
     }
 
+    /// Width of the drawn line in screen pixels
     pub fn line_width(&self) -> f32 {
-        self.line_width * TILE_SCALE
+        match self.kind {
+            style::LayerType::Fill => FILL_OUTLINE_WIDTH + ANTIALIAS_WIDTH,
+            _ => self.line_width * TILE_SCALE,
+        }
+    }
+
+    /// The line properties below only apply to line layers
+    fn line_property(&self, value: f32) -> f32 {
+        match self.kind {
+            style::LayerType::Line => value * TILE_SCALE,
+            _ => 0.0,
+        }
+    }
+
+    pub fn line_gap_width(&self) -> f32 {
+        self.line_property(self.line_gap_width)
+    }
+
+    pub fn line_offset(&self) -> f32 {
+        self.line_property(self.line_offset)
+    }
+
+    /// Fill outlines fade out over their outer pixel, the coverage of a line
+    /// `FILL_OUTLINE_WIDTH` wide by the pixels it crosses
+    pub fn line_blur(&self) -> f32 {
+        match self.kind {
+            style::LayerType::Fill => ANTIALIAS_WIDTH,
+            _ => self.line_property(self.line_blur),
+        }
     }
 
     pub fn fill_translate(&self) -> V2<f32> {
@@ -1162,8 +1445,25 @@ impl FeatureStyle {
         self.text_halo_width * TILE_SCALE
     }
 
-    pub fn line_dasharray(&self) -> SmallVec<[f32; 8]> {
-        self.line_dasharray.clone()
+    /// Dash and gap lengths, in multiples of the line width
+    pub fn line_dasharray(&self) -> &[f32] {
+        &self.line_dasharray
+    }
+
+    pub fn line_gradient(&self) -> Option<&Arc<style::ColorRamp>> {
+        match self.kind {
+            style::LayerType::Line => self.line_gradient.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Sprite pattern drawn in place of the fill or line color
+    pub fn pattern(&self) -> Option<&style::CrossFaded<BString>> {
+        match self.kind {
+            style::LayerType::Fill => self.fill_pattern.as_ref(),
+            style::LayerType::Line => self.line_pattern.as_ref(),
+            _ => None,
+        }
     }
 }
 
@@ -1226,6 +1526,8 @@ struct VectorTileTesselator {
     stroke_tessellator: StrokeTessellator,
     stroke_options: StrokeOptions,
     geometry: VertexBuffers<GeoVertex, u32>,
+    /// Fill outlines, appended to the geometry once the tile is tessellated
+    outlines: VertexBuffers<GeoVertex, u32>,
     tile_dims: V2<f32>,
     fonts: FontCollection,
     tile_container: TileContainer,
@@ -1241,6 +1543,7 @@ impl VectorTileTesselator {
             .with_line_width(0.01); // These values are very sensitive and can cause very different issues
         let stroke_tessellator = StrokeTessellator::new();
         let geometry: VertexBuffers<GeoVertex, u32> = VertexBuffers::new();
+        let outlines: VertexBuffers<GeoVertex, u32> = VertexBuffers::new();
         let fonts = FontCollection::new();
         let tile_container = TileContainer::new(&style);
         let draw_commands = DrawCommands::new();
@@ -1252,6 +1555,7 @@ impl VectorTileTesselator {
             stroke_tessellator,
             stroke_options,
             geometry,
+            outlines,
             tile_dims,
             fonts,
             tile_container,
@@ -1264,33 +1568,48 @@ impl VectorTileTesselator {
 
         self.geometry.vertices.clear();
         self.geometry.indices.clear();
+        self.outlines.vertices.clear();
+        self.outlines.indices.clear();
         self.tile_container.clear();
         self.draw_commands.clear();
 
-        for style_layer in self.style.layers.iter() {
+        for (layer_idx, style_layer) in self.style.layers.iter().enumerate() {
+            self.draw_commands.layer = layer_idx;
+
             if style_layer.kind == style::LayerType::Background {
                 let range_start = self.geometry.indices.len();
-                self.draw_commands.add_draw_cmds(None, range_start);
+                let outline_start = self.outlines.indices.len();
+                self.draw_commands
+                    .add_draw_cmds(None, range_start, outline_start);
+                let feature_idx = self.draw_commands.feature_draw.len() as u32;
                 self.geometry
                     .vertices
-                    .extend_from_slice(GeoVertex::BACKGROUND_VERTICES);
+                    .extend(
+                        GeoVertex::BACKGROUND_VERTICES
+                            .iter()
+                            .map(|vertex| GeoVertex {
+                                feature: feature_idx,
+                                ..*vertex
+                            }),
+                    );
                 self.geometry
                     .indices
                     .extend_from_slice(GeoVertex::BACKGROUND_INDICES);
 
                 let range_end = self.geometry.indices.len();
                 let draw = FeatureDraw {
-                    paint: FeaturePaint::new(style_layer, &FeatureView::empty()),
+                    layer: layer_idx,
+                    paint: FeaturePaint::new(style_layer, &FeatureView::empty(zoom)),
                     elements: range_start..range_end,
+                    outline_elements: outline_start..outline_start,
                 };
 
                 self.draw_commands.feature_draw.push(draw);
                 continue;
             }
 
-            let Some(target_layer) = style_layer.layer.as_ref() else {
-                continue;
-            };
+            // GeoJSON sources have a single layer, which style layers don't name
+            let target_layer = style_layer.layer.as_deref().unwrap_or(geojson::LAYER_NAME);
 
             let Some(source_id) = style_layer.source.as_ref() else {
                 continue;
@@ -1302,15 +1621,34 @@ impl VectorTileTesselator {
                 continue;
             };
 
-            let Some(layer) = tile.layers.iter().find(|layer| &layer.name == target_layer) else {
+            let Some(layer) = tile.layers.iter().find(|layer| layer.name == target_layer) else {
                 continue;
             };
 
+            // Only sources with line metrics know where each line is along the whole line
+            let line_metrics = self
+                .style
+                .sources
+                .get(source_id)
+                .is_some_and(|source| source.line_metrics);
+
             self.draw_commands.layer_labels.clear();
             self.draw_commands.draw_range_start = self.geometry.indices.len();
+            self.draw_commands.outline_range_start = self.outlines.indices.len();
+
+            let key_ids = self
+                .style
+                .keys
+                .resolve(layer.keys.iter().map(|key| key.as_slice()));
 
             for feature in layer.features.iter() {
-                let view = FeatureView { layer, feature };
+                let view = FeatureView {
+                    layer,
+                    feature,
+                    key_ids: &key_ids,
+                    zoom,
+                    line_progress: 0.0,
+                };
                 let layout = FeatureLayout::new(&view, style_layer, zoom);
 
                 if !layout.visible() {
@@ -1320,8 +1658,16 @@ impl VectorTileTesselator {
                 let paint = FeaturePaint::new(&style_layer, &view);
                 let style = paint.style(zoom);
 
-                self.draw_commands
-                    .add_draw_cmds(Some(&paint), self.geometry.indices.len());
+                self.draw_commands.add_draw_cmds(
+                    Some(&paint),
+                    self.geometry.indices.len(),
+                    self.outlines.indices.len(),
+                );
+
+                // Features with the same paint share a draw, which is the next one pushed
+                let feature_idx = self.draw_commands.feature_draw.len() as u32;
+                let first_vertex = self.geometry.vertices.len();
+                let first_outline_vertex = self.outlines.vertices.len();
 
                 self.stroke_options = self
                     .stroke_options
@@ -1339,8 +1685,12 @@ impl VectorTileTesselator {
                                     GeoVertex {
                                         position: vertex.position().to_tuple().into(),
                                         normal: V2::fill(0.0),
+                                        offset: V2::fill(0.0),
+                                        side: 0.0,
                                         advancement: 0.0,
+                                        line_progress: 0.0,
                                         fill: gfx::FillMode::Polygon,
+                                        feature: 0,
                                     }
                                 });
 
@@ -1356,28 +1706,38 @@ impl VectorTileTesselator {
                             }
                         }
 
-                        let stroke = match layout.kind {
-                            style::LayerType::Line => true,
-                            style::LayerType::Fill => style.fill_outline_color().is_some(),
-                            _ => false,
-                        };
-
-                        if stroke {
-                            let polygon =
-                                PolygonIter::new(feature.geometry.iter().copied(), *tile_rect);
+                        if layout.kind == style::LayerType::Fill && style.fill_outline() {
+                            let outline = OutlineIter::new(PolygonIter::new(
+                                feature.geometry.iter().copied(),
+                                *tile_rect,
+                            ));
 
                             let mut stroke_builder =
-                                BuffersBuilder::new(&mut self.geometry, |vertex: StrokeVertex| {
-                                    GeoVertex {
-                                        position: vertex.position_on_path().to_tuple().into(),
-                                        normal: vertex.normal().to_tuple().into(),
-                                        advancement: vertex.advancement(),
-                                        fill: gfx::FillMode::Line,
-                                    }
-                                });
+                                BuffersBuilder::new(&mut self.outlines, outline_vertex);
 
                             let result = self.stroke_tessellator.tessellate(
-                                polygon,
+                                outline,
+                                &self.stroke_options,
+                                &mut stroke_builder,
+                            );
+
+                            match result {
+                                Err(e) => eprintln!("polygon outline {:?}", e),
+                                _ => (),
+                            }
+                        }
+
+                        if layout.kind == style::LayerType::Line {
+                            let polygon = offset_path(PolygonIter::new(
+                                feature.geometry.iter().copied(),
+                                *tile_rect,
+                            ));
+
+                            let mut stroke_builder =
+                                BuffersBuilder::new(&mut self.geometry, stroke_vertex);
+
+                            let result = self.stroke_tessellator.tessellate_path(
+                                &polygon,
                                 &self.stroke_options,
                                 &mut stroke_builder,
                             );
@@ -1389,130 +1749,62 @@ impl VectorTileTesselator {
                         }
                     }
                     GeomType::Linestring if layout.kind == style::LayerType::Line => {
-                        let polygon =
+                        let line =
                             LineStringIter::new(feature.geometry.iter().copied(), *tile_rect);
 
-                        let mut stroke_builder =
-                            BuffersBuilder::new(&mut self.geometry, |vertex: StrokeVertex| {
-                                GeoVertex {
-                                    position: vertex.position_on_path().to_tuple().into(),
-                                    normal: vertex.normal().to_tuple().into(),
-                                    advancement: vertex.advancement(),
-                                    fill: gfx::FillMode::Line,
+                        // Each part of a multi-linestring starts its advancement over, so
+                        // lines that need `line-progress` are tessellated a part at a time
+                        let line_clip =
+                            line_metrics.then(|| view.line_clip().unwrap_or((0.0, 1.0)));
+                        let mut parts = Vec::new();
+                        match line_clip {
+                            Some(_) => {
+                                let mut part = Vec::new();
+                                for event in line {
+                                    let end = matches!(event, lyon::path::PathEvent::End { .. });
+                                    part.push(event);
+                                    if end {
+                                        parts.push(std::mem::take(&mut part));
+                                    }
                                 }
-                            });
+                            }
+                            None => parts.push(line.collect()),
+                        }
+
+                        for part in parts {
+                            let first_part_vertex = self.geometry.vertices.len();
+                            let mut stroke_builder =
+                                BuffersBuilder::new(&mut self.geometry, stroke_vertex);
+
+                            let result = self.stroke_tessellator.tessellate_path(
+                                &offset_path(part),
+                                &self.stroke_options,
+                                &mut stroke_builder,
+                            );
 
-                        let result = self.stroke_tessellator.tessellate(
-                            polygon,
-                            &self.stroke_options,
-                            &mut stroke_builder,
-                        );
+                            match result {
+                                Err(e) => eprintln!("line string {:?}", e),
+                                _ => (),
+                            }
 
-                        match result {
-                            Err(e) => eprintln!("line string {:?}", e),
-                            _ => (),
+                            if let Some(clip) = line_clip {
+                                set_line_progress(
+                                    &mut self.geometry.vertices[first_part_vertex..],
+                                    clip,
+                                );
+                            }
                         }
                     }
                     GeomType::Point => {
                         if layout.kind == style::LayerType::Symbol {
                             if let Some(text) = layout.text() {
-                                let (font_id, font) = self.fonts.font(layout.text_font());
-                                let font_size = layout.text_size();
-                                let max_text_width = layout.text_max_width() * font_size;
-                                let v_advance = font
-                                    .horizontal_line_metrics(font_size)
-                                    .map(|m| m.new_line_size)
-                                    .unwrap_or_default();
-                                let mut h_offset = 0.0;
-                                let mut v_offset = 0.0;
-                                let mut glyphs = SmallVec::new();
-                                let mut lines: SmallVec<[LineDraw; 3]> = SmallVec::new();
-                                let mut widest_line: f32 = 0.0;
-
-                                let mut bounds_min = V2::fill(f32::MAX);
-                                let mut bounds_max = V2::fill(f32::MIN);
-
-                                let mut last_glyph = None;
-
-                                for (idx, c) in text.chars().enumerate() {
-                                    if ((h_offset > max_text_width && c == ' ') || c == '\n')
-                                        && text.len() > idx + 2
-                                    {
-                                        widest_line = widest_line.max(h_offset);
-                                        let line = LineDraw {
-                                            width: h_offset,
-                                            glyphs: glyphs.clone(),
-                                        };
-                                        lines.push(line);
-                                        glyphs.clear();
-                                        last_glyph = None;
-                                        h_offset = 0.0;
-                                        v_offset -= v_advance;
-                                        continue;
-                                    }
-
-                                    if c.is_control() {
-                                        last_glyph = None;
-                                        continue;
-                                    }
-
-                                    if font.lookup_glyph_index(c) == 0 {
-                                        last_glyph = None;
-                                        continue;
-                                    }
-
-                                    let kern = last_glyph
-                                        .and_then(|g| font.horizontal_kern(g, c, font_size))
-                                        .unwrap_or_default();
-
-                                    last_glyph = Some(c);
-
-                                    let metrics = font.metrics(c, font_size);
-
-                                    let min = V2::new(metrics.xmin, metrics.ymin).as_f32()
-                                        + V2::new(h_offset + kern, v_offset);
-                                    let dims = V2::new(metrics.width, metrics.height).as_f32();
-
-                                    let bounds = Rect::new(min, min + dims);
-                                    let glyph_id = GlyphId(font_id, c);
-
-                                    bounds_min = bounds_min.min(bounds.min).min(bounds.max);
-                                    bounds_max = bounds_max.max(bounds.max).max(bounds.min);
-
-                                    if !c.is_whitespace() {
-                                        glyphs.push(GlyphDraw {
-                                            bounds,
-                                            glyph: glyph_id,
-                                        });
-                                    }
-
-                                    h_offset += metrics.advance_width;
-                                }
-
                                 let points =
                                     PointIter::new(feature.geometry.iter().copied(), *tile_rect);
 
-                                if glyphs.len() > 0 {
-                                    widest_line = widest_line.max(h_offset);
-                                    let line = LineDraw {
-                                        width: h_offset,
-                                        glyphs,
-                                    };
-                                    lines.push(line);
-                                }
-
-                                if lines.len() > 0 {
-                                    if lines.len() > 1 {
-                                        for line in lines.iter_mut() {
-                                            let adj_width = (widest_line - line.width) / 2.0;
-                                            for glyph in line.glyphs.iter_mut() {
-                                                glyph.bounds.min.x += adj_width;
-                                                glyph.bounds.max.x += adj_width;
-                                            }
-                                        }
-                                    }
-
-                                    let bounds = Rect::new(bounds_min, bounds_max);
+                                if let Some((lines, bounds)) =
+                                    shape_text(&layout, &text, &mut self.fonts)
+                                {
+                                    let anchors = layout.text_anchors(bounds);
 
                                     for point in points {
                                         if point.x > 1.0
@@ -1524,10 +1816,11 @@ impl VectorTileTesselator {
                                         }
 
                                         let label = LabelDraw {
-                                            text_size: layout.text_size(),
+                                            size: layout.label_size(),
                                             offset: point,
                                             bounds,
                                             lines: lines.clone(),
+                                            anchors: anchors.clone(),
                                         };
 
                                         self.draw_commands.layer_labels.push(label);
@@ -1538,10 +1831,43 @@ impl VectorTileTesselator {
                     }
                     _ => {}
                 }
+
+                let vertices = &mut self.geometry.vertices[first_vertex..];
+                let outline_vertices = &mut self.outlines.vertices[first_outline_vertex..];
+                for vertex in vertices.iter_mut().chain(outline_vertices) {
+                    vertex.feature = feature_idx;
+                }
             }
 
-            self.draw_commands
-                .add_draw_cmds(None, self.geometry.indices.len());
+            self.draw_commands.add_draw_cmds(
+                None,
+                self.geometry.indices.len(),
+                self.outlines.indices.len(),
+            );
+        }
+
+        self.append_outlines();
+    }
+
+    /// Moves the fill outlines to the end of the tile's geometry, so each draw's outlines
+    /// sit in their own range of elements
+    fn append_outlines(&mut self) {
+        let vertex_offset = self.geometry.vertices.len() as u32;
+        let index_offset = self.geometry.indices.len();
+
+        self.geometry
+            .vertices
+            .extend_from_slice(&self.outlines.vertices);
+        self.geometry.indices.extend(
+            self.outlines
+                .indices
+                .iter()
+                .map(|index| index + vertex_offset),
+        );
+
+        for draw in self.draw_commands.feature_draw.iter_mut() {
+            let outlines = &mut draw.outline_elements;
+            *outlines = outlines.start + index_offset..outlines.end + index_offset;
         }
     }
 
@@ -1562,12 +1888,147 @@ impl VectorTileTesselator {
     }
 }
 
+/// Which side of the line a stroke vertex is on. Tile coordinates point y down, so the
+/// positive side is the right, the direction `line-offset` moves lines in.
+fn side(side: lyon::path::Side) -> f32 {
+    match side {
+        lyon::path::Side::Positive => 1.0,
+        lyon::path::Side::Negative => -1.0,
+    }
+}
+
+fn stroke_vertex(mut vertex: StrokeVertex) -> GeoVertex {
+    let offset = match vertex.interpolated_attributes() {
+        &[x, y] => V2::new(x, y),
+        _ => V2::zero(),
+    };
+
+    GeoVertex {
+        position: vertex.position_on_path().to_tuple().into(),
+        normal: vertex.normal().to_tuple().into(),
+        offset,
+        side: side(vertex.side()),
+        advancement: vertex.advancement(),
+        line_progress: 0.0,
+        fill: gfx::FillMode::Line,
+        feature: 0,
+    }
+}
+
+/// Fill outlines are stroked like lines, but patterns are placed on them like on fills
+fn outline_vertex(vertex: StrokeVertex) -> GeoVertex {
+    GeoVertex {
+        fill: gfx::FillMode::Outline,
+        ..stroke_vertex(vertex)
+    }
+}
+
+/// How far a corner of an offset line may move from the line, in multiples of the offset, so
+/// lines turning back on themselves don't send their offset far off
+const OFFSET_MITER_LIMIT: f32 = 4.0;
+
+/// Builds a line's path with each point's `line-offset` direction as attributes. Like MapLibre's
+/// line bucket, the offset is applied to the line before it's stroked: each corner of the offset
+/// line sits on the bisector of its segments' right-hand perpendiculars, so caps and joins are
+/// stroked around the offset line rather than pushed sideways from the original one.
+fn offset_path<I: IntoIterator<Item = lyon::path::PathEvent>>(events: I) -> lyon::path::Path {
+    use lyon::path::PathEvent;
+
+    let mut builder = lyon::path::Path::builder_with_attributes(2);
+    let mut points = Vec::new();
+    for event in events {
+        match event {
+            PathEvent::Begin { at } => {
+                points.clear();
+                points.push(at);
+            }
+            PathEvent::Line { to, .. }
+            | PathEvent::Quadratic { to, .. }
+            | PathEvent::Cubic { to, .. } => points.push(to),
+            PathEvent::End { close, .. } => {
+                let Some((&first, rest)) = points.split_first() else {
+                    continue;
+                };
+
+                let offsets = line_offsets(&points, close);
+                builder.begin(first, &offsets[0]);
+                for (&point, offset) in rest.iter().zip(&offsets[1..]) {
+                    builder.line_to(point, offset);
+                }
+                builder.end(close);
+            }
+        }
+    }
+
+    builder.build()
+}
+
+/// The `line-offset` direction at each point of a line, with y pointing down positive offsets
+/// move to the right of the line's direction
+fn line_offsets(points: &[lyon::math::Point], close: bool) -> Vec<[f32; 2]> {
+    let perpendicular = |from: lyon::math::Point, to: lyon::math::Point| {
+        let direction = (to - from).try_normalize().unwrap_or_default();
+        lyon::math::vector(-direction.y, direction.x)
+    };
+
+    let count = points.len();
+    let segment = |i: usize| perpendicular(points[i], points[(i + 1) % count]);
+
+    (0..count)
+        .map(|i| {
+            let before = match i {
+                0 if close => segment(count - 1),
+                0 => lyon::math::Vector::zero(),
+                i => segment(i - 1),
+            };
+            let after = match i {
+                i if i + 1 < count || close => segment(i),
+                _ => lyon::math::Vector::zero(),
+            };
+
+            let offset = match (before.square_length() > 0.0, after.square_length() > 0.0) {
+                (true, true) => match (before + after).try_normalize() {
+                    Some(bisector) => {
+                        let cos = bisector.dot(before).max(1.0 / OFFSET_MITER_LIMIT);
+                        bisector / cos
+                    }
+                    None => before,
+                },
+                (true, false) => before,
+                _ => after,
+            };
+
+            [offset.x, offset.y]
+        })
+        .collect()
+}
+
+/// Sets the `line-progress` of a line part's stroke vertices from their advancement, spread
+/// over the `(start, end)` fractions of the whole line that the part spans, following
+/// MapLibre's line bucket
+fn set_line_progress(vertices: &mut [GeoVertex], (start, end): (f32, f32)) {
+    let length = vertices
+        .iter()
+        .map(|vertex| vertex.advancement)
+        .fold(0.0, f32::max);
+
+    if length <= 0.0 {
+        return;
+    }
+
+    for vertex in vertices {
+        vertex.line_progress = start + (end - start) * vertex.advancement / length;
+    }
+}
+
 struct DrawCommands {
     feature_draw: Vec<FeatureDraw>,
     labels: Vec<LayerLabelDraw>,
     layer_labels: Vec<LabelDraw>,
     last_paint: Option<FeaturePaint>,
     draw_range_start: usize,
+    outline_range_start: usize,
+    layer: usize,
 }
 
 impl DrawCommands {
@@ -1578,6 +2039,8 @@ impl DrawCommands {
             layer_labels: Vec::new(),
             last_paint: None,
             draw_range_start: 0,
+            outline_range_start: 0,
+            layer: 0,
         }
     }
 
@@ -1586,15 +2049,22 @@ impl DrawCommands {
         self.labels.clear();
         self.layer_labels.clear();
         self.draw_range_start = 0;
+        self.outline_range_start = 0;
         self.last_paint = None;
     }
 
-    fn add_draw_cmds(&mut self, next_paint: Option<&FeaturePaint>, indices: usize) {
+    fn add_draw_cmds(
+        &mut self,
+        next_paint: Option<&FeaturePaint>,
+        indices: usize,
+        outline_indices: usize,
+    ) {
         if (next_paint.is_none() || next_paint != self.last_paint.as_ref())
             && let Some(last) = self.last_paint.take()
         {
             if self.layer_labels.len() > 0 {
                 let draw = LayerLabelDraw {
+                    layer: self.layer,
                     paint: last.clone(),
                     labels: self.layer_labels.clone(),
                 };
@@ -1604,12 +2074,15 @@ impl DrawCommands {
             }
 
             let range_end = indices;
-            if range_end > self.draw_range_start {
+            if range_end > self.draw_range_start || outline_indices > self.outline_range_start {
                 let draw = FeatureDraw {
+                    layer: self.layer,
                     paint: last,
                     elements: self.draw_range_start..range_end,
+                    outline_elements: self.outline_range_start..outline_indices,
                 };
                 self.draw_range_start = range_end;
+                self.outline_range_start = outline_indices;
 
                 self.feature_draw.push(draw);
             }
@@ -1622,6 +2095,7 @@ impl DrawCommands {
 
 #[derive(Clone, Debug)]
 pub struct LayerLabelDraw {
+    pub layer: usize,
     pub paint: FeaturePaint,
     pub labels: Vec<LabelDraw>,
 }
@@ -1631,25 +2105,192 @@ pub struct LabelDraw {
     pub offset: V2<f32>,
     pub bounds: Rect<f32>,
     pub lines: SmallVec<[LineDraw; 3]>,
-    pub text_size: f32,
+    pub size: LabelSize,
+    pub anchors: SmallVec<[LabelAnchor; 1]>,
+}
+
+#[derive(Clone, Debug)]
+pub struct LabelSize {
+    layout: f32,
+    size: style::Parameter<f32>,
+}
+
+impl LabelSize {
+    /// Factor that scales glyphs from their layout size to `text-size` at the camera's zoom
+    pub fn scale(&self, zoom: f32) -> f32 {
+        let size = self.size.eval(zoom).unwrap_or(style::DEFAULT_TEXT_SIZE) * TILE_SCALE;
+        size / self.layout
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct LabelAnchor {
+    pub shift: V2<f32>,
+    pub justify: f32,
 }
 
 impl LabelDraw {
     fn glyphs(&self) -> impl Iterator<Item = &GlyphDraw> {
         self.lines.iter().flat_map(|l| l.glyphs.iter())
     }
+
+    /// Glyphs paired with the offset that justifies their line and moves the block to `anchor`
+    fn positioned_glyphs<'a>(
+        &'a self,
+        anchor: &'a LabelAnchor,
+    ) -> impl Iterator<Item = (V2<f32>, &'a GlyphDraw)> + 'a {
+        let width = self.bounds.width();
+        self.lines.iter().flat_map(move |line| {
+            let justify = V2::new((width - line.width) * anchor.justify, 0.0);
+            let shift = anchor.shift + justify;
+            line.glyphs.iter().map(move |glyph| (shift, glyph))
+        })
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct LineDraw {
     pub glyphs: SmallVec<[GlyphDraw; 20]>,
-    width: f32,
+    pub width: f32,
 }
 
 #[derive(Clone, Debug)]
 pub struct GlyphDraw {
     pub bounds: Rect<f32>,
     pub glyph: GlyphId,
+    /// Size the glyph is rasterized at, including its section's `font-scale`
+    pub size: f32,
+    /// Section color overriding the layer's `text-color`
+    pub color: Option<Color>,
+}
+
+/// Lays the text sections out into lines of glyphs with the first baseline at zero, returning
+/// the lines and the unjustified bounds of the text block
+fn shape_text(
+    layout: &FeatureLayout<'_>,
+    sections: &[style::TextSection],
+    fonts: &mut FontCollection,
+) -> Option<(SmallVec<[LineDraw; 3]>, Rect<f32>)> {
+    let font_size = layout.text_size();
+    let spacing = layout.text_letter_spacing() * font_size;
+    let max_width = layout.text_max_width() * font_size;
+    let v_advance = layout.text_line_height() * font_size;
+
+    let section_fonts: SmallVec<[FontId; 2]> = sections
+        .iter()
+        .map(|section| match section.font.as_ref() {
+            Some(font) => fonts.font(font).0,
+            None => fonts.font(layout.text_font()).0,
+        })
+        .collect();
+    let fonts = &*fonts;
+
+    let mut chars: SmallVec<[char; 64]> = SmallVec::new();
+    let mut char_sections: SmallVec<[usize; 64]> = SmallVec::new();
+    for (idx, section) in sections.iter().enumerate() {
+        chars.extend(section.text.chars());
+        char_sections.resize(chars.len(), idx);
+    }
+
+    let section_font = |idx: usize| {
+        let section = char_sections[idx];
+        let font = fonts.font_id(section_fonts[section]);
+        (section, font, font_size * sections[section].scale)
+    };
+
+    let breaks = text::line_breaks(&chars, max_width, |idx, c| {
+        let (_, font, size) = section_font(idx);
+        font.metrics(c, size).advance_width + spacing
+    });
+
+    let mut lines: SmallVec<[LineDraw; 3]> = SmallVec::new();
+    let mut widest_line: f32 = 0.0;
+    let mut v_offset = 0.0;
+    let mut line_start = 0;
+
+    let mut bounds_min = V2::fill(f32::MAX);
+    let mut bounds_max = V2::fill(f32::MIN);
+
+    for (line_idx, line_end) in breaks.into_iter().enumerate() {
+        let line = &chars[line_start..line_end];
+        let line_offset = line_start;
+        line_start = line_end;
+
+        let first = line
+            .iter()
+            .position(|c| !c.is_whitespace())
+            .unwrap_or(line.len());
+        let last = line
+            .iter()
+            .rposition(|c| !c.is_whitespace())
+            .map_or(first, |idx| idx + 1);
+
+        // Lines are spaced by their largest section so scaled text doesn't overlap
+        let line_scale = (line_offset + first..line_offset + last)
+            .map(|idx| sections[char_sections[idx]].scale)
+            .fold(1.0f32, f32::max);
+        if line_idx > 0 {
+            v_offset -= v_advance * line_scale;
+        }
+
+        let mut h_offset = 0.0;
+        let mut glyphs = SmallVec::new();
+        let mut last_glyph = None;
+
+        for (idx, &c) in line.iter().enumerate().take(last).skip(first) {
+            let (section, font, size) = section_font(line_offset + idx);
+
+            if c.is_control() || font.lookup_glyph_index(c) == 0 {
+                last_glyph = None;
+                continue;
+            }
+
+            h_offset += last_glyph
+                .filter(|(last_section, _)| *last_section == section)
+                .and_then(|(_, g)| font.horizontal_kern(g, c, size))
+                .unwrap_or_default();
+
+            last_glyph = Some((section, c));
+
+            let metrics = font.metrics(c, size);
+
+            if !c.is_whitespace() {
+                let min =
+                    V2::new(metrics.xmin, metrics.ymin).as_f32() + V2::new(h_offset, v_offset);
+                let dims = V2::new(metrics.width, metrics.height).as_f32();
+                let bounds = Rect::new(min, min + dims);
+
+                bounds_min = bounds_min.min(bounds.min);
+                bounds_max = bounds_max.max(bounds.max);
+
+                glyphs.push(GlyphDraw {
+                    bounds,
+                    glyph: GlyphId(section_fonts[section], c),
+                    size,
+                    color: sections[section].color.map(Color::from),
+                });
+            }
+
+            h_offset += metrics.advance_width + spacing;
+        }
+
+        // Letter spacing only applies between glyphs
+        let width = (h_offset - spacing).max(0.0);
+        widest_line = widest_line.max(width);
+
+        lines.push(LineDraw { glyphs, width });
+    }
+
+    if bounds_min.x > bounds_max.x {
+        return None;
+    }
+
+    let bounds = Rect::new(
+        V2::new(0.0, bounds_min.y),
+        V2::new(widest_line, bounds_max.y),
+    );
+
+    Some((lines, bounds))
 }
 
 struct PolygonIter<I: Iterator<Item = u32>> {
@@ -1748,6 +2389,109 @@ impl<I: Iterator<Item = u32>> Iterator for PolygonIter<I> {
     }
 }
 
+/// Polygon rings with the edges on the tile's clip boundary removed, those are shared with
+/// the neighbouring tile and outlining them draws seams along the tile borders. A ring that
+/// loses an edge is split into open lines.
+struct OutlineIter<I: Iterator<Item = lyon::path::PathEvent>> {
+    inner: I,
+    pending: SmallVec<[lyon::path::PathEvent; 3]>,
+    begin: lyon::math::Point,
+    previous: lyon::math::Point,
+    open: bool,
+    split: bool,
+}
+
+impl<I: Iterator<Item = lyon::path::PathEvent>> OutlineIter<I> {
+    fn new(inner: I) -> Self {
+        OutlineIter {
+            inner,
+            pending: SmallVec::new(),
+            begin: lyon::math::Point::zero(),
+            previous: lyon::math::Point::zero(),
+            open: false,
+            split: false,
+        }
+    }
+
+    fn on_border(from: lyon::math::Point, to: lyon::math::Point) -> bool {
+        (from.x <= 0.0 && to.x <= 0.0)
+            || (from.x >= 1.0 && to.x >= 1.0)
+            || (from.y <= 0.0 && to.y <= 0.0)
+            || (from.y >= 1.0 && to.y >= 1.0)
+    }
+
+    /// Queues an edge, starting a new line if the previous edge was removed
+    fn edge(&mut self, from: lyon::math::Point, to: lyon::math::Point) {
+        use lyon::path::PathEvent;
+
+        if Self::on_border(from, to) {
+            self.split = true;
+            self.end();
+            return;
+        }
+
+        if !self.open {
+            self.open = true;
+            self.begin = from;
+            self.pending.push(PathEvent::Begin { at: from });
+        }
+
+        self.previous = to;
+        self.pending.push(PathEvent::Line { from, to });
+    }
+
+    fn end(&mut self) {
+        use lyon::path::PathEvent;
+
+        if self.open {
+            self.open = false;
+            self.pending.push(PathEvent::End {
+                last: self.previous,
+                first: self.begin,
+                close: false,
+            });
+        }
+    }
+}
+
+impl<I: Iterator<Item = lyon::path::PathEvent>> Iterator for OutlineIter<I> {
+    type Item = lyon::path::PathEvent;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        use lyon::path::PathEvent;
+
+        loop {
+            if !self.pending.is_empty() {
+                return Some(self.pending.remove(0));
+            }
+
+            match self.inner.next()? {
+                PathEvent::Begin { .. } => {
+                    self.open = false;
+                    self.split = false;
+                }
+                PathEvent::Line { from, to } => self.edge(from, to),
+                PathEvent::End { last, first, .. } => {
+                    if !self.split && self.open && !Self::on_border(last, first) {
+                        self.open = false;
+                        return Some(PathEvent::End {
+                            last,
+                            first,
+                            close: true,
+                        });
+                    }
+
+                    if last != first {
+                        self.edge(last, first);
+                    }
+                    self.end();
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
 struct LineStringIter<I: Iterator<Item = u32>> {
     inner: std::iter::Fuse<I>,
     cursor: GeoCursor,
@@ -1956,11 +2700,13 @@ impl Color {
         V4::new(self.r, self.g, self.b, self.a)
     }
 
-    pub fn as_srgb(&self) -> Self {
+    /// Decodes the sRGB color channels to linear, with the exact sRGB transfer function
+    pub fn to_linear(&self) -> Self {
+        let linear = style::color::srgb_to_linear;
         Color {
-            r: self.r.powf(2.2),
-            g: self.r.powf(2.2),
-            b: self.r.powf(2.2),
+            r: linear(self.r),
+            g: linear(self.g),
+            b: linear(self.b),
             a: self.a,
         }
     }
@@ -2041,3 +2787,414 @@ impl TileId {
         2u32.pow(self.zoom as u32)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A tile layer holding a single feature, for evaluating style expressions against
+    pub(crate) struct TestLayer {
+        layer: proto::tile::Layer,
+        key_ids: Vec<Option<style::KeyId>>,
+    }
+
+    impl TestLayer {
+        /// Vector tiles can't hold null properties, so like GeoJSON tiles they are left out
+        pub(crate) fn new(id: Option<u64>, kind: GeomType, properties: serde_json::Value) -> Self {
+            let mut layer = proto::tile::Layer::default();
+            let mut feature = proto::tile::Feature {
+                id,
+                ..Default::default()
+            };
+            feature.set_type(kind);
+
+            for (key, value) in properties.as_object().into_iter().flatten() {
+                let value = match value {
+                    serde_json::Value::String(s) => proto::tile::Value {
+                        string_value: Some(s.clone().into_bytes()),
+                        ..Default::default()
+                    },
+                    serde_json::Value::Number(n) => proto::tile::Value {
+                        double_value: n.as_f64(),
+                        ..Default::default()
+                    },
+                    serde_json::Value::Bool(b) => proto::tile::Value {
+                        bool_value: Some(*b),
+                        ..Default::default()
+                    },
+                    _ => continue,
+                };
+
+                feature.tags.push(layer.keys.len() as u32);
+                feature.tags.push(layer.values.len() as u32);
+                layer.keys.push(key.clone().into_bytes());
+                layer.values.push(value);
+            }
+
+            layer.features.push(feature);
+            Self {
+                layer,
+                key_ids: Vec::new(),
+            }
+        }
+
+        /// Maps the layer's keys to the ids `keys` interned them as
+        pub(crate) fn resolve(mut self, keys: &style::PropertyKeys) -> Self {
+            self.key_ids = keys.resolve(self.layer.keys.iter().map(|key| key.as_slice()));
+            self
+        }
+
+        pub(crate) fn view(&self, zoom: f32) -> FeatureView<'_> {
+            FeatureView {
+                layer: &self.layer,
+                feature: &self.layer.features[0],
+                key_ids: &self.key_ids,
+                zoom,
+                line_progress: 0.0,
+            }
+        }
+    }
+
+    /// Loads a style of `layers` drawn from a vector source named `tiles`
+    pub(crate) fn load_style(layers: serde_json::Value) -> style::Style {
+        let style = serde_json::json!({
+            "version": 8,
+            "sources": {
+                "tiles": { "type": "vector", "tiles": ["https://example.com/{z}/{x}/{y}.pbf"] }
+            },
+            "layers": layers
+        });
+        style::Style::load(style.to_string().as_bytes()).unwrap()
+    }
+
+    /// The shift and justification of each placement `text_anchors` gives a symbol layer with
+    /// `layout`, for a 40x16 text block whose first baseline is 6 below its top
+    fn text_anchors(layout: serde_json::Value) -> Vec<(V2<f32>, f32)> {
+        let style = load_style(serde_json::json!([{
+            "id": "labels",
+            "type": "symbol",
+            "source": "tiles",
+            "source-layer": "places",
+            "layout": layout
+        }]));
+        let layer = TestLayer::new(None, GeomType::Point, serde_json::json!({}));
+        let layer = layer.resolve(&style.keys);
+        let view = layer.view(10.0);
+        let layout = FeatureLayout::new(&view, &style.layers[0], 10.0);
+
+        let bounds = Rect::new(V2::new(0.0, -10.0), V2::new(40.0, 6.0));
+        layout
+            .text_anchors(bounds)
+            .into_iter()
+            .map(|anchor| (anchor.shift, anchor.justify))
+            .collect()
+    }
+
+    #[test]
+    fn text_anchors_place_the_block_around_the_point() {
+        // Offsets are in ems of the 10 pixel text, drawn at twice its size
+        let layout = serde_json::json!({
+            "text-size": 10,
+            "text-anchor": "bottom-left",
+            "text-offset": [1, 2],
+            "text-justify": "right"
+        });
+        assert_eq!(text_anchors(layout), [(V2::new(20.0, -30.0), 1.0)]);
+
+        let layout = serde_json::json!({ "text-size": 10 });
+        assert_eq!(text_anchors(layout), [(V2::new(-20.0, 2.0), 0.5)]);
+    }
+
+    #[test]
+    fn variable_anchors_push_text_away_from_the_point() {
+        let expected = [
+            (V2::new(-20.0, -46.0), 0.5),
+            (V2::new(20.0, 50.0), 0.0),
+            (V2::new(-20.0, 2.0), 0.5),
+            (V2::new(-60.0, 2.0), 1.0),
+        ];
+
+        // Only the size of each offset is used, its direction comes from the anchor
+        for offset in [[1, 2], [-1, -2], [1, -2]] {
+            let layout = serde_json::json!({
+                "text-size": 10,
+                "text-offset": offset,
+                "text-justify": "auto",
+                "text-variable-anchor": ["top", "bottom-left", "center", "right"]
+            });
+            assert_eq!(text_anchors(layout), expected, "{offset:?}");
+        }
+    }
+
+    /// The `LabelSize` of a symbol layer with `layout`, laid out at zoom 10
+    fn label_size(layout: serde_json::Value) -> LabelSize {
+        let style = load_style(serde_json::json!([{
+            "id": "labels",
+            "type": "symbol",
+            "source": "tiles",
+            "source-layer": "places",
+            "layout": layout
+        }]));
+        let layer = TestLayer::new(None, GeomType::Point, serde_json::json!({}));
+        let layer = layer.resolve(&style.keys);
+        let view = layer.view(10.0);
+        FeatureLayout::new(&view, &style.layers[0], 10.0).label_size()
+    }
+
+    #[test]
+    fn labels_scale_to_text_size_at_the_camera_zoom() {
+        // Laid out at the larger size up to the next zoom level, so only ever scaled down
+        let size = label_size(serde_json::json!({
+            "text-size": ["interpolate", ["linear"], ["zoom"], 10, 10, 12, 20]
+        }));
+        assert_eq!(size.layout, 30.0);
+        assert_eq!(size.scale(10.0), 20.0 / 30.0);
+        assert_eq!(size.scale(10.5), 25.0 / 30.0);
+        assert_eq!(size.scale(11.0), 1.0);
+        assert_eq!(size.scale(13.0), 40.0 / 30.0);
+
+        let size = label_size(serde_json::json!({ "text-size": 12 }));
+        assert_eq!(size.layout, 24.0);
+        assert_eq!(size.scale(10.25), 1.0);
+
+        let size = label_size(serde_json::json!({}));
+        assert_eq!(size.layout, style::DEFAULT_TEXT_SIZE * TILE_SCALE);
+        assert_eq!(size.scale(10.75), 1.0);
+    }
+
+    #[test]
+    fn glyphs_take_their_format_section_font_scale_and_color() {
+        let style = load_style(serde_json::json!([{
+            "id": "labels",
+            "type": "symbol",
+            "source": "tiles",
+            "source-layer": "places",
+            "layout": {
+                "text-size": 10,
+                "text-font": ["Noto Sans Regular"],
+                "text-field": [
+                    "format",
+                    "ab", { "font-scale": 2, "text-font": ["literal", ["Noto Sans Bold"]] },
+                    "c", { "text-color": "#ff0000" }
+                ]
+            }
+        }]));
+        let layer = TestLayer::new(None, GeomType::Point, serde_json::json!({}));
+        let layer = layer.resolve(&style.keys);
+        let view = layer.view(10.0);
+        let layout = FeatureLayout::new(&view, &style.layers[0], 10.0);
+
+        let mut fonts = FontCollection::new();
+        let text = layout.text().unwrap();
+        let (lines, _) = shape_text(&layout, &text, &mut fonts).unwrap();
+        let (regular, _) = fonts.font(["Noto Sans Regular"]);
+        let (bold, _) = fonts.font(["Noto Sans Bold"]);
+        assert_ne!(regular, bold);
+
+        let glyphs: Vec<_> = lines[0]
+            .glyphs
+            .iter()
+            .map(|glyph| (glyph.glyph, glyph.size, glyph.color.map(|c| c.as_v4())))
+            .collect();
+        let red = V4::new(1.0, 0.0, 0.0, 1.0);
+        assert_eq!(
+            glyphs,
+            [
+                (GlyphId(bold, 'a'), 40.0, None),
+                (GlyphId(bold, 'b'), 40.0, None),
+                (GlyphId(regular, 'c'), 20.0, Some(red)),
+            ]
+        );
+
+        // The larger section's glyphs are wider
+        let width = |glyph: &GlyphDraw| glyph.bounds.width();
+        assert!(width(&lines[0].glyphs[0]) > width(&lines[0].glyphs[2]));
+    }
+
+    fn line_vertex(advancement: f32) -> GeoVertex {
+        GeoVertex {
+            position: V2::new(advancement, 0.0),
+            normal: V2::new(0.0, 1.0),
+            offset: V2::new(0.0, 1.0),
+            side: 1.0,
+            advancement,
+            line_progress: 0.0,
+            fill: gfx::FillMode::Line,
+            feature: 0,
+        }
+    }
+
+    #[test]
+    fn line_progress_spans_the_part_of_the_line() {
+        let mut vertices: Vec<_> = [0.0, 5.0, 10.0].map(line_vertex).into();
+        set_line_progress(&mut vertices, (0.25, 0.75));
+
+        let progress: Vec<_> = vertices.iter().map(|v| v.line_progress).collect();
+        assert_eq!(progress, [0.25, 0.5, 0.75]);
+
+        let mut vertices: Vec<_> = [0.0, 4.0].map(line_vertex).into();
+        set_line_progress(&mut vertices, (0.0, 1.0));
+
+        let progress: Vec<_> = vertices.iter().map(|v| v.line_progress).collect();
+        assert_eq!(progress, [0.0, 1.0]);
+    }
+
+    fn stroke(events: Vec<lyon::path::PathEvent>) -> Vec<GeoVertex> {
+        let mut geometry: VertexBuffers<GeoVertex, u32> = VertexBuffers::new();
+        let options = StrokeOptions::default()
+            .with_line_cap(lyon::path::LineCap::Round)
+            .with_line_join(lyon::path::LineJoin::Round);
+
+        StrokeTessellator::new()
+            .tessellate_path(
+                &offset_path(events),
+                &options,
+                &mut BuffersBuilder::new(&mut geometry, stroke_vertex),
+            )
+            .unwrap();
+
+        geometry.vertices
+    }
+
+    fn line(points: &[(f32, f32)]) -> Vec<lyon::path::PathEvent> {
+        let mut builder = lyon::path::Path::builder();
+        builder.begin(lyon::math::point(points[0].0, points[0].1));
+        for &(x, y) in &points[1..] {
+            builder.line_to(lyon::math::point(x, y));
+        }
+        builder.end(false);
+        builder.build().iter().collect()
+    }
+
+    fn ring(points: &[(f32, f32)]) -> Vec<lyon::path::PathEvent> {
+        let mut events = line(points);
+        if let Some(lyon::path::PathEvent::End { close, .. }) = events.last_mut() {
+            *close = true;
+        }
+        events
+    }
+
+    /// The lines an outline is drawn along, and whether each is closed
+    fn outlines(events: Vec<lyon::path::PathEvent>) -> Vec<(Vec<(f32, f32)>, bool)> {
+        use lyon::path::PathEvent;
+
+        let mut lines = Vec::new();
+        let mut points = Vec::new();
+        for event in OutlineIter::new(events.into_iter()) {
+            match event {
+                PathEvent::Begin { at } => points = vec![(at.x, at.y)],
+                PathEvent::Line { to, .. } => points.push((to.x, to.y)),
+                PathEvent::End { close, .. } => lines.push((std::mem::take(&mut points), close)),
+                _ => panic!("unexpected {event:?}"),
+            }
+        }
+        lines
+    }
+
+    #[test]
+    fn outlines_skip_tile_border_edges() {
+        // Rings inside the tile are outlined whole
+        let square = [(0.2, 0.2), (0.8, 0.2), (0.8, 0.8), (0.2, 0.8)];
+        assert_eq!(outlines(ring(&square)), [(square.to_vec(), true)]);
+
+        // The edge clipped along the east border is dropped and the rest kept as an open line
+        let clipped = [(0.5, 0.2), (1.0, 0.2), (1.0, 0.8), (0.5, 0.8)];
+        assert_eq!(
+            outlines(ring(&clipped)),
+            [
+                (vec![(0.5, 0.2), (1.0, 0.2)], false),
+                (vec![(1.0, 0.8), (0.5, 0.8), (0.5, 0.2)], false),
+            ]
+        );
+
+        // Edges in the buffer beyond the border are dropped too, including the closing edge
+        let corner = [(-0.1, 0.5), (-0.1, -0.1), (0.5, -0.1), (0.5, 0.5)];
+        assert_eq!(
+            outlines(ring(&corner)),
+            [(vec![(0.5, -0.1), (0.5, 0.5), (-0.1, 0.5)], false)]
+        );
+
+        // Edges crossing the border are inside the tile for part of their length
+        let crossing = [(0.5, 0.5), (1.5, 0.6), (0.5, 0.7)];
+        assert_eq!(outlines(ring(&crossing)), [(crossing.to_vec(), true)]);
+
+        // A polygon covering the whole tile has no outline
+        let tile = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+        assert_eq!(outlines(ring(&tile)), []);
+    }
+
+    #[test]
+    fn line_offset_moves_caps_with_the_line() {
+        let vertices = stroke(line(&[(0.0, 0.0), (10.0, 0.0)]));
+
+        // Every vertex, round caps included, moves right of the line's direction
+        for vertex in &vertices {
+            assert_eq!(vertex.offset, V2::new(0.0, 1.0));
+        }
+
+        // The same direction the stroke's own normals give across the straight line
+        let across: Vec<_> = vertices
+            .iter()
+            .filter(|v| v.normal.x.abs() < 1e-6)
+            .collect();
+        assert!(!across.is_empty());
+        for vertex in across {
+            assert_eq!(vertex.normal.y * vertex.side, vertex.offset.y);
+        }
+    }
+
+    #[test]
+    fn line_offset_meets_at_corners() {
+        let vertices = stroke(line(&[(0.0, 0.0), (10.0, 0.0), (10.0, 10.0)]));
+
+        // The corner's vertices all move to where the offset segments meet
+        let corner: Vec<_> = vertices
+            .iter()
+            .filter(|v| v.position == V2::new(10.0, 0.0))
+            .collect();
+        assert!(corner.len() > 2);
+        for vertex in corner {
+            assert!((vertex.offset.x + 1.0).abs() < 1e-5 && (vertex.offset.y - 1.0).abs() < 1e-5);
+        }
+
+        let offsets = line_offsets(
+            &[
+                lyon::math::point(0.0, 0.0),
+                lyon::math::point(10.0, 0.0),
+                lyon::math::point(0.0, 0.0),
+            ],
+            false,
+        );
+        assert_eq!(offsets[1], [0.0, 1.0]);
+    }
+
+    #[test]
+    fn line_progress_ignores_empty_lines() {
+        let mut vertices = vec![line_vertex(0.0), line_vertex(0.0)];
+        set_line_progress(&mut vertices, (0.25, 0.75));
+
+        assert!(vertices.iter().all(|v| v.line_progress == 0.0));
+    }
+
+    #[test]
+    fn colors_round_trip_through_srgb_targets_exactly() {
+        // An sRGB target encodes the linear color and rounds it back to 8 bits on write
+        let encode = |c: f32| (style::color::linear_to_srgb(c) * 255.0).round() as u8;
+
+        for n in 0..=255 {
+            let color = Color::from_rgb(n, n, n).to_linear();
+            assert_eq!([color.r, color.g, color.b].map(encode), [n; 3], "{n}");
+        }
+
+        for brand in ["#e10600", "#1db954", "#0a66c2", "#ff9900", "#7f7f7f"] {
+            let color = Color::from(brand.parse::<style::color::Color>().unwrap());
+            let srgb = [color.r, color.g, color.b].map(|c| (c * 255.0).round() as u8);
+            let expected = u32::from_str_radix(&brand[1..], 16).unwrap().to_be_bytes();
+            assert_eq!(srgb, expected[1..], "{brand}");
+
+            let linear = color.to_linear();
+            assert_eq!([linear.r, linear.g, linear.b].map(encode), srgb, "{brand}");
+            assert_eq!(linear.a, 1.0);
+        }
+    }
+}
@@ -0,0 +1,246 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Result, bail};
+use serde::Deserialize;
+
+/// A style's sprite sheet, the images used by `fill-pattern` and `line-pattern`
+pub struct Sprite {
+    pub width: u32,
+    pub height: u32,
+    /// Straight alpha RGBA8 pixels
+    pub pixels: Vec<u8>,
+    pub images: HashMap<String, SpriteImage>,
+}
+
+/// Where an image is in the sprite sheet, in sheet pixels
+#[derive(Deserialize, Debug, Copy, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SpriteImage {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    #[serde(default = "default_pixel_ratio")]
+    pub pixel_ratio: f32,
+}
+
+fn default_pixel_ratio() -> f32 {
+    1.0
+}
+
+impl SpriteImage {
+    /// Size of the image in style pixels
+    pub fn size(&self) -> (f32, f32) {
+        (
+            self.width as f32 / self.pixel_ratio,
+            self.height as f32 / self.pixel_ratio,
+        )
+    }
+
+    /// Whether the image is a non-empty area within a sheet of `width` by `height` pixels
+    fn fits(&self, width: u32, height: u32) -> bool {
+        self.width > 0
+            && self.height > 0
+            && self
+                .x
+                .checked_add(self.width)
+                .is_some_and(|right| right <= width)
+            && self
+                .y
+                .checked_add(self.height)
+                .is_some_and(|bottom| bottom <= height)
+    }
+}
+
+impl Sprite {
+    /// Loads the sprite's index and sheet from the data directory, the same way tile sources
+    /// are found. Tiles are drawn at twice their pixel size so the `@2x` sheet is preferred.
+    pub fn load<P: Into<PathBuf>>(data_dir: P, uri: &url::Url) -> Result<Self> {
+        let mut path = data_dir.into();
+        for seg in uri.path_segments().into_iter().flatten() {
+            path.push(seg);
+        }
+
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            bail!("sprite url has no file name: {uri}");
+        };
+        let name = name.to_string();
+
+        let mut error = None;
+        for suffix in ["@2x", ""] {
+            match Self::load_sheet(&path.with_file_name(format!("{name}{suffix}"))) {
+                Ok(sprite) => return Ok(sprite),
+                Err(e) => error = Some(e),
+            }
+        }
+
+        Err(error.unwrap())
+    }
+
+    fn load_sheet(path: &Path) -> Result<Self> {
+        let index = std::fs::File::open(path.with_extension("json"))?;
+        let images: HashMap<String, SpriteImage> = serde_json::from_reader(index)?;
+
+        let png = std::fs::read(path.with_extension("png"))?;
+        let (width, height, pixels) = decode_png(&png)?;
+
+        let images = images
+            .into_iter()
+            .filter(|(_, image)| image.fits(width, height))
+            .collect();
+
+        Ok(Sprite {
+            width,
+            height,
+            pixels,
+            images,
+        })
+    }
+}
+
+/// Decodes a PNG to straight alpha RGBA8
+fn decode_png(data: &[u8]) -> Result<(u32, u32, Vec<u8>)> {
+    let mut decoder = png::Decoder::new(data);
+    // Palettes, transparency chunks and other bit depths are expanded to 8 bit channels
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+
+    let mut reader = decoder.read_info()?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let frame = reader.next_frame(&mut buf)?;
+    buf.truncate(frame.buffer_size());
+
+    let pixels = match frame.color_type {
+        png::ColorType::Rgba => buf,
+        png::ColorType::Rgb => buf
+            .chunks_exact(3)
+            .flat_map(|p| [p[0], p[1], p[2], 255])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => buf
+            .chunks_exact(2)
+            .flat_map(|p| [p[0], p[0], p[0], p[1]])
+            .collect(),
+        png::ColorType::Grayscale => buf.iter().flat_map(|&p| [p, p, p, 255]).collect(),
+        png::ColorType::Indexed => bail!("png palette was not expanded"),
+    };
+
+    Ok((frame.width, frame.height, pixels))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(x: u32, y: u32, width: u32, height: u32) -> SpriteImage {
+        SpriteImage {
+            x,
+            y,
+            width,
+            height,
+            pixel_ratio: 1.0,
+        }
+    }
+
+    #[test]
+    fn images_must_fit_in_the_sheet() {
+        assert!(image(0, 0, 64, 32).fits(64, 32));
+        assert!(image(48, 16, 16, 16).fits(64, 32));
+        assert!(!image(0, 0, 0, 16).fits(64, 32));
+        assert!(!image(0, 0, 16, 0).fits(64, 32));
+        // Past the right and bottom edges
+        assert!(!image(49, 0, 16, 16).fits(64, 32));
+        assert!(!image(0, 17, 16, 16).fits(64, 32));
+        assert!(!image(64, 0, 1, 1).fits(64, 32));
+        // The far edge would wrap around to within the sheet
+        assert!(!image(u32::MAX, 0, 2, 1).fits(64, 32));
+        assert!(!image(0, u32::MAX - 1, 1, 4).fits(64, 32));
+    }
+
+    fn encode(color: png::ColorType, depth: png::BitDepth, data: &[u8]) -> Vec<u8> {
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, 2, 1);
+        encoder.set_color(color);
+        encoder.set_depth(depth);
+        if color == png::ColorType::Indexed {
+            encoder.set_palette(vec![10, 20, 30, 40, 50, 60]);
+            encoder.set_trns(vec![128]);
+        }
+
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(data).unwrap();
+        writer.finish().unwrap();
+        png
+    }
+
+    #[test]
+    fn pngs_decode_to_rgba() {
+        use png::{BitDepth, ColorType};
+
+        let cases: &[(ColorType, BitDepth, &[u8], [u8; 8])] = &[
+            (
+                ColorType::Rgba,
+                BitDepth::Eight,
+                &[1, 2, 3, 4, 5, 6, 7, 8],
+                [1, 2, 3, 4, 5, 6, 7, 8],
+            ),
+            (
+                ColorType::Rgb,
+                BitDepth::Eight,
+                &[1, 2, 3, 4, 5, 6],
+                [1, 2, 3, 255, 4, 5, 6, 255],
+            ),
+            (
+                ColorType::GrayscaleAlpha,
+                BitDepth::Eight,
+                &[7, 100, 9, 200],
+                [7, 7, 7, 100, 9, 9, 9, 200],
+            ),
+            (
+                ColorType::Grayscale,
+                BitDepth::Eight,
+                &[7, 9],
+                [7, 7, 7, 255, 9, 9, 9, 255],
+            ),
+            // Other bit depths are scaled to 8 bits
+            (
+                ColorType::Grayscale,
+                BitDepth::Sixteen,
+                &[0x12, 0x34, 0xff, 0xff],
+                [0x12, 0x12, 0x12, 255, 255, 255, 255, 255],
+            ),
+            (
+                ColorType::Grayscale,
+                BitDepth::Four,
+                &[0xf0],
+                [255, 255, 255, 255, 0, 0, 0, 255],
+            ),
+            // Palette entries without a transparency entry are opaque
+            (
+                ColorType::Indexed,
+                BitDepth::Eight,
+                &[0, 1],
+                [10, 20, 30, 128, 40, 50, 60, 255],
+            ),
+        ];
+
+        for (color, depth, data, expected) in cases {
+            let png = encode(*color, *depth, data);
+            let (width, height, pixels) = decode_png(&png).unwrap();
+            assert_eq!((width, height), (2, 1), "{color:?} {depth:?}");
+            assert_eq!(pixels, expected, "{color:?} {depth:?}");
+        }
+    }
+
+    #[test]
+    fn invalid_pngs_are_errors() {
+        assert!(decode_png(b"not a png").is_err());
+        let png = encode(
+            png::ColorType::Rgb,
+            png::BitDepth::Eight,
+            &[1, 2, 3, 4, 5, 6],
+        );
+        assert!(decode_png(&png[..png.len() / 2]).is_err());
+    }
+}
@@ -1,70 +1,123 @@
+use std::sync::Arc;
+
 use bstr::{BStr, BString, ByteSlice};
 use serde::Deserialize;
 use smallvec::SmallVec;
 
 use super::FeatureView;
 
-use std::collections::HashMap;
-
 pub mod color;
 mod data_expression;
 mod filter_expression;
 mod source;
+mod validate;
 
 use color::*;
-use data_expression::{DataExpression, ExpressionValue};
+use data_expression::{
+    Comparison, DataExpression, ExpressionType, ExpressionValue, Program, ValueType,
+};
+pub use data_expression::{KeyId, PropertyKeys};
 use filter_expression::FilterExpression;
-pub use source::{Source, SourceCollection, SourceId};
+pub use source::{Source, SourceCollection, SourceId, SourceType};
+pub use validate::{Diagnostic, Diagnostics, Severity, validate};
 
-#[derive(Deserialize, Debug, Clone)]
-#[serde(rename_all = "kebab-case")]
+#[derive(Debug, Clone)]
 pub struct Style {
     pub sources: SourceCollection,
+    /// Location of the sprite sheet holding the style's pattern images
+    pub sprite: Option<url::Url>,
     pub layers: Vec<Layer>,
+    /// Property keys read by the style's expressions
+    pub keys: PropertyKeys,
+}
+
+/// Layers are deserialized one at a time so a layer that fails doesn't fail the whole style
+#[derive(Deserialize)]
+struct StyleDocument {
+    sources: SourceCollection,
+    /// Multiple sprites aren't supported, only a single sprite url is loaded
+    #[serde(default)]
+    sprite: serde_json::Value,
+    layers: Vec<serde_json::Value>,
 }
 
 impl Style {
-    pub fn load<R: std::io::Read>(reader: R) -> Result<Self, serde_json::Error> {
-        let mut style: Self = serde_json::from_reader(reader)?;
-        style.remap_source_ids();
-        style.print_remaining_fields();
+    /// Loads a style, layers with problems are skipped with a warning and the rest are drawn
+    pub fn load<R: std::io::Read>(reader: R) -> anyhow::Result<Self> {
+        let document: serde_json::Value = serde_json::from_reader(reader)?;
+        let report = validate::validate_document(&document, false);
+
+        let (errors, warnings): (Vec<_>, Vec<_>) = report
+            .style
+            .into_iter()
+            .partition(|diagnostic| diagnostic.severity == Severity::Error);
+        for warning in warnings {
+            eprintln!("{warning}");
+        }
+        if !errors.is_empty() {
+            return Err(Diagnostics(errors).into());
+        }
 
-        Ok(style)
-    }
+        let document: StyleDocument = serde_json::from_value(document)?;
+        let sprite = document
+            .sprite
+            .as_str()
+            .and_then(|sprite| url::Url::parse(sprite).ok());
+        let mut style = Style {
+            sources: document.sources,
+            sprite,
+            layers: Vec::new(),
+            keys: PropertyKeys::default(),
+        };
 
-    fn print_remaining_fields(&self) {
-        use std::collections::HashSet;
-        let mut layout_fields = HashSet::new();
-        let mut paint_fields = HashSet::new();
-        layout_fields.extend(
-            self.layers
-                .iter()
-                .flat_map(|l| l.layout.remaining_fields.keys()),
-        );
-        paint_fields.extend(
-            self.layers
-                .iter()
-                .flat_map(|l| l.paint.remaining_fields.keys()),
-        );
+        let layers = document.layers.into_iter().zip(report.layers);
+        for (idx, (layer, diagnostics)) in layers.enumerate() {
+            let path = format!("layers[{idx}]");
+            let id = layer
+                .get("id")
+                .and_then(serde_json::Value::as_str)
+                .map(str::to_string);
+
+            let mut skipped = layer
+                .get("type")
+                .and_then(serde_json::Value::as_str)
+                .is_some_and(|kind| validate::UNSUPPORTED_LAYER_TYPES.contains(&kind));
+            for diagnostic in diagnostics {
+                skipped |= diagnostic.severity == Severity::Error;
+                eprintln!("{}", diagnostic.into_warning());
+            }
 
-        println!("Unsupported layout fields:");
-        for field in layout_fields {
-            println!("\t{field}");
-        }
-        println!();
+            if skipped {
+                eprintln!(
+                    "{}",
+                    Diagnostic::warning(path, id, "layer skipped".to_string())
+                );
+                continue;
+            }
 
-        println!("Unsupported paint fields:");
-        for field in paint_fields {
-            println!("\t{field}");
+            match style.load_layer(layer) {
+                Ok(layer) => style.layers.push(layer),
+                Err(e) => {
+                    let warning = Diagnostic::warning(path, id, format!("{e}, layer skipped"));
+                    eprintln!("{warning}");
+                }
+            }
         }
+
+        Ok(style)
     }
 
-    fn remap_source_ids(&mut self) {
-        for layer in self.layers.iter_mut() {
-            if let Some(id) = layer.source.take() {
-                layer.source = self.sources.remap(id);
-            }
+    /// Deserializes and compiles a layer, type checking and folding its expressions and
+    /// interning the property keys they read
+    fn load_layer(&mut self, layer: serde_json::Value) -> Result<Layer, String> {
+        let mut layer = Layer::deserialize(layer).map_err(|e| e.to_string())?;
+
+        if let Some(id) = layer.source.take() {
+            layer.source = self.sources.remap(id);
         }
+        layer.compile(&mut self.keys)?;
+
+        Ok(layer)
     }
 }
 
@@ -88,6 +141,14 @@ pub struct Layer {
 }
 
 impl Layer {
+    fn compile(&mut self, keys: &mut PropertyKeys) -> Result<(), String> {
+        self.filter
+            .compile(keys)
+            .map_err(|e| format!("filter: {e}"))?;
+        self.layout.compile(keys)?;
+        self.paint.compile(keys)
+    }
+
     pub fn filter(&self, features: &FeatureView<'_>) -> bool {
         self.filter.eval(features)
     }
@@ -104,35 +165,86 @@ pub enum LayerType {
     FillExtrusion,
 }
 
-#[derive(Deserialize, Default, Debug, Clone)]
-#[serde(rename_all = "kebab-case")]
-#[serde(default)]
-pub struct Layout {
-    pub visibility: Visibility,
-    pub line_cap: LineCap,
-    pub line_join: LineJoin,
-    // pub text_allow_overlap: Option<bool>,
-    text_anchor: Field<TextAnchor>,
-    text_field: Option<BString>,
-    pub text_font: Vec<String>,
-    // pub text_ignore_placement: Option<bool>,
-    pub text_letter_spacing: Option<f32>,
-    pub text_max_width: Option<f32>,
-    pub text_offset: Option<(f32, f32)>,
-    //pub text_optional: Option<bool>,
-    pub text_padding: Option<f32>,
-    pub text_rotation_alignment: Option<TextRotationAlignment>,
-    text_size: Field<f32>,
-    pub text_transform: Option<TextTransform>,
-    symbol_placement: Field<SymbolPlacement>,
-    //symbol_spacing: Option<f32>,
-    #[serde(flatten)]
-    remaining_fields: HashMap<String, Exists>,
+pub const DEFAULT_TEXT_SIZE: f32 = 16.0;
+
+/// Declares a section's properties once, naming each field's property in the style so that
+/// deserializing, checking and compiling the section all follow the same list
+macro_rules! style_properties {
+    (
+        $(#[$attr:meta])*
+        pub struct $name:ident {
+            $($(#[$field_attr:meta])* $field_vis:vis $field:ident: $ty:ty = $property:literal,)*
+            $(; $($(#[$extra_attr:meta])* $extra:ident: $extra_ty:ty,)*)?
+        }
+    ) => {
+        $(#[$attr])*
+        pub struct $name {
+            $($(#[$field_attr])* #[serde(rename = $property)] $field_vis $field: $ty,)*
+            $($($(#[$extra_attr])* $extra: $extra_ty,)*)?
+        }
+
+        impl $name {
+            /// Checks a property's value, `None` if the property isn't supported
+            fn check_property(
+                name: &str,
+                value: &serde_json::Value,
+            ) -> Option<Result<(), String>> {
+                match name {
+                    $($property => Some(<$ty as Property>::check(value)),)*
+                    _ => None,
+                }
+            }
+
+            fn compile_properties(&mut self, keys: &mut PropertyKeys) -> Result<(), String> {
+                $(Property::compile(&mut self.$field, $property, keys)?;)*
+                Ok(())
+            }
+        }
+    };
+}
+
+style_properties! {
+    #[derive(Deserialize, Default, Debug, Clone)]
+    #[serde(default)]
+    pub struct Layout {
+        pub visibility: Visibility = "visibility",
+        pub line_cap: LineCap = "line-cap",
+        pub line_join: LineJoin = "line-join",
+        // pub text_allow_overlap: Option<bool>,
+        text_anchor: Field<TextAnchor> = "text-anchor",
+        text_field: Option<DataExpression<'static>> = "text-field",
+        pub text_font: Vec<String> = "text-font",
+        // pub text_ignore_placement: Option<bool>,
+        text_justify: Field<TextJustify> = "text-justify",
+        text_letter_spacing: Field<f32> = "text-letter-spacing",
+        text_line_height: Field<f32> = "text-line-height",
+        text_max_width: Field<f32> = "text-max-width",
+        text_offset: Field<(f32, f32)> = "text-offset",
+        //pub text_optional: Option<bool>,
+        pub text_padding: Option<f32> = "text-padding",
+        pub text_rotation_alignment: Option<TextRotationAlignment> = "text-rotation-alignment",
+        text_size: Field<f32> = "text-size",
+        pub text_transform: Option<TextTransform> = "text-transform",
+        text_variable_anchor: Option<SmallVec<[TextAnchor; 9]>> = "text-variable-anchor",
+        symbol_placement: Field<SymbolPlacement> = "symbol-placement",
+        //symbol_spacing: Option<f32>,
+    }
 }
 
 impl Layout {
+    fn compile(&mut self, keys: &mut PropertyKeys) -> Result<(), String> {
+        self.compile_properties(keys)
+    }
+
     pub fn text_size(&self, features: &FeatureView<'_>, zoom: f32) -> f32 {
-        self.text_size.eval(features).eval(zoom).unwrap_or(16.0)
+        self.text_size
+            .eval(features)
+            .eval(zoom)
+            .unwrap_or(DEFAULT_TEXT_SIZE)
+    }
+
+    pub fn text_size_parameter(&self, features: &FeatureView<'_>) -> Parameter<f32> {
+        self.text_size.eval(features)
     }
 
     pub fn symbol_placement(
@@ -143,49 +255,211 @@ impl Layout {
         self.symbol_placement.eval(features).eval(zoom)
     }
 
-    pub fn text_max_width(&self) -> f32 {
-        self.text_max_width.unwrap_or(10.0)
+    pub fn text_max_width(&self, features: &FeatureView<'_>, zoom: f32) -> f32 {
+        self.text_max_width
+            .eval(features)
+            .eval(zoom)
+            .unwrap_or(10.0)
+    }
+
+    pub fn text_letter_spacing(&self, features: &FeatureView<'_>, zoom: f32) -> f32 {
+        self.text_letter_spacing
+            .eval(features)
+            .eval(zoom)
+            .unwrap_or_default()
     }
 
-    pub fn text(&self, view: &FeatureView<'_>) -> Option<smartstring::alias::String> {
-        let format = self.text_field.as_ref()?;
+    pub fn text_anchor(&self, features: &FeatureView<'_>, zoom: f32) -> TextAnchor {
+        self.text_anchor
+            .eval(features)
+            .eval(zoom)
+            .unwrap_or_default()
+    }
+
+    pub fn text_variable_anchor(&self) -> Option<&[TextAnchor]> {
+        self.text_variable_anchor
+            .as_deref()
+            .filter(|anchors| !anchors.is_empty())
+    }
+
+    pub fn text_justify(&self, features: &FeatureView<'_>, zoom: f32) -> TextJustify {
+        self.text_justify
+            .eval(features)
+            .eval(zoom)
+            .unwrap_or_default()
+    }
+
+    pub fn text_line_height(&self, features: &FeatureView<'_>, zoom: f32) -> f32 {
+        self.text_line_height
+            .eval(features)
+            .eval(zoom)
+            .unwrap_or(1.2)
+    }
+
+    pub fn text_offset(&self, features: &FeatureView<'_>, zoom: f32) -> (f32, f32) {
+        self.text_offset
+            .eval(features)
+            .eval(zoom)
+            .unwrap_or((0.0, 0.0))
+    }
+
+    /// The label text split into sections that share a font, scale and color
+    pub fn text(&self, view: &FeatureView<'_>) -> Option<SmallVec<[TextSection; 1]>> {
+        let field = self.text_field.as_ref()?;
 
-        let mut text = smartstring::alias::String::new();
-        let mut in_field = false;
-        let mut span_start = 0;
         let transform = self.text_transform.unwrap_or_default();
-        for (idx, c) in format.chars().enumerate() {
-            match c {
-                '{' if in_field == false => {
-                    span_start = idx + 1;
-                    in_field = true;
-                }
-                '}' if in_field == true => {
-                    let field = &format[span_start..idx];
-                    let field = BStr::new(field);
-                    for c in view
-                        .key(field)
-                        .and_then(|v| v.as_str())
-                        .iter()
-                        .map(|s| s.chars())
-                        .flatten()
-                    {
-                        transform.transform(c, &mut text);
-                    }
-                    in_field = false
-                }
-                c if in_field == false => {
-                    transform.transform(c, &mut text);
-                }
-                _ => (),
+        let transformed = |text: &BStr| {
+            let mut transformed = smartstring::alias::String::new();
+            for c in text.chars() {
+                transform.transform(c, &mut transformed);
+            }
+            transformed
+        };
+
+        let mut sections: SmallVec<[TextSection; 1]> = match field.eval(view) {
+            ExpressionValue::Formatted(sections) => sections
+                .into_iter()
+                .map(|section| TextSection {
+                    text: transformed(section.text.as_ref()),
+                    scale: section.scale.unwrap_or(1.0),
+                    font: section.font,
+                    color: section.color,
+                })
+                .collect(),
+            value => {
+                smallvec::smallvec![TextSection::new(transformed(value.to_bstring().as_ref()))]
             }
+        };
+
+        sections.retain(|section| !section.text.is_empty());
+
+        if sections
+            .iter()
+            .all(|section| section.text.trim().is_empty())
+        {
+            return None;
         }
 
-        if text.trim().is_empty() {
-            None
-        } else {
-            let text = text.trim().into();
-            Some(text)
+        if let Some(first) = sections.first_mut() {
+            first.text = first.text.trim_start().into();
+        }
+        if let Some(last) = sections.last_mut() {
+            last.text = last.text.trim_end().into();
+        }
+
+        Some(sections)
+    }
+}
+
+/// Plain strings are token strings, strings produced by expressions are used verbatim
+fn compile_text_field(
+    text_field: &mut DataExpression<'static>,
+    keys: &mut PropertyKeys,
+) -> Result<(), String> {
+    if let DataExpression::Constant(ExpressionValue::String(format)) = text_field {
+        *text_field = DataExpression::from_tokens(format.as_ref());
+    }
+
+    check_type(text_field, ExpressionType::Formatted, keys)
+}
+
+/// Compiles an expression, checking that it produces a value usable as `expected`
+fn check_type(
+    exp: &mut DataExpression<'static>,
+    expected: ExpressionType,
+    keys: &mut PropertyKeys,
+) -> Result<(), String> {
+    if exp.contains_line_progress() {
+        return Err("line-progress may only be used in line-gradient".to_string());
+    }
+
+    let ty = exp.compile(keys)?;
+    if !ty.is_compatible(expected) {
+        return Err(format!("expected {expected}, found {ty}"));
+    }
+
+    Ok(())
+}
+
+fn check_constant<T: serde::de::DeserializeOwned>(value: &serde_json::Value) -> Result<(), String> {
+    T::deserialize(value).map(drop).map_err(|e| e.to_string())
+}
+
+/// A property declared with [`style_properties`]
+trait Property {
+    /// Checks the property's value in a style document
+    fn check(value: &serde_json::Value) -> Result<(), String>;
+
+    /// Type checks and folds the property's expressions when the style is loaded
+    fn compile(&mut self, _name: &str, _keys: &mut PropertyKeys) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Properties that only take constants
+macro_rules! constant_property {
+    ($($ty:ty),*) => {
+        $(impl Property for $ty {
+            fn check(value: &serde_json::Value) -> Result<(), String> {
+                check_constant::<$ty>(value)
+            }
+        })*
+    };
+}
+
+constant_property!(
+    Visibility,
+    LineCap,
+    LineJoin,
+    Vec<String>,
+    Option<f32>,
+    Option<TextRotationAlignment>,
+    Option<TextTransform>,
+    Option<SmallVec<[TextAnchor; 9]>>
+);
+
+/// `text-field` is the only property that takes a bare expression
+impl Property for Option<DataExpression<'static>> {
+    fn check(value: &serde_json::Value) -> Result<(), String> {
+        let mut text_field = DataExpression::deserialize(value).map_err(|e| e.to_string())?;
+        compile_text_field(&mut text_field, &mut PropertyKeys::default())
+    }
+
+    fn compile(&mut self, name: &str, keys: &mut PropertyKeys) -> Result<(), String> {
+        match self {
+            Some(text_field) => {
+                compile_text_field(text_field, keys).map_err(|e| format!("{name}: {e}"))
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+/// Constant arrays such as `text-offset` are told apart from expressions by their first item
+fn is_expression(value: &serde_json::Value) -> bool {
+    value
+        .as_array()
+        .and_then(|items| items.first())
+        .is_some_and(serde_json::Value::is_string)
+}
+
+#[derive(Debug, Clone)]
+pub struct TextSection {
+    pub text: smartstring::alias::String,
+    pub scale: f32,
+    /// Overrides the layer's `text-font`
+    pub font: Option<Vec<String>>,
+    /// Overrides the layer's `text-color`
+    pub color: Option<Color>,
+}
+
+impl TextSection {
+    fn new(text: smartstring::alias::String) -> Self {
+        Self {
+            text,
+            scale: 1.0,
+            font: None,
+            color: None,
         }
     }
 }
@@ -275,6 +549,90 @@ impl Default for TextAnchor {
     }
 }
 
+impl TextAnchor {
+    /// Position of the anchor within the text box, as fractions of its width
+    /// from the left edge and of its height from the top edge
+    pub fn alignment(&self) -> (f32, f32) {
+        match self {
+            TextAnchor::Center => (0.5, 0.5),
+            TextAnchor::Left => (0.0, 0.5),
+            TextAnchor::Right => (1.0, 0.5),
+            TextAnchor::Top => (0.5, 0.0),
+            TextAnchor::Bottom => (0.5, 1.0),
+            TextAnchor::TopLeft => (0.0, 0.0),
+            TextAnchor::TopRight => (1.0, 0.0),
+            TextAnchor::BottomLeft => (0.0, 1.0),
+            TextAnchor::BottomRight => (1.0, 1.0),
+        }
+    }
+}
+
+impl TryFrom<ExpressionValue<'_>> for TextAnchor {
+    type Error = ();
+
+    fn try_from(value: ExpressionValue<'_>) -> Result<Self, Self::Error> {
+        let value: Option<&[u8]> = value.as_str().map(|s| s.as_ref());
+        match value {
+            Some(b"center") => Ok(Self::Center),
+            Some(b"left") => Ok(Self::Left),
+            Some(b"right") => Ok(Self::Right),
+            Some(b"top") => Ok(Self::Top),
+            Some(b"bottom") => Ok(Self::Bottom),
+            Some(b"top-left") => Ok(Self::TopLeft),
+            Some(b"top-right") => Ok(Self::TopRight),
+            Some(b"bottom-left") => Ok(Self::BottomLeft),
+            Some(b"bottom-right") => Ok(Self::BottomRight),
+            _ => Err(()),
+        }
+    }
+}
+
+impl EnumParameter for TextAnchor {}
+
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TextJustify {
+    Auto,
+    Left,
+    Center,
+    Right,
+}
+
+impl Default for TextJustify {
+    fn default() -> Self {
+        TextJustify::Center
+    }
+}
+
+impl TextJustify {
+    /// Horizontal alignment of each line within the widest line, `auto` follows the anchor
+    pub fn alignment(&self, anchor: TextAnchor) -> f32 {
+        match self {
+            TextJustify::Auto => anchor.alignment().0,
+            TextJustify::Left => 0.0,
+            TextJustify::Center => 0.5,
+            TextJustify::Right => 1.0,
+        }
+    }
+}
+
+impl TryFrom<ExpressionValue<'_>> for TextJustify {
+    type Error = ();
+
+    fn try_from(value: ExpressionValue<'_>) -> Result<Self, Self::Error> {
+        let value: Option<&[u8]> = value.as_str().map(|s| s.as_ref());
+        match value {
+            Some(b"auto") => Ok(Self::Auto),
+            Some(b"left") => Ok(Self::Left),
+            Some(b"center") => Ok(Self::Center),
+            Some(b"right") => Ok(Self::Right),
+            _ => Err(()),
+        }
+    }
+}
+
+impl EnumParameter for TextJustify {}
+
 #[derive(Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
 #[serde(rename_all = "kebab-case")]
 pub enum TextRotationAlignment {
@@ -319,44 +677,71 @@ impl TryFrom<ExpressionValue<'_>> for SymbolPlacement {
 
 impl EnumParameter for SymbolPlacement {}
 
-#[derive(Deserialize, Debug, Clone, Default)]
-#[serde(rename_all = "kebab-case")]
-#[serde(default)]
-pub struct PaintFields {
-    background_color: Field<Color>,
-    line_color: Field<Color>,
-    line_opacity: Field<f32>,
-    line_width: Field<f32>,
-    line_dasharray: Option<SmallVec<[f32; 8]>>,
-    line_gap_width: Option<Exists>,
-    fill_antialias: Field<bool>,
-    fill_color: Field<Color>,
-    fill_opacity: Field<f32>,
-    fill_outline_color: Field<Color>,
-    fill_translate: Field<(f32, f32)>,
-    fill_pattern: Option<Exists>,
-    text_color: Field<Color>,
-    text_opacity: Field<f32>,
-    text_halo_blur: Field<f32>,
-    text_halo_color: Field<Color>,
-    text_halo_width: Field<f32>,
-    #[serde(flatten)]
-    remaining_fields: HashMap<String, Exists>,
+style_properties! {
+    #[derive(Deserialize, Debug, Clone, Default)]
+    #[serde(default)]
+    pub struct PaintFields {
+        background_color: Field<Color> = "background-color",
+        line_color: Field<Color> = "line-color",
+        line_opacity: Field<f32> = "line-opacity",
+        line_width: Field<f32> = "line-width",
+        line_dasharray: Field<SmallVec<[f32; 8]>> = "line-dasharray",
+        line_gap_width: Field<f32> = "line-gap-width",
+        line_offset: Field<f32> = "line-offset",
+        line_blur: Field<f32> = "line-blur",
+        fill_antialias: Field<bool> = "fill-antialias",
+        fill_color: Field<Color> = "fill-color",
+        fill_opacity: Field<f32> = "fill-opacity",
+        fill_outline_color: Field<Color> = "fill-outline-color",
+        fill_translate: Field<(f32, f32)> = "fill-translate",
+        fill_pattern: Field<BString> = "fill-pattern",
+        line_pattern: Field<BString> = "line-pattern",
+        line_gradient: Option<LineGradient> = "line-gradient",
+        text_color: Field<Color> = "text-color",
+        text_opacity: Field<f32> = "text-opacity",
+        text_halo_blur: Field<f32> = "text-halo-blur",
+        text_halo_color: Field<Color> = "text-halo-color",
+        text_halo_width: Field<f32> = "text-halo-width",
+        ;
+        /// The paint shared by every feature when no field depends on the feature
+        #[serde(skip)]
+        evaluated: Option<Paint>,
+    }
 }
 
 impl PaintFields {
+    fn compile(&mut self, keys: &mut PropertyKeys) -> Result<(), String> {
+        self.compile_properties(keys)?;
+
+        if !self.is_computed_from_feature() {
+            self.evaluated = Some(self.eval(&FeatureView::empty(0.0)));
+        }
+
+        Ok(())
+    }
+
     pub fn eval(&self, features: &FeatureView<'_>) -> Paint {
+        if let Some(paint) = self.evaluated.as_ref() {
+            return paint.clone();
+        }
+
         Paint {
             background_color: self.background_color.eval(features),
             line_color: self.line_color.eval(features),
             line_opacity: self.line_opacity.eval(features),
             line_width: self.line_width.eval(features),
-            line_dasharray: self.line_dasharray.clone(),
+            line_dasharray: self.line_dasharray.eval(features),
+            line_gap_width: self.line_gap_width.eval(features),
+            line_offset: self.line_offset.eval(features),
+            line_blur: self.line_blur.eval(features),
             fill_antialias: self.fill_antialias.eval(features),
             fill_color: self.fill_color.eval(features),
             fill_opacity: self.fill_opacity.eval(features),
             fill_outline_color: self.fill_outline_color.eval(features),
             fill_translate: self.fill_translate.eval(features),
+            fill_pattern: self.fill_pattern.eval(features),
+            line_pattern: self.line_pattern.eval(features),
+            line_gradient: self.line_gradient.as_ref().and_then(LineGradient::ramp),
             text_color: self.text_color.eval(features),
             text_opacity: self.text_opacity.eval(features),
             text_halo_blur: self.text_halo_blur.eval(features),
@@ -365,20 +750,22 @@ impl PaintFields {
         }
     }
 
-    pub fn unsupported(&self) -> bool {
-        self.fill_pattern.is_some() || self.line_gap_width.is_some()
-    }
-
     pub fn is_computed_from_feature(&self) -> bool {
         self.background_color.is_computer_from_feature()
             || self.line_color.is_computer_from_feature()
             || self.line_opacity.is_computer_from_feature()
             || self.line_width.is_computer_from_feature()
+            || self.line_dasharray.is_computer_from_feature()
+            || self.line_gap_width.is_computer_from_feature()
+            || self.line_offset.is_computer_from_feature()
+            || self.line_blur.is_computer_from_feature()
             || self.fill_antialias.is_computer_from_feature()
             || self.fill_color.is_computer_from_feature()
             || self.fill_opacity.is_computer_from_feature()
             || self.fill_outline_color.is_computer_from_feature()
             || self.fill_translate.is_computer_from_feature()
+            || self.fill_pattern.is_computer_from_feature()
+            || self.line_pattern.is_computer_from_feature()
             || self.text_color.is_computer_from_feature()
             || self.text_opacity.is_computer_from_feature()
             || self.text_halo_blur.is_computer_from_feature()
@@ -393,12 +780,18 @@ pub struct Paint {
     line_color: Parameter<Color>,
     line_opacity: Parameter<f32>,
     line_width: Parameter<f32>,
-    line_dasharray: Option<SmallVec<[f32; 8]>>,
+    line_dasharray: Parameter<SmallVec<[f32; 8]>>,
+    line_gap_width: Parameter<f32>,
+    line_offset: Parameter<f32>,
+    line_blur: Parameter<f32>,
     fill_antialias: Parameter<bool>,
     fill_color: Parameter<Color>,
     fill_opacity: Parameter<f32>,
     fill_outline_color: Parameter<Color>,
     fill_translate: Parameter<(f32, f32)>,
+    fill_pattern: Parameter<BString>,
+    line_pattern: Parameter<BString>,
+    line_gradient: Option<Arc<ColorRamp>>,
     text_color: Parameter<Color>,
     text_opacity: Parameter<f32>,
     text_halo_blur: Parameter<f32>,
@@ -439,6 +832,20 @@ impl Paint {
         }
     }
 
+    pub fn fill_opacity(&self, zoom: f32) -> f32 {
+        self.fill_opacity.eval(zoom).unwrap_or(1.0)
+    }
+
+    /// Sprite image drawn in place of `fill-color`
+    pub fn fill_pattern(&self, zoom: f32) -> Option<CrossFaded<BString>> {
+        pattern(&self.fill_pattern, zoom)
+    }
+
+    /// Sprite image drawn along the line in place of `line-color`
+    pub fn line_pattern(&self, zoom: f32) -> Option<CrossFaded<BString>> {
+        pattern(&self.line_pattern, zoom)
+    }
+
     pub fn fill_translate(&self, zoom: f32) -> (f32, f32) {
         self.fill_translate.eval(zoom).unwrap_or((0.0, 0.0))
     }
@@ -454,6 +861,15 @@ impl Paint {
         }
     }
 
+    pub fn line_opacity(&self, zoom: f32) -> f32 {
+        self.line_opacity.eval(zoom).unwrap_or(1.0)
+    }
+
+    /// Line color sampled along the line, drawn in place of `line-color` when set
+    pub fn line_gradient(&self) -> Option<&Arc<ColorRamp>> {
+        self.line_gradient.as_ref()
+    }
+
     pub fn text_color(&self, zoom: f32) -> Color {
         let color = self.text_color.eval(zoom);
         let opacity = self.text_opacity.eval(zoom);
@@ -481,9 +897,29 @@ impl Paint {
         self.line_width.eval(zoom).unwrap_or(1.0)
     }
 
-    pub fn line_dasharray(&self) -> SmallVec<[f32; 8]> {
-        self.line_dasharray.clone().unwrap_or(SmallVec::new())
+    pub fn line_gap_width(&self, zoom: f32) -> f32 {
+        self.line_gap_width.eval(zoom).unwrap_or_default().max(0.0)
+    }
+
+    pub fn line_offset(&self, zoom: f32) -> f32 {
+        self.line_offset.eval(zoom).unwrap_or_default()
+    }
+
+    pub fn line_blur(&self, zoom: f32) -> f32 {
+        self.line_blur.eval(zoom).unwrap_or_default().max(0.0)
     }
+
+    /// Dash and gap lengths, in multiples of the line width
+    pub fn line_dasharray(&self, zoom: f32) -> SmallVec<[f32; 8]> {
+        self.line_dasharray.eval(zoom).unwrap_or_default()
+    }
+}
+
+/// An empty image name draws no pattern
+fn pattern(pattern: &Parameter<BString>, zoom: f32) -> Option<CrossFaded<BString>> {
+    pattern
+        .cross_faded(zoom)
+        .filter(|pattern| !pattern.to.is_empty())
 }
 
 impl Interpolate for f32 {
@@ -502,84 +938,332 @@ impl<T: Interpolate> Interpolate for Option<T> {
             None
         }
     }
+
+    fn interpolate_in(&self, space: ColorSpace, factor: f32, other: Self) -> Self {
+        if let Some(value) = self
+            && let Some(other) = other
+        {
+            Some(value.interpolate_in(space, factor, other))
+        } else {
+            None
+        }
+    }
 }
 
+/// A legacy function, converted to the equivalent expression when the style is compiled
 #[derive(Deserialize, Debug, Clone)]
 #[serde(rename_all = "kebab-case")]
-struct Function<T> {
+struct Function {
+    #[serde(rename = "type")]
+    kind: Option<FunctionType>,
     base: Option<f32>,
     property: Option<BString>,
-    stops: smallvec::SmallVec<[(f32, T); 8]>,
+    #[serde(default)]
+    stops: Vec<(StopInput, ExpressionValue<'static>)>,
+    #[serde(default, rename = "colorSpace")]
+    color_space: ColorSpace,
+    default: Option<ExpressionValue<'static>>,
+}
+
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+enum FunctionType {
+    Identity,
+    Exponential,
+    Interval,
+    Categorical,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+enum StopInput {
+    /// A stop of a zoom and property function
+    Composite {
+        zoom: f64,
+        value: ExpressionValue<'static>,
+    },
+    Value(ExpressionValue<'static>),
 }
 
-impl<T> Function<T> {
+impl Function {
     fn is_computed_from_feature(&self) -> bool {
         self.property.is_some()
     }
-}
 
-impl<T: Copy + Interpolate> Function<T> {
-    fn eval(&self, feature: &FeatureView<'_>) -> Parameter<T> {
-        let Some(property) = self.property.as_ref() else {
-            return Parameter::ZoomFunction(ZoomFunction {
-                base: self.base,
-                stops: self.stops.clone(),
-            });
+    /// Converts the function following MapLibre's `convertFunction`, checking its outputs are
+    /// valid for the field
+    fn to_expression<O>(&self) -> Result<DataExpression<'static>, String>
+    where
+        O: FieldType + for<'f> TryFrom<ExpressionValue<'f>>,
+    {
+        let outputs = self.stops.iter().map(|(_, output)| output);
+        if let Some(output) = outputs
+            .chain(self.default.as_ref())
+            .find(|output| O::try_from((*output).clone()).is_err())
+        {
+            return Err(format!(
+                "invalid function output '{}', expected {}",
+                output.to_bstring(),
+                O::TYPE
+            ));
+        }
+
+        // Interpolated fields default to exponential functions, the rest to interval functions
+        let interpolated = O::INTERPOLATED;
+        let kind = self.kind.unwrap_or(if interpolated {
+            FunctionType::Exponential
+        } else {
+            FunctionType::Interval
+        });
+        let default = self.default.clone().map(DataExpression::Constant);
+
+        let property = match (&self.property, self.stops.first()) {
+            (Some(property), _) => property,
+            (None, Some((StopInput::Value(_), _))) => {
+                let stops = self.stops.iter().map(|(input, output)| match input {
+                    StopInput::Value(input) => Ok((input, output)),
+                    StopInput::Composite { .. } => Err("unexpected zoom and property stop"),
+                });
+                let stops = numeric_stops(stops)?;
+                return curve(
+                    kind,
+                    self.base,
+                    self.color_space,
+                    DataExpression::Zoom,
+                    stops,
+                );
+            }
+            (None, None) => return Err("expected stops for function".to_string()),
+            (None, _) => return Err("expected property for function".to_string()),
         };
 
-        let value = match feature.key(property) {
-            Some(super::Value::Number(n)) => n,
-            _ => 0.0,
+        let get = || {
+            let key = DataExpression::Constant(ExpressionValue::String(property.clone()));
+            DataExpression::Get(Box::new(key))
         };
 
-        let value = value as f32;
+        if kind == FunctionType::Identity || self.stops.is_empty() {
+            return Ok(identity(get(), O::TYPE, default));
+        }
 
-        assert!(self.stops.len() != 0);
+        if let (StopInput::Composite { .. }, _) = &self.stops[0] {
+            // Each zoom level gets a property function of the stops at that zoom
+            let mut zooms: Vec<(f64, Vec<(&ExpressionValue, &ExpressionValue)>)> = Vec::new();
+            for (input, output) in self.stops.iter() {
+                let StopInput::Composite { zoom, value } = input else {
+                    return Err("expected zoom and property stop".to_string());
+                };
+
+                match zooms.last_mut() {
+                    Some((last, stops)) if last == zoom => stops.push((value, output)),
+                    _ => zooms.push((*zoom, vec![(value, output)])),
+                }
+            }
 
-        let first = self.stops.first().unwrap();
-        let last = self.stops.last().unwrap();
+            let zoom_kind = if interpolated {
+                FunctionType::Exponential
+            } else {
+                FunctionType::Interval
+            };
+            let zoom_stops = zooms
+                .into_iter()
+                .map(|(zoom, stops)| {
+                    let stops = stops.into_iter().map(Ok);
+                    let output = property_function(
+                        kind,
+                        self.base,
+                        self.color_space,
+                        get,
+                        stops,
+                        default.clone(),
+                    )?;
+                    Ok((zoom, output))
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+
+            return curve(
+                zoom_kind,
+                None,
+                self.color_space,
+                DataExpression::Zoom,
+                zoom_stops,
+            );
+        }
 
-        let result = if value <= first.0 || self.stops.len() == 1 {
-            first.1
-        } else if value >= last.0 {
-            last.1
-        } else {
-            let mut steps = self.stops.iter();
-            let mut last = steps.next().unwrap();
+        let stops = self.stops.iter().map(|(input, output)| match input {
+            StopInput::Value(input) => Ok((input, output)),
+            StopInput::Composite { .. } => Err("unexpected zoom and property stop"),
+        });
+        property_function(kind, self.base, self.color_space, get, stops, default)
+    }
+}
 
-            let mut result = None;
+type FunctionStop<'a> =
+    Result<(&'a ExpressionValue<'static>, &'a ExpressionValue<'static>), &'static str>;
 
-            for next in steps {
-                if value >= last.0 && value <= next.0 {
-                    let range = next.0 - last.0;
-                    let start = value - last.0;
+fn property_function<'a, G, I>(
+    kind: FunctionType,
+    base: Option<f32>,
+    space: ColorSpace,
+    get: G,
+    stops: I,
+    default: Option<DataExpression<'static>>,
+) -> Result<DataExpression<'static>, String>
+where
+    G: Fn() -> DataExpression<'static>,
+    I: Iterator<Item = FunctionStop<'a>>,
+{
+    let constant = |value: &ExpressionValue<'static>| DataExpression::Constant(value.clone());
+    let fallback = || {
+        Box::new(
+            default
+                .clone()
+                .unwrap_or(DataExpression::Constant(ExpressionValue::Null)),
+        )
+    };
+
+    let exp = match kind {
+        FunctionType::Categorical => {
+            let stops = stops
+                .map(|stop| stop.map(|(input, output)| (constant(input), constant(output))))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            if let Some((DataExpression::Constant(ExpressionValue::Bool(_)), _)) = stops.first() {
+                let cases = stops
+                    .into_iter()
+                    .map(|(input, output)| {
+                        let cmp =
+                            DataExpression::Cmp(Comparison::Eq, Box::new(get()), Box::new(input));
+                        (cmp, output)
+                    })
+                    .collect();
+                DataExpression::Case(cases, fallback())
+            } else {
+                DataExpression::Match(Box::new(get()), stops, fallback())
+            }
+        }
+        FunctionType::Interval | FunctionType::Exponential => {
+            let input = DataExpression::Assert(ValueType::Number, vec![get()]);
+            let exp = curve(kind, base, space, input, numeric_stops(stops)?)?;
+
+            match default {
+                // Only features without a numeric property get the default
+                Some(default) => {
+                    let is_number = DataExpression::Cmp(
+                        Comparison::Eq,
+                        Box::new(DataExpression::TypeOf(Box::new(get()))),
+                        Box::new(DataExpression::Constant(ExpressionValue::Str(BStr::new(
+                            "number",
+                        )))),
+                    );
+                    DataExpression::Case(vec![(is_number, exp)], Box::new(default))
+                }
+                None => exp,
+            }
+        }
+        FunctionType::Identity => unreachable!("identity functions have no stops"),
+    };
 
-                    let n = start / range;
+    Ok(exp)
+}
 
-                    let base = self.base.unwrap_or(1.0);
+fn numeric_stops<'a, I>(stops: I) -> Result<Vec<(f64, DataExpression<'static>)>, String>
+where
+    I: Iterator<Item = FunctionStop<'a>>,
+{
+    let mut numeric: Vec<(f64, DataExpression<'static>)> = Vec::new();
+    for stop in stops {
+        let (input, output) = stop?;
+        let input = input.as_number().ok_or_else(|| {
+            format!(
+                "expected number for stop input, found {}",
+                input.type_name()
+            )
+        })?;
+
+        match numeric.last() {
+            // Duplicate stops were never rejected for functions, the first one wins
+            Some((last, _)) if *last == input => continue,
+            Some((last, _)) if *last > input => {
+                return Err("expected stop inputs in ascending order".to_string());
+            }
+            _ => numeric.push((input, DataExpression::Constant(output.clone()))),
+        }
+    }
 
-                    if base == 1.0 {
-                        result = Some(last.1.interpolate(n.powf(self.base.unwrap_or(1.0)), next.1));
-                        break;
-                    } else {
-                        let factor = (base.powf(start) - 1.0) / (base.powf(range) - 1.0);
-                        result = Some(last.1.interpolate(factor, next.1));
-                        break;
-                    }
-                }
+    Ok(numeric)
+}
 
-                last = next;
-            }
+/// The zoom or property curve of an exponential or interval function
+fn curve(
+    kind: FunctionType,
+    base: Option<f32>,
+    space: ColorSpace,
+    input: DataExpression<'static>,
+    mut stops: Vec<(f64, DataExpression<'static>)>,
+) -> Result<DataExpression<'static>, String> {
+    if stops.is_empty() {
+        return Err("expected stops for function".to_string());
+    }
 
-            result.unwrap()
-        };
+    let exp = match kind {
+        FunctionType::Exponential => {
+            let interpolation = match base.unwrap_or(1.0) {
+                1.0 => Interpolation::Linear,
+                base => Interpolation::Exponential(base),
+            };
+            DataExpression::Interpolate(interpolation, space, Box::new(input), stops)
+        }
+        FunctionType::Interval => {
+            // The first stop's output applies below every stop so its input is redundant
+            let (_, first) = stops.remove(0);
+            DataExpression::Step(Box::new(input), Box::new(first), stops)
+        }
+        FunctionType::Categorical | FunctionType::Identity => {
+            return Err("categorical and identity functions need a property".to_string());
+        }
+    };
+
+    Ok(exp)
+}
 
-        Parameter::Constant(Some(result))
+/// Uses the property's value as the field's value, converted to the field's type
+fn identity(
+    get: DataExpression<'static>,
+    ty: ExpressionType,
+    default: Option<DataExpression<'static>>,
+) -> DataExpression<'static> {
+    let values = std::iter::once(get).chain(default).collect();
+    match ty {
+        ExpressionType::Number => DataExpression::Assert(ValueType::Number, values),
+        ExpressionType::String => DataExpression::Assert(ValueType::String, values),
+        ExpressionType::Boolean => DataExpression::Assert(ValueType::Boolean, values),
+        ExpressionType::Color => DataExpression::ToColor(values),
+        _ => DataExpression::Coalesce(values),
     }
 }
 
 pub trait Interpolate {
     fn interpolate(&self, factor: f32, other: Self) -> Self;
+
+    /// Interpolates colors in `space`, anything else the same as [`Interpolate::interpolate`]
+    fn interpolate_in(&self, space: ColorSpace, factor: f32, other: Self) -> Self
+    where
+        Self: Sized,
+    {
+        let _ = space;
+        self.interpolate(factor, other)
+    }
+}
+
+/// The color space `interpolate`, `interpolate-lab` and `interpolate-hcl` blend colors in
+#[derive(Debug, Copy, Clone, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorSpace {
+    #[default]
+    Rgb,
+    Lab,
+    Hcl,
 }
 
 impl<A: Interpolate, B: Interpolate> Interpolate for (A, B) {
@@ -593,6 +1277,41 @@ impl<A: Interpolate, B: Interpolate> Interpolate for (A, B) {
 
 trait EnumParameter: Copy {}
 
+/// The expression type a field's expressions must produce
+trait FieldType {
+    const TYPE: ExpressionType;
+    /// Whether the field's zoom functions interpolate between stops rather than step
+    const INTERPOLATED: bool = matches!(
+        Self::TYPE,
+        ExpressionType::Number | ExpressionType::Color | ExpressionType::Array
+    );
+}
+
+impl FieldType for f32 {
+    const TYPE: ExpressionType = ExpressionType::Number;
+}
+
+impl FieldType for bool {
+    const TYPE: ExpressionType = ExpressionType::Boolean;
+}
+
+impl FieldType for Color {
+    const TYPE: ExpressionType = ExpressionType::Color;
+}
+
+impl FieldType for (f32, f32) {
+    const TYPE: ExpressionType = ExpressionType::Array;
+}
+
+impl FieldType for SmallVec<[f32; 8]> {
+    const TYPE: ExpressionType = ExpressionType::Array;
+    const INTERPOLATED: bool = false;
+}
+
+impl<T: EnumParameter> FieldType for T {
+    const TYPE: ExpressionType = ExpressionType::String;
+}
+
 impl<T: EnumParameter> Interpolate for T {
     fn interpolate(&self, factor: f32, other: Self) -> Self {
         if factor < 0.5 { *self } else { other }
@@ -605,37 +1324,155 @@ impl Interpolate for bool {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
-pub struct Exists;
+impl Interpolate for SmallVec<[f32; 8]> {
+    fn interpolate(&self, factor: f32, other: Self) -> Self {
+        if factor < 0.5 { self.clone() } else { other }
+    }
+}
 
-impl<'de> serde::Deserialize<'de> for Exists {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: serde::Deserializer<'de>,
-    {
-        let _ = serde::de::IgnoredAny::deserialize(deserializer)?;
-        Ok(Exists)
+/// Sprite image names, as used by `fill-pattern` and `line-pattern`
+impl FieldType for BString {
+    const TYPE: ExpressionType = ExpressionType::String;
+}
+
+impl Interpolate for BString {
+    fn interpolate(&self, factor: f32, other: Self) -> Self {
+        if factor < 0.5 { self.clone() } else { other }
     }
 }
 
-#[derive(Deserialize, Debug, Clone)]
+/// `line-gradient`, sampled into a color ramp when the style is compiled since it may only
+/// depend on `line-progress`
+#[derive(Debug, Clone, Deserialize)]
 #[serde(untagged)]
-enum Filter {
-    FilterExpression(FilterExpression),
+enum LineGradient {
     DataExpression(DataExpression<'static>),
+    #[serde(skip)]
+    Compiled(Arc<ColorRamp>),
 }
 
-impl Default for Filter {
-    fn default() -> Self {
-        Filter::FilterExpression(filter_expression::FilterExpression::True)
+impl LineGradient {
+    fn compile(&mut self, keys: &mut PropertyKeys) -> Result<(), String> {
+        let LineGradient::DataExpression(exp) = self else {
+            return Ok(());
+        };
+
+        let ty = exp.compile(keys)?;
+        if !ty.is_compatible(ExpressionType::Color) {
+            return Err(format!("expected {}, found {ty}", ExpressionType::Color));
+        }
+
+        if exp.contains_zoom() || exp.is_computed_from_feature() {
+            return Err("expression may only depend on line-progress".to_string());
+        }
+
+        *self = LineGradient::Compiled(Arc::new(ColorRamp::sample(exp)));
+
+        Ok(())
+    }
+
+    fn ramp(&self) -> Option<Arc<ColorRamp>> {
+        match self {
+            LineGradient::DataExpression(_) => None,
+            LineGradient::Compiled(ramp) => Some(ramp.clone()),
+        }
+    }
+}
+
+impl Property for Option<LineGradient> {
+    fn check(value: &serde_json::Value) -> Result<(), String> {
+        let mut gradient = LineGradient::deserialize(value).map_err(|e| e.to_string())?;
+        gradient.compile(&mut PropertyKeys::default())
+    }
+
+    fn compile(&mut self, name: &str, keys: &mut PropertyKeys) -> Result<(), String> {
+        match self {
+            Some(gradient) => gradient.compile(keys).map_err(|e| format!("{name}: {e}")),
+            None => Ok(()),
+        }
+    }
+}
+
+pub const COLOR_RAMP_WIDTH: usize = 256;
+
+/// Colors at evenly spaced points from the start to the end of a line, as straight alpha
+/// RGBA8 texels
+#[derive(Debug, PartialEq)]
+pub struct ColorRamp([[u8; 4]; COLOR_RAMP_WIDTH]);
+
+impl ColorRamp {
+    fn sample(exp: &DataExpression<'static>) -> Self {
+        let mut texels = [[0; 4]; COLOR_RAMP_WIDTH];
+        for (i, texel) in texels.iter_mut().enumerate() {
+            let progress = i as f32 / (COLOR_RAMP_WIDTH - 1) as f32;
+            let feature = FeatureView::at_line_progress(progress);
+            let color = Color::try_from(exp.eval(&feature))
+                .unwrap_or_default()
+                .to_rgba();
+
+            let channel = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+            *texel = [
+                channel(color.r),
+                channel(color.g),
+                channel(color.b),
+                channel(color.a),
+            ];
+        }
+
+        ColorRamp(texels)
+    }
+
+    pub fn texels(&self) -> &[[u8; 4]] {
+        &self.0
+    }
+}
+
+/// Filters written in the legacy syntax are converted to expressions when they are loaded
+#[derive(Debug, Clone, Default)]
+struct Filter {
+    expression: DataExpression<'static>,
+    /// The expression flattened when the style was compiled
+    program: Option<Program>,
+}
+
+impl<'de> serde::Deserialize<'de> for Filter {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        use serde::de::Error;
+
+        let filter = serde_json::Value::deserialize(deserializer)?;
+        let exp = if filter_expression::is_expression_filter(&filter) {
+            DataExpression::deserialize(&filter)
+        } else {
+            FilterExpression::deserialize(&filter).map(FilterExpression::into_expression)
+        };
+
+        exp.map(|expression| Filter {
+            expression,
+            program: None,
+        })
+        .map_err(D::Error::custom)
     }
 }
 
 impl Filter {
+    fn compile(&mut self, keys: &mut PropertyKeys) -> Result<(), String> {
+        check_type(&mut self.expression, ExpressionType::Boolean, keys)?;
+        self.program = Some(Program::new(&self.expression));
+        Ok(())
+    }
+
+    fn check(value: &serde_json::Value) -> Result<(), String> {
+        let mut filter = Filter::deserialize(value).map_err(|e| e.to_string())?;
+        filter.compile(&mut PropertyKeys::default())
+    }
+
     pub fn eval(&self, feature: &FeatureView<'_>) -> bool {
-        match self {
-            Filter::FilterExpression(exp) => exp.eval(feature),
-            Filter::DataExpression(exp) => exp.eval(feature).into(),
+        match self.program.as_ref() {
+            Some(program) => program.eval(feature).into(),
+            None => self.expression.eval(feature).into(),
         }
     }
 }
@@ -644,8 +1481,14 @@ impl Filter {
 #[serde(untagged)]
 enum Field<O> {
     Constant(Option<O>),
-    Function(Function<O>),
+    Function(Function),
     DataExpression(DataExpression<'static>),
+    /// An expression that doesn't depend on the feature, evaluated when the style was loaded
+    #[serde(skip)]
+    Compiled(Parameter<O>),
+    /// An expression that depends on the feature, flattened when the style was loaded
+    #[serde(skip)]
+    Program(FieldProgram<O>),
 }
 
 impl<O> Default for Field<O> {
@@ -660,90 +1503,843 @@ impl<O> Field<O> {
             Field::Constant(_) => false,
             Field::Function(function) => function.is_computed_from_feature(),
             Field::DataExpression(exp) => exp.is_computed_from_feature(),
+            Field::Compiled(_) => false,
+            Field::Program(_) => true,
         }
     }
 }
 
-impl<'f, O: Copy + Interpolate + Default + TryFrom<ExpressionValue<'f>>> Field<O> {
+impl<O> Property for Field<O>
+where
+    O: FieldType
+        + Clone
+        + Interpolate
+        + Default
+        + serde::de::DeserializeOwned
+        + for<'f> TryFrom<ExpressionValue<'f>>,
+{
+    fn check(value: &serde_json::Value) -> Result<(), String> {
+        match value {
+            value if is_expression(value) => {
+                let mut exp = DataExpression::deserialize(value).map_err(|e| e.to_string())?;
+                compile_field_expression::<O>(&mut exp, &mut PropertyKeys::default())
+            }
+            serde_json::Value::Object(_) => {
+                let function = Function::deserialize(value).map_err(|e| e.to_string())?;
+                let mut exp = function.to_expression::<O>()?;
+                compile_field_expression::<O>(&mut exp, &mut PropertyKeys::default())
+            }
+            value => check_constant::<Option<O>>(value),
+        }
+    }
+
+    fn compile(&mut self, name: &str, keys: &mut PropertyKeys) -> Result<(), String> {
+        if let Field::Function(function) = self {
+            let exp = function
+                .to_expression::<O>()
+                .map_err(|e| format!("{name}: {e}"))?;
+            *self = Field::DataExpression(exp);
+        }
+
+        let Field::DataExpression(exp) = self else {
+            return Ok(());
+        };
+
+        compile_field_expression::<O>(exp, keys).map_err(|e| format!("{name}: {e}"))?;
+
+        *self = if exp.is_computed_from_feature() {
+            Field::Program(FieldProgram::new(exp))
+        } else {
+            Field::Compiled(exp.eval_parameter(&FeatureView::empty(0.0)))
+        };
+
+        Ok(())
+    }
+}
+
+impl<'f, O: Clone + Interpolate + Default + TryFrom<ExpressionValue<'f>>> Field<O> {
     fn eval<'a: 'f>(&'a self, feature: &'f FeatureView<'_>) -> Parameter<O> {
         match self {
-            Field::Constant(c) => Parameter::Constant(*c),
-            Field::Function(f) => f.eval(feature),
-            Field::DataExpression(exp) => exp.eval(feature).to_parameter(),
+            Field::Constant(c) => Parameter::Constant(c.clone()),
+            // Functions are converted to expressions when the style is compiled
+            Field::Function(_) => Parameter::Constant(None),
+            Field::DataExpression(exp) => exp.eval_parameter(feature),
+            Field::Compiled(parameter) => parameter.clone(),
+            Field::Program(program) => program.eval(feature),
         }
     }
 }
 
+/// A feature dependent field's program, a top level zoom curve keeps its stops so that it can
+/// be evaluated at the camera zoom while rendering
+#[derive(Debug, Clone)]
+enum FieldProgram<O> {
+    Value(Program),
+    CameraExpression(Curve, Vec<(f32, Stop<O>)>),
+}
+
+/// A zoom curve's output, already converted when it doesn't depend on the feature
+#[derive(Debug, Clone)]
+enum Stop<O> {
+    Constant(O),
+    Program(Program),
+}
+
+impl<O> FieldProgram<O>
+where
+    O: for<'f> TryFrom<ExpressionValue<'f>>,
+{
+    fn new(exp: &DataExpression<'static>) -> Self {
+        let stop = |stop: f64, output: &DataExpression<'static>| {
+            let output = match output {
+                DataExpression::Constant(value) => Stop::Constant(O::try_from(value.clone()).ok()?),
+                output => Stop::Program(Program::new(output)),
+            };
+            Some((stop as f32, output))
+        };
+
+        match exp {
+            DataExpression::Interpolate(interpolation, space, input, stops)
+                if matches!(**input, DataExpression::Zoom) =>
+            {
+                let stops = stops
+                    .iter()
+                    .filter_map(|(s, output)| stop(*s, output))
+                    .collect();
+                FieldProgram::CameraExpression(Curve::Interpolate(*interpolation, *space), stops)
+            }
+            DataExpression::Step(input, first, stops)
+                if matches!(**input, DataExpression::Zoom) =>
+            {
+                let stops = std::iter::once((f64::NEG_INFINITY, &**first))
+                    .chain(stops.iter().map(|(s, output)| (*s, output)))
+                    .filter_map(|(s, output)| stop(s, output))
+                    .collect();
+                FieldProgram::CameraExpression(Curve::Step, stops)
+            }
+            exp => FieldProgram::Value(Program::new(exp)),
+        }
+    }
+}
+
+impl<'f, O: Clone + Default + TryFrom<ExpressionValue<'f>>> FieldProgram<O> {
+    fn eval<'a: 'f>(&'a self, feature: &'f FeatureView<'_>) -> Parameter<O> {
+        match self {
+            FieldProgram::Value(program) => program.eval(feature).to_parameter(),
+            FieldProgram::CameraExpression(curve, stops) => {
+                let stops = stops
+                    .iter()
+                    .filter_map(|(stop, output)| {
+                        let output = match output {
+                            Stop::Constant(output) => output.clone(),
+                            Stop::Program(program) => O::try_from(program.eval(feature)).ok()?,
+                        };
+                        Some((*stop, output))
+                    })
+                    .collect();
+                Parameter::CameraExpression(CameraExpression::new(*curve, stops))
+            }
+        }
+    }
+}
+
+/// Property expressions may only use `zoom` as the input of a top level curve, which only
+/// fields that interpolate between zoom levels may make an `interpolate`
+fn compile_field_expression<O: FieldType>(
+    exp: &mut DataExpression<'static>,
+    keys: &mut PropertyKeys,
+) -> Result<(), String> {
+    check_type(exp, O::TYPE, keys)?;
+    exp.check_zoom_placement()?;
+
+    match exp {
+        DataExpression::Interpolate(_, _, input, _)
+            if !O::INTERPOLATED && matches!(**input, DataExpression::Zoom) =>
+        {
+            Err("interpolate expressions cannot be used with this property".to_string())
+        }
+        _ => Ok(()),
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Parameter<O> {
     Constant(Option<O>),
-    ZoomFunction(ZoomFunction<O>),
     CameraExpression(CameraExpression<O>),
 }
 
-impl<O: Copy + Interpolate> Parameter<O> {
-    fn eval(&self, zoom: f32) -> Option<O> {
+impl<O: Clone + Interpolate> Parameter<O> {
+    pub fn eval(&self, zoom: f32) -> Option<O> {
         match self {
-            Parameter::Constant(c) => *c,
-            Parameter::ZoomFunction(z) => Some(z.eval(zoom)),
-            Parameter::CameraExpression(c) => Some(c.eval(zoom)),
+            Parameter::Constant(c) => c.clone(),
+            Parameter::CameraExpression(c) => c.eval(zoom),
         }
     }
+
+    /// Values that step between zoom levels fade in across the zoom level after each step,
+    /// from the value one zoom level out
+    pub fn cross_faded(&self, zoom: f32) -> Option<CrossFaded<O>> {
+        let to = self.eval(zoom)?;
+        let from = self.eval(zoom - 1.0).unwrap_or_else(|| to.clone());
+
+        Some(CrossFaded {
+            from,
+            to,
+            fade: zoom.fract(),
+        })
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
-pub struct ZoomFunction<T> {
-    base: Option<f32>,
-    stops: smallvec::SmallVec<[(f32, T); 8]>,
+pub struct CrossFaded<O> {
+    pub from: O,
+    pub to: O,
+    /// How far the fade is from showing only `from` at 0.0 to only `to` at 1.0
+    pub fade: f32,
 }
 
-impl<T: Copy + Interpolate> ZoomFunction<T> {
-    fn eval(&self, zoom: f32) -> T {
-        assert!(self.stops.len() != 0);
+/// A zoom curve whose outputs have already been evaluated against a feature
+#[derive(Debug, Clone, PartialEq)]
+pub struct CameraExpression<O> {
+    curve: Curve,
+    stops: SmallVec<[(f32, O); 8]>,
+}
 
-        let first = self.stops.first().unwrap();
-        let last = self.stops.last().unwrap();
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Curve {
+    Interpolate(Interpolation, ColorSpace),
+    Step,
+}
 
-        if zoom <= first.0 || self.stops.len() == 1 {
-            first.1
-        } else if zoom >= last.0 {
-            last.1
-        } else {
-            let mut steps = self.stops.iter();
-            let mut last = steps.next().unwrap();
+impl<O> CameraExpression<O> {
+    pub fn new(curve: Curve, stops: SmallVec<[(f32, O); 8]>) -> Self {
+        Self { curve, stops }
+    }
+}
 
-            for next in steps {
-                if zoom >= last.0 && zoom <= next.0 {
-                    let range = next.0 - last.0;
-                    let start = zoom - last.0;
+impl<O: Clone + Interpolate> CameraExpression<O> {
+    fn eval(&self, zoom: f32) -> Option<O> {
+        match self.curve {
+            Curve::Interpolate(interpolation, space) => {
+                interpolate_stops(&self.stops, interpolation, space, zoom)
+            }
+            Curve::Step => step_stops(&self.stops, zoom),
+        }
+    }
+}
 
-                    let n = start / range;
+/// Output of the last stop at or below `input`, or the first stop when `input` is below them all
+pub fn step_stops<I: PartialOrd + Copy, T: Clone>(stops: &[(I, T)], input: I) -> Option<T> {
+    let first = stops.first()?;
+    let stop = stops
+        .iter()
+        .take_while(|(stop, _)| *stop <= input)
+        .last()
+        .unwrap_or(first);
 
-                    let base = self.base.unwrap_or(1.0);
+    Some(stop.1.clone())
+}
 
-                    if base == 1.0 {
-                        return last.1.interpolate(n.powf(self.base.unwrap_or(1.0)), next.1);
-                    } else {
-                        let factor = (base.powf(start) - 1.0) / (base.powf(range) - 1.0);
-                        return last.1.interpolate(factor, next.1);
-                    }
-                }
+pub fn interpolate_stops<T: Clone + Interpolate>(
+    stops: &[(f32, T)],
+    interpolation: Interpolation,
+    space: ColorSpace,
+    input: f32,
+) -> Option<T> {
+    let first = stops.first()?;
+    let last = stops.last()?;
+
+    if input <= first.0 {
+        return Some(first.1.clone());
+    } else if input >= last.0 {
+        return Some(last.1.clone());
+    }
 
-                last = next;
-            }
+    let upper_idx = stops.iter().position(|(stop, _)| *stop > input)?;
+    let (lower, lower_value) = &stops[upper_idx - 1];
+    let (upper, upper_value) = &stops[upper_idx];
+
+    let factor = interpolation.factor(input, *lower, *upper);
+
+    Some(lower_value.interpolate_in(space, factor, upper_value.clone()))
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Interpolation {
+    Linear,
+    Exponential(f32),
+    CubicBezier(f32, f32, f32, f32),
+}
+
+impl Interpolation {
+    /// Progress of `input` between the `lower` and `upper` stops, from 0.0 to 1.0
+    pub fn factor(&self, input: f32, lower: f32, upper: f32) -> f32 {
+        let range = upper - lower;
+        let progress = input - lower;
 
-            unreachable!()
+        if range == 0.0 {
+            return 0.0;
+        }
+
+        match *self {
+            Interpolation::Linear => progress / range,
+            Interpolation::Exponential(base) if base == 1.0 => progress / range,
+            Interpolation::Exponential(base) => {
+                (base.powf(progress) - 1.0) / (base.powf(range) - 1.0)
+            }
+            Interpolation::CubicBezier(x1, y1, x2, y2) => {
+                cubic_bezier(x1, y1, x2, y2, progress / range)
+            }
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
-pub struct CameraExpression<O> {
-    _marker: std::marker::PhantomData<O>,
+impl<'de> serde::Deserialize<'de> for Interpolation {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_seq(InterpolationVisitor)
+    }
 }
 
-impl<O> CameraExpression<O> {
-    fn eval(&self, _zoom: f32) -> O {
-        todo!()
+struct InterpolationVisitor;
+
+impl<'de> serde::de::Visitor<'de> for InterpolationVisitor {
+    type Value = Interpolation;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(formatter, "an interpolation type array")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: serde::de::SeqAccess<'de>,
+    {
+        use serde::de::Error as E;
+
+        let kind: String = seq
+            .next_element()?
+            .ok_or(E::custom("expected interpolation type"))?;
+
+        let mut param = |name| -> Result<f32, A::Error> {
+            seq.next_element()?
+                .ok_or_else(|| E::custom(format!("expected {name} for {kind} interpolation")))
+        };
+
+        let interpolation = match kind.as_str() {
+            "linear" => Interpolation::Linear,
+            "exponential" => Interpolation::Exponential(param("base")?),
+            "cubic-bezier" => {
+                Interpolation::CubicBezier(param("x1")?, param("y1")?, param("x2")?, param("y2")?)
+            }
+            _ => return Err(E::custom(format!("unexpected interpolation type '{kind}'"))),
+        };
+
+        Ok(interpolation)
+    }
+}
+
+/// Solves the unit bezier through (0, 0), (x1, y1), (x2, y2) and (1, 1) for the y value at `x`
+fn cubic_bezier(x1: f32, y1: f32, x2: f32, y2: f32, x: f32) -> f32 {
+    const EPSILON: f32 = 1e-6;
+
+    let cx = 3.0 * x1;
+    let bx = 3.0 * (x2 - x1) - cx;
+    let ax = 1.0 - cx - bx;
+
+    let cy = 3.0 * y1;
+    let by = 3.0 * (y2 - y1) - cy;
+    let ay = 1.0 - cy - by;
+
+    let sample_x = |t: f32| ((ax * t + bx) * t + cx) * t;
+    let sample_dx = |t: f32| (3.0 * ax * t + 2.0 * bx) * t + cx;
+    let sample_y = |t: f32| ((ay * t + by) * t + cy) * t;
+
+    let x = x.clamp(0.0, 1.0);
+
+    // Newton's method converges quickly for most curves
+    let mut t = x;
+    for _ in 0..8 {
+        let error = sample_x(t) - x;
+        if error.abs() < EPSILON {
+            return sample_y(t);
+        }
+
+        let slope = sample_dx(t);
+        if slope.abs() < EPSILON {
+            break;
+        }
+
+        t -= error / slope;
+    }
+
+    // Fall back to bisection for flat sections of the curve
+    let mut lower = 0.0;
+    let mut upper = 1.0;
+    t = x;
+    for _ in 0..32 {
+        let sample = sample_x(t);
+        if (sample - x).abs() < EPSILON {
+            break;
+        }
+
+        if x > sample {
+            lower = t;
+        } else {
+            upper = t;
+        }
+
+        t = (upper - lower) / 2.0 + lower;
+    }
+
+    sample_y(t)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+
+    use super::*;
+    use crate::proto::tile::GeomType;
+    use crate::tests::TestLayer;
+
+    /// Checks the converted function against MapLibre's `convertFunction` result by evaluating
+    /// both for a few features at zooms around the stops
+    fn check<O>(function: Value, expected: Value)
+    where
+        O: FieldType + for<'f> TryFrom<ExpressionValue<'f>>,
+    {
+        let parsed: Function = serde_json::from_value(function.clone()).unwrap();
+        let exp = parsed
+            .to_expression::<O>()
+            .unwrap_or_else(|err| panic!("{function}: {err}"));
+        let expected: DataExpression = serde_json::from_value(expected).unwrap();
+
+        let features = [
+            json!({ "class": "park", "rank": 2, "flag": true, "color": "#ff0000" }),
+            json!({ "class": "water", "rank": 7, "flag": false, "color": "blue" }),
+            json!({ "class": "road", "rank": "high", "color": "wide" }),
+            json!({}),
+        ];
+        for properties in features {
+            let layer = TestLayer::new(Some(1), GeomType::Point, properties.clone());
+            for zoom in [0.0, 3.0, 5.0, 7.5, 10.0, 14.0] {
+                let view = layer.view(zoom);
+                assert_eq!(
+                    exp.eval(&view),
+                    expected.eval(&view),
+                    "{function} for {properties} at zoom {zoom}"
+                );
+            }
+        }
+    }
+
+    fn error<O>(function: Value) -> String
+    where
+        O: FieldType + for<'f> TryFrom<ExpressionValue<'f>>,
+    {
+        let parsed: Function = serde_json::from_value(function.clone()).unwrap();
+        match parsed.to_expression::<O>() {
+            Ok(exp) => panic!("{function}: expected an error, got {exp:?}"),
+            Err(err) => err,
+        }
+    }
+
+    #[test]
+    fn zoom_functions() {
+        // Interpolated fields default to exponential functions, the rest to interval functions
+        check::<f32>(
+            json!({ "stops": [[0, 1], [10, 21]] }),
+            json!(["interpolate", ["linear"], ["zoom"], 0, 1, 10, 21]),
+        );
+        check::<f32>(
+            json!({ "base": 2, "stops": [[0, 1], [10, 21]] }),
+            json!(["interpolate", ["exponential", 2], ["zoom"], 0, 1, 10, 21]),
+        );
+        check::<BString>(
+            json!({ "stops": [[0, "a"], [5, "b"], [10, "c"]] }),
+            json!(["step", ["zoom"], "a", 5, "b", 10, "c"]),
+        );
+        check::<f32>(
+            json!({ "type": "interval", "stops": [[3, 1], [7, 2]] }),
+            json!(["step", ["zoom"], 1, 7, 2]),
+        );
+        check::<Color>(
+            json!({ "stops": [[0, "red"], [10, "blue"]] }),
+            json!(["interpolate", ["linear"], ["zoom"], 0, "red", 10, "blue"]),
+        );
+    }
+
+    #[test]
+    fn categorical_functions() {
+        check::<f32>(
+            json!({
+                "type": "categorical",
+                "property": "class",
+                "stops": [["park", 1], ["water", 2]],
+                "default": 0
+            }),
+            json!(["match", ["get", "class"], "park", 1, "water", 2, 0]),
+        );
+        // Without a default unmatched features fall back to the field's default
+        check::<f32>(
+            json!({
+                "type": "categorical",
+                "property": "class",
+                "stops": [["park", 1], ["water", 2]]
+            }),
+            json!(["match", ["get", "class"], "park", 1, "water", 2, null]),
+        );
+        // Boolean stops compare with == instead of matching
+        check::<BString>(
+            json!({
+                "type": "categorical",
+                "property": "flag",
+                "stops": [[true, "yes"], [false, "no"]],
+                "default": "unknown"
+            }),
+            json!([
+                "case",
+                ["==", ["get", "flag"], true],
+                "yes",
+                ["==", ["get", "flag"], false],
+                "no",
+                "unknown"
+            ]),
+        );
+    }
+
+    #[test]
+    fn interval_and_exponential_defaults() {
+        check::<f32>(
+            json!({ "type": "interval", "property": "rank", "stops": [[0, 1], [5, 2]] }),
+            json!(["step", ["number", ["get", "rank"]], 1, 5, 2]),
+        );
+        // The default only applies to features without a numeric property
+        check::<f32>(
+            json!({
+                "type": "interval",
+                "property": "rank",
+                "stops": [[0, 1], [5, 2]],
+                "default": 9
+            }),
+            json!([
+                "case",
+                ["==", ["typeof", ["get", "rank"]], "number"],
+                ["step", ["number", ["get", "rank"]], 1, 5, 2],
+                9
+            ]),
+        );
+        check::<f32>(
+            json!({ "property": "rank", "stops": [[0, 0], [10, 100]] }),
+            json!([
+                "interpolate",
+                ["linear"],
+                ["number", ["get", "rank"]],
+                0,
+                0,
+                10,
+                100
+            ]),
+        );
+        check::<f32>(
+            json!({
+                "type": "exponential",
+                "base": 2,
+                "property": "rank",
+                "stops": [[0, 0], [10, 100]],
+                "default": 50
+            }),
+            json!([
+                "case",
+                ["==", ["typeof", ["get", "rank"]], "number"],
+                [
+                    "interpolate",
+                    ["exponential", 2],
+                    ["number", ["get", "rank"]],
+                    0,
+                    0,
+                    10,
+                    100
+                ],
+                50
+            ]),
+        );
+    }
+
+    #[test]
+    fn identity_functions() {
+        check::<f32>(
+            json!({ "type": "identity", "property": "rank" }),
+            json!(["number", ["get", "rank"]]),
+        );
+        check::<f32>(
+            json!({ "type": "identity", "property": "rank", "default": 4 }),
+            json!(["number", ["get", "rank"], 4]),
+        );
+        check::<BString>(
+            json!({ "type": "identity", "property": "class" }),
+            json!(["string", ["get", "class"]]),
+        );
+        check::<Color>(
+            json!({ "type": "identity", "property": "color", "default": "green" }),
+            json!(["to-color", ["get", "color"], "green"]),
+        );
+    }
+
+    #[test]
+    fn zoom_and_property_functions() {
+        // Stops are grouped by zoom into a property function for each zoom level
+        check::<f32>(
+            json!({
+                "property": "rank",
+                "stops": [
+                    [{ "zoom": 0, "value": 0 }, 0],
+                    [{ "zoom": 0, "value": 10 }, 10],
+                    [{ "zoom": 10, "value": 0 }, 100],
+                    [{ "zoom": 10, "value": 10 }, 200]
+                ]
+            }),
+            json!([
+                "interpolate",
+                ["linear"],
+                ["zoom"],
+                0,
+                [
+                    "interpolate",
+                    ["linear"],
+                    ["number", ["get", "rank"]],
+                    0,
+                    0,
+                    10,
+                    10
+                ],
+                10,
+                [
+                    "interpolate",
+                    ["linear"],
+                    ["number", ["get", "rank"]],
+                    0,
+                    100,
+                    10,
+                    200
+                ]
+            ]),
+        );
+        check::<f32>(
+            json!({
+                "type": "categorical",
+                "property": "class",
+                "default": 0,
+                "stops": [
+                    [{ "zoom": 0, "value": "park" }, 1],
+                    [{ "zoom": 0, "value": "water" }, 2],
+                    [{ "zoom": 10, "value": "park" }, 10]
+                ]
+            }),
+            json!([
+                "interpolate",
+                ["linear"],
+                ["zoom"],
+                0,
+                ["match", ["get", "class"], "park", 1, "water", 2, 0],
+                10,
+                ["match", ["get", "class"], "park", 10, 0]
+            ]),
+        );
+        // Fields that don't interpolate step between zoom levels
+        check::<BString>(
+            json!({
+                "type": "categorical",
+                "property": "class",
+                "stops": [
+                    [{ "zoom": 0, "value": "park" }, "a"],
+                    [{ "zoom": 5, "value": "park" }, "b"]
+                ]
+            }),
+            json!([
+                "step",
+                ["zoom"],
+                ["match", ["get", "class"], "park", "a", null],
+                5,
+                ["match", ["get", "class"], "park", "b", null]
+            ]),
+        );
+    }
+
+    #[test]
+    fn zoom_interpolate_needs_an_interpolated_field() {
+        type Dashes = SmallVec<[f32; 8]>;
+        let interpolate = json!(["interpolate", ["linear"], ["zoom"], 0, 1, 10, 4]);
+        let dashes = json!([
+            "interpolate",
+            ["linear"],
+            ["zoom"],
+            0,
+            ["literal", [1, 1]],
+            10,
+            ["literal", [2, 2]]
+        ]);
+        let error = "interpolate expressions cannot be used with this property";
+
+        assert_eq!(<Field<f32>>::check(&interpolate), Ok(()));
+        assert_eq!(
+            <Field<Color>>::check(&json!([
+                "interpolate",
+                ["linear"],
+                ["zoom"],
+                0,
+                "red",
+                10,
+                "blue"
+            ])),
+            Ok(())
+        );
+        assert_eq!(<Field<Dashes>>::check(&dashes), Err(error.to_string()));
+        assert_eq!(
+            <Field<Dashes>>::check(&json!([
+                "step",
+                ["zoom"],
+                ["literal", [1, 1]],
+                10,
+                ["literal", [2, 2]]
+            ])),
+            Ok(())
+        );
+
+        // Legacy zoom functions are converted to the same curves
+        let function = json!({ "stops": [[0, [1, 1]], [10, [2, 2]]] });
+        assert_eq!(<Field<Dashes>>::check(&function), Ok(()));
+        let function = json!({ "type": "exponential", "stops": [[0, [1, 1]], [10, [2, 2]]] });
+        assert_eq!(<Field<Dashes>>::check(&function), Err(error.to_string()));
+
+        // Only the zoom curve is restricted, other curves are just number expressions
+        let nested = json!([
+            "case",
+            [
+                ">",
+                ["interpolate", ["linear"], ["get", "rank"], 0, 0, 10, 1],
+                0.5
+            ],
+            "a",
+            "b"
+        ]);
+        assert_eq!(<Field<BString>>::check(&nested), Ok(()));
+    }
+
+    #[test]
+    fn invalid_functions() {
+        assert_eq!(
+            error::<f32>(json!({ "stops": [[10, 1], [5, 2]] })),
+            "expected stop inputs in ascending order"
+        );
+        assert_eq!(
+            error::<f32>(json!({ "property": "rank", "stops": [[10, 1], [5, 2]] })),
+            "expected stop inputs in ascending order"
+        );
+        assert_eq!(
+            error::<f32>(json!({ "type": "categorical", "stops": [[0, 1], [5, 2]] })),
+            "categorical and identity functions need a property"
+        );
+        assert_eq!(
+            error::<f32>(json!({ "stops": [[0, "wide"]] })),
+            "invalid function output 'wide', expected number"
+        );
+        assert_eq!(
+            error::<f32>(json!({ "stops": [[{ "zoom": 0, "value": 1 }, 1]] })),
+            "expected property for function"
+        );
+        assert_eq!(
+            error::<f32>(json!({ "stops": [] })),
+            "expected stops for function"
+        );
+    }
+
+    #[test]
+    fn bad_layers_and_sources_only_cost_their_layers() {
+        let fill = |id: &str, source: &str, paint: Value| {
+            json!({
+                "id": id,
+                "type": "fill",
+                "source": source,
+                "source-layer": "water",
+                "paint": paint
+            })
+        };
+        let style = json!({
+            "version": 8,
+            "sources": {
+                "tiles": { "type": "vector", "tiles": ["https://example.com/{z}/{x}/{y}.pbf"] },
+                "bad": { "type": "vector", "tiles": ["not a url"] }
+            },
+            "layers": [
+                { "id": "background", "type": "background" },
+                fill("bad-color", "tiles", json!({ "fill-color": "not a color" })),
+                fill("bad-source", "bad", json!({})),
+                fill("missing-source", "missing", json!({})),
+                { "id": "unsupported", "type": "circle", "source": "tiles", "source-layer": "pois" },
+                fill("water", "tiles", json!({ "fill-color": "#0000ff" }))
+            ]
+        });
+
+        let style = Style::load(style.to_string().as_bytes()).unwrap();
+        let ids: Vec<_> = style.layers.iter().map(|layer| layer.id.as_str()).collect();
+        assert_eq!(ids, ["background", "water"]);
+
+        let sources: Vec<_> = style.sources.iter().map(|(name, _)| name).collect();
+        assert_eq!(sources, ["tiles"]);
+        assert!(style.layers[1].source.is_some());
+    }
+
+    #[test]
+    fn format_sections_keep_their_font_scale_and_color() {
+        let style = crate::tests::load_style(json!([{
+            "id": "labels",
+            "type": "symbol",
+            "source": "tiles",
+            "source-layer": "places",
+            "layout": {
+                "text-transform": "uppercase",
+                "text-field": [
+                    "format",
+                    " main ", { "font-scale": 1.5, "text-font": ["literal", ["Noto Sans Bold"]] },
+                    "", {},
+                    ["get", "ref"], { "text-color": ["get", "color"] },
+                    " street ", {}
+                ]
+            }
+        }]));
+        let layer = TestLayer::new(
+            None,
+            GeomType::Point,
+            json!({ "ref": "a1", "color": "#ff0000" }),
+        );
+        let layer = layer.resolve(&style.keys);
+        let view = layer.view(10.0);
+
+        let sections: Vec<_> = style.layers[0]
+            .layout
+            .text(&view)
+            .unwrap()
+            .into_iter()
+            .map(|section| (section.text, section.scale, section.font, section.color))
+            .collect();
+
+        // Empty sections are dropped and only the ends of the whole label are trimmed
+        let red = "#ff0000".parse::<Color>().unwrap();
+        assert_eq!(
+            sections,
+            [
+                (
+                    "MAIN ".into(),
+                    1.5,
+                    Some(vec!["Noto Sans Bold".to_string()]),
+                    None
+                ),
+                ("A1".into(), 1.0, None, Some(red)),
+                (" STREET".into(), 1.0, None, None),
+            ]
+        );
     }
 }
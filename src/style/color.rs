@@ -1,6 +1,6 @@
 use std::str::FromStr;
 
-use super::Interpolate;
+use super::{ColorSpace, Interpolate};
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Color {
@@ -40,187 +40,303 @@ impl FromStr for Color {
     type Err = &'static str;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let to_b = |c| {
-            if c >= b'0' && c <= b'9' {
-                (c - b'0') as u8
-            } else if c >= b'a' && c <= b'f' {
-                ((c - b'a') as u8) + 10
-            } else if c >= b'A' && c <= b'F' {
-                ((c - b'A') as u8) + 10
-            } else {
-                0
-            }
-        };
+        let s = s.trim();
 
-        let color = if s.starts_with('#') && s.len() == 4 {
-            let mut c = s.bytes();
-            let _ = c.next();
-            let r = c.next().map(to_b).unwrap_or_default() << 4;
-            let g = c.next().map(to_b).unwrap_or_default() << 4;
-            let b = c.next().map(to_b).unwrap_or_default() << 4;
+        if let Some(hex) = s.strip_prefix('#') {
+            return parse_hex(hex);
+        }
 
-            Color::Rgba(Rgba {
-                r: r as f32 / 0xff as f32,
-                g: g as f32 / 0xff as f32,
-                b: b as f32 / 0xff as f32,
-                a: 1.0,
-            })
-        } else if s.starts_with('#') && s.len() == 7 {
-            let mut c = s.bytes();
-            let _ = c.next();
-            let r = c.next().map(to_b).unwrap_or_default() << 4;
-            let rr = c.next().map(to_b).unwrap_or_default();
-            let g = c.next().map(to_b).unwrap_or_default() << 4;
-            let gg = c.next().map(to_b).unwrap_or_default();
-            let b = c.next().map(to_b).unwrap_or_default() << 4;
-            let bb = c.next().map(to_b).unwrap_or_default();
-
-            let r = r | rr;
-            let g = g | gg;
-            let b = b | bb;
+        let s = s.to_ascii_lowercase();
+        if let Some((name, args)) = s.split_once('(') {
+            let args = args
+                .strip_suffix(')')
+                .ok_or("missing ')' in color function")?;
+            return parse_function(name, args);
+        }
 
-            Color::Rgba(Rgba {
-                r: r as f32 / 0xff as f32,
-                g: g as f32 / 0xff as f32,
-                b: b as f32 / 0xff as f32,
-                a: 1.0,
-            })
-        } else if s.starts_with("rgba(") {
-            let (_, n) = s.split_once("rgba(").ok_or("invalid rgba color")?;
-            let (n, _) = n.split_once(")").ok_or("invalid rgba color")?;
-
-            let mut parts = n.split(',');
-            let r = parts
-                .next()
-                .map(|p| str_to_f32(p.bytes()))
-                .unwrap_or_default();
-            let g = parts
-                .next()
-                .map(|p| str_to_f32(p.bytes()))
-                .unwrap_or_default();
-            let b = parts
-                .next()
-                .map(|p| str_to_f32(p.bytes()))
-                .unwrap_or_default();
-            let a = parts
-                .next()
-                .map(|p| str_to_f32(p.bytes()))
-                .unwrap_or_default();
+        if s == "transparent" {
+            return Ok(Color::Rgba(Rgba {
+                r: 0.0,
+                g: 0.0,
+                b: 0.0,
+                a: 0.0,
+            }));
+        }
 
-            Color::Rgba(Rgba {
-                r: r / 255.0,
-                g: g / 255.0,
-                b: b / 255.0,
-                a,
-            })
-        } else if s.starts_with("rgb(") {
-            let (_, n) = s.split_once("rgb(").ok_or("invalid rgb color")?;
-            let (n, _) = n.split_once(")").ok_or("invalid rgb color")?;
-
-            let mut parts = n.split(',');
-            let r = parts
-                .next()
-                .map(|p| str_to_f32(p.bytes()))
-                .unwrap_or_default();
-            let g = parts
-                .next()
-                .map(|p| str_to_f32(p.bytes()))
-                .unwrap_or_default();
-            let b = parts
-                .next()
-                .map(|p| str_to_f32(p.bytes()))
-                .unwrap_or_default();
+        let index = NAMED_COLORS
+            .binary_search_by_key(&s.as_str(), |(name, _)| name)
+            .map_err(|_| "unknown color name")?;
 
-            Color::Rgba(Rgba {
-                r: r / 255.0,
-                g: g / 255.0,
-                b: b / 255.0,
-                a: 1.0,
-            })
-        } else if s.starts_with("hsla(") {
-            let (_, n) = s.split_once("hsla(").ok_or("invalid hsla color")?;
-            let (n, _) = n.split_once(")").ok_or("invalid hsla color")?;
-
-            let mut parts = n.split(',');
-            let h = parts
-                .next()
-                .map(|p| str_to_f32(p.bytes()))
-                .unwrap_or_default();
-            let s = parts
-                .next()
-                .map(|p| str_to_f32(p.bytes()))
-                .unwrap_or_default();
-            let l = parts
-                .next()
-                .map(|p| str_to_f32(p.bytes()))
-                .unwrap_or_default();
-            let a = parts
-                .next()
-                .map(|p| str_to_f32(p.bytes()))
-                .unwrap_or_default();
-
-            Color::Hsla(Hsla {
-                h: h / 360.0,
-                s: s / 100.0,
-                l: l / 100.0,
-                a,
-            })
-        } else if s.starts_with("hsl(") {
-            let (_, n) = s.split_once("hsl(").ok_or("invalid hsl color")?;
-            let (n, _) = n.split_once(")").ok_or("invalid hsl color")?;
-
-            let mut parts = n.split(',');
-            let h = parts
-                .next()
-                .map(|p| str_to_f32(p.bytes()))
-                .unwrap_or_default();
-            let s = parts
-                .next()
-                .map(|p| str_to_f32(p.bytes()))
-                .unwrap_or_default();
-            let l = parts
-                .next()
-                .map(|p| str_to_f32(p.bytes()))
-                .unwrap_or_default();
-
-            Color::Hsla(Hsla {
-                h: h / 360.0,
-                s: s / 100.0,
-                l: l / 100.0,
-                a: 1.0,
-            })
-        } else {
-            return Err("invalid color");
-        };
+        Ok(rgb_from_u32(NAMED_COLORS[index].1, 1.0))
+    }
+}
 
-        Ok(color)
+fn rgb_from_u32(rgb: u32, a: f32) -> Color {
+    Color::Rgba(Rgba {
+        r: ((rgb >> 16) & 0xff) as f32 / 255.0,
+        g: ((rgb >> 8) & 0xff) as f32 / 255.0,
+        b: (rgb & 0xff) as f32 / 255.0,
+        a,
+    })
+}
+
+/// Parses `rgb`, `rgba`, `rrggbb` and `rrggbbaa` hex notation, without the leading `#`.
+fn parse_hex(hex: &str) -> Result<Color, &'static str> {
+    if ![3, 4, 6, 8].contains(&hex.len()) {
+        return Err("hex color must have 3, 4, 6 or 8 digits");
+    }
+    if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err("invalid hex color");
     }
+
+    let n = u32::from_str_radix(hex, 16).map_err(|_| "invalid hex color")?;
+    // Short notation repeats every digit, so 0xf becomes 0xff
+    let nibble = |shift: u32| ((n >> shift) & 0xf) * 0x11;
+
+    let color = match hex.len() {
+        3 => rgb_from_u32(nibble(8) << 16 | nibble(4) << 8 | nibble(0), 1.0),
+        4 => rgb_from_u32(
+            nibble(12) << 16 | nibble(8) << 8 | nibble(4),
+            nibble(0) as f32 / 255.0,
+        ),
+        6 => rgb_from_u32(n, 1.0),
+        8 => rgb_from_u32(n >> 8, (n & 0xff) as f32 / 255.0),
+        _ => unreachable!("hex color length was checked"),
+    };
+
+    Ok(color)
 }
 
-fn str_to_f32<I: IntoIterator<Item = u8>>(iter: I) -> f32 {
-    let mut whole = 0.0;
-    let mut frac = None;
-    let mut frac_scale = 1.0;
-
-    for b in iter {
-        let n = if b >= b'0' && b <= b'9' {
-            (b - b'0') as f32
-        } else if b == b'.' {
-            frac = Some(0.0);
-            continue;
-        } else {
-            continue;
+#[derive(Debug, Copy, Clone)]
+enum Component {
+    Number(f32),
+    Percentage(f32),
+    /// Angle in degrees
+    Angle(f32),
+    None,
+}
+
+impl Component {
+    fn parse(s: &str) -> Result<Self, &'static str> {
+        if s == "none" {
+            return Ok(Component::None);
+        }
+
+        let number = |n: &str| match n.parse::<f32>() {
+            Ok(n) if n.is_finite() => Ok(n),
+            _ => Err("invalid number in color"),
         };
 
-        if let Some(frac) = frac.as_mut() {
-            frac_scale *= 0.1;
-            *frac += frac_scale * n;
+        if let Some(n) = s.strip_suffix('%') {
+            Ok(Component::Percentage(number(n)?))
+        } else if let Some(n) = s.strip_suffix("deg") {
+            Ok(Component::Angle(number(n)?))
+        } else if let Some(n) = s.strip_suffix("grad") {
+            Ok(Component::Angle(number(n)? * 0.9))
+        } else if let Some(n) = s.strip_suffix("rad") {
+            Ok(Component::Angle(number(n)?.to_degrees()))
+        } else if let Some(n) = s.strip_suffix("turn") {
+            Ok(Component::Angle(number(n)? * 360.0))
         } else {
-            whole = (whole * 10.0) + n;
+            Ok(Component::Number(number(s)?))
+        }
+    }
+
+    /// Resolves a number or percentage, `100%` being equal to `full`.
+    fn value(self, full: f32) -> Result<f32, &'static str> {
+        match self {
+            Component::Number(n) => Ok(n),
+            Component::Percentage(p) => Ok(p / 100.0 * full),
+            Component::None => Ok(0.0),
+            Component::Angle(_) => Err("unexpected angle in color"),
         }
     }
 
-    whole + frac.unwrap_or(0.0)
+    /// Resolves a hue to degrees in `0..360`.
+    fn hue(self) -> Result<f32, &'static str> {
+        match self {
+            Component::Number(h) | Component::Angle(h) => Ok(h.rem_euclid(360.0)),
+            Component::None => Ok(0.0),
+            Component::Percentage(_) => Err("hue must be a number or an angle"),
+        }
+    }
+}
+
+/// Splits the arguments of a color function into three channels and an optional alpha,
+/// accepting both the legacy comma separated and the space separated syntax.
+fn parse_arguments(args: &str) -> Result<([Component; 3], Option<Component>), &'static str> {
+    let (channels, alpha): (Vec<&str>, Option<&str>) = if args.contains(',') {
+        let mut parts: Vec<&str> = args.split(',').map(str::trim).collect();
+        if parts.len() != 3 && parts.len() != 4 {
+            return Err("expected 3 or 4 comma separated values in color function");
+        }
+        let alpha = if parts.len() == 4 { parts.pop() } else { None };
+        (parts, alpha)
+    } else {
+        let (channels, alpha) = match args.split_once('/') {
+            Some((channels, alpha)) => (channels, Some(alpha.trim())),
+            None => (args, None),
+        };
+        let channels: Vec<&str> = channels.split_whitespace().collect();
+        if channels.len() != 3 {
+            return Err("expected 3 space separated values in color function");
+        }
+        (channels, alpha)
+    };
+
+    let alpha = alpha.map(Component::parse).transpose()?;
+
+    Ok((
+        [
+            Component::parse(channels[0])?,
+            Component::parse(channels[1])?,
+            Component::parse(channels[2])?,
+        ],
+        alpha,
+    ))
+}
+
+fn parse_function(name: &str, args: &str) -> Result<Color, &'static str> {
+    const FUNCTIONS: &[&str] = &[
+        "rgb", "rgba", "hsl", "hsla", "hwb", "lab", "lch", "oklab", "oklch",
+    ];
+    if !FUNCTIONS.contains(&name) {
+        return Err("unsupported color function");
+    }
+
+    let ([c0, c1, c2], alpha) = parse_arguments(args)?;
+    let a = match alpha {
+        Some(alpha) => alpha.value(1.0)?.clamp(0.0, 1.0),
+        None => 1.0,
+    };
+
+    let color = match name {
+        "rgb" | "rgba" => {
+            let channel =
+                |c: Component| Ok::<_, &'static str>((c.value(255.0)? / 255.0).clamp(0.0, 1.0));
+            Color::Rgba(Rgba {
+                r: channel(c0)?,
+                g: channel(c1)?,
+                b: channel(c2)?,
+                a,
+            })
+        }
+        "hsl" | "hsla" => Color::Hsla(Hsla {
+            h: c0.hue()? / 360.0,
+            s: (c1.value(100.0)? / 100.0).clamp(0.0, 1.0),
+            l: (c2.value(100.0)? / 100.0).clamp(0.0, 1.0),
+            a,
+        }),
+        "hwb" => {
+            let white = (c1.value(100.0)? / 100.0).clamp(0.0, 1.0);
+            let black = (c2.value(100.0)? / 100.0).clamp(0.0, 1.0);
+
+            let rgba = if white + black >= 1.0 {
+                let gray = white / (white + black);
+                Rgba {
+                    r: gray,
+                    g: gray,
+                    b: gray,
+                    a,
+                }
+            } else {
+                let hue = Hsla {
+                    h: c0.hue()? / 360.0,
+                    s: 1.0,
+                    l: 0.5,
+                    a,
+                }
+                .to_rgba();
+                let scale = |c: f32| c * (1.0 - white - black) + white;
+                Rgba {
+                    r: scale(hue.r),
+                    g: scale(hue.g),
+                    b: scale(hue.b),
+                    a,
+                }
+            };
+
+            Color::Rgba(rgba)
+        }
+        "lab" => Color::Rgba(
+            Lab {
+                l: c0.value(100.0)?.clamp(0.0, 100.0),
+                a: c1.value(125.0)?,
+                b: c2.value(125.0)?,
+                alpha: a,
+            }
+            .into(),
+        ),
+        "lch" => {
+            let (a_, b_) = polar(c1.value(150.0)?.max(0.0), c2.hue()?);
+            Color::Rgba(
+                Lab {
+                    l: c0.value(100.0)?.clamp(0.0, 100.0),
+                    a: a_,
+                    b: b_,
+                    alpha: a,
+                }
+                .into(),
+            )
+        }
+        "oklab" => Color::Rgba(oklab_to_rgba(
+            c0.value(1.0)?.clamp(0.0, 1.0),
+            c1.value(0.4)?,
+            c2.value(0.4)?,
+            a,
+        )),
+        "oklch" => {
+            let (a_, b_) = polar(c1.value(0.4)?.max(0.0), c2.hue()?);
+            Color::Rgba(oklab_to_rgba(c0.value(1.0)?.clamp(0.0, 1.0), a_, b_, a))
+        }
+        _ => return Err("unsupported color function"),
+    };
+
+    Ok(color)
+}
+
+/// Converts chroma and hue in degrees to the `a` and `b` axes.
+fn polar(chroma: f32, hue: f32) -> (f32, f32) {
+    let (sin, cos) = hue.to_radians().sin_cos();
+    (chroma * cos, chroma * sin)
+}
+
+fn oklab_to_rgba(l: f32, a: f32, b: f32, alpha: f32) -> Rgba {
+    let l_ = l + 0.39633778 * a + 0.21580376 * b;
+    let m_ = l - 0.10556135 * a - 0.06385417 * b;
+    let s_ = l - 0.08948418 * a - 1.2914855 * b;
+
+    let (l, m, s) = (l_ * l_ * l_, m_ * m_ * m_, s_ * s_ * s_);
+
+    let r = 4.0767417 * l - 3.3077116 * m + 0.23096993 * s;
+    let g = -1.268438 * l + 2.6097574 * m - 0.3413194 * s;
+    let b = -0.004196086 * l - 0.7034186 * m + 1.7076147 * s;
+
+    Rgba {
+        r: linear_to_srgb(r),
+        g: linear_to_srgb(g),
+        b: linear_to_srgb(b),
+        a: alpha,
+    }
+}
+
+/// Inverts the sRGB transfer function.
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Applies the sRGB transfer function, clamping colors outside of the sRGB gamut.
+pub fn linear_to_srgb(c: f32) -> f32 {
+    let c = c.clamp(0.0, 1.0);
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
 }
 
 impl<'de> serde::Deserialize<'de> for Color {
@@ -231,7 +347,7 @@ impl<'de> serde::Deserialize<'de> for Color {
         use serde::de::Error;
 
         let s = String::deserialize(deserializer)?;
-        Color::from_str(&s).map_err(|e| D::Error::custom(e))
+        Color::from_str(&s).map_err(|e| D::Error::custom(format!("{e} '{s}'")))
     }
 }
 
@@ -259,6 +375,19 @@ impl Interpolate for Color {
             }
         }
     }
+
+    fn interpolate_in(&self, space: ColorSpace, factor: f32, other: Self) -> Self {
+        let (last, next) = (self.to_rgba(), other.to_rgba());
+        match space {
+            ColorSpace::Rgb => self.interpolate(factor, other),
+            ColorSpace::Lab => {
+                Color::Rgba(Lab::from(last).interpolate(factor, Lab::from(next)).into())
+            }
+            ColorSpace::Hcl => {
+                Color::Rgba(Hcl::from(last).interpolate(factor, Hcl::from(next)).into())
+            }
+        }
+    }
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -279,6 +408,168 @@ impl Interpolate for Rgba {
         }
     }
 }
+/// CIE Lab relative to the D50 white point, as used by CSS and by MapLibre when interpolating
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Lab {
+    l: f32,
+    a: f32,
+    b: f32,
+    alpha: f32,
+}
+
+impl Lab {
+    const WHITE: [f32; 3] = [0.96422, 1.0, 0.82521];
+    const KAPPA: f32 = 24389.0 / 27.0;
+    const EPSILON: f32 = 216.0 / 24389.0;
+}
+
+impl From<Rgba> for Lab {
+    fn from(c: Rgba) -> Self {
+        let f = |t: f32| {
+            if t > Lab::EPSILON {
+                t.cbrt()
+            } else {
+                (Lab::KAPPA * t + 16.0) / 116.0
+            }
+        };
+
+        let (r, g, b) = (
+            srgb_to_linear(c.r),
+            srgb_to_linear(c.g),
+            srgb_to_linear(c.b),
+        );
+
+        // Linear sRGB to XYZ (D50), including the Bradford adaptation from D65
+        let [xn, yn, zn] = Lab::WHITE;
+        let y = f((0.2224932 * r + 0.716887 * g + 0.0606198 * b) / yn);
+        // Grays are exactly neutral, without the rounding error of the matrix
+        let (x, z) = if r == g && g == b {
+            (y, y)
+        } else {
+            (
+                f((0.4360657 * r + 0.3851515 * g + 0.1430784 * b) / xn),
+                f((0.0139239 * r + 0.0970813 * g + 0.7140994 * b) / zn),
+            )
+        };
+
+        Lab {
+            l: 116.0 * y - 16.0,
+            a: 500.0 * (x - y),
+            b: 200.0 * (y - z),
+            alpha: c.a,
+        }
+    }
+}
+
+impl From<Lab> for Rgba {
+    fn from(c: Lab) -> Self {
+        let inverse = |f: f32| {
+            let f3 = f * f * f;
+            if f3 > Lab::EPSILON {
+                f3
+            } else {
+                (116.0 * f - 16.0) / Lab::KAPPA
+            }
+        };
+
+        let fy = (c.l + 16.0) / 116.0;
+        let [xn, yn, zn] = Lab::WHITE;
+        let x = inverse(fy + c.a / 500.0) * xn;
+        let y = if c.l > Lab::KAPPA * Lab::EPSILON {
+            fy * fy * fy
+        } else {
+            c.l / Lab::KAPPA
+        } * yn;
+        let z = inverse(fy - c.b / 200.0) * zn;
+
+        // XYZ (D50) to linear sRGB, including the Bradford adaptation to D65
+        Rgba {
+            r: linear_to_srgb(3.134136 * x - 1.6173864 * y - 0.4906619 * z),
+            g: linear_to_srgb(-0.9787955 * x + 1.9162546 * y + 0.0334427 * z),
+            b: linear_to_srgb(0.0719554 * x - 0.2289768 * y + 1.4053861 * z),
+            a: c.alpha,
+        }
+    }
+}
+
+impl Interpolate for Lab {
+    fn interpolate(&self, factor: f32, other: Self) -> Self {
+        Lab {
+            l: self.l.interpolate(factor, other.l),
+            a: self.a.interpolate(factor, other.a),
+            b: self.b.interpolate(factor, other.b),
+            alpha: self.alpha.interpolate(factor, other.alpha),
+        }
+    }
+}
+
+/// The polar form of [`Lab`], with the hue in degrees. Grays have no hue.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Hcl {
+    h: Option<f32>,
+    c: f32,
+    l: f32,
+    alpha: f32,
+}
+
+impl From<Rgba> for Hcl {
+    fn from(c: Rgba) -> Self {
+        let lab = Lab::from(c);
+        let chroma = lab.a.hypot(lab.b);
+        let h = ((chroma * 10000.0).round() != 0.0)
+            .then(|| lab.b.atan2(lab.a).to_degrees().rem_euclid(360.0));
+
+        Hcl {
+            h,
+            c: chroma,
+            l: lab.l,
+            alpha: lab.alpha,
+        }
+    }
+}
+
+impl From<Hcl> for Rgba {
+    fn from(c: Hcl) -> Self {
+        let (a, b) = polar(c.c, c.h.unwrap_or(0.0));
+        Lab {
+            l: c.l,
+            a,
+            b,
+            alpha: c.alpha,
+        }
+        .into()
+    }
+}
+
+impl Interpolate for Hcl {
+    fn interpolate(&self, factor: f32, other: Self) -> Self {
+        // A gray takes the hue of the color it's blended with, and hues take the short way around
+        let h = match (self.h, other.h) {
+            (Some(from), Some(to)) => {
+                let delta = to - from;
+                let delta = delta - 360.0 * (delta / 360.0).round();
+                Some((from + factor * delta).rem_euclid(360.0))
+            }
+            (from, to) => from.or(to),
+        };
+
+        // Like MapLibre, blending a color with black keeps the color's chroma
+        let black = |c: &Hcl| c.h.is_none() && c.l < 1e-3;
+        let c = match (self.h, other.h) {
+            (Some(_), None) if black(&other) => self.c,
+            (None, Some(_)) if black(self) => other.c,
+            _ => self.c.interpolate(factor, other.c),
+        };
+
+        Hcl {
+            h,
+            c,
+            l: self.l.interpolate(factor, other.l),
+            alpha: self.alpha.interpolate(factor, other.alpha),
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Hsla {
     h: f32,
@@ -328,3 +619,333 @@ impl Interpolate for Hsla {
         }
     }
 }
+
+/// The CSS named colors, sorted by name.
+const NAMED_COLORS: &[(&str, u32)] = &[
+    ("aliceblue", 0xf0f8ff),
+    ("antiquewhite", 0xfaebd7),
+    ("aqua", 0x00ffff),
+    ("aquamarine", 0x7fffd4),
+    ("azure", 0xf0ffff),
+    ("beige", 0xf5f5dc),
+    ("bisque", 0xffe4c4),
+    ("black", 0x000000),
+    ("blanchedalmond", 0xffebcd),
+    ("blue", 0x0000ff),
+    ("blueviolet", 0x8a2be2),
+    ("brown", 0xa52a2a),
+    ("burlywood", 0xdeb887),
+    ("cadetblue", 0x5f9ea0),
+    ("chartreuse", 0x7fff00),
+    ("chocolate", 0xd2691e),
+    ("coral", 0xff7f50),
+    ("cornflowerblue", 0x6495ed),
+    ("cornsilk", 0xfff8dc),
+    ("crimson", 0xdc143c),
+    ("cyan", 0x00ffff),
+    ("darkblue", 0x00008b),
+    ("darkcyan", 0x008b8b),
+    ("darkgoldenrod", 0xb8860b),
+    ("darkgray", 0xa9a9a9),
+    ("darkgreen", 0x006400),
+    ("darkgrey", 0xa9a9a9),
+    ("darkkhaki", 0xbdb76b),
+    ("darkmagenta", 0x8b008b),
+    ("darkolivegreen", 0x556b2f),
+    ("darkorange", 0xff8c00),
+    ("darkorchid", 0x9932cc),
+    ("darkred", 0x8b0000),
+    ("darksalmon", 0xe9967a),
+    ("darkseagreen", 0x8fbc8f),
+    ("darkslateblue", 0x483d8b),
+    ("darkslategray", 0x2f4f4f),
+    ("darkslategrey", 0x2f4f4f),
+    ("darkturquoise", 0x00ced1),
+    ("darkviolet", 0x9400d3),
+    ("deeppink", 0xff1493),
+    ("deepskyblue", 0x00bfff),
+    ("dimgray", 0x696969),
+    ("dimgrey", 0x696969),
+    ("dodgerblue", 0x1e90ff),
+    ("firebrick", 0xb22222),
+    ("floralwhite", 0xfffaf0),
+    ("forestgreen", 0x228b22),
+    ("fuchsia", 0xff00ff),
+    ("gainsboro", 0xdcdcdc),
+    ("ghostwhite", 0xf8f8ff),
+    ("gold", 0xffd700),
+    ("goldenrod", 0xdaa520),
+    ("gray", 0x808080),
+    ("green", 0x008000),
+    ("greenyellow", 0xadff2f),
+    ("grey", 0x808080),
+    ("honeydew", 0xf0fff0),
+    ("hotpink", 0xff69b4),
+    ("indianred", 0xcd5c5c),
+    ("indigo", 0x4b0082),
+    ("ivory", 0xfffff0),
+    ("khaki", 0xf0e68c),
+    ("lavender", 0xe6e6fa),
+    ("lavenderblush", 0xfff0f5),
+    ("lawngreen", 0x7cfc00),
+    ("lemonchiffon", 0xfffacd),
+    ("lightblue", 0xadd8e6),
+    ("lightcoral", 0xf08080),
+    ("lightcyan", 0xe0ffff),
+    ("lightgoldenrodyellow", 0xfafad2),
+    ("lightgray", 0xd3d3d3),
+    ("lightgreen", 0x90ee90),
+    ("lightgrey", 0xd3d3d3),
+    ("lightpink", 0xffb6c1),
+    ("lightsalmon", 0xffa07a),
+    ("lightseagreen", 0x20b2aa),
+    ("lightskyblue", 0x87cefa),
+    ("lightslategray", 0x778899),
+    ("lightslategrey", 0x778899),
+    ("lightsteelblue", 0xb0c4de),
+    ("lightyellow", 0xffffe0),
+    ("lime", 0x00ff00),
+    ("limegreen", 0x32cd32),
+    ("linen", 0xfaf0e6),
+    ("magenta", 0xff00ff),
+    ("maroon", 0x800000),
+    ("mediumaquamarine", 0x66cdaa),
+    ("mediumblue", 0x0000cd),
+    ("mediumorchid", 0xba55d3),
+    ("mediumpurple", 0x9370db),
+    ("mediumseagreen", 0x3cb371),
+    ("mediumslateblue", 0x7b68ee),
+    ("mediumspringgreen", 0x00fa9a),
+    ("mediumturquoise", 0x48d1cc),
+    ("mediumvioletred", 0xc71585),
+    ("midnightblue", 0x191970),
+    ("mintcream", 0xf5fffa),
+    ("mistyrose", 0xffe4e1),
+    ("moccasin", 0xffe4b5),
+    ("navajowhite", 0xffdead),
+    ("navy", 0x000080),
+    ("oldlace", 0xfdf5e6),
+    ("olive", 0x808000),
+    ("olivedrab", 0x6b8e23),
+    ("orange", 0xffa500),
+    ("orangered", 0xff4500),
+    ("orchid", 0xda70d6),
+    ("palegoldenrod", 0xeee8aa),
+    ("palegreen", 0x98fb98),
+    ("paleturquoise", 0xafeeee),
+    ("palevioletred", 0xdb7093),
+    ("papayawhip", 0xffefd5),
+    ("peachpuff", 0xffdab9),
+    ("peru", 0xcd853f),
+    ("pink", 0xffc0cb),
+    ("plum", 0xdda0dd),
+    ("powderblue", 0xb0e0e6),
+    ("purple", 0x800080),
+    ("rebeccapurple", 0x663399),
+    ("red", 0xff0000),
+    ("rosybrown", 0xbc8f8f),
+    ("royalblue", 0x4169e1),
+    ("saddlebrown", 0x8b4513),
+    ("salmon", 0xfa8072),
+    ("sandybrown", 0xf4a460),
+    ("seagreen", 0x2e8b57),
+    ("seashell", 0xfff5ee),
+    ("sienna", 0xa0522d),
+    ("silver", 0xc0c0c0),
+    ("skyblue", 0x87ceeb),
+    ("slateblue", 0x6a5acd),
+    ("slategray", 0x708090),
+    ("slategrey", 0x708090),
+    ("snow", 0xfffafa),
+    ("springgreen", 0x00ff7f),
+    ("steelblue", 0x4682b4),
+    ("tan", 0xd2b48c),
+    ("teal", 0x008080),
+    ("thistle", 0xd8bfd8),
+    ("tomato", 0xff6347),
+    ("turquoise", 0x40e0d0),
+    ("violet", 0xee82ee),
+    ("wheat", 0xf5deb3),
+    ("white", 0xffffff),
+    ("whitesmoke", 0xf5f5f5),
+    ("yellow", 0xffff00),
+    ("yellowgreen", 0x9acd32),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses a color into 8 bit channels, with the alpha in hundredths
+    fn parse(s: &str) -> Result<[u8; 4], &'static str> {
+        let c = Color::from_str(s)?.to_rgba();
+        let channel = |c: f32| (c * 255.0).round() as u8;
+        Ok([
+            channel(c.r),
+            channel(c.g),
+            channel(c.b),
+            (c.a * 100.0).round() as u8,
+        ])
+    }
+
+    #[test]
+    fn named_colors() {
+        assert_eq!(NAMED_COLORS.len(), 148);
+        assert!(NAMED_COLORS.windows(2).all(|w| w[0].0 < w[1].0));
+
+        for (name, rgb) in NAMED_COLORS {
+            let hex = format!("#{rgb:06x}");
+            assert_eq!(parse(name), parse(&hex), "{name}");
+            assert_eq!(parse(&name.to_uppercase()), parse(&hex), "{name}");
+        }
+
+        assert_eq!(parse("rebeccapurple"), Ok([102, 51, 153, 100]));
+        assert_eq!(parse("transparent"), Ok([0, 0, 0, 0]));
+        assert_eq!(parse(" Transparent "), Ok([0, 0, 0, 0]));
+    }
+
+    #[test]
+    fn hex_colors() {
+        for (s, expected) in [
+            ("#f80", [255, 136, 0, 100]),
+            ("#F80", [255, 136, 0, 100]),
+            ("#f808", [255, 136, 0, 53]),
+            ("#ff8800", [255, 136, 0, 100]),
+            ("#ff880080", [255, 136, 0, 50]),
+            ("#00000000", [0, 0, 0, 0]),
+        ] {
+            assert_eq!(parse(s), Ok(expected), "{s}");
+        }
+    }
+
+    #[test]
+    fn color_functions() {
+        for (s, expected) in [
+            ("rgb(255, 136, 0)", [255, 136, 0, 100]),
+            ("rgba(255, 136, 0, 0.5)", [255, 136, 0, 50]),
+            ("rgb(255 136 0)", [255, 136, 0, 100]),
+            ("rgb(255 136 0 / 0.25)", [255, 136, 0, 25]),
+            ("rgb(255 136 0 / 25%)", [255, 136, 0, 25]),
+            ("rgb(100% 50% 0%)", [255, 128, 0, 100]),
+            ("rgba(100%, 50%, 0%, 50%)", [255, 128, 0, 50]),
+            ("rgb(300 -20 none)", [255, 0, 0, 100]),
+            ("RGB(255, 136, 0)", [255, 136, 0, 100]),
+            ("hsl(120, 100%, 25%)", [0, 128, 0, 100]),
+            ("hsla(240, 100%, 50%, 0.5)", [0, 0, 255, 50]),
+            ("hsl(120deg 100% 25% / 50%)", [0, 128, 0, 50]),
+            ("hsl(0.5turn 100% 50%)", [0, 255, 255, 100]),
+            ("hsl(-120 100% 50%)", [0, 0, 255, 100]),
+            ("hwb(120 0% 50%)", [0, 128, 0, 100]),
+            ("hwb(0 20% 20%)", [204, 51, 51, 100]),
+            ("hwb(0 60% 60%)", [128, 128, 128, 100]),
+            // The CSS Color 4 examples of the same color in each space
+            ("lab(29.2345% 39.3825 20.0664)", [125, 35, 41, 100]),
+            ("lch(29.2345% 44.2 27)", [125, 35, 41, 100]),
+            ("oklab(40.101% 0.1147 0.0453)", [125, 35, 41, 100]),
+            ("oklch(40.101% 0.12332 21.555)", [125, 35, 41, 100]),
+            ("lab(100 0 0)", [255, 255, 255, 100]),
+            ("lch(0% 0 0 / 0.5)", [0, 0, 0, 50]),
+            ("oklab(1 0 0)", [255, 255, 255, 100]),
+            ("oklch(0.628 0.2577 29.23)", [255, 0, 0, 100]),
+        ] {
+            assert_eq!(parse(s), Ok(expected), "{s}");
+        }
+    }
+
+    #[test]
+    fn interpolation_ramps() {
+        // Expected values follow MapLibre's interpolate-lab and interpolate-hcl
+        for (space, from, to, expected) in [
+            (
+                ColorSpace::Lab,
+                "red",
+                "blue",
+                [[227, 0, 79, 100], [193, 0, 136, 100], [145, 0, 194, 100]],
+            ),
+            (
+                ColorSpace::Lab,
+                "white",
+                "black",
+                [
+                    [185, 185, 185, 100],
+                    [119, 119, 119, 100],
+                    [59, 59, 59, 100],
+                ],
+            ),
+            (
+                ColorSpace::Hcl,
+                "red",
+                "blue",
+                [[255, 0, 72, 100], [245, 0, 134, 100], [187, 0, 200, 100]],
+            ),
+            (
+                ColorSpace::Hcl,
+                "yellow",
+                "blue",
+                [[255, 156, 0, 100], [255, 0, 94, 100], [251, 0, 181, 100]],
+            ),
+            (
+                ColorSpace::Hcl,
+                "red",
+                "gray",
+                [[229, 71, 41, 100], [200, 97, 71, 100], [168, 115, 99, 100]],
+            ),
+            (
+                ColorSpace::Hcl,
+                "red",
+                "black",
+                [[210, 0, 0, 100], [166, 0, 0, 100], [125, 0, 0, 100]],
+            ),
+        ] {
+            let (from_color, to_color) = (Color::from_str(from), Color::from_str(to));
+            let (from_color, to_color) = (from_color.unwrap(), to_color.unwrap());
+            for (factor, expected) in [0.25, 0.5, 0.75].into_iter().zip(expected) {
+                let c = from_color.interpolate_in(space, factor, to_color).to_rgba();
+                let actual = [c.r * 255.0, c.g * 255.0, c.b * 255.0, c.a * 100.0];
+                let close = actual
+                    .iter()
+                    .zip(expected)
+                    .all(|(actual, expected)| (actual - expected as f32).abs() <= 1.0);
+                assert!(
+                    close,
+                    "{space:?} {from} {to} {factor}: {actual:?} {expected:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_colors() {
+        for (s, error) in [
+            ("", "unknown color name"),
+            ("reddish", "unknown color name"),
+            ("#", "hex color must have 3, 4, 6 or 8 digits"),
+            ("#12345", "hex color must have 3, 4, 6 or 8 digits"),
+            ("#123456789", "hex color must have 3, 4, 6 or 8 digits"),
+            ("#ggg", "invalid hex color"),
+            ("#-12", "invalid hex color"),
+            ("rgb(1, 2, 3", "missing ')' in color function"),
+            ("cmyk(1, 2, 3)", "unsupported color function"),
+            (
+                "rgb(1, 2)",
+                "expected 3 or 4 comma separated values in color function",
+            ),
+            (
+                "rgb(1, 2, 3, 4, 5)",
+                "expected 3 or 4 comma separated values in color function",
+            ),
+            (
+                "rgb(1 2 / 0.5)",
+                "expected 3 space separated values in color function",
+            ),
+            ("rgb(a, 2, 3)", "invalid number in color"),
+            ("rgb(1 2 3 / x)", "invalid number in color"),
+            ("rgb(inf, 2, 3)", "invalid number in color"),
+            ("rgb(1deg, 2, 3)", "unexpected angle in color"),
+            ("hsl(10%, 50%, 50%)", "hue must be a number or an angle"),
+            ("lch(50% 10 10%)", "hue must be a number or an angle"),
+        ] {
+            assert_eq!(parse(s), Err(error), "{s}");
+        }
+    }
+}
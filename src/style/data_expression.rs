@@ -1,14 +1,26 @@
+use std::collections::BTreeMap;
+
 use bstr::{BStr, BString, ByteSlice};
+use serde::Deserialize;
+use smallvec::SmallVec;
 
-use super::{Color, Parameter};
+use super::{
+    CameraExpression, Color, ColorSpace, Curve, Interpolate, Interpolation, Parameter, Rgba,
+};
 use crate::{FeatureView, Value};
 
+mod compile;
+mod program;
+
+pub use compile::{ExpressionType, KeyId, PropertyKeys};
+pub use program::Program;
+
 #[derive(Debug, Clone)]
 pub enum DataExpression<'a> {
     All(Vec<DataExpression<'a>>),
     Any(Vec<DataExpression<'a>>),
-    In(Box<DataExpression<'a>>, Vec<DataExpression<'a>>),
-    Has(Box<DataExpression<'a>>),
+    In(Box<DataExpression<'a>>, Box<DataExpression<'a>>),
+    Has(Box<DataExpression<'a>>, Option<Box<DataExpression<'a>>>),
     Get(Box<DataExpression<'a>>),
     Cmp(Comparison, Box<DataExpression<'a>>, Box<DataExpression<'a>>),
     ToBoolean(Box<DataExpression<'a>>),
@@ -21,61 +33,482 @@ pub enum DataExpression<'a> {
         Vec<(DataExpression<'a>, DataExpression<'a>)>,
         Box<DataExpression<'a>>,
     ),
+    Interpolate(
+        Interpolation,
+        ColorSpace,
+        Box<DataExpression<'a>>,
+        Vec<(f64, DataExpression<'a>)>,
+    ),
+    Step(
+        Box<DataExpression<'a>>,
+        Box<DataExpression<'a>>,
+        Vec<(f64, DataExpression<'a>)>,
+    ),
+    Zoom,
+    /// Distance along a line from 0.0 to 1.0, only known when drawing `line-gradient`
+    LineProgress,
+    Math(MathOp, Vec<DataExpression<'a>>),
+    Concat(Vec<DataExpression<'a>>),
+    Upcase(Box<DataExpression<'a>>),
+    Downcase(Box<DataExpression<'a>>),
+    ToString(Box<DataExpression<'a>>),
+    NumberFormat(Box<DataExpression<'a>>, Box<NumberFormatOptions>),
+    Coalesce(Vec<DataExpression<'a>>),
+    Length(Box<DataExpression<'a>>),
+    Slice(
+        Box<DataExpression<'a>>,
+        Box<DataExpression<'a>>,
+        Option<Box<DataExpression<'a>>>,
+    ),
+    IndexOf(
+        Box<DataExpression<'a>>,
+        Box<DataExpression<'a>>,
+        Option<Box<DataExpression<'a>>>,
+    ),
+    IsSupportedScript(Box<DataExpression<'a>>),
+    ResolvedLocale(Box<Collator>),
+    Format(Vec<FormatSection>),
+    Assert(ValueType, Vec<DataExpression<'a>>),
+    AssertArray(Option<ValueType>, Option<usize>, Box<DataExpression<'a>>),
+    ToNumber(Vec<DataExpression<'a>>),
+    ToColor(Vec<DataExpression<'a>>),
+    ToRgba(Box<DataExpression<'a>>),
+    TypeOf(Box<DataExpression<'a>>),
+    Let(Vec<(BString, DataExpression<'a>)>, Box<DataExpression<'a>>),
+    Var(BString),
+    At(Box<DataExpression<'a>>, Box<DataExpression<'a>>),
+    Not(Box<DataExpression<'a>>),
+    Id,
+    GeometryType,
+    Properties,
+    /// A `get` of a constant key, interned when the style was compiled
+    Property(KeyId),
+    /// A `has` of a constant key, interned when the style was compiled
+    HasProperty(KeyId),
     Constant(ExpressionValue<'a>),
 }
 
 impl<'a> DataExpression<'a> {
     pub fn eval<'f>(&'a self, feature: &'f FeatureView<'_>) -> ExpressionValue<'f>
+    where
+        'a: 'f,
+    {
+        self.eval_scoped(feature, &Scope::Empty)
+    }
+
+    fn eval_scoped<'f>(
+        &'a self,
+        feature: &'f FeatureView<'_>,
+        scope: &Scope<'_, 'f>,
+    ) -> ExpressionValue<'f>
     where
         'a: 'f,
     {
         match self {
-            DataExpression::All(filters) => filters.iter().all(|f| f.eval(feature).into()).into(),
-            DataExpression::Any(filters) => filters.iter().any(|f| f.eval(feature).into()).into(),
-            DataExpression::In(needle, values) => {
-                let needle = needle.eval(feature);
-                values.iter().any(|v| v.eval(feature) == needle).into()
-            }
-            DataExpression::Has(tag) => tag
-                .eval(feature)
+            DataExpression::All(filters) => filters
+                .iter()
+                .all(|f| f.eval_scoped(feature, scope).into())
+                .into(),
+            DataExpression::Any(filters) => filters
+                .iter()
+                .any(|f| f.eval_scoped(feature, scope).into())
+                .into(),
+            DataExpression::In(needle, haystack) => {
+                let needle = needle.eval_scoped(feature, scope);
+                contains(&needle, haystack.eval_scoped(feature, scope)).into()
+            }
+            DataExpression::Has(tag, None) => tag
+                .eval_scoped(feature, scope)
                 .as_str()
                 .and_then(|k| feature.key(k))
                 .is_some()
                 .into(),
-            DataExpression::Cmp(cmp, l, r) => cmp.cmp(&l.eval(feature), &r.eval(feature)).into(),
-            DataExpression::ToBoolean(value) => bool::from(value.eval(feature)).into(),
+            DataExpression::Has(key, Some(object)) => {
+                let key = key.eval_scoped(feature, scope);
+                match (key.as_str(), object.eval_scoped(feature, scope)) {
+                    (Some(key), ExpressionValue::Object(object)) => object.contains_key(key).into(),
+                    _ => false.into(),
+                }
+            }
+            DataExpression::Cmp(cmp, l, r) => cmp
+                .cmp(
+                    &l.eval_scoped(feature, scope),
+                    &r.eval_scoped(feature, scope),
+                )
+                .into(),
+            DataExpression::ToBoolean(value) => {
+                bool::from(value.eval_scoped(feature, scope)).into()
+            }
             DataExpression::Match(input, cases, fallback) => {
-                let input = input.eval(feature);
+                let input = input.eval_scoped(feature, scope);
                 for (label, value) in cases {
-                    let label = label.eval(feature);
+                    let label = label.eval_scoped(feature, scope);
                     if input == label {
-                        return value.eval(feature);
+                        return value.eval_scoped(feature, scope);
                     }
                 }
 
-                fallback.eval(feature)
+                fallback.eval_scoped(feature, scope)
             }
             DataExpression::Case(cases, fallback) => {
                 for (condition, value) in cases {
-                    let condition = bool::from(condition.eval(feature));
+                    let condition = bool::from(condition.eval_scoped(feature, scope));
                     if condition {
-                        return value.eval(feature);
+                        return value.eval_scoped(feature, scope);
                     }
                 }
 
-                fallback.eval(feature)
+                fallback.eval_scoped(feature, scope)
             }
             DataExpression::Get(value) => {
-                let tag = value.eval(feature).as_str().and_then(|v| feature.key(v));
+                let tag = value
+                    .eval_scoped(feature, scope)
+                    .as_str()
+                    .and_then(|v| feature.key(v));
                 match tag {
                     Some(s) => s.into(),
                     None => ExpressionValue::Null,
                 }
             }
+            DataExpression::Interpolate(interpolation, space, input, stops) => {
+                let Some(input) = input.eval_scoped(feature, scope).as_number() else {
+                    return ExpressionValue::Null;
+                };
+
+                let (Some(first), Some(last)) = (stops.first(), stops.last()) else {
+                    return ExpressionValue::Null;
+                };
+
+                if input <= first.0 {
+                    return first.1.eval_scoped(feature, scope);
+                } else if input >= last.0 {
+                    return last.1.eval_scoped(feature, scope);
+                }
+
+                let upper_idx = stops.partition_point(|(stop, _)| *stop <= input);
+                let (lower, lower_value) = &stops[upper_idx - 1];
+                let (upper, upper_value) = &stops[upper_idx];
+
+                let factor = interpolation.factor(input as f32, *lower as f32, *upper as f32);
+
+                lower_value.eval_scoped(feature, scope).interpolate_in(
+                    *space,
+                    factor,
+                    upper_value.eval_scoped(feature, scope),
+                )
+            }
+            DataExpression::Step(input, first, stops) => {
+                let Some(input) = input.eval_scoped(feature, scope).as_number() else {
+                    return ExpressionValue::Null;
+                };
+
+                let step = stops.partition_point(|(stop, _)| *stop <= input);
+                match step.checked_sub(1) {
+                    Some(idx) => stops[idx].1.eval_scoped(feature, scope),
+                    None => first.eval_scoped(feature, scope),
+                }
+            }
+            DataExpression::Zoom => (feature.zoom() as f64).into(),
+            DataExpression::LineProgress => (feature.line_progress() as f64).into(),
+            DataExpression::Math(op, args) => {
+                let args: Option<SmallVec<[f64; 4]>> = args
+                    .iter()
+                    .map(|arg| arg.eval_scoped(feature, scope).as_number())
+                    .collect();
+
+                args.and_then(|args| op.apply(&args))
+                    .map(ExpressionValue::Number)
+                    .unwrap_or(ExpressionValue::Null)
+            }
+            DataExpression::Concat(values) => {
+                let mut text = BString::default();
+                for value in values {
+                    text.extend_from_slice(&value.eval_scoped(feature, scope).to_bstring());
+                }
+
+                ExpressionValue::String(text)
+            }
+            DataExpression::Upcase(value) => match value.eval_scoped(feature, scope).as_str() {
+                Some(s) => ExpressionValue::String(s.to_uppercase().into()),
+                None => ExpressionValue::Null,
+            },
+            DataExpression::Downcase(value) => match value.eval_scoped(feature, scope).as_str() {
+                Some(s) => ExpressionValue::String(s.to_lowercase().into()),
+                None => ExpressionValue::Null,
+            },
+            DataExpression::ToString(value) => {
+                ExpressionValue::String(value.eval_scoped(feature, scope).to_bstring())
+            }
+            DataExpression::NumberFormat(value, options) => {
+                match value.eval_scoped(feature, scope).as_number() {
+                    Some(n) => ExpressionValue::String(options.format(n, feature, scope).into()),
+                    None => ExpressionValue::Null,
+                }
+            }
+            DataExpression::Coalesce(values) => values
+                .iter()
+                .map(|v| v.eval_scoped(feature, scope))
+                .find(|v| !matches!(v, ExpressionValue::Null))
+                .unwrap_or(ExpressionValue::Null),
+            DataExpression::Length(value) => match value.eval_scoped(feature, scope) {
+                ExpressionValue::Array(values) => (values.len() as f64).into(),
+                value => match value.as_str() {
+                    Some(s) => (s.chars().count() as f64).into(),
+                    None => ExpressionValue::Null,
+                },
+            },
+            DataExpression::Slice(input, start, end) => {
+                let input = input.eval_scoped(feature, scope);
+                let len = match &input {
+                    ExpressionValue::Array(values) => values.len(),
+                    input => match input.as_str() {
+                        Some(s) => s.chars().count(),
+                        None => return ExpressionValue::Null,
+                    },
+                };
+
+                let index = |exp: &'a DataExpression<'a>| {
+                    let n = exp.eval_scoped(feature, scope).as_number()?;
+                    Some(char_index(n, len))
+                };
+
+                let Some(start) = index(start) else {
+                    return ExpressionValue::Null;
+                };
+                let end = match end {
+                    Some(end) => match index(end) {
+                        Some(end) => end,
+                        None => return ExpressionValue::Null,
+                    },
+                    None => len,
+                };
+                let range = start..end.max(start);
+
+                match input {
+                    ExpressionValue::Array(mut values) => {
+                        values.truncate(range.end);
+                        values.drain(..range.start);
+                        ExpressionValue::Array(values)
+                    }
+                    input => {
+                        let chars = input.as_str().into_iter().flat_map(|s| s.chars());
+                        let slice: String = chars.skip(range.start).take(range.len()).collect();
+                        ExpressionValue::String(slice.into())
+                    }
+                }
+            }
+            DataExpression::IndexOf(keyword, input, from) => {
+                let keyword = keyword.eval_scoped(feature, scope);
+                let input = input.eval_scoped(feature, scope);
+                let from = from
+                    .as_ref()
+                    .map(|from| from.eval_scoped(feature, scope).as_number());
+                // Like javascript's indexOf, arrays are searched from the end for negative
+                // indices but strings are searched from the start
+                let from = |len, from_end: bool| match from {
+                    Some(Some(from)) if from < 0.0 && !from_end => Some(0),
+                    Some(Some(from)) => Some(char_index(from, len)),
+                    Some(None) => None,
+                    None => Some(0),
+                };
+
+                let index = match input {
+                    ExpressionValue::Array(values) => {
+                        let Some(from) = from(values.len(), true) else {
+                            return ExpressionValue::Null;
+                        };
+                        (from..values.len()).find(|idx| values[*idx] == keyword)
+                    }
+                    input => {
+                        let (Some(keyword), Some(input)) = (keyword.as_str(), input.as_str())
+                        else {
+                            return ExpressionValue::Null;
+                        };
+
+                        let chars: Vec<char> = input.chars().collect();
+                        let Some(from) = from(chars.len(), false) else {
+                            return ExpressionValue::Null;
+                        };
+
+                        let keyword: Vec<char> = keyword.chars().collect();
+                        (from..=chars.len().saturating_sub(keyword.len()))
+                            .find(|idx| chars[*idx..].starts_with(&keyword))
+                    }
+                };
+
+                match index {
+                    Some(idx) => (idx as f64).into(),
+                    None => (-1.0f64).into(),
+                }
+            }
+            DataExpression::IsSupportedScript(value) => {
+                match value.eval_scoped(feature, scope).as_str() {
+                    Some(s) => s.chars().all(crate::text::is_supported_script).into(),
+                    None => ExpressionValue::Null,
+                }
+            }
+            DataExpression::ResolvedLocale(collator) => {
+                ExpressionValue::String(collator.resolved_locale(feature, scope).into())
+            }
+            DataExpression::Format(sections) => ExpressionValue::Formatted(
+                sections
+                    .iter()
+                    .map(|section| section.eval(feature, scope))
+                    .collect(),
+            ),
+            DataExpression::Assert(ty, values) => values
+                .iter()
+                .map(|v| v.eval_scoped(feature, scope))
+                .find(|v| ty.matches(v))
+                .unwrap_or(ExpressionValue::Null),
+            DataExpression::AssertArray(item, len, value) => {
+                match value.eval_scoped(feature, scope) {
+                    ExpressionValue::Array(values)
+                        if len.is_none_or(|len| values.len() == len)
+                            && item.is_none_or(|item| values.iter().all(|v| item.matches(v))) =>
+                    {
+                        ExpressionValue::Array(values)
+                    }
+                    _ => ExpressionValue::Null,
+                }
+            }
+            DataExpression::ToNumber(values) => values
+                .iter()
+                .find_map(|v| v.eval_scoped(feature, scope).to_number())
+                .map(ExpressionValue::Number)
+                .unwrap_or(ExpressionValue::Null),
+            DataExpression::ToColor(values) => values
+                .iter()
+                .find_map(|v| match v.eval_scoped(feature, scope) {
+                    ExpressionValue::Array(channels) => color_from_channels(&channels),
+                    value => Color::try_from(value).ok(),
+                })
+                .map(ExpressionValue::Color)
+                .unwrap_or(ExpressionValue::Null),
+            DataExpression::ToRgba(value) => {
+                match Color::try_from(value.eval_scoped(feature, scope)) {
+                    Ok(color) => {
+                        let c = color.to_rgba();
+                        let channel = |v: f32| ExpressionValue::Number((v as f64 * 255.0).round());
+                        ExpressionValue::Array(vec![
+                            channel(c.r),
+                            channel(c.g),
+                            channel(c.b),
+                            ExpressionValue::Number(c.a as f64),
+                        ])
+                    }
+                    Err(_) => ExpressionValue::Null,
+                }
+            }
+            DataExpression::TypeOf(value) => {
+                ExpressionValue::String(value.eval_scoped(feature, scope).type_name().into())
+            }
+            DataExpression::Let(bindings, body) => {
+                let values: SmallVec<[ExpressionValue<'f>; 4]> = bindings
+                    .iter()
+                    .map(|(_, value)| value.eval_scoped(feature, scope))
+                    .collect();
+                let names = bindings.iter().map(|(name, _)| name.as_ref());
+
+                Self::eval_bound(names.zip(values), body, feature, scope)
+            }
+            DataExpression::Var(name) => scope.get(name.as_ref()).unwrap_or(ExpressionValue::Null),
+            DataExpression::At(index, array) => {
+                let index = index.eval_scoped(feature, scope).as_number();
+                match (index, array.eval_scoped(feature, scope)) {
+                    (Some(index), ExpressionValue::Array(mut values))
+                        if index >= 0.0
+                            && index.fract() == 0.0
+                            && (index as usize) < values.len() =>
+                    {
+                        values.swap_remove(index as usize)
+                    }
+                    _ => ExpressionValue::Null,
+                }
+            }
+            DataExpression::Not(value) => (!bool::from(value.eval_scoped(feature, scope))).into(),
+            DataExpression::Id => match feature.id() {
+                Some(id) => (id as f64).into(),
+                None => ExpressionValue::Null,
+            },
+            DataExpression::GeometryType => ExpressionValue::Str(feature.geometry_type().into()),
+            DataExpression::Properties => ExpressionValue::Object(
+                feature
+                    .properties()
+                    .map(|(key, value)| (key.to_owned(), value.into()))
+                    .collect(),
+            ),
+            DataExpression::Property(key) => match feature.property(*key) {
+                Some(value) => value.into(),
+                None => ExpressionValue::Null,
+            },
+            DataExpression::HasProperty(key) => feature.property(*key).is_some().into(),
             DataExpression::Constant(value) => value.ref_clone(),
         }
     }
 
+    /// Evaluates `body` with each binding added to the scope in turn
+    fn eval_bound<'f, I>(
+        mut bindings: I,
+        body: &'a DataExpression<'a>,
+        feature: &'f FeatureView<'_>,
+        scope: &Scope<'_, 'f>,
+    ) -> ExpressionValue<'f>
+    where
+        'a: 'f,
+        I: Iterator<Item = (&'a BStr, ExpressionValue<'f>)>,
+    {
+        match bindings.next() {
+            Some((name, value)) => {
+                let scope = Scope::Binding {
+                    name,
+                    value,
+                    parent: scope,
+                };
+                Self::eval_bound(bindings, body, feature, &scope)
+            }
+            None => body.eval_scoped(feature, scope),
+        }
+    }
+
+    /// Evaluates the expression for a feature, keeping a top level zoom curve
+    /// so that it can be evaluated at the camera zoom while rendering
+    pub fn eval_parameter<'f, O>(&'a self, feature: &'f FeatureView<'_>) -> Parameter<O>
+    where
+        'a: 'f,
+        O: Default + TryFrom<ExpressionValue<'f>>,
+    {
+        let stop = |(stop, output): &'a (f64, DataExpression<'a>)| {
+            let output = O::try_from(output.eval(feature)).ok()?;
+            Some((*stop as f32, output))
+        };
+
+        match self {
+            DataExpression::Interpolate(interpolation, space, input, stops)
+                if matches!(**input, DataExpression::Zoom) =>
+            {
+                let stops = stops.iter().filter_map(stop).collect();
+                Parameter::CameraExpression(CameraExpression::new(
+                    Curve::Interpolate(*interpolation, *space),
+                    stops,
+                ))
+            }
+            DataExpression::Step(input, first, stops)
+                if matches!(**input, DataExpression::Zoom) =>
+            {
+                let first = O::try_from(first.eval(feature))
+                    .ok()
+                    .map(|first| (f32::NEG_INFINITY, first));
+                let stops = first
+                    .into_iter()
+                    .chain(stops.iter().filter_map(stop))
+                    .collect();
+                Parameter::CameraExpression(CameraExpression::new(Curve::Step, stops))
+            }
+            _ => self.eval(feature).to_parameter(),
+        }
+    }
+
     pub fn is_computed_from_feature(&self) -> bool {
         let child = |exp: &DataExpression| exp.is_computed_from_feature();
         let children = |exps: &[DataExpression]| exps.iter().any(child);
@@ -89,10 +522,57 @@ impl<'a> DataExpression<'a> {
             DataExpression::Match(input, cases, fallback) => {
                 child(input) || child(fallback) || cases.iter().any(|(l, v)| child(l) || child(v))
             }
+            DataExpression::Interpolate(_, _, input, stops) => {
+                child(input) || stops.iter().any(|(_, v)| child(v))
+            }
+            DataExpression::Step(input, first, stops) => {
+                child(input) || child(first) || stops.iter().any(|(_, v)| child(v))
+            }
+            DataExpression::Zoom => false,
+            DataExpression::LineProgress => false,
+            DataExpression::Math(_, args) => children(args),
+            DataExpression::Concat(values) => children(values),
+            DataExpression::Coalesce(values) => children(values),
+            DataExpression::Upcase(value) => child(value),
+            DataExpression::Downcase(value) => child(value),
+            DataExpression::ToString(value) => child(value),
+            DataExpression::Length(value) => child(value),
+            DataExpression::IsSupportedScript(value) => child(value),
+            DataExpression::NumberFormat(value, options) => {
+                child(value) || options.is_computed_from_feature()
+            }
+            DataExpression::Slice(input, start, end) => {
+                child(input) || child(start) || end.as_deref().is_some_and(child)
+            }
+            DataExpression::IndexOf(keyword, input, from) => {
+                child(keyword) || child(input) || from.as_deref().is_some_and(child)
+            }
+            DataExpression::ResolvedLocale(collator) => collator.is_computed_from_feature(),
+            DataExpression::Assert(_, values) => children(values),
+            DataExpression::AssertArray(_, _, value) => child(value),
+            DataExpression::ToNumber(values) => children(values),
+            DataExpression::ToColor(values) => children(values),
+            DataExpression::ToRgba(value) => child(value),
+            DataExpression::TypeOf(value) => child(value),
+            DataExpression::Format(sections) => sections
+                .iter()
+                .any(|section| section.is_computed_from_feature()),
             DataExpression::Constant(_) => false,
             DataExpression::Get(_) => true,
-            DataExpression::Has(_) => true,
-            DataExpression::In(needle, values) => child(needle) || children(values),
+            DataExpression::Has(_, None) => true,
+            DataExpression::Has(key, Some(object)) => child(key) || child(object),
+            DataExpression::Let(bindings, body) => {
+                child(body) || bindings.iter().any(|(_, value)| child(value))
+            }
+            DataExpression::Var(_) => false,
+            DataExpression::At(index, array) => child(index) || child(array),
+            DataExpression::Not(value) => child(value),
+            DataExpression::Id => true,
+            DataExpression::GeometryType => true,
+            DataExpression::Properties => true,
+            DataExpression::Property(_) => true,
+            DataExpression::HasProperty(_) => true,
+            DataExpression::In(needle, haystack) => child(needle) || child(haystack),
             DataExpression::Cmp(_, left, right) => child(left) || child(right),
         }
     }
@@ -151,6 +631,13 @@ impl<'de> serde::de::Visitor<'de> for ExpressionVisitor {
         Ok(DataExpression::Constant(v.to_string().into()))
     }
 
+    fn visit_unit<E>(self) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        Ok(DataExpression::Constant(ExpressionValue::Null))
+    }
+
     fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E>
     where
         E: serde::de::Error,
@@ -191,22 +678,27 @@ impl<'de> serde::de::Visitor<'de> for ExpressionVisitor {
                 DataExpression::Any(filters)
             }
             "in" => {
-                let tag = seq
+                let needle = seq
                     .next_element()?
-                    .ok_or(E::custom("expected tag for in filter expression"))?;
-                let mut values = Vec::new();
-                while let Some(value) = seq.next_element()? {
-                    values.push(value)
+                    .ok_or(E::custom("expected keyword for in expression"))?;
+                let haystack = seq
+                    .next_element()?
+                    .ok_or(E::custom("expected input for in expression"))?;
+                if seq.next_element::<serde::de::IgnoredAny>()?.is_some() {
+                    return Err(E::custom(
+                        "unexpected number of arguments for 'in' expression",
+                    ));
                 }
 
-                DataExpression::In(tag, values)
+                DataExpression::In(needle, haystack)
             }
             "has" => {
                 let tag = seq
                     .next_element()?
                     .ok_or(E::custom("expected tag for has filter expression"))?;
+                let object = seq.next_element()?;
 
-                DataExpression::Has(tag)
+                DataExpression::Has(tag, object)
             }
             "to-boolean" => {
                 let value = seq
@@ -281,6 +773,271 @@ impl<'de> serde::de::Visitor<'de> for ExpressionVisitor {
 
                 DataExpression::Match(input, cases, Box::new(fallback))
             }
+            "interpolate" | "interpolate-hcl" | "interpolate-lab" => {
+                let space = match kind.as_str() {
+                    "interpolate-hcl" => ColorSpace::Hcl,
+                    "interpolate-lab" => ColorSpace::Lab,
+                    _ => ColorSpace::Rgb,
+                };
+                let interpolation = seq.next_element()?.ok_or_else(|| {
+                    E::custom(format!("expected interpolation type for {kind} expression"))
+                })?;
+                let input = seq
+                    .next_element()?
+                    .ok_or_else(|| E::custom(format!("expected input for {kind} expression")))?;
+
+                let stops = stops(&mut seq, &kind)?;
+                if stops.is_empty() {
+                    return Err(E::custom(format!("expected stops for {kind} expression")));
+                }
+
+                DataExpression::Interpolate(interpolation, space, input, stops)
+            }
+            "step" => {
+                let input = seq
+                    .next_element()?
+                    .ok_or(E::custom("expected input for step expression"))?;
+                let first = seq
+                    .next_element()?
+                    .ok_or(E::custom("expected first output for step expression"))?;
+
+                let stops = stops(&mut seq, "step")?;
+
+                DataExpression::Step(input, first, stops)
+            }
+            "zoom" => DataExpression::Zoom,
+            "line-progress" => DataExpression::LineProgress,
+            "concat" => {
+                let mut values = Vec::new();
+                while let Some(value) = seq.next_element()? {
+                    values.push(value)
+                }
+
+                DataExpression::Concat(values)
+            }
+            "coalesce" => {
+                let mut values = Vec::new();
+                while let Some(value) = seq.next_element()? {
+                    values.push(value)
+                }
+
+                DataExpression::Coalesce(values)
+            }
+            "upcase" => {
+                let value = seq
+                    .next_element()?
+                    .ok_or(E::custom("expected value for upcase expression"))?;
+
+                DataExpression::Upcase(value)
+            }
+            "downcase" => {
+                let value = seq
+                    .next_element()?
+                    .ok_or(E::custom("expected value for downcase expression"))?;
+
+                DataExpression::Downcase(value)
+            }
+            "to-string" => {
+                let value = seq
+                    .next_element()?
+                    .ok_or(E::custom("expected value for to-string expression"))?;
+
+                DataExpression::ToString(value)
+            }
+            "number-format" => {
+                let value = seq
+                    .next_element()?
+                    .ok_or(E::custom("expected value for number-format expression"))?;
+                let options = seq
+                    .next_element()?
+                    .ok_or(E::custom("expected options for number-format expression"))?;
+
+                DataExpression::NumberFormat(value, options)
+            }
+            "length" => {
+                let value = seq
+                    .next_element()?
+                    .ok_or(E::custom("expected value for length expression"))?;
+
+                DataExpression::Length(value)
+            }
+            "slice" => {
+                let input = seq
+                    .next_element()?
+                    .ok_or(E::custom("expected input for slice expression"))?;
+                let start = seq
+                    .next_element()?
+                    .ok_or(E::custom("expected start index for slice expression"))?;
+                let end = seq.next_element()?;
+
+                DataExpression::Slice(input, start, end)
+            }
+            "index-of" => {
+                let keyword = seq
+                    .next_element()?
+                    .ok_or(E::custom("expected keyword for index-of expression"))?;
+                let input = seq
+                    .next_element()?
+                    .ok_or(E::custom("expected input for index-of expression"))?;
+                let from = seq.next_element()?;
+
+                DataExpression::IndexOf(keyword, input, from)
+            }
+            "is-supported-script" => {
+                let value = seq.next_element()?.ok_or(E::custom(
+                    "expected value for is-supported-script expression",
+                ))?;
+
+                DataExpression::IsSupportedScript(value)
+            }
+            "resolved-locale" => {
+                let collator = seq.next_element()?.ok_or(E::custom(
+                    "expected collator for resolved-locale expression",
+                ))?;
+
+                DataExpression::ResolvedLocale(collator)
+            }
+            "format" => {
+                let mut sections: Vec<FormatSection> = Vec::new();
+                while let Some(arg) = seq.next_element()? {
+                    match (arg, sections.last_mut()) {
+                        (FormatArg::Text(text), _) => sections.push(FormatSection {
+                            text,
+                            options: FormatOptions::default(),
+                        }),
+                        (FormatArg::Options(options), Some(section)) => section.options = options,
+                        (FormatArg::Options(_), None) => {
+                            return Err(E::custom(
+                                "expected text before options in format expression",
+                            ));
+                        }
+                    }
+                }
+
+                DataExpression::Format(sections)
+            }
+            "let" => {
+                let mut args: Vec<DataExpression> = Vec::new();
+                while let Some(arg) = seq.next_element()? {
+                    args.push(arg)
+                }
+
+                let body = args
+                    .pop()
+                    .ok_or(E::custom("expected body for let expression"))?;
+                if args.is_empty() || args.len() % 2 != 0 {
+                    return Err(E::custom(
+                        "expected name and value pairs for let expression",
+                    ));
+                }
+
+                let mut bindings = Vec::new();
+                let mut args = args.into_iter();
+                while let (Some(name), Some(value)) = (args.next(), args.next()) {
+                    let DataExpression::Constant(ExpressionValue::String(name)) = name else {
+                        return Err(E::custom("expected string name for let expression binding"));
+                    };
+                    bindings.push((name, value));
+                }
+
+                DataExpression::Let(bindings, Box::new(body))
+            }
+            "var" => {
+                let name: String = seq
+                    .next_element()?
+                    .ok_or(E::custom("expected name for var expression"))?;
+
+                DataExpression::Var(name.into())
+            }
+            "at" => {
+                let index = seq
+                    .next_element()?
+                    .ok_or(E::custom("expected index for at expression"))?;
+                let array = seq
+                    .next_element()?
+                    .ok_or(E::custom("expected array for at expression"))?;
+
+                DataExpression::At(index, array)
+            }
+            "!" => {
+                let value = seq
+                    .next_element()?
+                    .ok_or(E::custom("expected value for ! expression"))?;
+
+                DataExpression::Not(value)
+            }
+            "id" => DataExpression::Id,
+            "geometry-type" => DataExpression::GeometryType,
+            "properties" => DataExpression::Properties,
+            "literal" => {
+                let value = seq
+                    .next_element()?
+                    .ok_or(E::custom("expected value for literal expression"))?;
+
+                DataExpression::Constant(value)
+            }
+            "array" => {
+                let mut args: SmallVec<[DataExpression; 3]> = SmallVec::new();
+                while let Some(arg) = seq.next_element()? {
+                    args.push(arg)
+                }
+
+                let value = args
+                    .pop()
+                    .ok_or(E::custom("expected value for array expression"))?;
+
+                let item = match args.first() {
+                    Some(DataExpression::Constant(ExpressionValue::String(ty))) => {
+                        let ty = ValueType::from_str(&ty.to_str_lossy())
+                            .ok_or(E::custom("unexpected item type for array expression"))?;
+                        Some(ty)
+                    }
+                    Some(_) => return Err(E::custom("expected item type for array expression")),
+                    None => None,
+                };
+                let len = match args.get(1) {
+                    Some(DataExpression::Constant(ExpressionValue::Number(len))) => {
+                        Some(*len as usize)
+                    }
+                    Some(_) => return Err(E::custom("expected length for array expression")),
+                    None => None,
+                };
+
+                DataExpression::AssertArray(item, len, Box::new(value))
+            }
+            "to-number" => {
+                let mut values = Vec::new();
+                while let Some(value) = seq.next_element()? {
+                    values.push(value)
+                }
+
+                DataExpression::ToNumber(values)
+            }
+            "to-color" => {
+                let mut values = Vec::new();
+                while let Some(value) = seq.next_element()? {
+                    values.push(value)
+                }
+
+                DataExpression::ToColor(values)
+            }
+            "to-rgba" => {
+                let value = seq
+                    .next_element()?
+                    .ok_or(E::custom("expected value for to-rgba expression"))?;
+
+                DataExpression::ToRgba(value)
+            }
+            "typeof" => {
+                let value = seq
+                    .next_element()?
+                    .ok_or(E::custom("expected value for typeof expression"))?;
+
+                DataExpression::TypeOf(value)
+            }
+            "pi" => DataExpression::Constant(std::f64::consts::PI.into()),
+            "e" => DataExpression::Constant(std::f64::consts::E.into()),
+            "ln2" => DataExpression::Constant(std::f64::consts::LN_2.into()),
             s @ _ => {
                 if let Some(cmp) = Comparison::from_str(s) {
                     let left = seq
@@ -290,8 +1047,36 @@ impl<'de> serde::de::Visitor<'de> for ExpressionVisitor {
                         .next_element()?
                         .ok_or(E::custom("expected right value for comparison expression"))?;
                     DataExpression::Cmp(cmp, left, right)
+                } else if let Some(ty) = ValueType::from_str(s)
+                    && !matches!(ty, ValueType::Color | ValueType::Value)
+                {
+                    let mut values = Vec::new();
+                    while let Some(value) = seq.next_element()? {
+                        values.push(value)
+                    }
+
+                    if values.is_empty() {
+                        return Err(E::custom(format!("expected value for {} expression", kind)));
+                    }
+
+                    DataExpression::Assert(ty, values)
+                } else if let Some(op) = MathOp::from_str(s) {
+                    let mut args = Vec::new();
+                    while let Some(arg) = seq.next_element()? {
+                        args.push(arg)
+                    }
+
+                    let (min, max) = op.arity();
+                    if args.len() < min || max.is_some_and(|max| args.len() > max) {
+                        return Err(E::custom(format!(
+                            "unexpected number of arguments for '{}' expression",
+                            kind
+                        )));
+                    }
+
+                    DataExpression::Math(op, args)
                 } else {
-                    return Err(E::custom(format!("unexpected filter type '{}'", kind)));
+                    return Err(E::custom(format!("unsupported expression '{}'", kind)));
                 }
             }
         };
@@ -300,15 +1085,182 @@ impl<'de> serde::de::Visitor<'de> for ExpressionVisitor {
     }
 }
 
-#[derive(Debug, Clone)]
-pub enum ExpressionValue<'a> {
-    String(BString),
-    Str(&'a BStr),
-    Number(f64),
-    Bool(bool),
-    Color(Color),
-    Null,
-}
+/// Reads the remaining `input, output` pairs of a curve expression, which must be in ascending order
+fn stops<'de, A>(seq: &mut A, kind: &str) -> Result<Vec<(f64, DataExpression<'static>)>, A::Error>
+where
+    A: serde::de::SeqAccess<'de>,
+{
+    use serde::de::Error as E;
+
+    let mut stops: Vec<(f64, DataExpression)> = Vec::new();
+    while let Some(input) = seq.next_element::<f64>()? {
+        let output = seq.next_element()?.ok_or_else(|| {
+            E::custom(format!(
+                "expected output for each stop in {kind} expression"
+            ))
+        })?;
+
+        if stops.last().is_some_and(|(last, _)| *last >= input) {
+            return Err(E::custom(format!(
+                "expected stop inputs in ascending order for {kind} expression"
+            )));
+        }
+
+        stops.push((input, output));
+    }
+
+    Ok(stops)
+}
+
+/// Variables bound by the enclosing `let` expressions
+enum Scope<'n, 'f> {
+    Empty,
+    Binding {
+        name: &'n BStr,
+        value: ExpressionValue<'f>,
+        parent: &'n Scope<'n, 'f>,
+    },
+}
+
+impl<'n, 'f> Scope<'n, 'f> {
+    fn get(&self, name: &BStr) -> Option<ExpressionValue<'f>> {
+        match self {
+            Scope::Empty => None,
+            Scope::Binding {
+                name: bound,
+                value,
+                parent,
+            } => {
+                if *bound == name {
+                    Some(value.clone())
+                } else {
+                    parent.get(name)
+                }
+            }
+        }
+    }
+}
+
+/// A run of text in a `format` expression with the options that override the layer's
+#[derive(Debug, Clone)]
+pub struct FormatSection {
+    text: DataExpression<'static>,
+    options: FormatOptions,
+}
+
+impl FormatSection {
+    fn is_computed_from_feature(&self) -> bool {
+        let options = &self.options;
+        self.text.is_computed_from_feature()
+            || [&options.font_scale, &options.text_color]
+                .into_iter()
+                .flatten()
+                .any(|exp| exp.is_computed_from_feature())
+    }
+
+    fn eval(&self, feature: &FeatureView<'_>, scope: &Scope<'_, '_>) -> FormattedSection {
+        let options = &self.options;
+        FormattedSection {
+            text: self.text.eval_scoped(feature, scope).to_bstring(),
+            scale: options
+                .font_scale
+                .as_ref()
+                .and_then(|exp| exp.eval_scoped(feature, scope).as_number())
+                .map(|n| n as f32),
+            font: options.text_font.as_ref().map(|font| font.0.clone()),
+            color: options
+                .text_color
+                .as_ref()
+                .and_then(|exp| Color::try_from(exp.eval_scoped(feature, scope)).ok()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct FormatOptions {
+    font_scale: Option<DataExpression<'static>>,
+    text_font: Option<FontStack>,
+    text_color: Option<DataExpression<'static>>,
+}
+
+/// Arguments of a `format` expression are either a section's text or an object with its options
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FormatArg {
+    Text(DataExpression<'static>),
+    Options(FormatOptions),
+}
+
+/// A `text-font` override, written as `["literal", ["Font Name", ...]]`
+#[derive(Debug, Clone)]
+struct FontStack(Vec<String>);
+
+impl<'de> serde::de::Deserialize<'de> for FontStack {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct FontStackVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for FontStackVisitor {
+            type Value = FontStack;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(formatter, "a list of font names")
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: serde::de::SeqAccess<'de>,
+            {
+                use serde::de::Error as E;
+
+                let first: String = seq
+                    .next_element()?
+                    .ok_or(E::custom("expected font names for text-font"))?;
+
+                if first == "literal" {
+                    let fonts = seq
+                        .next_element()?
+                        .ok_or(E::custom("expected font names for text-font"))?;
+                    return Ok(FontStack(fonts));
+                }
+
+                let mut fonts = vec![first];
+                while let Some(font) = seq.next_element()? {
+                    fonts.push(font)
+                }
+
+                Ok(FontStack(fonts))
+            }
+        }
+
+        deserializer.deserialize_seq(FontStackVisitor)
+    }
+}
+
+/// A section of text produced by a `format` expression
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormattedSection {
+    pub text: BString,
+    pub scale: Option<f32>,
+    pub font: Option<Vec<String>>,
+    pub color: Option<Color>,
+}
+
+#[derive(Debug, Clone)]
+pub enum ExpressionValue<'a> {
+    String(BString),
+    Str(&'a BStr),
+    Number(f64),
+    Bool(bool),
+    Color(Color),
+    Formatted(Vec<FormattedSection>),
+    Array(Vec<ExpressionValue<'a>>),
+    Object(BTreeMap<BString, ExpressionValue<'a>>),
+    Null,
+}
 
 impl<'a> ExpressionValue<'a> {
     pub fn as_str(&self) -> Option<&'_ BStr> {
@@ -318,10 +1270,126 @@ impl<'a> ExpressionValue<'a> {
             ExpressionValue::Number(_) => None,
             ExpressionValue::Bool(_) => None,
             ExpressionValue::Color(_) => None,
+            ExpressionValue::Formatted(_) => None,
+            ExpressionValue::Array(_) => None,
+            ExpressionValue::Object(_) => None,
             ExpressionValue::Null => None,
         }
     }
 
+    /// Converts the value to a number following the `to-number` expression rules
+    pub fn to_number(&self) -> Option<f64> {
+        match self {
+            ExpressionValue::Number(n) => Some(*n),
+            ExpressionValue::Bool(b) => Some(if *b { 1.0 } else { 0.0 }),
+            ExpressionValue::Null => Some(0.0),
+            ExpressionValue::String(_) | ExpressionValue::Str(_) => {
+                string_to_number(self.as_str()?.to_str().ok()?)
+            }
+            _ => None,
+        }
+    }
+
+    /// Name of the value's type as returned by the `typeof` expression
+    pub fn type_name(&self) -> String {
+        match self {
+            ExpressionValue::String(_) | ExpressionValue::Str(_) => "string".to_string(),
+            ExpressionValue::Number(_) => "number".to_string(),
+            ExpressionValue::Bool(_) => "boolean".to_string(),
+            ExpressionValue::Color(_) => "color".to_string(),
+            ExpressionValue::Formatted(_) => "formatted".to_string(),
+            ExpressionValue::Object(_) => "object".to_string(),
+            ExpressionValue::Null => "null".to_string(),
+            ExpressionValue::Array(values) => {
+                let mut item_types = values.iter().map(|v| v.type_name());
+                let first = item_types.next();
+                let item_type = match first {
+                    Some(first) if item_types.all(|ty| ty == first) => first,
+                    _ => "value".to_string(),
+                };
+
+                format!("array<{}, {}>", item_type, values.len())
+            }
+        }
+    }
+
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            ExpressionValue::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// Converts the value to a string following the `to-string` expression rules
+    pub fn to_bstring(&self) -> BString {
+        match self {
+            ExpressionValue::String(s) => s.clone(),
+            ExpressionValue::Str(s) => (*s).to_owned(),
+            ExpressionValue::Number(n) => number_to_string(*n).into(),
+            ExpressionValue::Bool(b) => b.to_string().into(),
+            ExpressionValue::Color(c) => {
+                let c = c.to_rgba();
+                let channel = |v: f32| (v * 255.0).round();
+                format!(
+                    "rgba({},{},{},{})",
+                    channel(c.r),
+                    channel(c.g),
+                    channel(c.b),
+                    c.a
+                )
+                .into()
+            }
+            ExpressionValue::Formatted(sections) => {
+                let mut text = BString::default();
+                for section in sections {
+                    text.extend_from_slice(&section.text);
+                }
+                text
+            }
+            ExpressionValue::Array(_) | ExpressionValue::Object(_) => {
+                let mut json = String::new();
+                self.write_json(&mut json);
+                json.into()
+            }
+            ExpressionValue::Null => BString::default(),
+        }
+    }
+
+    fn write_json(&self, out: &mut String) {
+        use std::fmt::Write;
+
+        match self {
+            ExpressionValue::Array(values) => {
+                out.push('[');
+                for (idx, value) in values.iter().enumerate() {
+                    if idx != 0 {
+                        out.push(',');
+                    }
+                    value.write_json(out);
+                }
+                out.push(']');
+            }
+            ExpressionValue::Object(values) => {
+                out.push('{');
+                for (idx, (key, value)) in values.iter().enumerate() {
+                    if idx != 0 {
+                        out.push(',');
+                    }
+                    let _ = write!(out, "{:?}:", key.to_str_lossy());
+                    value.write_json(out);
+                }
+                out.push('}');
+            }
+            ExpressionValue::Null => out.push_str("null"),
+            ExpressionValue::Number(_) | ExpressionValue::Bool(_) => {
+                out.push_str(&self.to_bstring().to_str_lossy())
+            }
+            _ => {
+                let _ = write!(out, "{:?}", self.to_bstring().to_str_lossy());
+            }
+        }
+    }
+
     fn ref_clone(&self) -> ExpressionValue<'_> {
         match self {
             ExpressionValue::String(s) => ExpressionValue::Str(s.as_ref()),
@@ -329,12 +1397,337 @@ impl<'a> ExpressionValue<'a> {
         }
     }
 
+    /// Copies any borrowed strings so the value can outlive the feature it came from
+    fn into_static(self) -> ExpressionValue<'static> {
+        match self {
+            ExpressionValue::String(s) => ExpressionValue::String(s),
+            ExpressionValue::Str(s) => ExpressionValue::String(s.to_owned()),
+            ExpressionValue::Number(n) => ExpressionValue::Number(n),
+            ExpressionValue::Bool(b) => ExpressionValue::Bool(b),
+            ExpressionValue::Color(c) => ExpressionValue::Color(c),
+            ExpressionValue::Formatted(sections) => ExpressionValue::Formatted(sections),
+            ExpressionValue::Array(values) => {
+                ExpressionValue::Array(values.into_iter().map(Self::into_static).collect())
+            }
+            ExpressionValue::Object(values) => ExpressionValue::Object(
+                values
+                    .into_iter()
+                    .map(|(key, value)| (key, value.into_static()))
+                    .collect(),
+            ),
+            ExpressionValue::Null => ExpressionValue::Null,
+        }
+    }
+
     pub fn to_parameter<O: Default + TryFrom<Self>>(self) -> Parameter<O> {
         let value = O::try_from(self).ok();
         Parameter::Constant(value)
     }
 }
 
+impl<'a> Interpolate for ExpressionValue<'a> {
+    fn interpolate(&self, factor: f32, other: Self) -> Self {
+        self.interpolate_in(ColorSpace::Rgb, factor, other)
+    }
+
+    fn interpolate_in(&self, space: ColorSpace, factor: f32, other: Self) -> Self {
+        if let (ExpressionValue::Number(l), ExpressionValue::Number(r)) = (self, &other) {
+            return ExpressionValue::Number((*l as f32).interpolate(factor, *r as f32) as f64);
+        }
+
+        if let (ExpressionValue::Array(l), ExpressionValue::Array(r)) = (self, &other)
+            && l.len() == r.len()
+        {
+            return ExpressionValue::Array(
+                l.iter()
+                    .zip(r)
+                    .map(|(l, r)| l.interpolate_in(space, factor, r.clone()))
+                    .collect(),
+            );
+        }
+
+        let l = Color::try_from(self.ref_clone());
+        let r = Color::try_from(other.ref_clone());
+        if let (Ok(l), Ok(r)) = (l, r) {
+            return ExpressionValue::Color(l.interpolate_in(space, factor, r));
+        }
+
+        ExpressionValue::Null
+    }
+}
+
+/// Formats numbers the way javascript does, without a trailing `.0` for whole numbers
+fn number_to_string(n: f64) -> String {
+    if n.is_nan() {
+        "NaN".to_string()
+    } else if n.is_infinite() {
+        if n > 0.0 { "Infinity" } else { "-Infinity" }.to_string()
+    } else {
+        n.to_string()
+    }
+}
+
+/// Resolves a possibly negative `slice` or `index-of` index against a string of `len` chars
+fn char_index(n: f64, len: usize) -> usize {
+    let n = n as i64;
+    let idx = if n < 0 { len as i64 + n } else { n };
+    idx.clamp(0, len as i64) as usize
+}
+
+/// Converts the `[r, g, b]` or `[r, g, b, a]` arrays `to-color` accepts, with channels from
+/// 0 to 255 and alpha from 0 to 1
+fn color_from_channels(channels: &[ExpressionValue<'_>]) -> Option<Color> {
+    let channels: SmallVec<[f64; 4]> = channels
+        .iter()
+        .map(|c| c.as_number())
+        .collect::<Option<_>>()?;
+    let (rgb, a) = match channels.as_slice() {
+        [r, g, b] => ([*r, *g, *b], 1.0),
+        [r, g, b, a] => ([*r, *g, *b], *a),
+        _ => return None,
+    };
+
+    if rgb.iter().any(|c| !(0.0..=255.0).contains(c)) || !(0.0..=1.0).contains(&a) {
+        return None;
+    }
+
+    let [r, g, b] = rgb.map(|c| (c / 255.0) as f32);
+    Some(Color::Rgba(Rgba {
+        r,
+        g,
+        b,
+        a: a as f32,
+    }))
+}
+
+/// Whether an `in` expression finds `needle` among an array's items or within a string
+fn contains(needle: &ExpressionValue<'_>, haystack: ExpressionValue<'_>) -> bool {
+    match haystack {
+        ExpressionValue::Array(values) => values.iter().any(|v| v == needle),
+        haystack => match haystack.as_str() {
+            Some(haystack) if !matches!(needle, ExpressionValue::Null) => {
+                haystack.find(needle.to_bstring()).is_some()
+            }
+            _ => false,
+        },
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct NumberFormatOptions {
+    locale: Option<DataExpression<'static>>,
+    currency: Option<DataExpression<'static>>,
+    min_fraction_digits: Option<DataExpression<'static>>,
+    max_fraction_digits: Option<DataExpression<'static>>,
+}
+
+impl NumberFormatOptions {
+    fn is_computed_from_feature(&self) -> bool {
+        [
+            &self.locale,
+            &self.currency,
+            &self.min_fraction_digits,
+            &self.max_fraction_digits,
+        ]
+        .into_iter()
+        .flatten()
+        .any(|exp| exp.is_computed_from_feature())
+    }
+
+    fn format(&self, n: f64, feature: &FeatureView<'_>, scope: &Scope<'_, '_>) -> String {
+        let string = |exp: &Option<DataExpression<'static>>| {
+            exp.as_ref()
+                .map(|exp| exp.eval_scoped(feature, scope).to_bstring().to_string())
+        };
+        let digits = |exp: &Option<DataExpression<'static>>| {
+            exp.as_ref()
+                .and_then(|exp| exp.eval_scoped(feature, scope).as_number())
+                .map(|n| n.clamp(0.0, 20.0) as usize)
+        };
+
+        let locale = string(&self.locale).unwrap_or_else(|| DEFAULT_LOCALE.to_string());
+        let currency = string(&self.currency);
+
+        let default_digits = match currency.as_deref() {
+            Some("JPY" | "KRW") => 0,
+            Some(_) => 2,
+            None => 0,
+        };
+        let min = digits(&self.min_fraction_digits).unwrap_or(default_digits);
+        let max = digits(&self.max_fraction_digits)
+            .unwrap_or(if currency.is_some() { min } else { 3 })
+            .max(min);
+
+        let (group, decimal) = locale_separators(&locale);
+
+        if !n.is_finite() {
+            return number_to_string(n);
+        }
+
+        let fixed = round_fraction(n.abs(), max);
+        let (whole, digits) = fixed.split_once('.').unwrap_or((&fixed, ""));
+        let fraction = digits.trim_end_matches('0');
+        let fraction = format!("{fraction:0<min$}");
+
+        let mut number = String::new();
+        if n.is_sign_negative() && n != 0.0 {
+            number.push('-');
+        }
+
+        for (idx, c) in whole.chars().enumerate() {
+            if idx != 0 && (whole.len() - idx) % 3 == 0 {
+                number.push_str(group);
+            }
+            number.push(c);
+        }
+
+        if !fraction.is_empty() {
+            number.push(decimal);
+            number.push_str(&fraction);
+        }
+
+        match currency {
+            Some(currency) => {
+                let symbol = match currency.as_str() {
+                    "USD" => Some("$"),
+                    "EUR" => Some("€"),
+                    "GBP" => Some("£"),
+                    "JPY" => Some("¥"),
+                    _ => None,
+                };
+
+                match symbol {
+                    _ if decimal == ',' => format!("{number} {}", symbol.unwrap_or(&currency)),
+                    Some(symbol) => format!("{symbol}{number}"),
+                    None => format!("{currency} {number}"),
+                }
+            }
+            None => number,
+        }
+    }
+}
+
+/// Writes `n` with at most `digits` fraction digits, rounding halves away from zero in the
+/// shortest decimal form of `n` the way `Intl.NumberFormat` does
+fn round_fraction(n: f64, digits: usize) -> String {
+    let shortest = n.to_string();
+    let (whole, fraction) = shortest.split_once('.').unwrap_or((&shortest, ""));
+    if fraction.len() <= digits {
+        return shortest;
+    }
+
+    let mut number: Vec<u8> = whole.bytes().chain(fraction[..digits].bytes()).collect();
+    if fraction.as_bytes()[digits] >= b'5' {
+        // Carries the rounding up through any trailing nines
+        match number.iter().rposition(|digit| *digit != b'9') {
+            Some(idx) => {
+                number[idx] += 1;
+                number[idx + 1..].fill(b'0');
+            }
+            None => {
+                number.fill(b'0');
+                number.insert(0, b'1');
+            }
+        }
+    }
+
+    let mut rounded = String::from_utf8(number).unwrap_or_default();
+    if digits > 0 {
+        rounded.insert(rounded.len() - digits, '.');
+    }
+    rounded
+}
+
+const DEFAULT_LOCALE: &str = "en";
+
+/// Digit group and decimal separators for a BCP 47 locale, falling back to english conventions
+fn locale_separators(locale: &str) -> (&'static str, char) {
+    let language = locale.split(['-', '_']).next().unwrap_or(locale);
+    match language {
+        "de" | "es" | "it" | "nl" | "pt" | "id" | "tr" | "da" | "el" => (".", ','),
+        "fr" | "ru" | "sv" | "nb" | "no" | "fi" | "cs" | "pl" | "uk" | "sk" | "hu" => {
+            ("\u{202f}", ',')
+        }
+        _ => (",", '.'),
+    }
+}
+
+/// Locale options from a `["collator", {...}]` expression
+#[derive(Debug, Clone)]
+pub struct Collator {
+    locale: Option<DataExpression<'static>>,
+}
+
+impl Collator {
+    fn is_computed_from_feature(&self) -> bool {
+        self.locale
+            .as_ref()
+            .is_some_and(|exp| exp.is_computed_from_feature())
+    }
+
+    fn resolved_locale(&self, feature: &FeatureView<'_>, scope: &Scope<'_, '_>) -> String {
+        self.locale
+            .as_ref()
+            .map(|exp| exp.eval_scoped(feature, scope).to_bstring().to_string())
+            .filter(|locale| !locale.is_empty())
+            .unwrap_or_else(|| DEFAULT_LOCALE.to_string())
+    }
+}
+
+impl<'de> serde::de::Deserialize<'de> for Collator {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "kebab-case")]
+        struct CollatorOptions {
+            #[allow(dead_code)]
+            case_sensitive: Option<DataExpression<'static>>,
+            #[allow(dead_code)]
+            diacritic_sensitive: Option<DataExpression<'static>>,
+            locale: Option<DataExpression<'static>>,
+        }
+
+        struct CollatorVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for CollatorVisitor {
+            type Value = Collator;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(formatter, "a collator expression")
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: serde::de::SeqAccess<'de>,
+            {
+                use serde::de::Error as E;
+
+                let kind: String = seq
+                    .next_element()?
+                    .ok_or(E::custom("expected collator expression"))?;
+                if kind != "collator" {
+                    return Err(E::custom(format!(
+                        "expected collator expression, found '{kind}'"
+                    )));
+                }
+
+                let options: CollatorOptions = seq
+                    .next_element()?
+                    .ok_or(E::custom("expected options for collator expression"))?;
+
+                Ok(Collator {
+                    locale: options.locale,
+                })
+            }
+        }
+
+        deserializer.deserialize_seq(CollatorVisitor)
+    }
+}
+
 macro_rules! filter_value_from {
     ($($name:ident($ty:ident)),*) => {
         $(
@@ -349,6 +1742,51 @@ macro_rules! filter_value_from {
 
 filter_value_from! {Bool(bool), String(String), Number(f32), Number(f64), Number(i32), Number(i16), Number(i8), Number(u32), Number(u16), Number(u8)}
 
+/// Converts a string to a number the way JavaScript's `Number` does, which unlike Rust's
+/// float parsing rejects `inf` and `NaN` but accepts `Infinity` and hex, octal and binary
+/// integers
+fn string_to_number(s: &str) -> Option<f64> {
+    let s = s.trim_matches(|c: char| c.is_whitespace() || c == '\u{feff}');
+    if s.is_empty() {
+        return Some(0.0);
+    }
+
+    let radix = match s.get(..2) {
+        Some("0x" | "0X") => Some(16),
+        Some("0o" | "0O") => Some(8),
+        Some("0b" | "0B") => Some(2),
+        _ => None,
+    };
+    if let Some(radix) = radix {
+        let digits = &s[2..];
+        if digits.is_empty() {
+            return None;
+        }
+        return digits.chars().try_fold(0.0, |n, c| {
+            Some(n * radix as f64 + c.to_digit(radix)? as f64)
+        });
+    }
+
+    let unsigned = s.strip_prefix(['+', '-']).unwrap_or(s);
+    if unsigned == "Infinity" {
+        return Some(if s.starts_with('-') {
+            f64::NEG_INFINITY
+        } else {
+            f64::INFINITY
+        });
+    }
+
+    // Rust also accepts `inf`, `infinity` and `NaN` in any case, JavaScript only decimals
+    if !unsigned
+        .chars()
+        .all(|c| c.is_ascii_digit() || matches!(c, '.' | 'e' | 'E' | '+' | '-'))
+    {
+        return None;
+    }
+
+    s.parse().ok()
+}
+
 impl<'a> From<ExpressionValue<'a>> for bool {
     fn from(value: ExpressionValue) -> Self {
         (&value).into()
@@ -362,7 +1800,10 @@ impl<'a> From<&'_ ExpressionValue<'a>> for bool {
             ExpressionValue::Str(s) => !s.is_empty(),
             ExpressionValue::Number(n) => *n != 0.0 && !n.is_nan(),
             ExpressionValue::Bool(b) => *b,
-            ExpressionValue::Color(_) => false,
+            ExpressionValue::Color(_) => true,
+            ExpressionValue::Formatted(_) => true,
+            ExpressionValue::Array(_) => true,
+            ExpressionValue::Object(_) => true,
             ExpressionValue::Null => false,
         }
     }
@@ -388,6 +1829,10 @@ impl<'a> PartialEq for ExpressionValue<'a> {
             (ExpressionValue::Number(l), ExpressionValue::Number(r)) => l == r,
             (ExpressionValue::Bool(l), ExpressionValue::Bool(r)) => l == r,
             (ExpressionValue::Color(l), ExpressionValue::Color(r)) => l == r,
+            (ExpressionValue::Formatted(l), ExpressionValue::Formatted(r)) => l == r,
+            (ExpressionValue::Array(l), ExpressionValue::Array(r)) => l == r,
+            (ExpressionValue::Object(l), ExpressionValue::Object(r)) => l == r,
+            (ExpressionValue::Null, ExpressionValue::Null) => true,
             _ => false,
         }
     }
@@ -429,11 +1874,160 @@ impl TryFrom<ExpressionValue<'_>> for Color {
     }
 }
 
+impl TryFrom<ExpressionValue<'_>> for BString {
+    type Error = ();
+
+    fn try_from(value: ExpressionValue<'_>) -> Result<Self, Self::Error> {
+        match value {
+            ExpressionValue::String(bstring) => Ok(bstring),
+            ExpressionValue::Str(bstr) => Ok(bstr.to_owned()),
+            _ => Err(()),
+        }
+    }
+}
+
 impl TryFrom<ExpressionValue<'_>> for (f32, f32) {
     type Error = ();
 
-    fn try_from(_value: ExpressionValue<'_>) -> Result<Self, Self::Error> {
-        panic!("data expressions for array values is not currently supported")
+    fn try_from(value: ExpressionValue<'_>) -> Result<Self, Self::Error> {
+        match value {
+            ExpressionValue::Array(values) => match values.as_slice() {
+                [ExpressionValue::Number(x), ExpressionValue::Number(y)] => {
+                    Ok((*x as f32, *y as f32))
+                }
+                _ => Err(()),
+            },
+            _ => Err(()),
+        }
+    }
+}
+
+impl TryFrom<ExpressionValue<'_>> for SmallVec<[f32; 8]> {
+    type Error = ();
+
+    fn try_from(value: ExpressionValue<'_>) -> Result<Self, Self::Error> {
+        match value {
+            ExpressionValue::Array(values) => values
+                .iter()
+                .map(|v| v.as_number().map(|n| n as f32).ok_or(()))
+                .collect(),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Types that can be checked with the type assertion expressions
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ValueType {
+    Number,
+    String,
+    Boolean,
+    Color,
+    Object,
+    Value,
+}
+
+impl ValueType {
+    fn matches(&self, value: &ExpressionValue<'_>) -> bool {
+        match (self, value) {
+            (ValueType::Number, ExpressionValue::Number(_)) => true,
+            (ValueType::String, ExpressionValue::String(_) | ExpressionValue::Str(_)) => true,
+            (ValueType::Boolean, ExpressionValue::Bool(_)) => true,
+            (ValueType::Color, ExpressionValue::Color(_)) => true,
+            (ValueType::Object, ExpressionValue::Object(_)) => true,
+            (ValueType::Value, _) => true,
+            _ => false,
+        }
+    }
+
+    fn from_str(s: &str) -> Option<Self> {
+        let v = match s {
+            "number" => ValueType::Number,
+            "string" => ValueType::String,
+            "boolean" => ValueType::Boolean,
+            "color" => ValueType::Color,
+            "object" => ValueType::Object,
+            "value" => ValueType::Value,
+            _ => return None,
+        };
+
+        Some(v)
+    }
+}
+
+/// Parses the raw JSON argument of a `literal` expression
+impl<'de> serde::de::Deserialize<'de> for ExpressionValue<'static> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_any(LiteralVisitor)
+    }
+}
+
+struct LiteralVisitor;
+
+impl<'de> serde::de::Visitor<'de> for LiteralVisitor {
+    type Value = ExpressionValue<'static>;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(formatter, "a literal value")
+    }
+
+    fn visit_bool<E>(self, v: bool) -> Result<Self::Value, E> {
+        Ok(v.into())
+    }
+
+    fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E> {
+        Ok(ExpressionValue::Number(v as f64))
+    }
+
+    fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E> {
+        Ok(ExpressionValue::Number(v as f64))
+    }
+
+    fn visit_f64<E>(self, v: f64) -> Result<Self::Value, E> {
+        Ok(v.into())
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E> {
+        Ok(v.to_string().into())
+    }
+
+    fn visit_string<E>(self, v: String) -> Result<Self::Value, E> {
+        Ok(v.into())
+    }
+
+    fn visit_unit<E>(self) -> Result<Self::Value, E> {
+        Ok(ExpressionValue::Null)
+    }
+
+    fn visit_none<E>(self) -> Result<Self::Value, E> {
+        Ok(ExpressionValue::Null)
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: serde::de::SeqAccess<'de>,
+    {
+        let mut values = Vec::new();
+        while let Some(value) = seq.next_element()? {
+            values.push(value)
+        }
+
+        Ok(ExpressionValue::Array(values))
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: serde::de::MapAccess<'de>,
+    {
+        let mut values = BTreeMap::new();
+        while let Some((key, value)) = map.next_entry::<String, _>()? {
+            values.insert(key.into(), value);
+        }
+
+        Ok(ExpressionValue::Object(values))
     }
 }
 
@@ -459,7 +2053,7 @@ impl Comparison {
         }
     }
 
-    fn from_str(s: &str) -> Option<Self> {
+    pub(super) fn from_str(s: &str) -> Option<Self> {
         let v = match s {
             "==" => Comparison::Eq,
             "!=" => Comparison::Neq,
@@ -473,3 +2067,715 @@ impl Comparison {
         Some(v)
     }
 }
+
+#[derive(Debug, Copy, Clone)]
+pub enum MathOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Pow,
+    Abs,
+    Ceil,
+    Floor,
+    Round,
+    Sqrt,
+    Ln,
+    Log10,
+    Log2,
+    Min,
+    Max,
+    Sin,
+    Cos,
+    Tan,
+    Asin,
+    Acos,
+    Atan,
+}
+
+impl MathOp {
+    /// Returns `None` when the arguments don't match the arity of the operation
+    fn apply(&self, args: &[f64]) -> Option<f64> {
+        let value = match (self, args) {
+            (MathOp::Add, args) => args.iter().sum(),
+            (MathOp::Mul, args) => args.iter().product(),
+            (MathOp::Sub, [n]) => -n,
+            (MathOp::Sub, [l, r]) => l - r,
+            (MathOp::Div, [l, r]) => l / r,
+            (MathOp::Rem, [l, r]) => l % r,
+            (MathOp::Pow, [l, r]) => l.powf(*r),
+            (MathOp::Abs, [n]) => n.abs(),
+            (MathOp::Ceil, [n]) => n.ceil(),
+            (MathOp::Floor, [n]) => n.floor(),
+            // Rounds half away from zero, matching the style spec
+            (MathOp::Round, [n]) => n.round(),
+            (MathOp::Sqrt, [n]) => n.sqrt(),
+            (MathOp::Ln, [n]) => n.ln(),
+            (MathOp::Log10, [n]) => n.log10(),
+            (MathOp::Log2, [n]) => n.log2(),
+            (MathOp::Min, args) => args.iter().copied().reduce(f64::min)?,
+            (MathOp::Max, args) => args.iter().copied().reduce(f64::max)?,
+            (MathOp::Sin, [n]) => n.sin(),
+            (MathOp::Cos, [n]) => n.cos(),
+            (MathOp::Tan, [n]) => n.tan(),
+            (MathOp::Asin, [n]) => n.asin(),
+            (MathOp::Acos, [n]) => n.acos(),
+            (MathOp::Atan, [n]) => n.atan(),
+            _ => return None,
+        };
+
+        Some(value)
+    }
+
+    /// The minimum and maximum number of arguments, `None` for variadic operations
+    fn arity(&self) -> (usize, Option<usize>) {
+        match self {
+            MathOp::Add | MathOp::Mul => (2, None),
+            MathOp::Min | MathOp::Max => (1, None),
+            MathOp::Sub => (1, Some(2)),
+            MathOp::Div | MathOp::Rem | MathOp::Pow => (2, Some(2)),
+            _ => (1, Some(1)),
+        }
+    }
+
+    fn from_str(s: &str) -> Option<Self> {
+        let v = match s {
+            "+" => MathOp::Add,
+            "-" => MathOp::Sub,
+            "*" => MathOp::Mul,
+            "/" => MathOp::Div,
+            "%" => MathOp::Rem,
+            "^" => MathOp::Pow,
+            "abs" => MathOp::Abs,
+            "ceil" => MathOp::Ceil,
+            "floor" => MathOp::Floor,
+            "round" => MathOp::Round,
+            "sqrt" => MathOp::Sqrt,
+            "ln" => MathOp::Ln,
+            "log10" => MathOp::Log10,
+            "log2" => MathOp::Log2,
+            "min" => MathOp::Min,
+            "max" => MathOp::Max,
+            "sin" => MathOp::Sin,
+            "cos" => MathOp::Cos,
+            "tan" => MathOp::Tan,
+            "asin" => MathOp::Asin,
+            "acos" => MathOp::Acos,
+            "atan" => MathOp::Atan,
+            _ => return None,
+        };
+
+        Some(v)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+
+    use super::*;
+    use crate::proto::tile::GeomType;
+    use crate::tests::TestLayer;
+
+    fn parse(expression: &Value) -> Result<DataExpression<'static>, serde_json::Error> {
+        serde_json::from_value(expression.clone())
+    }
+
+    /// Evaluates each expression for a feature at zoom 10, numbers only need to be close
+    fn check(cases: &[(Value, Value)]) {
+        let properties = json!({ "name": "Main Street", "rank": 3, "flag": true, "empty": "" });
+        let layer = TestLayer::new(Some(7), GeomType::Point, properties);
+        let view = layer.view(10.0);
+
+        for (expression, expected) in cases {
+            let exp = parse(expression).unwrap_or_else(|err| panic!("{expression}: {err}"));
+            let expected: ExpressionValue = serde_json::from_value(expected.clone()).unwrap();
+            let value = exp.eval(&view);
+
+            let matches = match (&value, &expected) {
+                (ExpressionValue::Number(a), ExpressionValue::Number(b)) => (a - b).abs() < 1e-4,
+                (value, expected) => value == expected,
+            };
+            assert!(matches, "{expression}: {value:?} != {expected:?}");
+        }
+    }
+
+    #[test]
+    fn interpolate() {
+        check(&[
+            (
+                json!(["interpolate", ["linear"], ["get", "rank"], 0, 0, 10, 100]),
+                json!(30),
+            ),
+            (
+                json!(["interpolate", ["linear"], ["zoom"], 0, 0, 8, 4, 12, 12]),
+                json!(8),
+            ),
+            // Inputs past the end stops take the end stops' outputs
+            (
+                json!(["interpolate", ["linear"], ["get", "rank"], 5, 10, 10, 20]),
+                json!(10),
+            ),
+            (
+                json!(["interpolate", ["linear"], ["zoom"], 0, 10, 5, 20]),
+                json!(20),
+            ),
+            (
+                json!(["interpolate", ["linear"], ["get", "rank"], 3, 1, 4, 2]),
+                json!(1),
+            ),
+            (json!(["interpolate", ["linear"], ["zoom"], 5, 7]), json!(7)),
+            // Exponential curves grow by the base for each unit of input
+            (
+                json!([
+                    "interpolate",
+                    ["exponential", 2],
+                    ["get", "rank"],
+                    0,
+                    0,
+                    10,
+                    1023
+                ]),
+                json!(7),
+            ),
+            (
+                json!([
+                    "interpolate",
+                    ["exponential", 1],
+                    ["get", "rank"],
+                    0,
+                    0,
+                    10,
+                    100
+                ]),
+                json!(30),
+            ),
+            (
+                json!(["interpolate", ["exponential", 2], ["zoom"], 0, 0, 5, 31]),
+                json!(31),
+            ),
+            // The ease curve is symmetric so its midpoint is halfway
+            (
+                json!([
+                    "interpolate",
+                    ["cubic-bezier", 0.42, 0, 0.58, 1],
+                    ["get", "rank"],
+                    0,
+                    0,
+                    6,
+                    100
+                ]),
+                json!(50),
+            ),
+            (
+                json!([
+                    "interpolate",
+                    ["cubic-bezier", 0.42, 0, 0.58, 1],
+                    ["get", "rank"],
+                    0,
+                    0,
+                    1,
+                    100
+                ]),
+                json!(100),
+            ),
+            (
+                json!(["interpolate", ["linear"], ["get", "name"], 0, 0, 10, 100]),
+                json!(null),
+            ),
+        ]);
+
+        for expression in [
+            json!(["interpolate", ["linear"], ["zoom"]]),
+            json!(["interpolate", ["linear"], ["zoom"], 5, 1, 2, 3]),
+            json!(["interpolate", ["linear"], ["zoom"], 5, 1, 5, 3]),
+            json!(["interpolate", ["linear"], ["zoom"], 5]),
+            json!(["interpolate", ["quadratic"], ["zoom"], 5, 1]),
+        ] {
+            assert!(parse(&expression).is_err(), "{expression}");
+        }
+    }
+
+    #[test]
+    fn step() {
+        check(&[
+            (
+                json!(["step", ["get", "rank"], "below", 5, "five", 10, "ten"]),
+                json!("below"),
+            ),
+            (
+                json!(["step", ["get", "rank"], "below", 3, "three", 10, "ten"]),
+                json!("three"),
+            ),
+            (json!(["step", ["zoom"], 0, 5, 1, 10, 2]), json!(2)),
+            (json!(["step", ["zoom"], 0, 10.5, 1]), json!(0)),
+            (json!(["step", ["get", "rank"], "only"]), json!("only")),
+            // Like interpolate, inputs that aren't numbers are errors rather than below every stop
+            (
+                json!(["step", ["get", "name"], "below", 0, "zero"]),
+                json!(null),
+            ),
+            (
+                json!(["step", ["get", "missing"], "below", 0, "zero"]),
+                json!(null),
+            ),
+            (
+                json!(["step", ["get", "flag"], "below", 0, "zero"]),
+                json!(null),
+            ),
+            (json!(["step", ["get", "name"], "only"]), json!(null)),
+            (
+                json!(["coalesce", ["step", ["get", "name"], 1, 0, 2], 3]),
+                json!(3),
+            ),
+        ]);
+
+        assert!(parse(&json!(["step", ["zoom"], 0, 5, 1, 4, 2])).is_err());
+        assert!(parse(&json!(["step", ["zoom"]])).is_err());
+    }
+
+    #[test]
+    fn math() {
+        check(&[
+            (json!(["+", 1, 2, 3]), json!(6)),
+            (json!(["*", 2, ["get", "rank"], 4]), json!(24)),
+            (json!(["-", 5]), json!(-5)),
+            (json!(["-", ["get", "rank"]]), json!(-3)),
+            (json!(["-", 10, 4]), json!(6)),
+            (json!(["/", 9, ["get", "rank"]]), json!(3)),
+            (json!(["%", 7, 3]), json!(1)),
+            // The remainder takes the dividend's sign, as in javascript
+            (json!(["%", -7, 3]), json!(-1)),
+            (json!(["%", 7.5, 2]), json!(1.5)),
+            (json!(["^", 2, 10]), json!(1024)),
+            (json!(["^", 4, 0.5]), json!(2)),
+            (json!(["^", ["get", "rank"], 2]), json!(9)),
+            // Halves round away from zero
+            (json!(["round", 2.5]), json!(3)),
+            (json!(["round", -2.5]), json!(-3)),
+            (json!(["round", 0.5]), json!(1)),
+            (json!(["round", 2.4]), json!(2)),
+            (json!(["floor", -1.5]), json!(-2)),
+            (json!(["ceil", 1.2]), json!(2)),
+            (json!(["abs", -4]), json!(4)),
+            (json!(["sqrt", 16]), json!(4)),
+            (json!(["log2", 8]), json!(3)),
+            (json!(["log10", 1000]), json!(3)),
+            (json!(["ln", ["e"]]), json!(1)),
+            (json!(["min", 4, ["get", "rank"], 5]), json!(3)),
+            (json!(["max", 4]), json!(4)),
+            (json!(["sin", 0]), json!(0)),
+            (json!(["cos", ["pi"]]), json!(-1)),
+            (json!(["atan", 1]), json!(std::f64::consts::FRAC_PI_4)),
+            (json!(["ln2"]), json!(std::f64::consts::LN_2)),
+            // Arguments that aren't numbers make the whole expression null
+            (json!(["+", 1, ["get", "missing"]]), json!(null)),
+            (json!(["*", 2, ["get", "name"]]), json!(null)),
+            (json!(["-", ["get", "flag"]]), json!(null)),
+            (json!(["round", ["get", "empty"]]), json!(null)),
+            (json!(["max", 1, ["get", "missing"]]), json!(null)),
+        ]);
+
+        for expression in [
+            json!(["+", 1]),
+            json!(["*"]),
+            json!(["-"]),
+            json!(["-", 1, 2, 3]),
+            json!(["/", 1]),
+            json!(["/", 1, 2, 3]),
+            json!(["%", 1]),
+            json!(["^", 2]),
+            json!(["round"]),
+            json!(["round", 1, 2]),
+            json!(["sqrt", 1, 2]),
+            json!(["min"]),
+        ] {
+            assert!(parse(&expression).is_err(), "{expression}");
+        }
+    }
+
+    #[test]
+    fn string_slices() {
+        check(&[
+            (json!(["slice", ["get", "name"], 5]), json!("Street")),
+            (json!(["slice", ["get", "name"], 0, 4]), json!("Main")),
+            // Negative indices count back from the end
+            (json!(["slice", ["get", "name"], -6]), json!("Street")),
+            (json!(["slice", ["get", "name"], 0, -7]), json!("Main")),
+            (json!(["slice", ["get", "name"], -6, -3]), json!("Str")),
+            // Out of range indices are clamped to the string
+            (json!(["slice", ["get", "name"], 20]), json!("")),
+            (json!(["slice", ["get", "name"], -20, 4]), json!("Main")),
+            (json!(["slice", ["get", "name"], 5, 100]), json!("Street")),
+            (json!(["slice", ["get", "name"], 5, 2]), json!("")),
+            (json!(["slice", "日本語の道", 3]), json!("の道")),
+            (json!(["slice", ["get", "missing"], 0]), json!(null)),
+            (
+                json!(["slice", ["get", "name"], ["get", "missing"]]),
+                json!(null),
+            ),
+        ]);
+    }
+
+    #[test]
+    fn string_index_of() {
+        check(&[
+            (json!(["index-of", "Street", ["get", "name"]]), json!(5)),
+            (json!(["index-of", "e", ["get", "name"]]), json!(8)),
+            (json!(["index-of", "x", ["get", "name"]]), json!(-1)),
+            (json!(["index-of", "", ["get", "name"]]), json!(0)),
+            (json!(["index-of", "e", ["get", "name"], 9]), json!(9)),
+            (json!(["index-of", "M", ["get", "name"], 1]), json!(-1)),
+            // Strings are searched from the start for negative indices, as in javascript
+            (json!(["index-of", "M", ["get", "name"], -2]), json!(0)),
+            (json!(["index-of", "e", ["get", "name"], 20]), json!(-1)),
+            (json!(["index-of", "", ["get", "name"], 20]), json!(11)),
+            (json!(["index-of", "道", "日本語の道"]), json!(4)),
+            (json!(["index-of", "a", ["get", "missing"]]), json!(null)),
+        ]);
+    }
+
+    #[test]
+    fn number_format() {
+        check(&[
+            (json!(["number-format", 1234.5678, {}]), json!("1,234.568")),
+            (json!(["number-format", -1234.5, {}]), json!("-1,234.5")),
+            (json!(["number-format", 1000000, {}]), json!("1,000,000")),
+            (
+                json!(["number-format", 1234.5, { "min-fraction-digits": 2 }]),
+                json!("1,234.50"),
+            ),
+            (
+                json!(["number-format", 1.5, { "min-fraction-digits": 4 }]),
+                json!("1.5000"),
+            ),
+            (
+                json!(["number-format", 3.14159, { "max-fraction-digits": 2 }]),
+                json!("3.14"),
+            ),
+            // Halves round away from zero, as Intl.NumberFormat rounds them
+            (
+                json!(["number-format", 0.5, { "max-fraction-digits": 0 }]),
+                json!("1"),
+            ),
+            (
+                json!(["number-format", 0.25, { "max-fraction-digits": 1 }]),
+                json!("0.3"),
+            ),
+            (
+                json!(["number-format", -2.5, { "max-fraction-digits": 0 }]),
+                json!("-3"),
+            ),
+            (
+                json!(["number-format", 1.005, { "max-fraction-digits": 2 }]),
+                json!("1.01"),
+            ),
+            (json!(["number-format", 999.9996, {}]), json!("1,000")),
+            (
+                json!(["number-format", 1234.5, { "currency": "USD" }]),
+                json!("$1,234.50"),
+            ),
+            (
+                json!(["number-format", 1234.5, { "currency": "JPY" }]),
+                json!("¥1,235"),
+            ),
+            (
+                json!(["number-format", 1234.5, { "locale": "de" }]),
+                json!("1.234,5"),
+            ),
+            (
+                json!(["number-format", 1234.5, { "locale": "de-DE", "currency": "EUR" }]),
+                json!("1.234,50 €"),
+            ),
+            (
+                json!(["number-format", 1234.5, { "locale": "fr" }]),
+                json!("1\u{202f}234,5"),
+            ),
+            (
+                json!(["number-format", ["get", "rank"], { "min-fraction-digits": ["get", "rank"] }]),
+                json!("3.000"),
+            ),
+            (json!(["number-format", ["get", "name"], {}]), json!(null)),
+        ]);
+    }
+
+    #[test]
+    fn array_slices() {
+        check(&[
+            (
+                json!(["slice", ["literal", [1, 2, 3, 4]], 1]),
+                json!([2, 3, 4]),
+            ),
+            (
+                json!(["slice", ["literal", [1, 2, 3, 4]], -2]),
+                json!([3, 4]),
+            ),
+            (
+                json!(["slice", ["literal", [1, 2, 3, 4]], 1, -1]),
+                json!([2, 3]),
+            ),
+            (json!(["slice", ["literal", [1, 2, 3, 4]], 10]), json!([])),
+            (
+                json!(["slice", ["literal", [1, 2, 3, 4]], -10, 2]),
+                json!([1, 2]),
+            ),
+            (json!(["slice", ["literal", [1, 2, 3, 4]], 3, 1]), json!([])),
+            (json!(["length", ["literal", [1, 2, 3, 4]]]), json!(4)),
+            (json!(["length", ["get", "name"]]), json!(11)),
+        ]);
+    }
+
+    #[test]
+    fn array_index_of() {
+        check(&[
+            (json!(["index-of", 3, ["literal", [1, 2, 3, 3]]]), json!(2)),
+            (
+                json!(["index-of", 3, ["literal", [1, 2, 3, 3]], 3]),
+                json!(3),
+            ),
+            // Arrays are searched from the end for negative indices, as in javascript
+            (
+                json!(["index-of", 3, ["literal", [1, 2, 3, 3]], -1]),
+                json!(3),
+            ),
+            (
+                json!(["index-of", 1, ["literal", [1, 2, 3, 3]], -1]),
+                json!(-1),
+            ),
+            (
+                json!(["index-of", 3, ["literal", [1, 2, 3, 3]], -10]),
+                json!(2),
+            ),
+            (
+                json!(["index-of", 3, ["literal", [1, 2, 3, 3]], 10]),
+                json!(-1),
+            ),
+            (json!(["index-of", "a", ["literal", ["b", "a"]]]), json!(1)),
+            (json!(["index-of", "3", ["literal", [1, 2, 3]]]), json!(-1)),
+            (
+                json!(["index-of", ["get", "rank"], ["literal", [1, 2, 3]]]),
+                json!(2),
+            ),
+            (json!(["in", 2, ["literal", [1, 2, 3]]]), json!(true)),
+        ]);
+    }
+
+    #[test]
+    fn conversions() {
+        check(&[
+            // to-number takes the first value that converts
+            (json!(["to-number", "abc", "12", 5]), json!(12)),
+            (json!(["to-number", ["get", "missing"], 5]), json!(0)),
+            (
+                json!(["to-number", ["literal", [1]], " 2.5 ", 5]),
+                json!(2.5),
+            ),
+            (json!(["to-number", ["get", "flag"]]), json!(1)),
+            (json!(["to-number", false]), json!(0)),
+            (json!(["to-number", ["get", "empty"]]), json!(0)),
+            (json!(["to-number", "abc", "def"]), json!(null)),
+            // Strings convert the way JavaScript's Number does
+            (
+                json!(["to-number", "inf", "NaN", "infinity", "INF", 7]),
+                json!(7),
+            ),
+            (json!(["to-number", "nan"]), json!(null)),
+            (json!(["<", 1e308, ["to-number", "Infinity"]]), json!(true)),
+            (
+                json!([">", -1e308, ["to-number", " -Infinity "]]),
+                json!(true),
+            ),
+            (json!(["to-number", "0x1F"]), json!(31)),
+            (json!(["to-number", "0b101"]), json!(5)),
+            (json!(["to-number", "0o17"]), json!(15)),
+            (json!(["to-number", "-0x10", "0x", "0b2", 1]), json!(1)),
+            (json!(["to-number", " 1e3 "]), json!(1000)),
+            (json!(["to-number", "-.5"]), json!(-0.5)),
+            (json!(["to-number", "1_000", "1e", ".", "+-1", 2]), json!(2)),
+            // to-color does the same for colors
+            (
+                json!(["to-rgba", ["to-color", "not a color", "#ff0000", "blue"]]),
+                json!([255, 0, 0, 1]),
+            ),
+            (
+                json!(["to-rgba", ["to-color", ["get", "missing"], "blue"]]),
+                json!([0, 0, 255, 1]),
+            ),
+            (
+                json!([
+                    "to-rgba",
+                    ["to-color", ["get", "rank"], "rgba(0, 128, 0, 0.5)"]
+                ]),
+                json!([0, 128, 0, 0.5]),
+            ),
+            (
+                json!(["to-rgba", ["to-color", ["literal", [255, 128, 0]]]]),
+                json!([255, 128, 0, 1]),
+            ),
+            (
+                json!(["to-rgba", ["to-color", ["literal", [0, 0, 255, 0.25]]]]),
+                json!([0, 0, 255, 0.25]),
+            ),
+            (
+                json!(["to-color", ["literal", [0, 0]], "nope"]),
+                json!(null),
+            ),
+            (json!(["to-rgba", "red"]), json!([255, 0, 0, 1])),
+            (
+                json!(["to-string", ["to-color", "red"]]),
+                json!("rgba(255,0,0,1)"),
+            ),
+            (json!(["to-boolean", ["get", "empty"]]), json!(false)),
+            (json!(["to-boolean", ["get", "name"]]), json!(true)),
+            (json!(["to-boolean", 0]), json!(false)),
+            // Colors and formatted text are objects, which are always truthy
+            (json!(["to-boolean", ["to-color", "red"]]), json!(true)),
+            (
+                json!(["to-boolean", ["to-color", "transparent"]]),
+                json!(true),
+            ),
+            (json!(["to-boolean", ["format", "", {}]]), json!(true)),
+            (
+                json!(["typeof", ["literal", [1, 2]]]),
+                json!("array<number, 2>"),
+            ),
+            (
+                json!(["typeof", ["literal", [1, "a"]]]),
+                json!("array<value, 2>"),
+            ),
+            (json!(["typeof", ["literal", {}]]), json!("object")),
+            (json!(["typeof", ["get", "missing"]]), json!("null")),
+        ]);
+    }
+
+    #[test]
+    fn assertions() {
+        check(&[
+            (json!(["array", ["literal", [1, 2]]]), json!([1, 2])),
+            (
+                json!(["array", "number", ["literal", [1, 2]]]),
+                json!([1, 2]),
+            ),
+            (json!(["array", "string", ["literal", [1, 2]]]), json!(null)),
+            (
+                json!(["array", "number", 2, ["literal", [1, 2]]]),
+                json!([1, 2]),
+            ),
+            (
+                json!(["array", "number", 3, ["literal", [1, 2]]]),
+                json!(null),
+            ),
+            (json!(["array", "string", 0, ["literal", []]]), json!([])),
+            (
+                json!(["array", "boolean", ["literal", [true, 1]]]),
+                json!(null),
+            ),
+            (json!(["array", ["get", "name"]]), json!(null)),
+            // The first value of the asserted type is taken
+            (
+                json!(["number", ["get", "name"], ["get", "rank"]]),
+                json!(3),
+            ),
+            (json!(["string", ["get", "rank"], "x"]), json!("x")),
+            (json!(["boolean", ["get", "flag"]]), json!(true)),
+            (
+                json!(["object", ["get", "name"], ["literal", { "a": 1 }]]),
+                json!({ "a": 1 }),
+            ),
+            (json!(["number", ["get", "name"]]), json!(null)),
+        ]);
+
+        for expression in [
+            json!(["array", "point", ["literal", [1]]]),
+            json!(["array", "number", "two", ["literal", [1]]]),
+            json!(["array"]),
+            json!(["number"]),
+        ] {
+            assert!(parse(&expression).is_err(), "{expression}");
+        }
+    }
+
+    #[test]
+    fn let_bindings() {
+        check(&[
+            (json!(["let", "x", 1, ["var", "x"]]), json!(1)),
+            (
+                json!(["let", "a", 1, "b", 2, ["+", ["var", "a"], ["var", "b"]]]),
+                json!(3),
+            ),
+            // Inner bindings shadow outer ones of the same name only within their body
+            (
+                json!(["let", "x", 1, ["let", "x", 2, ["var", "x"]]]),
+                json!(2),
+            ),
+            (
+                json!([
+                    "let",
+                    "x",
+                    1,
+                    ["+", ["let", "x", 2, ["var", "x"]], ["var", "x"]]
+                ]),
+                json!(3),
+            ),
+            // Binding values are evaluated in the enclosing scope
+            (
+                json!([
+                    "let",
+                    "x",
+                    1,
+                    ["let", "x", ["+", ["var", "x"], 10], ["var", "x"]]
+                ]),
+                json!(11),
+            ),
+            (json!(["let", "x", 1, "x", 2, ["var", "x"]]), json!(2)),
+            (
+                json!(["let", "n", ["get", "name"], ["concat", ["var", "n"], "!"]]),
+                json!("Main Street!"),
+            ),
+            (json!(["var", "unbound"]), json!(null)),
+        ]);
+
+        for expression in [
+            json!(["let", ["var", "x"]]),
+            json!(["let", "x", 1, "y", ["var", "x"]]),
+            json!(["let", 1, 2, ["var", "x"]]),
+            json!(["var"]),
+        ] {
+            assert!(parse(&expression).is_err(), "{expression}");
+        }
+    }
+
+    #[test]
+    fn at() {
+        check(&[
+            (json!(["at", 0, ["literal", ["a", "b", "c"]]]), json!("a")),
+            (json!(["at", 2, ["literal", ["a", "b", "c"]]]), json!("c")),
+            // Indices outside the array, or that aren't whole numbers, give null
+            (json!(["at", 3, ["literal", ["a", "b", "c"]]]), json!(null)),
+            (json!(["at", -1, ["literal", ["a", "b", "c"]]]), json!(null)),
+            (
+                json!(["at", 1.5, ["literal", ["a", "b", "c"]]]),
+                json!(null),
+            ),
+            (json!(["at", 0, ["literal", []]]), json!(null)),
+            (
+                json!(["at", ["get", "missing"], ["literal", [1]]]),
+                json!(null),
+            ),
+            (json!(["at", 0, ["get", "name"]]), json!(null)),
+        ]);
+    }
+
+    #[test]
+    fn feature_accessors() {
+        check(&[
+            (json!(["id"]), json!(7)),
+            (json!(["geometry-type"]), json!("Point")),
+            (json!(["has", "flag", ["properties"]]), json!(true)),
+            (json!(["has", "missing", ["properties"]]), json!(false)),
+            (json!(["!", ["has", "missing"]]), json!(true)),
+            (json!(["!", ["get", "flag"]]), json!(false)),
+        ]);
+    }
+}
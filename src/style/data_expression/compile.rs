@@ -0,0 +1,685 @@
+use std::collections::HashMap;
+
+use bstr::{BStr, BString, ByteSlice};
+
+use super::{ColorSpace, DataExpression, ExpressionValue, ValueType};
+use crate::FeatureView;
+
+/// A property key interned when the style is loaded. Tile layers map their keys to these
+/// once so features can be matched without comparing strings.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct KeyId(u32);
+
+#[derive(Debug, Default, Clone)]
+pub struct PropertyKeys {
+    ids: HashMap<BString, KeyId>,
+}
+
+impl PropertyKeys {
+    fn intern(&mut self, name: &BStr) -> KeyId {
+        let next = KeyId(self.ids.len() as u32);
+        *self.ids.entry(name.to_owned()).or_insert(next)
+    }
+
+    /// Maps each of a tile layer's keys to its interned id, keys no expression reads map to `None`
+    pub fn resolve<'k, I: IntoIterator<Item = &'k [u8]>>(&self, keys: I) -> Vec<Option<KeyId>> {
+        keys.into_iter()
+            .map(|key| self.ids.get(key.as_bstr()).copied())
+            .collect()
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ExpressionType {
+    Number,
+    String,
+    Boolean,
+    Color,
+    Array,
+    Object,
+    Formatted,
+    Null,
+    Value,
+}
+
+impl ExpressionType {
+    /// Whether a value of this type can be used where `expected` is required, `value`
+    /// typed expressions such as `get` are checked when they are evaluated
+    pub fn is_compatible(&self, expected: ExpressionType) -> bool {
+        use ExpressionType as T;
+
+        match (self, expected) {
+            (a, b) if *a == b => true,
+            (T::Value | T::Null, _) | (_, T::Value) => true,
+            // Strings are parsed as colors
+            (T::String, T::Color) => true,
+            (T::String | T::Number | T::Boolean, T::Formatted) => true,
+            _ => false,
+        }
+    }
+
+    fn unify(self, other: ExpressionType) -> ExpressionType {
+        match (self, other) {
+            (a, b) if a == b => a,
+            (ExpressionType::Null, b) => b,
+            (a, ExpressionType::Null) => a,
+            _ => ExpressionType::Value,
+        }
+    }
+
+    fn of(value: &ExpressionValue<'_>) -> ExpressionType {
+        match value {
+            ExpressionValue::String(_) | ExpressionValue::Str(_) => ExpressionType::String,
+            ExpressionValue::Number(_) => ExpressionType::Number,
+            ExpressionValue::Bool(_) => ExpressionType::Boolean,
+            ExpressionValue::Color(_) => ExpressionType::Color,
+            ExpressionValue::Formatted(_) => ExpressionType::Formatted,
+            ExpressionValue::Array(_) => ExpressionType::Array,
+            ExpressionValue::Object(_) => ExpressionType::Object,
+            ExpressionValue::Null => ExpressionType::Null,
+        }
+    }
+}
+
+impl From<ValueType> for ExpressionType {
+    fn from(value: ValueType) -> Self {
+        match value {
+            ValueType::Number => ExpressionType::Number,
+            ValueType::String => ExpressionType::String,
+            ValueType::Boolean => ExpressionType::Boolean,
+            ValueType::Color => ExpressionType::Color,
+            ValueType::Object => ExpressionType::Object,
+            ValueType::Value => ExpressionType::Value,
+        }
+    }
+}
+
+impl std::fmt::Display for ExpressionType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ExpressionType::Number => "number",
+            ExpressionType::String => "string",
+            ExpressionType::Boolean => "boolean",
+            ExpressionType::Color => "color",
+            ExpressionType::Array => "array",
+            ExpressionType::Object => "object",
+            ExpressionType::Formatted => "formatted",
+            ExpressionType::Null => "null",
+            ExpressionType::Value => "value",
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+/// Calls `$f` with each direct subexpression, `$ref` is `&` or `&mut` to match `$exp`
+macro_rules! for_each_child {
+    ($exp:expr, $f:ident, $iter:ident, $($ref:tt)+) => {
+        match $exp {
+            DataExpression::All(values)
+            | DataExpression::Any(values)
+            | DataExpression::Math(_, values)
+            | DataExpression::Concat(values)
+            | DataExpression::Coalesce(values)
+            | DataExpression::Assert(_, values)
+            | DataExpression::ToNumber(values)
+            | DataExpression::ToColor(values) => values.$iter().for_each(|v| $f(v)),
+            DataExpression::Get(value)
+            | DataExpression::ToBoolean(value)
+            | DataExpression::Upcase(value)
+            | DataExpression::Downcase(value)
+            | DataExpression::ToString(value)
+            | DataExpression::Length(value)
+            | DataExpression::IsSupportedScript(value)
+            | DataExpression::ToRgba(value)
+            | DataExpression::TypeOf(value)
+            | DataExpression::Not(value)
+            | DataExpression::AssertArray(_, _, value) => $f($($ref)+ **value),
+            DataExpression::Has(key, object) => {
+                $f($($ref)+ **key);
+                if let Some(object) = object {
+                    $f($($ref)+ **object);
+                }
+            }
+            DataExpression::Cmp(_, left, right)
+            | DataExpression::In(left, right)
+            | DataExpression::At(left, right) => {
+                $f($($ref)+ **left);
+                $f($($ref)+ **right);
+            }
+            DataExpression::Slice(input, left, right) | DataExpression::IndexOf(input, left, right) => {
+                $f($($ref)+ **input);
+                $f($($ref)+ **left);
+                if let Some(right) = right {
+                    $f($($ref)+ **right);
+                }
+            }
+            DataExpression::Match(input, cases, fallback) => {
+                $f($($ref)+ **input);
+                for (label, value) in cases.$iter() {
+                    $f(label);
+                    $f(value);
+                }
+                $f($($ref)+ **fallback);
+            }
+            DataExpression::Case(cases, fallback) => {
+                for (condition, value) in cases.$iter() {
+                    $f(condition);
+                    $f(value);
+                }
+                $f($($ref)+ **fallback);
+            }
+            DataExpression::Interpolate(_, _, input, stops) => {
+                $f($($ref)+ **input);
+                stops.$iter().for_each(|(_, v)| $f(v));
+            }
+            DataExpression::Step(input, first, stops) => {
+                $f($($ref)+ **input);
+                $f($($ref)+ **first);
+                stops.$iter().for_each(|(_, v)| $f(v));
+            }
+            DataExpression::Let(bindings, body) => {
+                bindings.$iter().for_each(|(_, v)| $f(v));
+                $f($($ref)+ **body);
+            }
+            DataExpression::NumberFormat(value, options) => {
+                $f($($ref)+ **value);
+                for option in [
+                    $($ref)+ options.locale,
+                    $($ref)+ options.currency,
+                    $($ref)+ options.min_fraction_digits,
+                    $($ref)+ options.max_fraction_digits,
+                ] {
+                    if let Some(option) = option {
+                        $f(option);
+                    }
+                }
+            }
+            DataExpression::ResolvedLocale(collator) => {
+                if let Some(locale) = $($ref)+ collator.locale {
+                    $f(locale);
+                }
+            }
+            DataExpression::Format(sections) => {
+                for section in sections.$iter() {
+                    $f($($ref)+ section.text);
+                    if let Some(scale) = $($ref)+ section.options.font_scale {
+                        $f(scale);
+                    }
+                    if let Some(color) = $($ref)+ section.options.text_color {
+                        $f(color);
+                    }
+                }
+            }
+            DataExpression::Zoom
+            | DataExpression::LineProgress
+            | DataExpression::Var(_)
+            | DataExpression::Id
+            | DataExpression::GeometryType
+            | DataExpression::Properties
+            | DataExpression::Property(_)
+            | DataExpression::HasProperty(_)
+            | DataExpression::Constant(_) => (),
+        }
+    };
+}
+
+impl DataExpression<'static> {
+    /// Converts a legacy `text-field` string with `{key}` tokens into the equivalent expression
+    pub fn from_tokens(format: &BStr) -> Self {
+        let mut parts = Vec::new();
+        let mut literal = BString::default();
+        let mut in_field = false;
+        let mut span_start = 0;
+
+        for (start, end, c) in format.char_indices() {
+            match c {
+                '{' if in_field == false => {
+                    span_start = end;
+                    in_field = true;
+                }
+                '}' if in_field == true => {
+                    if !literal.is_empty() {
+                        let text = std::mem::take(&mut literal);
+                        parts.push(DataExpression::Constant(ExpressionValue::String(text)));
+                    }
+
+                    let key = BString::from(&format[span_start..start]);
+                    let key = DataExpression::Constant(ExpressionValue::String(key));
+                    let get = DataExpression::Get(Box::new(key));
+                    parts.push(DataExpression::ToString(Box::new(get)));
+                    in_field = false
+                }
+                c if in_field == false => literal.extend(c.encode_utf8(&mut [0; 4]).bytes()),
+                _ => (),
+            }
+        }
+
+        if !literal.is_empty() {
+            parts.push(DataExpression::Constant(ExpressionValue::String(literal)));
+        }
+
+        match parts.len() {
+            0 => DataExpression::Constant(ExpressionValue::String(BString::default())),
+            1 => parts.pop().unwrap(),
+            _ => DataExpression::Concat(parts),
+        }
+    }
+
+    /// Type checks the expression, then folds constant subexpressions and interns property keys
+    pub fn compile(&mut self, keys: &mut PropertyKeys) -> Result<ExpressionType, String> {
+        let ty = self.output_type(&mut Vec::new())?;
+        self.fold(keys);
+        Ok(ty)
+    }
+
+    /// `zoom` may only be the input of a top level curve in a property, where the curve can
+    /// be evaluated at the camera's zoom rather than the tile's
+    pub fn check_zoom_placement(&self) -> Result<(), String> {
+        let nested = match self {
+            DataExpression::Interpolate(_, _, input, stops)
+                if matches!(**input, DataExpression::Zoom) =>
+            {
+                stops.iter().any(|(_, v)| v.contains_zoom())
+            }
+            DataExpression::Step(input, first, stops)
+                if matches!(**input, DataExpression::Zoom) =>
+            {
+                first.contains_zoom() || stops.iter().any(|(_, v)| v.contains_zoom())
+            }
+            exp => exp.contains_zoom(),
+        };
+
+        if nested {
+            Err("zoom expression may only be used as input to a top-level step or interpolate expression".to_string())
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn contains_zoom(&self) -> bool {
+        let mut zoom = matches!(self, DataExpression::Zoom);
+        self.for_each_child(&mut |child| zoom |= child.contains_zoom());
+        zoom
+    }
+
+    pub fn contains_line_progress(&self) -> bool {
+        let mut progress = matches!(self, DataExpression::LineProgress);
+        self.for_each_child(&mut |child| progress |= child.contains_line_progress());
+        progress
+    }
+
+    fn for_each_child<F: FnMut(&DataExpression<'static>)>(&self, f: &mut F) {
+        for_each_child!(self, f, iter, &)
+    }
+
+    fn for_each_child_mut<F: FnMut(&mut DataExpression<'static>)>(&mut self, f: &mut F) {
+        for_each_child!(self, f, iter_mut, &mut)
+    }
+
+    fn fold(&mut self, keys: &mut PropertyKeys) {
+        self.for_each_child_mut(&mut |child| child.fold(keys));
+
+        match self {
+            DataExpression::Get(key) => {
+                if let Some(name) = key.property_name() {
+                    let id = keys.intern(name);
+                    *self = DataExpression::Property(id);
+                }
+            }
+            DataExpression::Has(key, None) => {
+                if let Some(name) = key.property_name() {
+                    let id = keys.intern(name);
+                    *self = DataExpression::HasProperty(id);
+                }
+            }
+            exp if exp.is_foldable() => {
+                let value = exp.eval(&FeatureView::empty(0.0)).into_static();
+                *exp = DataExpression::Constant(value);
+            }
+            _ => (),
+        }
+    }
+
+    /// Constant keys other than the legacy `$type` and `$id` keys can be interned
+    fn property_name(&self) -> Option<&BStr> {
+        match self {
+            DataExpression::Constant(ExpressionValue::String(name)) if !name.starts_with(b"$") => {
+                Some(name.as_ref())
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the expression gives the same value for every feature at every zoom
+    fn is_foldable(&self) -> bool {
+        let reads_input = matches!(
+            self,
+            DataExpression::Zoom
+                | DataExpression::LineProgress
+                | DataExpression::Var(_)
+                | DataExpression::Id
+                | DataExpression::GeometryType
+                | DataExpression::Properties
+                | DataExpression::Get(_)
+                | DataExpression::Has(_, None)
+                | DataExpression::Let(..)
+                | DataExpression::Property(_)
+                | DataExpression::HasProperty(_)
+                | DataExpression::Constant(_)
+        );
+
+        let mut constant = !reads_input;
+        self.for_each_child(&mut |child| constant &= matches!(child, DataExpression::Constant(_)));
+        constant
+    }
+
+    fn output_type(&self, vars: &mut Vec<BString>) -> Result<ExpressionType, String> {
+        use ExpressionType as T;
+
+        let name = self.name();
+        let mut expect = |exp: &DataExpression<'static>, expected: ExpressionType| {
+            let ty = exp.output_type(vars)?;
+            if ty.is_compatible(expected) {
+                Ok(ty)
+            } else {
+                Err(format!(
+                    "expected {expected} argument for {name} expression, found {ty}"
+                ))
+            }
+        };
+
+        let ty = match self {
+            DataExpression::All(values) | DataExpression::Any(values) => {
+                for value in values {
+                    expect(value, T::Boolean)?;
+                }
+                T::Boolean
+            }
+            DataExpression::In(needle, haystack) => {
+                expect(needle, T::Value)?;
+                array_or_string(expect(haystack, T::Value)?, name)?;
+                T::Boolean
+            }
+            DataExpression::Has(key, object) => {
+                expect(key, T::String)?;
+                if let Some(object) = object {
+                    expect(object, T::Object)?;
+                }
+                T::Boolean
+            }
+            DataExpression::HasProperty(_) => T::Boolean,
+            DataExpression::Get(key) => {
+                expect(key, T::String)?;
+                T::Value
+            }
+            DataExpression::Property(_) => T::Value,
+            DataExpression::Cmp(_, left, right) => {
+                let left = expect(left, T::Value)?;
+                let right = expect(right, T::Value)?;
+                if !left.is_compatible(right) && !right.is_compatible(left) {
+                    return Err(format!("cannot compare {left} and {right}"));
+                }
+                T::Boolean
+            }
+            DataExpression::ToBoolean(value) => {
+                expect(value, T::Value)?;
+                T::Boolean
+            }
+            DataExpression::Not(value) => {
+                expect(value, T::Boolean)?;
+                T::Boolean
+            }
+            DataExpression::Match(input, cases, fallback) => {
+                expect(input, T::Value)?;
+                let mut ty = expect(fallback, T::Value)?;
+                for (label, value) in cases {
+                    expect(label, T::Value)?;
+                    ty = ty.unify(expect(value, T::Value)?);
+                }
+                ty
+            }
+            DataExpression::Case(cases, fallback) => {
+                let mut ty = expect(fallback, T::Value)?;
+                for (condition, value) in cases {
+                    expect(condition, T::Boolean)?;
+                    ty = ty.unify(expect(value, T::Value)?);
+                }
+                ty
+            }
+            DataExpression::Interpolate(_, space, input, stops) => {
+                expect(input, T::Number)?;
+                let mut ty = T::Null;
+                for (_, value) in stops {
+                    ty = ty.unify(expect(value, T::Value)?);
+                }
+
+                // Color stops are usually written as strings
+                if *space != ColorSpace::Rgb {
+                    if !matches!(ty, T::Color | T::String | T::Value | T::Null) {
+                        return Err(format!(
+                            "{} expression outputs must be colors, found {ty}",
+                            self.name()
+                        ));
+                    }
+                    T::Color
+                } else if !matches!(
+                    ty,
+                    T::Number | T::Color | T::String | T::Array | T::Value | T::Null
+                ) {
+                    return Err(format!(
+                        "interpolate expression outputs must be numbers, colors or arrays, found {ty}"
+                    ));
+                } else {
+                    ty
+                }
+            }
+            DataExpression::Step(input, first, stops) => {
+                expect(input, T::Number)?;
+                let mut ty = expect(first, T::Value)?;
+                for (_, value) in stops {
+                    ty = ty.unify(expect(value, T::Value)?);
+                }
+                ty
+            }
+            DataExpression::Zoom | DataExpression::LineProgress => T::Number,
+            DataExpression::Math(_, values) => {
+                for value in values {
+                    expect(value, T::Number)?;
+                }
+                T::Number
+            }
+            DataExpression::Concat(values) => {
+                for value in values {
+                    expect(value, T::Value)?;
+                }
+                T::String
+            }
+            DataExpression::Upcase(value) | DataExpression::Downcase(value) => {
+                expect(value, T::String)?;
+                T::String
+            }
+            DataExpression::ToString(value) => {
+                expect(value, T::Value)?;
+                T::String
+            }
+            DataExpression::NumberFormat(value, options) => {
+                expect(value, T::Number)?;
+                for (option, ty) in [
+                    (&options.locale, T::String),
+                    (&options.currency, T::String),
+                    (&options.min_fraction_digits, T::Number),
+                    (&options.max_fraction_digits, T::Number),
+                ] {
+                    if let Some(option) = option {
+                        expect(option, ty)?;
+                    }
+                }
+                T::String
+            }
+            DataExpression::Coalesce(values) => {
+                let mut ty = T::Null;
+                for value in values {
+                    ty = ty.unify(expect(value, T::Value)?);
+                }
+                ty
+            }
+            DataExpression::Length(value) => {
+                array_or_string(expect(value, T::Value)?, name)?;
+                T::Number
+            }
+            DataExpression::Slice(input, start, end) => {
+                let ty = array_or_string(expect(input, T::Value)?, name)?;
+                expect(start, T::Number)?;
+                if let Some(end) = end {
+                    expect(end, T::Number)?;
+                }
+                ty
+            }
+            DataExpression::IndexOf(keyword, input, from) => {
+                expect(keyword, T::Value)?;
+                array_or_string(expect(input, T::Value)?, name)?;
+                if let Some(from) = from {
+                    expect(from, T::Number)?;
+                }
+                T::Number
+            }
+            DataExpression::IsSupportedScript(value) => {
+                expect(value, T::String)?;
+                T::Boolean
+            }
+            DataExpression::ResolvedLocale(collator) => {
+                if let Some(locale) = &collator.locale {
+                    expect(locale, T::String)?;
+                }
+                T::String
+            }
+            DataExpression::Format(sections) => {
+                for section in sections {
+                    expect(&section.text, T::Value)?;
+                    if let Some(scale) = &section.options.font_scale {
+                        expect(scale, T::Number)?;
+                    }
+                    if let Some(color) = &section.options.text_color {
+                        expect(color, T::Color)?;
+                    }
+                }
+                T::Formatted
+            }
+            DataExpression::Assert(ty, values) => {
+                for value in values {
+                    expect(value, T::Value)?;
+                }
+                (*ty).into()
+            }
+            DataExpression::AssertArray(_, _, value) => {
+                expect(value, T::Array)?;
+                T::Array
+            }
+            DataExpression::ToNumber(values) | DataExpression::ToColor(values) => {
+                for value in values {
+                    expect(value, T::Value)?;
+                }
+                match self {
+                    DataExpression::ToNumber(_) => T::Number,
+                    _ => T::Color,
+                }
+            }
+            DataExpression::ToRgba(value) => {
+                expect(value, T::Color)?;
+                T::Array
+            }
+            DataExpression::TypeOf(value) => {
+                expect(value, T::Value)?;
+                T::String
+            }
+            DataExpression::Let(bindings, body) => {
+                for (_, value) in bindings {
+                    expect(value, T::Value)?;
+                }
+
+                let scope_len = vars.len();
+                vars.extend(bindings.iter().map(|(name, _)| name.clone()));
+                let ty = body.output_type(vars);
+                vars.truncate(scope_len);
+                ty?
+            }
+            DataExpression::Var(var) => {
+                if !vars.contains(var) {
+                    return Err(format!("unknown variable '{var}'"));
+                }
+                T::Value
+            }
+            DataExpression::At(index, array) => {
+                expect(index, T::Number)?;
+                expect(array, T::Array)?;
+                T::Value
+            }
+            DataExpression::Id => T::Value,
+            DataExpression::GeometryType => T::String,
+            DataExpression::Properties => T::Object,
+            DataExpression::Constant(value) => ExpressionType::of(value),
+        };
+
+        Ok(ty)
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            DataExpression::All(_) => "all",
+            DataExpression::Any(_) => "any",
+            DataExpression::In(..) => "in",
+            DataExpression::Has(..) | DataExpression::HasProperty(_) => "has",
+            DataExpression::Get(_) | DataExpression::Property(_) => "get",
+            DataExpression::Cmp(..) => "comparison",
+            DataExpression::ToBoolean(_) => "to-boolean",
+            DataExpression::Match(..) => "match",
+            DataExpression::Case(..) => "case",
+            DataExpression::Interpolate(_, ColorSpace::Rgb, ..) => "interpolate",
+            DataExpression::Interpolate(_, ColorSpace::Lab, ..) => "interpolate-lab",
+            DataExpression::Interpolate(_, ColorSpace::Hcl, ..) => "interpolate-hcl",
+            DataExpression::Step(..) => "step",
+            DataExpression::Zoom => "zoom",
+            DataExpression::LineProgress => "line-progress",
+            DataExpression::Math(..) => "math",
+            DataExpression::Concat(_) => "concat",
+            DataExpression::Upcase(_) => "upcase",
+            DataExpression::Downcase(_) => "downcase",
+            DataExpression::ToString(_) => "to-string",
+            DataExpression::NumberFormat(..) => "number-format",
+            DataExpression::Coalesce(_) => "coalesce",
+            DataExpression::Length(_) => "length",
+            DataExpression::Slice(..) => "slice",
+            DataExpression::IndexOf(..) => "index-of",
+            DataExpression::IsSupportedScript(_) => "is-supported-script",
+            DataExpression::ResolvedLocale(_) => "resolved-locale",
+            DataExpression::Format(_) => "format",
+            DataExpression::Assert(..) => "type assertion",
+            DataExpression::AssertArray(..) => "array",
+            DataExpression::ToNumber(_) => "to-number",
+            DataExpression::ToColor(_) => "to-color",
+            DataExpression::ToRgba(_) => "to-rgba",
+            DataExpression::TypeOf(_) => "typeof",
+            DataExpression::Let(..) => "let",
+            DataExpression::Var(_) => "var",
+            DataExpression::At(..) => "at",
+            DataExpression::Not(_) => "!",
+            DataExpression::Id => "id",
+            DataExpression::GeometryType => "geometry-type",
+            DataExpression::Properties => "properties",
+            DataExpression::Constant(_) => "literal",
+        }
+    }
+}
+
+/// Checks the input of expressions that take either an array or a string, giving back its type
+fn array_or_string(ty: ExpressionType, name: &str) -> Result<ExpressionType, String> {
+    use ExpressionType as T;
+
+    match ty {
+        T::Array | T::String | T::Value | T::Null => Ok(ty),
+        _ => Err(format!(
+            "expected array or string input for {name} expression, found {ty}"
+        )),
+    }
+}
@@ -0,0 +1,638 @@
+use smallvec::SmallVec;
+
+use super::{Comparison, DataExpression, ExpressionValue, KeyId, MathOp};
+use crate::FeatureView;
+use crate::style::{CameraExpression, ColorSpace, Curve, Interpolate, Interpolation};
+
+/// A compiled expression flattened into operations on a value stack, so evaluating it for a
+/// feature walks a single list instead of the boxed expression tree
+#[derive(Debug, Clone, Default)]
+pub struct Program {
+    ops: Vec<Op>,
+}
+
+#[derive(Debug, Clone)]
+enum Op {
+    Constant(ExpressionValue<'static>),
+    Property(KeyId),
+    HasProperty(KeyId),
+    Id,
+    GeometryType,
+    Zoom,
+    LineProgress,
+    /// Pops the right then the left operand
+    Cmp(Comparison),
+    Not,
+    ToBoolean,
+    In,
+    /// Pops the given number of arguments
+    Math(MathOp, usize),
+    Jump(usize),
+    /// Pops the condition, jumping when it's false
+    Branch(usize),
+    /// Pops the value, jumping with `false` on the stack when it's false
+    AndThen(usize),
+    /// Pops the value, jumping with `true` on the stack when it's true
+    OrElse(usize),
+    /// Pops the value, jumping with it on the stack when it isn't null
+    UnlessNull(usize),
+    /// Pops the input, jumping to the first matching label's target or to the fallback
+    Match(Box<MatchTable>),
+    /// Pops the input, jumping to the target of the step it falls in
+    Step(Box<StepTable>),
+    /// Pops the input, then interpolates between the outputs of the stops around it
+    Interpolate(Interpolation, ColorSpace, Box<[(f64, Program)]>),
+    /// A zoom curve whose outputs didn't depend on the feature and were evaluated when compiled
+    Camera(Box<CameraExpression<ExpressionValue<'static>>>),
+    /// A subexpression with no flat form, evaluated by walking its tree
+    Tree(Box<DataExpression<'static>>),
+}
+
+#[derive(Debug, Clone, Default)]
+struct MatchTable {
+    cases: Vec<(ExpressionValue<'static>, usize)>,
+    fallback: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+struct StepTable {
+    first: usize,
+    stops: Vec<(f64, usize)>,
+    /// The op after the step, where inputs that aren't numbers skip to
+    end: usize,
+}
+
+impl Program {
+    /// Flattens an expression that has already been compiled
+    pub fn new(exp: &DataExpression<'static>) -> Self {
+        let mut program = Program::default();
+        program.push(exp);
+        program
+    }
+
+    fn push(&mut self, exp: &DataExpression<'static>) {
+        match exp {
+            DataExpression::Constant(value) => self.ops.push(Op::Constant(value.clone())),
+            DataExpression::Property(key) => self.ops.push(Op::Property(*key)),
+            DataExpression::HasProperty(key) => self.ops.push(Op::HasProperty(*key)),
+            DataExpression::Id => self.ops.push(Op::Id),
+            DataExpression::GeometryType => self.ops.push(Op::GeometryType),
+            DataExpression::Zoom => self.ops.push(Op::Zoom),
+            DataExpression::LineProgress => self.ops.push(Op::LineProgress),
+            DataExpression::Cmp(cmp, left, right) => {
+                self.push(left);
+                self.push(right);
+                self.ops.push(Op::Cmp(*cmp));
+            }
+            DataExpression::Not(value) => {
+                self.push(value);
+                self.ops.push(Op::Not);
+            }
+            DataExpression::ToBoolean(value) => {
+                self.push(value);
+                self.ops.push(Op::ToBoolean);
+            }
+            DataExpression::In(needle, haystack) => {
+                self.push(needle);
+                self.push(haystack);
+                self.ops.push(Op::In);
+            }
+            DataExpression::Math(op, args) => {
+                args.iter().for_each(|arg| self.push(arg));
+                self.ops.push(Op::Math(*op, args.len()));
+            }
+            DataExpression::All(values) => {
+                let jumps = self.push_each(values, Op::AndThen(0));
+                self.ops.push(Op::Constant(true.into()));
+                self.patch(jumps);
+            }
+            DataExpression::Any(values) => {
+                let jumps = self.push_each(values, Op::OrElse(0));
+                self.ops.push(Op::Constant(false.into()));
+                self.patch(jumps);
+            }
+            DataExpression::Coalesce(values) => {
+                let jumps = self.push_each(values, Op::UnlessNull(0));
+                self.ops.push(Op::Constant(ExpressionValue::Null));
+                self.patch(jumps);
+            }
+            DataExpression::Case(cases, fallback) => {
+                let mut ends = Vec::new();
+                for (condition, value) in cases {
+                    self.push(condition);
+                    let branch = self.ops.len();
+                    self.ops.push(Op::Branch(0));
+                    self.push(value);
+                    ends.push(self.ops.len());
+                    self.ops.push(Op::Jump(0));
+                    self.patch([branch]);
+                }
+                self.push(fallback);
+                self.patch(ends);
+            }
+            DataExpression::Match(input, cases, fallback) => {
+                self.push(input);
+                let at = self.ops.len();
+                self.ops.push(Op::Match(Box::default()));
+
+                let mut table = MatchTable::default();
+                let mut ends = Vec::new();
+                for (label, value) in cases {
+                    let DataExpression::Constant(label) = label else {
+                        unreachable!("match labels are constants");
+                    };
+                    table.cases.push((label.clone(), self.ops.len()));
+                    self.push(value);
+                    ends.push(self.ops.len());
+                    self.ops.push(Op::Jump(0));
+                }
+                table.fallback = self.ops.len();
+                self.push(fallback);
+                self.patch(ends);
+                self.ops[at] = Op::Match(Box::new(table));
+            }
+            DataExpression::Interpolate(interpolation, space, input, stops) => {
+                if let Some(camera) = camera_curve(exp) {
+                    self.ops.push(Op::Camera(Box::new(camera)));
+                    return;
+                }
+
+                self.push(input);
+                let stops = stops
+                    .iter()
+                    .map(|(stop, output)| (*stop, Program::new(output)))
+                    .collect();
+                self.ops
+                    .push(Op::Interpolate(*interpolation, *space, stops));
+            }
+            DataExpression::Step(input, first, stops) => {
+                if let Some(camera) = camera_curve(exp) {
+                    self.ops.push(Op::Camera(Box::new(camera)));
+                    return;
+                }
+
+                self.push(input);
+                let at = self.ops.len();
+                self.ops.push(Op::Step(Box::default()));
+
+                let mut table = StepTable {
+                    first: self.ops.len(),
+                    ..Default::default()
+                };
+                self.push(first);
+                let mut ends = vec![self.ops.len()];
+                self.ops.push(Op::Jump(0));
+                for (stop, output) in stops {
+                    table.stops.push((*stop, self.ops.len()));
+                    self.push(output);
+                    ends.push(self.ops.len());
+                    self.ops.push(Op::Jump(0));
+                }
+                self.patch(ends);
+                table.end = self.ops.len();
+                self.ops[at] = Op::Step(Box::new(table));
+            }
+            exp => self.ops.push(Op::Tree(Box::new(exp.clone()))),
+        }
+    }
+
+    /// Pushes each value followed by a copy of `jump`, returning the jumps to patch
+    fn push_each(&mut self, values: &[DataExpression<'static>], jump: Op) -> Vec<usize> {
+        let mut jumps = Vec::new();
+        for value in values {
+            self.push(value);
+            jumps.push(self.ops.len());
+            self.ops.push(jump.clone());
+        }
+        jumps
+    }
+
+    /// Points the jumps at the given ops to the next op pushed
+    fn patch<I: IntoIterator<Item = usize>>(&mut self, jumps: I) {
+        let next = self.ops.len();
+        for at in jumps {
+            match &mut self.ops[at] {
+                Op::Jump(target)
+                | Op::Branch(target)
+                | Op::AndThen(target)
+                | Op::OrElse(target)
+                | Op::UnlessNull(target) => *target = next,
+                op => unreachable!("{op:?} doesn't jump"),
+            }
+        }
+    }
+
+    pub fn eval<'f>(&'f self, feature: &'f FeatureView<'_>) -> ExpressionValue<'f> {
+        let mut stack: SmallVec<[ExpressionValue<'f>; 8]> = SmallVec::new();
+        let pop = |stack: &mut SmallVec<[ExpressionValue<'f>; 8]>| {
+            stack.pop().unwrap_or(ExpressionValue::Null)
+        };
+
+        let mut next = 0;
+        while let Some(op) = self.ops.get(next) {
+            next += 1;
+
+            let value = match op {
+                Op::Constant(value) => value.ref_clone(),
+                Op::Property(key) => match feature.property(*key) {
+                    Some(value) => value.into(),
+                    None => ExpressionValue::Null,
+                },
+                Op::HasProperty(key) => feature.property(*key).is_some().into(),
+                Op::Id => match feature.id() {
+                    Some(id) => (id as f64).into(),
+                    None => ExpressionValue::Null,
+                },
+                Op::GeometryType => ExpressionValue::Str(feature.geometry_type().into()),
+                Op::Zoom => (feature.zoom() as f64).into(),
+                Op::LineProgress => (feature.line_progress() as f64).into(),
+                Op::Cmp(cmp) => {
+                    let right = pop(&mut stack);
+                    let left = pop(&mut stack);
+                    cmp.cmp(&left, &right).into()
+                }
+                Op::Not => (!bool::from(pop(&mut stack))).into(),
+                Op::ToBoolean => bool::from(pop(&mut stack)).into(),
+                Op::In => {
+                    let haystack = pop(&mut stack);
+                    let needle = pop(&mut stack);
+                    super::contains(&needle, haystack).into()
+                }
+                Op::Math(op, count) => {
+                    let args: Option<SmallVec<[f64; 4]>> = stack
+                        .drain(stack.len().saturating_sub(*count)..)
+                        .map(|arg| arg.as_number())
+                        .collect();
+
+                    args.and_then(|args| op.apply(&args))
+                        .map(ExpressionValue::Number)
+                        .unwrap_or(ExpressionValue::Null)
+                }
+                Op::Jump(target) => {
+                    next = *target;
+                    continue;
+                }
+                Op::Branch(target) => {
+                    if !bool::from(pop(&mut stack)) {
+                        next = *target;
+                    }
+                    continue;
+                }
+                Op::AndThen(target) => {
+                    if bool::from(pop(&mut stack)) {
+                        continue;
+                    }
+                    next = *target;
+                    false.into()
+                }
+                Op::OrElse(target) => {
+                    if !bool::from(pop(&mut stack)) {
+                        continue;
+                    }
+                    next = *target;
+                    true.into()
+                }
+                Op::UnlessNull(target) => {
+                    let value = pop(&mut stack);
+                    if matches!(value, ExpressionValue::Null) {
+                        continue;
+                    }
+                    next = *target;
+                    value
+                }
+                Op::Match(table) => {
+                    let input = pop(&mut stack);
+                    next = table
+                        .cases
+                        .iter()
+                        .find(|(label, _)| input == *label)
+                        .map_or(table.fallback, |(_, target)| *target);
+                    continue;
+                }
+                Op::Step(table) => match pop(&mut stack).as_number() {
+                    Some(input) => {
+                        let step = table.stops.partition_point(|(stop, _)| *stop <= input);
+                        next = match step.checked_sub(1) {
+                            Some(idx) => table.stops[idx].1,
+                            None => table.first,
+                        };
+                        continue;
+                    }
+                    None => {
+                        next = table.end;
+                        ExpressionValue::Null
+                    }
+                },
+                Op::Interpolate(interpolation, space, stops) => {
+                    let input = pop(&mut stack).as_number();
+                    interpolate(*interpolation, *space, stops, input, feature)
+                }
+                Op::Camera(camera) => camera.eval(feature.zoom()).unwrap_or(ExpressionValue::Null),
+                Op::Tree(exp) => exp.eval(feature),
+            };
+
+            stack.push(value);
+        }
+
+        pop(&mut stack)
+    }
+}
+
+fn interpolate<'f>(
+    interpolation: Interpolation,
+    space: ColorSpace,
+    stops: &'f [(f64, Program)],
+    input: Option<f64>,
+    feature: &'f FeatureView<'_>,
+) -> ExpressionValue<'f> {
+    let (Some(input), Some(first), Some(last)) = (input, stops.first(), stops.last()) else {
+        return ExpressionValue::Null;
+    };
+
+    if input <= first.0 {
+        return first.1.eval(feature);
+    } else if input >= last.0 {
+        return last.1.eval(feature);
+    }
+
+    let upper_idx = stops.partition_point(|(stop, _)| *stop <= input);
+    let (lower, lower_value) = &stops[upper_idx - 1];
+    let (upper, upper_value) = &stops[upper_idx];
+
+    let factor = interpolation.factor(input as f32, *lower as f32, *upper as f32);
+
+    lower_value
+        .eval(feature)
+        .interpolate_in(space, factor, upper_value.eval(feature))
+}
+
+/// A zoom curve whose outputs are all constants is folded into its stops
+fn camera_curve(
+    exp: &DataExpression<'static>,
+) -> Option<CameraExpression<ExpressionValue<'static>>> {
+    let constant = |exp: &DataExpression<'static>| match exp {
+        DataExpression::Constant(value) => Some(value.clone()),
+        _ => None,
+    };
+    let stop =
+        |(stop, output): &(f64, DataExpression<'static>)| Some((*stop as f32, constant(output)?));
+
+    match exp {
+        DataExpression::Interpolate(interpolation, space, input, stops)
+            if matches!(**input, DataExpression::Zoom) =>
+        {
+            let stops = stops.iter().map(stop).collect::<Option<_>>()?;
+            Some(CameraExpression::new(
+                Curve::Interpolate(*interpolation, *space),
+                stops,
+            ))
+        }
+        DataExpression::Step(input, first, stops) if matches!(**input, DataExpression::Zoom) => {
+            let first = (f32::NEG_INFINITY, constant(first)?);
+            let stops = std::iter::once(Some(first))
+                .chain(stops.iter().map(stop))
+                .collect::<Option<_>>()?;
+            Some(CameraExpression::new(Curve::Step, stops))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::proto::tile::GeomType;
+    use crate::style::PropertyKeys;
+    use crate::tests::TestLayer;
+
+    /// Evaluates an expression by walking its tree and as a compiled program, which must agree
+    fn eval(
+        expression: &serde_json::Value,
+        properties: serde_json::Value,
+        zoom: f32,
+    ) -> (Program, ExpressionValue<'static>) {
+        let tree: DataExpression<'static> = serde_json::from_value(expression.clone())
+            .unwrap_or_else(|err| panic!("{expression}: {err}"));
+        let mut compiled = tree.clone();
+        let mut keys = PropertyKeys::default();
+        compiled.compile(&mut keys).unwrap();
+        let program = Program::new(&compiled);
+
+        let layer = TestLayer::new(Some(7), GeomType::Linestring, properties).resolve(&keys);
+        let view = layer.view(zoom);
+        let expected = tree.eval(&view).into_static();
+        let value = program.eval(&view).into_static();
+        assert_eq!(value, expected, "{expression}");
+
+        (program, value)
+    }
+
+    #[test]
+    fn programs_match_the_tree() {
+        let properties = json!({ "name": "Main", "rank": 3, "one": 1, "flag": true });
+        let cases = [
+            // all and any stop at the first deciding value, skipping what would fail
+            (
+                json!(["all", ["has", "name"], [">", ["get", "rank"], 2]]),
+                json!(true),
+            ),
+            (
+                json!(["all", ["has", "missing"], ["get", "name"]]),
+                json!(false),
+            ),
+            (json!(["all"]), json!(true)),
+            (
+                json!(["any", ["get", "flag"], ["get", "missing"]]),
+                json!(true),
+            ),
+            (
+                json!(["any", ["==", ["get", "rank"], 1], ["!", ["has", "rank"]]]),
+                json!(false),
+            ),
+            // coalesce takes the first value that isn't null
+            (
+                json!(["coalesce", ["get", "missing"], ["get", "name"], "fallback"]),
+                json!("Main"),
+            ),
+            (
+                json!(["coalesce", ["get", "missing"], ["get", "other"]]),
+                json!(null),
+            ),
+            // case takes the first true condition's value, or the fallback
+            (
+                json!([
+                    "case",
+                    ["<", ["get", "rank"], 2],
+                    "small",
+                    ["<", ["get", "rank"], 4],
+                    "medium",
+                    "large"
+                ]),
+                json!("medium"),
+            ),
+            (
+                json!(["case", ["get", "missing"], "yes", "no"]),
+                json!("no"),
+            ),
+            // match falls back when no label matches, including for missing input
+            (
+                json!(["match", ["get", "rank"], 1, "low", 3, "mid", "other"]),
+                json!("mid"),
+            ),
+            (json!(["match", ["get", "name"], "Side", 1, 0]), json!(0)),
+            (json!(["match", ["get", "missing"], "Main", 1, 0]), json!(0)),
+            // step gives the first output below the first stop or when the input isn't a number
+            (
+                json!(["step", ["get", "rank"], "below", 5, "five", 10, "ten"]),
+                json!("below"),
+            ),
+            (
+                json!(["step", ["get", "rank"], "below", 3, "three", 10, "ten"]),
+                json!("three"),
+            ),
+            (
+                json!(["step", ["sqrt", ["-", ["get", "one"]]], "nan", 0, "zero"]),
+                json!("nan"),
+            ),
+            // Inputs that aren't numbers skip past every output
+            (
+                json!(["step", ["get", "name"], "string", 0, "zero"]),
+                json!(null),
+            ),
+            (
+                json!([
+                    "coalesce",
+                    ["step", ["get", "name"], "string", 0, "zero"],
+                    "after"
+                ]),
+                json!("after"),
+            ),
+            // interpolate evaluates the outputs around the input
+            (
+                json!([
+                    "interpolate",
+                    ["linear"],
+                    ["get", "rank"],
+                    0,
+                    ["get", "one"],
+                    4,
+                    ["*", ["get", "rank"], 2]
+                ]),
+                json!(4.75),
+            ),
+            (
+                json!([
+                    "interpolate",
+                    ["linear"],
+                    ["get", "rank"],
+                    5,
+                    ["get", "rank"],
+                    6,
+                    0
+                ]),
+                json!(3),
+            ),
+            (
+                json!(["interpolate", ["linear"], ["get", "missing"], 0, 1, 1, 2]),
+                json!(null),
+            ),
+            // let and var have no flat form and are walked as a tree with interned keys
+            (
+                json!([
+                    "let",
+                    "r",
+                    ["get", "rank"],
+                    ["+", ["var", "r"], ["get", "one"]]
+                ]),
+                json!(4),
+            ),
+            (
+                json!([
+                    "let",
+                    "n",
+                    ["get", "missing"],
+                    ["coalesce", ["var", "n"], "none"]
+                ]),
+                json!("none"),
+            ),
+            (json!(["get", "rank"]), json!(3)),
+            (json!(["has", "flag"]), json!(true)),
+        ];
+
+        for (expression, expected) in cases {
+            let expected: ExpressionValue<'static> = serde_json::from_value(expected).unwrap();
+            let (_, value) = eval(&expression, properties.clone(), 0.0);
+            assert_eq!(value, expected, "{expression}");
+        }
+    }
+
+    #[test]
+    fn zoom_curves_fold_into_camera_stops() {
+        let interpolate = json!(["interpolate", ["linear"], ["zoom"], 5, 1, 6, ["+", 1, 1]]);
+        let step = json!(["step", ["zoom"], "low", 5, "mid", 10, "high"]);
+
+        for (zoom, expected) in [(4.0, json!(1)), (5.5, json!(1.5)), (7.0, json!(2))] {
+            let expected: ExpressionValue<'static> = serde_json::from_value(expected).unwrap();
+            let (program, value) = eval(&interpolate, json!({}), zoom);
+            assert!(matches!(program.ops[..], [Op::Camera(_)]));
+            assert_eq!(value, expected);
+        }
+
+        for (zoom, expected) in [(0.0, "low"), (5.0, "mid"), (12.0, "high")] {
+            let (program, value) = eval(&step, json!({}), zoom);
+            assert!(matches!(program.ops[..], [Op::Camera(_)]));
+            assert_eq!(value, ExpressionValue::Str(expected.into()));
+        }
+
+        // Outputs read from the feature keep the curve in the program
+        let feature = json!([
+            "interpolate",
+            ["linear"],
+            ["zoom"],
+            0,
+            0,
+            10,
+            ["get", "rank"]
+        ]);
+        let (program, value) = eval(&feature, json!({ "rank": 4 }), 5.0);
+        assert!(!program.ops.iter().any(|op| matches!(op, Op::Camera(_))));
+        assert_eq!(value, ExpressionValue::Number(2.0));
+    }
+
+    #[test]
+    fn interned_keys_missing_from_the_layer() {
+        // The layer's keys are resolved once, keys it doesn't hold are never found
+        let expressions = [
+            (json!(["get", "name"]), json!(null)),
+            (json!(["has", "name"]), json!(false)),
+            (json!(["!", ["has", "name"]]), json!(true)),
+            (json!(["get", "class"]), json!("park")),
+        ];
+
+        for (expression, expected) in expressions {
+            let expected: ExpressionValue<'static> = serde_json::from_value(expected).unwrap();
+            let (_, value) = eval(&expression, json!({ "class": "park", "other": 1 }), 0.0);
+            assert_eq!(value, expected, "{expression}");
+        }
+
+        // Keys interned by another expression don't match a key read by this one
+        let mut keys = PropertyKeys::default();
+        let mut name: DataExpression<'static> =
+            serde_json::from_value(json!(["get", "name"])).unwrap();
+        let mut class: DataExpression<'static> =
+            serde_json::from_value(json!(["get", "class"])).unwrap();
+        name.compile(&mut keys).unwrap();
+        class.compile(&mut keys).unwrap();
+
+        let layer =
+            TestLayer::new(None, GeomType::Point, json!({ "class": "park" })).resolve(&keys);
+        let view = layer.view(0.0);
+        assert_eq!(Program::new(&name).eval(&view), ExpressionValue::Null);
+        assert_eq!(
+            Program::new(&class).eval(&view),
+            ExpressionValue::Str("park".into())
+        );
+    }
+}
@@ -1,53 +1,127 @@
-use bstr::{BStr, BString};
+use bstr::BString;
 use serde::Deserialize;
+use serde_json::Value;
 
-use crate::{FeatureView, Value};
+use super::data_expression::{Comparison, DataExpression, ExpressionValue};
 
+/// A filter in the legacy syntax, only kept until it is converted to an expression
 #[derive(Debug, Clone)]
 pub enum FilterExpression {
     All(Vec<FilterExpression>),
     Any(Vec<FilterExpression>),
+    None(Vec<FilterExpression>),
     In(BString, Vec<FilterValue>),
     NotIn(BString, Vec<FilterValue>),
-    Has(FilterValue),
-    NotHas(FilterValue),
+    Has(BString),
+    NotHas(BString),
     Cmp(BString, Comparison, FilterValue),
-    True,
+    /// A boolean member of `all`, `any` or `none`
+    Constant(bool),
+}
+
+/// MapLibre's `isExpressionFilter`, legacy filters and expressions share operators so a filter
+/// is only legacy when its operands are in the legacy form
+pub fn is_expression_filter(filter: &Value) -> bool {
+    let Some(filter) = filter.as_array() else {
+        return filter.is_boolean();
+    };
+
+    let Some(kind) = filter.first().and_then(Value::as_str) else {
+        return false;
+    };
+
+    match kind {
+        "has" => filter
+            .get(1)
+            .is_some_and(|key| key != "$id" && key != "$type"),
+        "in" => filter.len() >= 3 && (!filter[1].is_string() || filter[2].is_array()),
+        "!in" | "!has" | "none" => false,
+        "==" | "!=" | ">" | ">=" | "<" | "<=" => {
+            filter.len() != 3 || filter[1].is_array() || filter[2].is_array()
+        }
+        "any" | "all" => filter[1..]
+            .iter()
+            .all(|f| f.is_boolean() || is_expression_filter(f)),
+        _ => true,
+    }
 }
 
 impl FilterExpression {
-    pub fn eval(&self, feature: &FeatureView<'_>) -> bool {
+    /// Converts the filter to the equivalent expression, following MapLibre's `convertFilter`
+    pub fn into_expression(self) -> DataExpression<'static> {
         match self {
-            FilterExpression::All(filters) => filters.iter().all(|f| f.eval(feature).into()).into(),
-            FilterExpression::Any(filters) => filters.iter().any(|f| f.eval(feature).into()).into(),
-            FilterExpression::In(tag, values) => {
-                if let Some(value) = feature.key(tag) {
-                    values.iter().any(|v| v == &value)
-                } else {
-                    false
-                }
+            FilterExpression::All(filters) => {
+                DataExpression::All(filters.into_iter().map(Self::into_expression).collect())
             }
-            FilterExpression::NotIn(tag, values) => {
-                if let Some(value) = feature.key(tag) {
-                    values.iter().all(|v| v != &value)
-                } else {
-                    true
+            FilterExpression::Any(filters) => {
+                DataExpression::Any(filters.into_iter().map(Self::into_expression).collect())
+            }
+            FilterExpression::None(filters) => {
+                let any = FilterExpression::Any(filters).into_expression();
+                DataExpression::Not(Box::new(any))
+            }
+            FilterExpression::In(key, values) => {
+                let values = values.into_iter().map(FilterValue::into_value).collect();
+                let values = DataExpression::Constant(ExpressionValue::Array(values));
+                DataExpression::In(Box::new(property(key)), Box::new(values))
+            }
+            FilterExpression::NotIn(key, values) => {
+                let exp = FilterExpression::In(key, values).into_expression();
+                DataExpression::Not(Box::new(exp))
+            }
+            FilterExpression::Constant(value) => {
+                DataExpression::Constant(ExpressionValue::Bool(value))
+            }
+            FilterExpression::Has(key) => has(key),
+            FilterExpression::NotHas(key) => DataExpression::Not(Box::new(has(key))),
+            FilterExpression::Cmp(key, cmp, FilterValue::Null) if key != "$id" => {
+                // A property explicitly set to null is still present
+                let is_null = DataExpression::Cmp(
+                    cmp,
+                    Box::new(property(key.clone())),
+                    Box::new(FilterValue::Null.into_expression()),
+                );
+                match cmp {
+                    Comparison::Eq => DataExpression::All(vec![has(key), is_null]),
+                    Comparison::Neq => {
+                        DataExpression::Any(vec![DataExpression::Not(Box::new(has(key))), is_null])
+                    }
+                    _ => is_null,
                 }
             }
-            FilterExpression::Has(tag) => tag.as_str().and_then(|k| feature.key(k)).is_some(),
-            FilterExpression::NotHas(tag) => tag.as_str().and_then(|k| feature.key(k)).is_none(),
-            FilterExpression::Cmp(tag, cmp, value) => feature
-                .key(tag)
-                .map(|v| cmp.cmp(value, &v))
-                .unwrap_or(cmp.default()),
-            FilterExpression::True => true,
+            FilterExpression::Cmp(key, cmp, value) => DataExpression::Cmp(
+                cmp,
+                Box::new(property(key)),
+                Box::new(value.into_expression()),
+            ),
+        }
+    }
+}
+
+/// Legacy filters read `$type` and `$id` as the feature's geometry type and id
+fn property(key: BString) -> DataExpression<'static> {
+    match key.as_slice() {
+        b"$type" => DataExpression::GeometryType,
+        b"$id" => DataExpression::Id,
+        _ => {
+            let key = DataExpression::Constant(ExpressionValue::String(key));
+            DataExpression::Get(Box::new(key))
         }
     }
 }
 
-impl Default for FilterExpression {
-    fn default() -> Self {
-        FilterExpression::True
+fn has(key: BString) -> DataExpression<'static> {
+    match key.as_slice() {
+        b"$type" => DataExpression::Constant(ExpressionValue::Bool(true)),
+        b"$id" => DataExpression::Cmp(
+            Comparison::Neq,
+            Box::new(DataExpression::Id),
+            Box::new(DataExpression::Constant(ExpressionValue::Null)),
+        ),
+        _ => {
+            let key = DataExpression::Constant(ExpressionValue::String(key));
+            DataExpression::Has(Box::new(key), None)
+        }
     }
 }
 
@@ -56,7 +130,7 @@ impl<'de> serde::de::Deserialize<'de> for FilterExpression {
     where
         D: serde::Deserializer<'de>,
     {
-        deserializer.deserialize_seq(FilterVisitor)
+        deserializer.deserialize_any(FilterVisitor)
     }
 }
 
@@ -69,6 +143,13 @@ impl<'de> serde::de::Visitor<'de> for FilterVisitor {
         write!(formatter, "a filter array expression")
     }
 
+    fn visit_bool<E>(self, v: bool) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        Ok(FilterExpression::Constant(v))
+    }
+
     fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
     where
         A: serde::de::SeqAccess<'de>,
@@ -80,19 +161,17 @@ impl<'de> serde::de::Visitor<'de> for FilterVisitor {
             .ok_or(E::custom("expected filter expression type"))?;
 
         let exp = match kind.as_str() {
-            "all" => {
+            "all" | "any" | "none" => {
                 let mut filters = Vec::new();
                 while let Some(filter) = seq.next_element()? {
                     filters.push(filter)
                 }
-                FilterExpression::All(filters)
-            }
-            "any" => {
-                let mut filters = Vec::new();
-                while let Some(filter) = seq.next_element()? {
-                    filters.push(filter)
+
+                match kind.as_str() {
+                    "all" => FilterExpression::All(filters),
+                    "any" => FilterExpression::Any(filters),
+                    _ => FilterExpression::None(filters),
                 }
-                FilterExpression::Any(filters)
             }
             "in" | "!in" => {
                 let tag = seq
@@ -146,78 +225,169 @@ pub enum FilterValue {
     String(BString),
     Number(f64),
     Bool(bool),
+    Null,
 }
 
 impl FilterValue {
-    fn as_str(&self) -> Option<&BStr> {
+    fn into_value(self) -> ExpressionValue<'static> {
         match self {
-            FilterValue::String(bstring) => Some(bstring.as_ref()),
-            FilterValue::Number(_) => None,
-            FilterValue::Bool(_) => None,
+            FilterValue::String(s) => ExpressionValue::String(s),
+            FilterValue::Number(n) => ExpressionValue::Number(n),
+            FilterValue::Bool(b) => ExpressionValue::Bool(b),
+            FilterValue::Null => ExpressionValue::Null,
         }
     }
-}
 
-impl PartialEq<Value<'_>> for FilterValue {
-    fn eq(&self, other: &Value<'_>) -> bool {
-        match (self, other) {
-            (FilterValue::String(s), Value::String(ss)) => s == *ss,
-            (FilterValue::Number(n), Value::Number(nn)) => n == nn,
-            (FilterValue::Bool(b), Value::Bool(bb)) => b == bb,
-            _ => false,
-        }
+    fn into_expression(self) -> DataExpression<'static> {
+        DataExpression::Constant(self.into_value())
     }
 }
 
-impl PartialOrd<Value<'_>> for FilterValue {
-    fn partial_cmp(&self, other: &Value<'_>) -> Option<std::cmp::Ordering> {
-        match (self, other) {
-            (FilterValue::Number(n), Value::Number(nn)) => n.partial_cmp(nn),
-            _ => None,
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+
+    use super::*;
+    use crate::proto::tile::GeomType;
+    use crate::style::{Filter, PropertyKeys};
+    use crate::tests::TestLayer;
+
+    #[test]
+    fn expression_filters_are_detected() {
+        let cases = [
+            (json!(["==", ["get", "class"], "park"]), true),
+            (json!(["in", ["get", "x"], ["literal", ["a", "b"]]]), true),
+            (json!(["in", "a", "abc"]), false),
+            (json!(["in", "class", "a", "b"]), false),
+            (json!(["in", "class"]), false),
+            (json!(["!in", "class", "a"]), false),
+            (json!(["has", "$type"]), false),
+            (json!(["has", "$id"]), false),
+            (json!(["has", "class"]), true),
+            (json!(["!has", "class"]), false),
+            (json!(["==", "class", "park"]), false),
+            (json!(["==", "$type", "Polygon"]), false),
+            (json!(["!=", "k", null]), false),
+            (json!(["<", ["get", "rank"], 3]), true),
+            (json!(["==", "class", "park", "extra"]), true),
+            (json!(["any", true, ["==", "a", 1]]), false),
+            (json!(["any", true, ["==", ["get", "a"], 1]]), true),
+            (
+                json!(["all", ["==", ["get", "a"], 1], ["==", "b", 2]]),
+                false,
+            ),
+            (json!(["all"]), true),
+            (json!(["none", ["==", "a", 1]]), false),
+            (json!(["to-boolean", ["get", "a"]]), true),
+            (json!(true), true),
+            (json!("class"), false),
+            (json!([1, 2]), false),
+        ];
+
+        for (filter, expected) in cases {
+            assert_eq!(is_expression_filter(&filter), expected, "{filter}");
         }
     }
-}
 
-#[derive(Debug, Copy, Clone)]
-pub enum Comparison {
-    Eq,
-    Neq,
-    Lteq,
-    GtEq,
-    Lt,
-    Gt,
-}
+    /// Evaluates each filter for a polygon with the id 7, tiles can't hold null properties
+    /// so the null valued `n` is read as missing
+    fn check(cases: &[(Value, bool)]) {
+        let properties = json!({ "class": "park", "k": "v", "rank": 3, "n": null });
 
-impl Comparison {
-    fn cmp(&self, l: &FilterValue, r: &Value<'_>) -> bool {
-        match self {
-            Comparison::Eq => l == r,
-            Comparison::Neq => l != r,
-            Comparison::Lteq => l <= r,
-            Comparison::GtEq => l >= r,
-            Comparison::Lt => l < r,
-            Comparison::Gt => l > r,
+        for (filter, expected) in cases {
+            let mut parsed: Filter = serde_json::from_value(filter.clone())
+                .unwrap_or_else(|err| panic!("{filter}: {err}"));
+            let tree = parsed.clone();
+
+            let mut keys = PropertyKeys::default();
+            parsed.compile(&mut keys).unwrap();
+            let layer = TestLayer::new(Some(7), GeomType::Polygon, properties.clone());
+            let layer = layer.resolve(&keys);
+            let view = layer.view(0.0);
+
+            assert_eq!(tree.eval(&view), *expected, "{filter}");
+            assert_eq!(parsed.eval(&view), *expected, "{filter}");
         }
     }
 
-    fn from_str(s: &str) -> Option<Self> {
-        let v = match s {
-            "==" => Comparison::Eq,
-            "!=" => Comparison::Neq,
-            "<=" => Comparison::Lteq,
-            ">=" => Comparison::GtEq,
-            "<" => Comparison::Lt,
-            ">" => Comparison::Gt,
-            _ => return None,
-        };
+    #[test]
+    fn legacy_filters() {
+        check(&[
+            (json!(["==", "class", "park"]), true),
+            (json!(["!=", "class", "park"]), false),
+            (json!(["in", "class", "a", "park"]), true),
+            (json!(["in", "class", "a", "b"]), false),
+            (json!(["!in", "class", "a", "b"]), true),
+            (json!(["in", "missing", "a"]), false),
+            (json!(["!in", "missing", "a"]), true),
+            (json!([">", "rank", 2]), true),
+            (json!(["<=", "rank", 2]), false),
+            (json!(["has", "class"]), true),
+            (json!(["!has", "class"]), false),
+            (json!(["!has", "missing"]), true),
+            (
+                json!(["all", ["==", "class", "park"], [">", "rank", 5]]),
+                false,
+            ),
+            (
+                json!(["any", ["==", "class", "park"], [">", "rank", 5]]),
+                true,
+            ),
+            (
+                json!(["none", ["==", "class", "park"], [">", "rank", 5]]),
+                false,
+            ),
+            (json!(["none"]), true),
+            (json!(["any"]), false),
+            (json!(["all"]), true),
+            (json!(["any", true, ["==", "a", 1]]), true),
+            (json!(["all", false, ["==", "class", "park"]]), false),
+            // Comparisons with missing keys are false, except for !=
+            (json!(["==", "missing", "park"]), false),
+            (json!(["!=", "missing", "park"]), true),
+            (json!(["<", "missing", 1]), false),
+        ]);
+    }
 
-        Some(v)
+    #[test]
+    fn null_comparisons() {
+        check(&[
+            // Legacy filters compare a present key's value with null
+            (json!(["==", "k", null]), false),
+            (json!(["==", "missing", null]), false),
+            (json!(["==", "n", null]), false),
+            (json!(["!=", "k", null]), true),
+            (json!(["!=", "missing", null]), true),
+            (json!(["!=", "n", null]), true),
+            // Expressions read missing keys as null
+            (json!(["==", ["get", "k"], null]), false),
+            (json!(["==", ["get", "missing"], null]), true),
+            (json!(["==", ["get", "n"], null]), true),
+            (json!(["!=", ["get", "k"], null]), true),
+            (json!(["!=", ["get", "missing"], null]), false),
+            (json!(["!=", ["get", "n"], null]), false),
+        ]);
     }
 
-    fn default(&self) -> bool {
-        match self {
-            Comparison::Neq => true,
-            _ => false,
-        }
+    #[test]
+    fn type_and_id_filters() {
+        check(&[
+            (json!(["==", "$type", "Polygon"]), true),
+            (json!(["!=", "$type", "Polygon"]), false),
+            (json!(["==", "$type", "Point"]), false),
+            (json!(["in", "$type", "LineString", "Polygon"]), true),
+            (json!(["!in", "$type", "LineString", "Polygon"]), false),
+            (json!(["has", "$type"]), true),
+            (json!(["!has", "$type"]), false),
+            (json!(["==", "$id", 7]), true),
+            (json!(["!=", "$id", 7]), false),
+            (json!([">", "$id", 5]), true),
+            (json!(["in", "$id", 1, 7]), true),
+            (json!(["has", "$id"]), true),
+            (json!(["==", "$id", null]), false),
+            (json!(["==", ["geometry-type"], "Polygon"]), true),
+            (json!(["==", ["id"], 7]), true),
+            (json!(["==", ["get", "$type"], "Polygon"]), true),
+        ]);
     }
 }
@@ -13,11 +13,16 @@ impl<'de> serde::Deserialize<'de> for SourceCollection {
     where
         D: serde::Deserializer<'de>,
     {
-        let source_map = HashMap::<String, Source>::deserialize(deserializer)?;
+        // Sources are deserialized one at a time, those that fail are reported by validation
+        // and dropped so that only the layers using them are skipped
+        let source_map = HashMap::<String, serde_json::Value>::deserialize(deserializer)?;
         let mut sources = Vec::new();
         let mut names = Vec::new();
 
         for (name, source) in source_map {
+            let Ok(source) = Source::deserialize(source) else {
+                continue;
+            };
             names.push(name);
             sources.push(source);
         }
@@ -63,6 +68,11 @@ pub struct Source {
     #[serde(default)]
     pub tiles: Vec<url::Url>,
     pub attribution: Option<String>,
+    /// Whether line features keep the distance along the line needed by `line-gradient`
+    #[serde(default, rename = "lineMetrics")]
+    pub line_metrics: bool,
+    /// The GeoJSON of a `geojson` source, inline or as a url
+    pub data: Option<serde_json::Value>,
 }
 
 #[derive(Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
@@ -0,0 +1,772 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use super::{Filter, Layout, PaintFields, Source};
+
+const LAYER_KEYS: &[&str] = &[
+    "id",
+    "type",
+    "metadata",
+    "source",
+    "source-layer",
+    "minzoom",
+    "maxzoom",
+    "filter",
+    "layout",
+    "paint",
+];
+
+/// Layer types in the style spec that can't be loaded, their layers are skipped
+pub(super) const UNSUPPORTED_LAYER_TYPES: &[&str] = &["circle", "heatmap", "hillshade", "sky"];
+
+/// Every layout property in the style spec, properties missing from [`Layout`] aren't supported
+const SPEC_LAYOUT_PROPERTIES: &[&str] = &[
+    "visibility",
+    "fill-sort-key",
+    "line-cap",
+    "line-join",
+    "line-miter-limit",
+    "line-round-limit",
+    "line-sort-key",
+    "circle-sort-key",
+    "symbol-placement",
+    "symbol-spacing",
+    "symbol-avoid-edges",
+    "symbol-sort-key",
+    "symbol-z-order",
+    "icon-allow-overlap",
+    "icon-overlap",
+    "icon-ignore-placement",
+    "icon-optional",
+    "icon-rotation-alignment",
+    "icon-size",
+    "icon-text-fit",
+    "icon-text-fit-padding",
+    "icon-image",
+    "icon-rotate",
+    "icon-padding",
+    "icon-keep-upright",
+    "icon-offset",
+    "icon-anchor",
+    "icon-pitch-alignment",
+    "text-pitch-alignment",
+    "text-rotation-alignment",
+    "text-field",
+    "text-font",
+    "text-size",
+    "text-max-width",
+    "text-line-height",
+    "text-letter-spacing",
+    "text-justify",
+    "text-radial-offset",
+    "text-variable-anchor",
+    "text-variable-anchor-offset",
+    "text-anchor",
+    "text-max-angle",
+    "text-writing-mode",
+    "text-rotate",
+    "text-padding",
+    "text-keep-upright",
+    "text-transform",
+    "text-offset",
+    "text-allow-overlap",
+    "text-overlap",
+    "text-ignore-placement",
+    "text-optional",
+];
+
+/// Every paint property in the style spec, properties missing from [`PaintFields`] aren't
+/// supported
+const SPEC_PAINT_PROPERTIES: &[&str] = &[
+    "background-color",
+    "background-pattern",
+    "background-opacity",
+    "fill-antialias",
+    "fill-opacity",
+    "fill-color",
+    "fill-outline-color",
+    "fill-translate",
+    "fill-translate-anchor",
+    "fill-pattern",
+    "line-opacity",
+    "line-color",
+    "line-translate",
+    "line-translate-anchor",
+    "line-width",
+    "line-gap-width",
+    "line-offset",
+    "line-blur",
+    "line-dasharray",
+    "line-pattern",
+    "line-gradient",
+    "circle-radius",
+    "circle-color",
+    "circle-blur",
+    "circle-opacity",
+    "circle-translate",
+    "circle-translate-anchor",
+    "circle-pitch-scale",
+    "circle-pitch-alignment",
+    "circle-stroke-width",
+    "circle-stroke-color",
+    "circle-stroke-opacity",
+    "heatmap-radius",
+    "heatmap-weight",
+    "heatmap-intensity",
+    "heatmap-color",
+    "heatmap-opacity",
+    "icon-opacity",
+    "icon-color",
+    "icon-halo-color",
+    "icon-halo-width",
+    "icon-halo-blur",
+    "icon-translate",
+    "icon-translate-anchor",
+    "text-opacity",
+    "text-color",
+    "text-halo-color",
+    "text-halo-width",
+    "text-halo-blur",
+    "text-translate",
+    "text-translate-anchor",
+    "raster-opacity",
+    "raster-hue-rotate",
+    "raster-brightness-min",
+    "raster-brightness-max",
+    "raster-saturation",
+    "raster-contrast",
+    "raster-resampling",
+    "raster-fade-duration",
+    "hillshade-illumination-direction",
+    "hillshade-illumination-anchor",
+    "hillshade-exaggeration",
+    "hillshade-shadow-color",
+    "hillshade-highlight-color",
+    "hillshade-accent-color",
+    "fill-extrusion-opacity",
+    "fill-extrusion-color",
+    "fill-extrusion-translate",
+    "fill-extrusion-translate-anchor",
+    "fill-extrusion-pattern",
+    "fill-extrusion-height",
+    "fill-extrusion-base",
+    "fill-extrusion-vertical-gradient",
+];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A problem found in a style document
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// JSON path to the offending value, such as `layers[3].paint.fill-color`
+    pub path: String,
+    pub layer: Option<String>,
+    pub property: Option<String>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn warning(path: String, layer: Option<String>, message: String) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            path,
+            layer,
+            property: None,
+            message,
+        }
+    }
+
+    /// Errors in a layer only cost that layer when loading a style
+    pub fn into_warning(self) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..self
+        }
+    }
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.severity, self.path)?;
+        match (&self.layer, &self.property) {
+            (Some(layer), Some(property)) => write!(f, " (layer '{layer}', {property})")?,
+            (Some(layer), None) => write!(f, " (layer '{layer}')")?,
+            (None, Some(property)) => write!(f, " ({property})")?,
+            (None, None) => (),
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// The errors that stopped a style from loading
+#[derive(Debug, Clone)]
+pub struct Diagnostics(pub Vec<Diagnostic>);
+
+impl std::fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "invalid style document:")?;
+        for diagnostic in self.0.iter() {
+            writeln!(f, "\t{diagnostic}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Diagnostics {}
+
+struct Validator {
+    diagnostics: Vec<Diagnostic>,
+    layer: Option<String>,
+    /// Whether unknown properties and sources that don't load are errors. Loading only warns
+    /// about them, so a typo, vendor property or property from a newer spec doesn't cost the
+    /// layer, and a bad source only costs the layers that use it.
+    strict: bool,
+}
+
+impl Validator {
+    fn report(
+        &mut self,
+        severity: Severity,
+        path: String,
+        property: Option<&str>,
+        message: String,
+    ) {
+        self.diagnostics.push(Diagnostic {
+            severity,
+            path,
+            layer: self.layer.clone(),
+            property: property.map(str::to_string),
+            message,
+        });
+    }
+
+    fn error(&mut self, path: String, property: Option<&str>, message: String) {
+        self.report(Severity::Error, path, property, message)
+    }
+
+    fn warning(&mut self, path: String, property: Option<&str>, message: String) {
+        self.report(Severity::Warning, path, property, message)
+    }
+
+    /// Reports an error when validating, and only a warning when loading
+    fn strict_error(&mut self, path: String, property: Option<&str>, message: String) {
+        let severity = match self.strict {
+            true => Severity::Error,
+            false => Severity::Warning,
+        };
+        self.report(severity, path, property, message)
+    }
+}
+
+/// Problems with the document as a whole, kept apart from each layer's so that a layer can be
+/// skipped without failing the whole style
+pub(super) struct Report {
+    pub style: Vec<Diagnostic>,
+    pub layers: Vec<Vec<Diagnostic>>,
+}
+
+/// Checks a style document, reporting every problem rather than stopping at the first
+pub fn validate(style: &Value) -> Vec<Diagnostic> {
+    let mut report = validate_document(style, true);
+    report.style.extend(report.layers.into_iter().flatten());
+    report.style
+}
+
+pub(super) fn validate_document(style: &Value, strict: bool) -> Report {
+    let mut layer_diagnostics = Vec::new();
+    let mut validator = Validator {
+        diagnostics: Vec::new(),
+        layer: None,
+        strict,
+    };
+
+    let Some(style) = style.as_object() else {
+        validator.error(
+            "$".to_string(),
+            None,
+            "expected style document to be an object".to_string(),
+        );
+        return Report {
+            style: validator.diagnostics,
+            layers: layer_diagnostics,
+        };
+    };
+
+    let mut sources = HashMap::new();
+    match style.get("sources") {
+        Some(Value::Object(definitions)) => {
+            // Sources that don't load are dropped, so the layers using them aren't defined
+            for (name, source) in definitions {
+                if let Err(e) = Source::deserialize(source) {
+                    validator.strict_error(
+                        format!("sources.{name}"),
+                        None,
+                        format!("{e}, source skipped"),
+                    );
+                    continue;
+                }
+                sources.insert(name.as_str(), source);
+            }
+        }
+        Some(_) => validator.error(
+            "sources".to_string(),
+            None,
+            "expected sources to be an object".to_string(),
+        ),
+        None => validator.error("sources".to_string(), None, "missing sources".to_string()),
+    }
+
+    match style.get("sprite") {
+        Some(Value::String(sprite)) if url::Url::parse(sprite).is_err() => validator.warning(
+            "sprite".to_string(),
+            None,
+            "sprite is not a valid url, patterns are not drawn".to_string(),
+        ),
+        Some(Value::String(_)) | None => (),
+        Some(_) => validator.warning(
+            "sprite".to_string(),
+            None,
+            "only a single sprite url is supported, patterns are not drawn".to_string(),
+        ),
+    }
+
+    let layers = match style.get("layers") {
+        Some(Value::Array(layers)) => layers.as_slice(),
+        Some(_) => {
+            validator.error(
+                "layers".to_string(),
+                None,
+                "expected layers to be an array".to_string(),
+            );
+            &[]
+        }
+        None => {
+            validator.error("layers".to_string(), None, "missing layers".to_string());
+            &[]
+        }
+    };
+
+    let mut ids = HashSet::new();
+    for (idx, layer) in layers.iter().enumerate() {
+        let start = validator.diagnostics.len();
+        validate_layer(&mut validator, &sources, &mut ids, idx, layer);
+        layer_diagnostics.push(validator.diagnostics.split_off(start));
+    }
+
+    Report {
+        style: validator.diagnostics,
+        layers: layer_diagnostics,
+    }
+}
+
+fn validate_layer<'a>(
+    validator: &mut Validator,
+    sources: &HashMap<&str, &Value>,
+    ids: &mut HashSet<&'a str>,
+    idx: usize,
+    layer: &'a Value,
+) {
+    let path = format!("layers[{idx}]");
+    validator.layer = None;
+
+    let Some(layer) = layer.as_object() else {
+        validator.error(path, None, "expected layer to be an object".to_string());
+        return;
+    };
+
+    match layer.get("id").and_then(Value::as_str) {
+        Some(id) => {
+            validator.layer = Some(id.to_string());
+            if !ids.insert(id) {
+                validator.error(format!("{path}.id"), None, "duplicate layer id".to_string());
+            }
+        }
+        None => validator.error(format!("{path}.id"), None, "missing layer id".to_string()),
+    }
+
+    for key in layer.keys() {
+        if !LAYER_KEYS.contains(&key.as_str()) {
+            validator.warning(
+                format!("{path}.{key}"),
+                Some(key),
+                format!("unknown layer property '{key}'"),
+            );
+        }
+    }
+
+    let kind = layer.get("type").and_then(Value::as_str);
+    match kind {
+        Some("background" | "fill" | "line" | "symbol") => (),
+        Some(kind @ ("raster" | "fill-extrusion")) => validator.warning(
+            format!("{path}.type"),
+            Some("type"),
+            format!("{kind} layers are not drawn"),
+        ),
+        Some(kind) if UNSUPPORTED_LAYER_TYPES.contains(&kind) => validator.warning(
+            format!("{path}.type"),
+            Some("type"),
+            format!("{kind} layers are not supported"),
+        ),
+        Some(kind) => validator.error(
+            format!("{path}.type"),
+            Some("type"),
+            format!("unknown layer type '{kind}'"),
+        ),
+        None => validator.error(
+            format!("{path}.type"),
+            Some("type"),
+            "missing layer type".to_string(),
+        ),
+    }
+
+    if kind != Some("background") {
+        match layer.get("source") {
+            Some(Value::String(source)) => match source_type(sources, source) {
+                Some(Some("vector")) if layer.get("source-layer").is_none() => validator.error(
+                    format!("{path}.source-layer"),
+                    Some("source-layer"),
+                    format!("missing source-layer for vector source '{source}'"),
+                ),
+                Some(_) => (),
+                None => validator.error(
+                    format!("{path}.source"),
+                    Some("source"),
+                    format!("source '{source}' is not defined"),
+                ),
+            },
+            Some(_) => validator.error(
+                format!("{path}.source"),
+                Some("source"),
+                "expected source to be a string".to_string(),
+            ),
+            None => validator.error(
+                format!("{path}.source"),
+                Some("source"),
+                "missing source".to_string(),
+            ),
+        }
+    }
+
+    for key in ["minzoom", "maxzoom"] {
+        if layer.get(key).is_some_and(|zoom| !zoom.is_number()) {
+            validator.error(
+                format!("{path}.{key}"),
+                Some(key),
+                format!("expected {key} to be a number"),
+            );
+        }
+    }
+
+    if let Some(filter) = layer.get("filter")
+        && let Err(e) = Filter::check(filter)
+    {
+        validator.error(format!("{path}.filter"), Some("filter"), e);
+    }
+
+    validate_properties(
+        validator,
+        &path,
+        "layout",
+        layer,
+        SPEC_LAYOUT_PROPERTIES,
+        Layout::check_property,
+    );
+    validate_properties(
+        validator,
+        &path,
+        "paint",
+        layer,
+        SPEC_PAINT_PROPERTIES,
+        PaintFields::check_property,
+    );
+
+    let line_gradient = layer
+        .get("paint")
+        .is_some_and(|paint| paint.get("line-gradient").is_some());
+    if line_gradient
+        && let Some(Value::String(source)) = layer.get("source")
+        && let Some(source) = sources.get(source.as_str())
+        && source.get("lineMetrics") != Some(&Value::Bool(true))
+    {
+        validator.error(
+            format!("{path}.paint.line-gradient"),
+            Some("line-gradient"),
+            "line-gradient requires a source with lineMetrics enabled".to_string(),
+        );
+    }
+}
+
+/// The type of a named source, `Some(None)` when the source has no type
+fn source_type<'a>(sources: &HashMap<&str, &'a Value>, name: &str) -> Option<Option<&'a str>> {
+    sources
+        .get(name)
+        .map(|source| source.get("type").and_then(Value::as_str))
+}
+
+fn validate_properties(
+    validator: &mut Validator,
+    path: &str,
+    section: &str,
+    layer: &serde_json::Map<String, Value>,
+    spec: &[&str],
+    check: fn(&str, &Value) -> Option<Result<(), String>>,
+) {
+    let properties = match layer.get(section) {
+        Some(Value::Object(properties)) => properties,
+        Some(_) => {
+            validator.error(
+                format!("{path}.{section}"),
+                None,
+                format!("expected {section} to be an object"),
+            );
+            return;
+        }
+        None => return,
+    };
+
+    for (name, value) in properties {
+        let path = format!("{path}.{section}.{name}");
+        match check(name, value) {
+            Some(Ok(())) => (),
+            Some(Err(e)) => validator.error(path, Some(name), e),
+            None if spec.contains(&name.as_str()) => validator.warning(
+                path,
+                Some(name),
+                format!("unsupported {section} property '{name}'"),
+            ),
+            None => validator.strict_error(
+                path,
+                Some(name),
+                format!("unknown {section} property '{name}'"),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    type Expected<'a> = (&'a str, Option<&'a str>, Option<&'a str>, Severity);
+
+    /// Validates `layers` in a style with a vector source, and GeoJSON sources with and
+    /// without line metrics, checking the path, layer, property and severity of each problem
+    fn check(layers: Value, strict: bool, expected: &[Expected]) {
+        let data = json!({ "type": "FeatureCollection", "features": [] });
+        let style = json!({
+            "version": 8,
+            "sources": {
+                "tiles": { "type": "vector", "tiles": ["https://example.com/{z}/{x}/{y}.pbf"] },
+                "lines": { "type": "geojson", "data": data },
+                "metrics": { "type": "geojson", "lineMetrics": true, "data": data }
+            },
+            "layers": layers
+        });
+
+        let report = validate_document(&style, strict);
+        assert!(report.style.is_empty(), "{:?}", report.style);
+
+        let found: Vec<Expected> = report
+            .layers
+            .iter()
+            .flatten()
+            .map(|d| {
+                let layer = d.layer.as_deref();
+                (d.path.as_str(), layer, d.property.as_deref(), d.severity)
+            })
+            .collect();
+        assert_eq!(found, expected, "{layers}");
+    }
+
+    fn fill(paint: Value) -> Value {
+        json!({
+            "id": "water",
+            "type": "fill",
+            "source": "tiles",
+            "source-layer": "water",
+            "paint": paint
+        })
+    }
+
+    fn gradient() -> Value {
+        json!([
+            "interpolate",
+            ["linear"],
+            ["line-progress"],
+            0,
+            "red",
+            1,
+            "blue"
+        ])
+    }
+
+    #[test]
+    fn valid_layers() {
+        let layers = json!([
+            { "id": "background", "type": "background", "paint": { "background-color": "#fff" } },
+            fill(json!({ "fill-color": ["match", ["get", "class"], "lake", "blue", "cyan"] })),
+            {
+                "id": "route",
+                "type": "line",
+                "source": "metrics",
+                "paint": {
+                    "line-gradient": gradient()
+                }
+            }
+        ]);
+        check(layers.clone(), true, &[]);
+        check(layers, false, &[]);
+    }
+
+    #[test]
+    fn unknown_properties() {
+        let layers = json!([fill(json!({ "fill-colour": "blue" }))]);
+        let path = "layers[0].paint.fill-colour";
+        check(
+            layers.clone(),
+            true,
+            &[(path, Some("water"), Some("fill-colour"), Severity::Error)],
+        );
+        check(
+            layers,
+            false,
+            &[(path, Some("water"), Some("fill-colour"), Severity::Warning)],
+        );
+
+        // Properties in the spec that aren't drawn are only ever warned about
+        let layers = json!([fill(json!({ "fill-translate-anchor": "viewport" }))]);
+        let unsupported = (
+            "layers[0].paint.fill-translate-anchor",
+            Some("water"),
+            Some("fill-translate-anchor"),
+            Severity::Warning,
+        );
+        check(layers.clone(), true, &[unsupported]);
+        check(layers, false, &[unsupported]);
+    }
+
+    #[test]
+    fn invalid_values() {
+        check(
+            json!([fill(json!({ "fill-color": "not a color" }))]),
+            false,
+            &[(
+                "layers[0].paint.fill-color",
+                Some("water"),
+                Some("fill-color"),
+                Severity::Error,
+            )],
+        );
+
+        let mut layer = fill(json!({}));
+        layer["filter"] = json!(["frobnicate", ["get", "class"]]);
+        check(
+            json!([layer]),
+            false,
+            &[(
+                "layers[0].filter",
+                Some("water"),
+                Some("filter"),
+                Severity::Error,
+            )],
+        );
+    }
+
+    #[test]
+    fn sources() {
+        let mut undefined = fill(json!({}));
+        undefined["source"] = json!("missing");
+        check(
+            json!([undefined]),
+            false,
+            &[(
+                "layers[0].source",
+                Some("water"),
+                Some("source"),
+                Severity::Error,
+            )],
+        );
+
+        let mut no_source_layer = fill(json!({}));
+        no_source_layer
+            .as_object_mut()
+            .unwrap()
+            .remove("source-layer");
+        check(
+            json!([no_source_layer]),
+            false,
+            &[(
+                "layers[0].source-layer",
+                Some("water"),
+                Some("source-layer"),
+                Severity::Error,
+            )],
+        );
+
+        let gradient = json!({
+            "id": "route",
+            "type": "line",
+            "source": "lines",
+            "paint": { "line-gradient": gradient() }
+        });
+        check(
+            json!([gradient]),
+            false,
+            &[(
+                "layers[0].paint.line-gradient",
+                Some("route"),
+                Some("line-gradient"),
+                Severity::Error,
+            )],
+        );
+    }
+
+    #[test]
+    fn duplicate_layer_ids() {
+        let layers = json!([fill(json!({})), fill(json!({})), fill(json!({}))]);
+        check(
+            layers,
+            true,
+            &[
+                ("layers[1].id", Some("water"), None, Severity::Error),
+                ("layers[2].id", Some("water"), None, Severity::Error),
+            ],
+        );
+    }
+
+    #[test]
+    fn sources_that_dont_load() {
+        let style = json!({
+            "sources": { "bad": { "type": "vector", "tiles": ["not a url"] } },
+            "layers": []
+        });
+
+        for (strict, severity) in [(true, Severity::Error), (false, Severity::Warning)] {
+            let report = validate_document(&style, strict);
+            let found: Vec<_> = report
+                .style
+                .iter()
+                .map(|d| (d.path.as_str(), d.layer.as_deref(), d.severity))
+                .collect();
+            assert_eq!(found, [("sources.bad", None, severity)]);
+        }
+    }
+}
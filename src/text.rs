@@ -4,6 +4,7 @@ use std::sync::{Arc, Mutex, RwLock};
 
 use ahash::AHashMap as HashMap;
 use math::V2;
+use smallvec::SmallVec;
 
 pub const TEXT_ATLAS_SIZE: u32 = 2048;
 
@@ -128,7 +129,7 @@ impl FontCollection {
         (FontId(font_id), face)
     }
 
-    pub fn font_id(&mut self, font_id: FontId) -> &Font {
+    pub fn font_id(&self, font_id: FontId) -> &Font {
         self.fonts.get(&font_id).unwrap()
     }
 
@@ -183,3 +184,328 @@ impl GlyphId {
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct GlyphKey(GlyphId, i32);
+
+/// Chooses where to wrap `text`, returning the char index that ends each line. Breaks are
+/// picked to keep every line close to the average line width rather than filling greedily,
+/// following the approach used by MapLibre GL. `advance` is given each char's index along
+/// with the char itself.
+pub fn line_breaks<F: Fn(usize, char) -> f32>(
+    text: &[char],
+    max_width: f32,
+    advance: F,
+) -> SmallVec<[usize; 4]> {
+    if text.is_empty() {
+        return smallvec::smallvec![0];
+    }
+
+    // Without a width to wrap to only newlines break
+    if max_width <= 0.0 {
+        let mut breaks: SmallVec<[usize; 4]> = text
+            .iter()
+            .enumerate()
+            .filter(|(idx, c)| **c == '\n' && idx + 1 < text.len())
+            .map(|(idx, _)| idx + 1)
+            .collect();
+        breaks.push(text.len());
+        return breaks;
+    }
+
+    let total_width: f32 = text
+        .iter()
+        .enumerate()
+        .map(|(idx, &c)| advance(idx, c))
+        .sum();
+    let line_count = (total_width / max_width).ceil().max(1.0);
+    let target_width = total_width / line_count;
+
+    // Zero width spaces mean the text has already been given explicit break opportunities
+    let has_suggested_breaks = text.contains(&'\u{200b}');
+
+    let mut potential_breaks: Vec<LineBreak> = Vec::new();
+    let mut x = 0.0;
+
+    for (idx, &c) in text.iter().enumerate() {
+        if !is_whitespace(c) {
+            x += advance(idx, c);
+        }
+
+        if idx + 1 < text.len() {
+            let ideographic = allows_ideographic_break(c);
+            if is_breakable(c) || ideographic {
+                let penalty = break_penalty(c, text[idx + 1], ideographic && has_suggested_breaks);
+                let line_break = LineBreak::evaluate(
+                    idx + 1,
+                    x,
+                    target_width,
+                    &potential_breaks,
+                    penalty,
+                    false,
+                );
+                potential_breaks.push(line_break);
+            }
+        }
+    }
+
+    let last = LineBreak::evaluate(text.len(), x, target_width, &potential_breaks, 0.0, true);
+
+    let mut breaks = SmallVec::new();
+    let mut next = Some(&last);
+    while let Some(line_break) = next {
+        breaks.push(line_break.index);
+        next = line_break.prior.map(|idx| &potential_breaks[idx]);
+    }
+    breaks.reverse();
+
+    breaks
+}
+
+struct LineBreak {
+    index: usize,
+    x: f32,
+    prior: Option<usize>,
+    badness: f32,
+}
+
+impl LineBreak {
+    fn evaluate(
+        index: usize,
+        x: f32,
+        target_width: f32,
+        potential_breaks: &[LineBreak],
+        penalty: f32,
+        is_last: bool,
+    ) -> Self {
+        let mut prior = None;
+        let mut badness = Self::badness(x, target_width, penalty, is_last);
+
+        for (idx, potential) in potential_breaks.iter().enumerate() {
+            let line_width = x - potential.x;
+            let break_badness =
+                Self::badness(line_width, target_width, penalty, is_last) + potential.badness;
+
+            if break_badness <= badness {
+                prior = Some(idx);
+                badness = break_badness;
+            }
+        }
+
+        LineBreak {
+            index,
+            x,
+            prior,
+            badness,
+        }
+    }
+
+    fn badness(line_width: f32, target_width: f32, penalty: f32, is_last: bool) -> f32 {
+        let raggedness = (line_width - target_width).powi(2);
+
+        if is_last {
+            // Prefer a final line shorter than the others over a longer one
+            if line_width < target_width {
+                raggedness / 2.0
+            } else {
+                raggedness * 2.0
+            }
+        } else {
+            raggedness + penalty.abs() * penalty
+        }
+    }
+}
+
+fn break_penalty(c: char, next: char, penalize_ideographic: bool) -> f32 {
+    let mut penalty = 0.0;
+
+    // Newlines are effectively forced breaks
+    if c == '\n' {
+        penalty -= 10000.0;
+    }
+
+    // Breaking at a space is preferable to breaking between ideographs
+    if penalize_ideographic {
+        penalty += 150.0;
+    }
+
+    if c == '(' || c == '\u{ff08}' {
+        penalty += 50.0;
+    }
+
+    if next == ')' || next == '\u{ff09}' {
+        penalty += 50.0;
+    }
+
+    penalty
+}
+
+fn is_whitespace(c: char) -> bool {
+    matches!(c, '\t' | '\n' | '\u{0b}' | '\u{0c}' | '\r' | ' ')
+}
+
+fn is_breakable(c: char) -> bool {
+    matches!(
+        c,
+        '\n' | ' '
+            | '&'
+            | '('
+            | ')'
+            | '+'
+            | '-'
+            | '/'
+            | '\u{ad}'
+            | '\u{b7}'
+            | '\u{200b}'
+            | '\u{2010}'
+            | '\u{2013}'
+            | '\u{2027}'
+    )
+}
+
+/// Scripts that are written without spaces and may wrap between any two characters
+fn allows_ideographic_break(c: char) -> bool {
+    matches!(
+        c as u32,
+        0x2e80..=0x2eff // CJK Radicals Supplement
+            | 0x2f00..=0x2fdf // Kangxi Radicals
+            | 0x2ff0..=0x2fff // Ideographic Description Characters
+            | 0x3000..=0x303f // CJK Symbols and Punctuation
+            | 0x3040..=0x309f // Hiragana
+            | 0x30a0..=0x30ff // Katakana
+            | 0x3100..=0x312f // Bopomofo
+            | 0x31a0..=0x31bf // Bopomofo Extended
+            | 0x31c0..=0x31ef // CJK Strokes
+            | 0x31f0..=0x31ff // Katakana Phonetic Extensions
+            | 0x3200..=0x32ff // Enclosed CJK Letters and Months
+            | 0x3300..=0x33ff // CJK Compatibility
+            | 0x3400..=0x4dbf // CJK Unified Ideographs Extension A
+            | 0x4e00..=0x9fff // CJK Unified Ideographs
+            | 0xa000..=0xa48f // Yi Syllables
+            | 0xa490..=0xa4cf // Yi Radicals
+            | 0xf900..=0xfaff // CJK Compatibility Ideographs
+            | 0xfe10..=0xfe1f // Vertical Forms
+            | 0xfe30..=0xfe4f // CJK Compatibility Forms
+            | 0xff00..=0xffef // Halfwidth and Fullwidth Forms
+    )
+}
+
+/// Whether text in the script `c` belongs to can be drawn without complex shaping or
+/// right-to-left layout, which glyphs are not laid out with
+pub fn is_supported_script(c: char) -> bool {
+    !matches!(
+        c as u32,
+        0x0590..=0x05ff // Hebrew
+            | 0x0600..=0x06ff // Arabic
+            | 0x0700..=0x074f // Syriac
+            | 0x0750..=0x077f // Arabic Supplement
+            | 0x0780..=0x07bf // Thaana
+            | 0x07c0..=0x07ff // NKo
+            | 0x0800..=0x089f // Samaritan, Mandaic and Syriac Supplement
+            | 0x08a0..=0x08ff // Arabic Extended-A
+            | 0x0900..=0x0dff // Devanagari through Sinhala
+            | 0x1000..=0x109f // Myanmar
+            | 0x1780..=0x17ff // Khmer
+            | 0xfb1d..=0xfb4f // Hebrew Presentation Forms
+            | 0xfb50..=0xfdff // Arabic Presentation Forms-A
+            | 0xfe70..=0xfeff // Arabic Presentation Forms-B
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Wraps `text` with every char one unit wide, trimming the whitespace lines end with
+    fn lines(text: &str, max_width: f32) -> Vec<String> {
+        let chars: Vec<char> = text.chars().collect();
+        let mut start = 0;
+        line_breaks(&chars, max_width, |_, _| 1.0)
+            .into_iter()
+            .map(|end| {
+                let line: String = chars[start..end].iter().collect();
+                start = end;
+                line.trim_end().to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn lines_are_balanced() {
+        assert_eq!(lines("Rio de Janeiro", 10.0), ["Rio de", "Janeiro"]);
+        // Filling greedily would leave "Colombia" alone on the second line
+        assert_eq!(
+            lines("Universidad Nacional de Colombia", 24.0),
+            ["Universidad Nacional", "de Colombia"]
+        );
+        assert_eq!(
+            lines("Universidad Nacional de Colombia", 12.0),
+            ["Universidad", "Nacional de", "Colombia"]
+        );
+        assert_eq!(lines("Saint-Jean-de-Luz", 9.0), ["Saint-Jean-", "de-Luz"]);
+        assert_eq!(lines("Rio de Janeiro", 20.0), ["Rio de Janeiro"]);
+    }
+
+    #[test]
+    fn ideographs_wrap_between_chars() {
+        assert_eq!(
+            lines("東京都千代田区丸の内", 4.0),
+            ["東京都千", "代田区", "丸の内"]
+        );
+        assert_eq!(
+            lines("東京都千代田区丸の内", 6.0),
+            ["東京都千代", "田区丸の内"]
+        );
+        // Zero width spaces are preferred over breaking between ideographs
+        assert_eq!(
+            lines("東京\u{200b}千代田区", 3.0),
+            ["東京\u{200b}", "千代田区"]
+        );
+    }
+
+    #[test]
+    fn newlines_always_break() {
+        assert_eq!(lines("Main\nStreet", 100.0), ["Main", "Street"]);
+        assert_eq!(lines("a b\nc d e f", 100.0), ["a b", "c d e f"]);
+        assert_eq!(lines("a\nb\nc", 100.0), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn brackets_are_kept_together() {
+        // The balanced break would split the brackets after 前
+        assert_eq!(lines("駅（前）広場", 3.0), ["駅（前）", "広場"]);
+
+        // Latin text can break after an opening bracket, but only when the lines would
+        // otherwise be far from balanced
+        let open = format!("{}({})", "x".repeat(50), "y".repeat(50));
+        assert_eq!(
+            lines(&open, 60.0),
+            [
+                format!("{}(", "x".repeat(50)),
+                format!("{})", "y".repeat(50))
+            ]
+        );
+        let spaced = format!("{} {}({})", "x".repeat(40), "x".repeat(9), "y".repeat(50));
+        assert_eq!(
+            lines(&spaced, 60.0),
+            [
+                "x".repeat(40),
+                format!("{}({})", "x".repeat(9), "y".repeat(50))
+            ]
+        );
+        assert_eq!(lines("Ames(North)", 5.0), ["Ames(North)"]);
+
+        assert_eq!(break_penalty('（', '前', false), 50.0);
+        assert_eq!(break_penalty('前', '）', false), 50.0);
+        assert_eq!(break_penalty('(', ')', false), 100.0);
+        assert_eq!(break_penalty(' ', 'a', false), 0.0);
+    }
+
+    #[test]
+    fn no_max_width_only_breaks_at_newlines() {
+        assert_eq!(lines("Rio de Janeiro", 0.0), ["Rio de Janeiro"]);
+        assert_eq!(lines("Rio de Janeiro", -3.0), ["Rio de Janeiro"]);
+        assert_eq!(lines("Main Street", 0.0), ["Main Street"]);
+        // Newlines still break
+        assert_eq!(lines("Main\nStreet", 0.0), ["Main", "Street"]);
+        assert_eq!(lines("a\n\nb\n", -1.0), ["a", "", "b"]);
+        assert_eq!(line_breaks(&[], 10.0, |_, _| 1.0).as_slice(), [0]);
+    }
+}
@@ -6,9 +6,10 @@ use std::{
 
 use crate::{
     TileId,
+    geojson::GeoJsonSource,
     mbtiles::MbTilesSource,
     proto::Tile,
-    style::{SourceId, Style},
+    style::{SourceId, SourceType, Style},
     versatiles::VersatilesSource,
 };
 
@@ -27,6 +28,13 @@ impl TileSourceCollection {
         for (name, source) in style.sources.iter() {
             let mut tile_source = None;
 
+            if let (SourceType::Geojson, Some(data)) = (source.kind, source.data.as_ref()) {
+                match GeoJsonSource::new(data_dir.as_ref(), data, source.line_metrics) {
+                    Ok(source) => tile_source = Some(TileSource::GeoJson(source)),
+                    Err(e) => eprintln!("unable to load geojson source '{name}': {e}"),
+                }
+            }
+
             for uri in source.tiles.iter() {
                 match TileSource::load(data_dir.as_ref(), uri) {
                     Ok(source) => {
@@ -96,6 +104,7 @@ impl TileSourceCollection {
 pub enum TileSource {
     Versatiles(VersatilesSource),
     MbTiles(MbTilesSource),
+    GeoJson(GeoJsonSource),
 }
 
 impl TileSource {
@@ -129,6 +138,7 @@ impl TileSource {
         let res = match self {
             TileSource::Versatiles(source) => TileSource::Versatiles(source.try_clone()?),
             TileSource::MbTiles(source) => TileSource::MbTiles(source.try_clone()?),
+            TileSource::GeoJson(source) => TileSource::GeoJson(source.try_clone()?),
         };
 
         Ok(res)
@@ -138,6 +148,7 @@ impl TileSource {
         match self {
             TileSource::Versatiles(versatiles_source) => versatiles_source.query_tile(tile_id),
             TileSource::MbTiles(mb_tiles_source) => mb_tiles_source.query_tile(tile_id),
+            TileSource::GeoJson(geojson_source) => geojson_source.query_tile(tile_id),
         }
     }
 }